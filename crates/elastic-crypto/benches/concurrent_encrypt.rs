@@ -0,0 +1,62 @@
+//! Measures AES-GCM encrypt throughput on a shared `ElasticCrypto` as the
+//! number of threads grows.
+//!
+//! Run with `cargo bench -p elastic-crypto --bench concurrent_encrypt`.
+
+use elastic_crypto::{ElasticCrypto, KeyConfig, KeyType};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PAYLOAD_SIZE: usize = 1024;
+const RUN_TIME: Duration = Duration::from_secs(2);
+const THREAD_COUNTS: [usize; 5] = [1, 2, 4, 8, 16];
+
+fn run(crypto: &Arc<ElasticCrypto>, handles: &[u32], threads: usize) -> f64 {
+    let barrier = Arc::new(Barrier::new(threads));
+    let workers: Vec<_> = (0..threads)
+        .map(|i| {
+            let crypto = Arc::clone(crypto);
+            let barrier = Arc::clone(&barrier);
+            let handle = handles[i % handles.len()];
+            thread::spawn(move || {
+                let payload = vec![0xa5u8; PAYLOAD_SIZE];
+                barrier.wait();
+                let start = Instant::now();
+                let mut ops = 0u64;
+                while start.elapsed() < RUN_TIME {
+                    crypto.encrypt(handle, payload.clone()).expect("encrypt failed");
+                    ops += 1;
+                }
+                ops as f64 / start.elapsed().as_secs_f64()
+            })
+        })
+        .collect();
+
+    workers.into_iter().map(|w| w.join().expect("worker panicked")).sum()
+}
+
+fn main() {
+    let available = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let crypto = Arc::new(ElasticCrypto::new().expect("Failed to initialize crypto"));
+    let config = KeyConfig {
+        key_type: KeyType::Symmetric,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: None,
+    };
+    let handles: Vec<u32> = (0..4)
+        .map(|_| crypto.generate_key(config.clone()).expect("Failed to generate key"))
+        .collect();
+
+    println!();
+    println!("encrypt {} B payloads, {} available CPUs", PAYLOAD_SIZE, available);
+    println!("{:>8} {:>14} {:>10}", "threads", "ops/s", "scaling");
+
+    let mut baseline = None;
+    for &threads in THREAD_COUNTS.iter().filter(|&&t| t <= available.max(2) * 2) {
+        let ops = run(&crypto, &handles, threads);
+        let base = *baseline.get_or_insert(ops);
+        println!("{:>8} {:>14.0} {:>9.2}x", threads, ops, ops / base);
+    }
+}
@@ -0,0 +1,57 @@
+//! Audit events for access-control decisions on the key store.
+//!
+//! Every denied access is reported to the context's [`AuditSink`] before the
+//! caller gets [`Error::KeyAccessDenied`](crate::Error::KeyAccessDenied) or
+//! [`Error::OperationNotPermitted`](crate::Error::OperationNotPermitted). The
+//! default sink writes to standard error; hosts can install their own with
+//! [`ElasticCrypto::set_audit_sink`](crate::ElasticCrypto::set_audit_sink).
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEvent {
+    /// A context used a handle owned by another namespace. `caller` and
+    /// `owner` are workload ids, `None` for the host.
+    KeyAccessDenied {
+        caller: Option<String>,
+        handle: u32,
+        owner: Option<String>,
+    },
+    /// A workload context called an operation reserved for the host.
+    HostOperationDenied { caller: String, operation: &'static str },
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn name(workload: &Option<String>) -> &str {
+            workload.as_deref().unwrap_or("<host>")
+        }
+        match self {
+            AuditEvent::KeyAccessDenied { caller, handle, owner } => write!(
+                f,
+                "denied: {} used key {} owned by {}",
+                name(caller),
+                handle,
+                name(owner)
+            ),
+            AuditEvent::HostOperationDenied { caller, operation } => {
+                write!(f, "denied: workload {} called host operation {}", caller, operation)
+            }
+        }
+    }
+}
+
+/// Receives audit events. Implementations must not block for long: events
+/// are recorded on the thread that made the denied call.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: AuditEvent);
+}
+
+/// Writes events to standard error.
+pub struct StderrAuditSink;
+
+impl AuditSink for StderrAuditSink {
+    fn record(&self, event: AuditEvent) {
+        eprintln!("[ElasticCrypto] audit: {}", event);
+    }
+}
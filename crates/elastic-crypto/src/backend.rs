@@ -0,0 +1,152 @@
+//! Handle-based key store API shared by in-process and remote backends.
+//!
+//! [`ElasticCrypto`] implements [`CryptoBackend`] by holding keys in the
+//! calling process. The `elastic-keyd` client implements it by forwarding
+//! each call to a key server, so code written against the trait can move its
+//! keys out of workload memory without other changes.
+
+use crate::{ElasticCrypto, HpkeAead, KeyAlgorithm, KeyConfig, Result};
+
+/// Operations on keys referenced by handle. Key material only leaves the
+/// backend through [`CryptoBackend::export_key`], which is refused for
+/// secure-storage keys.
+pub trait CryptoBackend {
+    fn generate_key(&self, config: KeyConfig) -> Result<u32>;
+    fn import_key(&self, key_data: Vec<u8>, config: KeyConfig) -> Result<u32>;
+    fn export_key(&self, handle: u32) -> Result<Vec<u8>>;
+    fn delete_key(&self, handle: u32) -> Result<()>;
+    fn key_algorithm(&self, handle: u32) -> Result<KeyAlgorithm>;
+    fn get_public_key(&self, handle: u32) -> Result<Vec<u8>>;
+    fn get_key_id(&self, handle: u32) -> Result<Vec<u8>>;
+
+    fn encrypt(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>>;
+    fn decrypt(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>>;
+    fn encrypt_siv(
+        &self,
+        handle: u32,
+        plaintext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>>;
+    fn decrypt_siv(
+        &self,
+        handle: u32,
+        ciphertext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>>;
+
+    fn sign(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>>;
+    fn verify(&self, handle: u32, data: Vec<u8>, signature: Vec<u8>) -> Result<bool>;
+    fn calculate_mac(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>>;
+    fn verify_mac(&self, handle: u32, data: Vec<u8>, mac: Vec<u8>) -> Result<bool>;
+
+    fn hpke_open(&self, handle: u32, sealed: Vec<u8>, info: Vec<u8>, aad: Vec<u8>, aead: HpkeAead)
+        -> Result<Vec<u8>>;
+    fn hpke_open_auth(
+        &self,
+        handle: u32,
+        sender_public_key: Vec<u8>,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>>;
+}
+
+impl CryptoBackend for ElasticCrypto {
+    fn generate_key(&self, config: KeyConfig) -> Result<u32> {
+        ElasticCrypto::generate_key(self, config)
+    }
+
+    fn import_key(&self, key_data: Vec<u8>, config: KeyConfig) -> Result<u32> {
+        ElasticCrypto::import_key(self, key_data, config)
+    }
+
+    fn export_key(&self, handle: u32) -> Result<Vec<u8>> {
+        ElasticCrypto::export_key(self, handle)
+    }
+
+    fn delete_key(&self, handle: u32) -> Result<()> {
+        ElasticCrypto::delete_key(self, handle)
+    }
+
+    fn key_algorithm(&self, handle: u32) -> Result<KeyAlgorithm> {
+        ElasticCrypto::key_algorithm(self, handle)
+    }
+
+    fn get_public_key(&self, handle: u32) -> Result<Vec<u8>> {
+        ElasticCrypto::get_public_key(self, handle)
+    }
+
+    fn get_key_id(&self, handle: u32) -> Result<Vec<u8>> {
+        ElasticCrypto::get_key_id(self, handle)
+    }
+
+    fn encrypt(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+        ElasticCrypto::encrypt(self, handle, data)
+    }
+
+    fn decrypt(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+        ElasticCrypto::decrypt(self, handle, data)
+    }
+
+    fn encrypt_siv(
+        &self,
+        handle: u32,
+        plaintext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        ElasticCrypto::encrypt_siv(self, handle, plaintext, associated_data, nonce)
+    }
+
+    fn decrypt_siv(
+        &self,
+        handle: u32,
+        ciphertext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        ElasticCrypto::decrypt_siv(self, handle, ciphertext, associated_data, nonce)
+    }
+
+    fn sign(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+        ElasticCrypto::sign(self, handle, data)
+    }
+
+    fn verify(&self, handle: u32, data: Vec<u8>, signature: Vec<u8>) -> Result<bool> {
+        ElasticCrypto::verify(self, handle, data, signature)
+    }
+
+    fn calculate_mac(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+        ElasticCrypto::calculate_mac(self, handle, data)
+    }
+
+    fn verify_mac(&self, handle: u32, data: Vec<u8>, mac: Vec<u8>) -> Result<bool> {
+        ElasticCrypto::verify_mac(self, handle, data, mac)
+    }
+
+    fn hpke_open(
+        &self,
+        handle: u32,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        ElasticCrypto::hpke_open(self, handle, sealed, info, aad, aead)
+    }
+
+    fn hpke_open_auth(
+        &self,
+        handle: u32,
+        sender_public_key: Vec<u8>,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        ElasticCrypto::hpke_open_auth(self, handle, sender_public_key, sealed, info, aad, aead)
+    }
+}
@@ -0,0 +1,150 @@
+//! COSE (RFC 9052) messages built from key handles.
+//!
+//! `COSE_Sign1` uses EdDSA, ES256 or PS256 depending on the key,
+//! `COSE_Encrypt0` uses A256GCM with a random IV in the unprotected header.
+//! In both cases the protected header carries `alg` and `kid`, so a recipient
+//! can pick the key and the algorithm cannot be swapped without breaking the
+//! signature or tag.
+//! Messages are emitted with their CBOR tag (18 and 16).
+
+use crate::rng::ElasticRng;
+use crate::signature::verify_with_public_key;
+use crate::{ElasticCrypto, Error, KeyAlgorithm, Result};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::Nonce;
+use coset::{
+    iana, CoseEncrypt0, CoseEncrypt0Builder, CoseSign1, CoseSign1Builder, Header, HeaderBuilder,
+    RegisteredLabelWithPrivate, TaggedCborSerializable,
+};
+
+const IV_LEN: usize = 12;
+
+fn cose_algorithm(algorithm: KeyAlgorithm) -> Result<iana::Algorithm> {
+    match algorithm {
+        KeyAlgorithm::Ed25519 => Ok(iana::Algorithm::EdDSA),
+        KeyAlgorithm::EcdsaP256 => Ok(iana::Algorithm::ES256),
+        KeyAlgorithm::RsaPssSha256 => Ok(iana::Algorithm::PS256),
+        KeyAlgorithm::Aes256Gcm => Ok(iana::Algorithm::A256GCM),
+        _ => Err(Error::UnsupportedOperation),
+    }
+}
+
+fn has_algorithm(header: &Header, expected: iana::Algorithm) -> bool {
+    header.alg == Some(RegisteredLabelWithPrivate::Assigned(expected))
+}
+
+fn encoding_error(e: coset::CoseError) -> Error {
+    Error::InvalidEncoding(e.to_string())
+}
+
+/// Verifies a tagged `COSE_Sign1` message with a raw public key and returns
+/// its payload. The `alg` header must match `algorithm`.
+pub fn cose_sign1_verify_with_public_key(
+    algorithm: KeyAlgorithm,
+    public_key: &[u8],
+    message: &[u8],
+    external_aad: &[u8],
+) -> Result<Vec<u8>> {
+    let sign1 = CoseSign1::from_tagged_slice(message).map_err(encoding_error)?;
+    if !has_algorithm(&sign1.protected.header, cose_algorithm(algorithm)?) {
+        return Err(Error::VerificationFailed);
+    }
+    sign1.verify_signature(external_aad, |signature, data| {
+        match verify_with_public_key(algorithm, public_key, data, signature)? {
+            true => Ok(()),
+            false => Err(Error::VerificationFailed),
+        }
+    })?;
+    sign1.payload.ok_or_else(|| Error::InvalidEncoding("detached payload".to_string()))
+}
+
+impl ElasticCrypto {
+    /// Signs `payload` into a tagged `COSE_Sign1` message.
+    pub fn cose_sign1(&self, handle: u32, payload: Vec<u8>, external_aad: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let protected = HeaderBuilder::new()
+            .algorithm(cose_algorithm(key.algorithm)?)
+            .key_id(key.key_id()?)
+            .build();
+
+        let mut result = Ok(());
+        let sign1 = CoseSign1Builder::new()
+            .protected(protected)
+            .payload(payload)
+            .create_signature(&external_aad, |data| {
+                key.sign(data).unwrap_or_else(|e| {
+                    result = Err(e);
+                    Vec::new()
+                })
+            })
+            .build();
+        result?;
+        sign1.to_tagged_vec().map_err(encoding_error)
+    }
+
+    /// Verifies a tagged `COSE_Sign1` message and returns its payload.
+    ///
+    /// Fails with `VerificationFailed` if the signature is bad or the
+    /// protected `alg`/`kid` do not belong to the key.
+    pub fn cose_sign1_verify(&self, handle: u32, message: Vec<u8>, external_aad: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let sign1 = CoseSign1::from_tagged_slice(&message).map_err(encoding_error)?;
+        if sign1.protected.header.key_id != key.key_id()? {
+            return Err(Error::VerificationFailed);
+        }
+        cose_sign1_verify_with_public_key(key.algorithm, &key.public_key()?, &message, &external_aad)
+    }
+
+    /// Encrypts `plaintext` into a tagged `COSE_Encrypt0` message.
+    pub fn cose_encrypt0(&self, handle: u32, plaintext: Vec<u8>, external_aad: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let cipher = key.cipher()?;
+        let iv = ElasticRng::new()?.get_random_bytes(IV_LEN)?;
+        let protected = HeaderBuilder::new()
+            .algorithm(cose_algorithm(key.algorithm)?)
+            .key_id(key.key_id()?)
+            .build();
+        let unprotected = HeaderBuilder::new().iv(iv.clone()).build();
+
+        let mut result = Ok(());
+        let encrypt0 = CoseEncrypt0Builder::new()
+            .protected(protected)
+            .unprotected(unprotected)
+            .create_ciphertext(&plaintext, &external_aad, |msg, aad| {
+                cipher
+                    .encrypt(Nonce::from_slice(&iv), Payload { msg, aad })
+                    .unwrap_or_else(|e| {
+                        result = Err(Error::EncryptionError(e.to_string()));
+                        Vec::new()
+                    })
+            })
+            .build();
+        result?;
+        encrypt0.to_tagged_vec().map_err(encoding_error)
+    }
+
+    /// Decrypts a tagged `COSE_Encrypt0` message produced for this key.
+    pub fn cose_decrypt0(&self, handle: u32, message: Vec<u8>, external_aad: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let cipher = key.cipher()?;
+        let encrypt0 = CoseEncrypt0::from_tagged_slice(&message).map_err(encoding_error)?;
+        let protected = &encrypt0.protected.header;
+        if !has_algorithm(protected, iana::Algorithm::A256GCM) || protected.key_id != key.key_id()? {
+            return Err(Error::DecryptionFailed);
+        }
+        if encrypt0.unprotected.iv.len() != IV_LEN || encrypt0.ciphertext.is_none() {
+            return Err(Error::InvalidCiphertext);
+        }
+
+        let nonce = Nonce::from_slice(&encrypt0.unprotected.iv);
+        encrypt0.decrypt(&external_aad, |msg, aad| {
+            cipher
+                .decrypt(nonce, Payload { msg, aad })
+                .map_err(|_| Error::DecryptionFailed)
+        })
+    }
+}
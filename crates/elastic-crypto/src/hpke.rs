@@ -0,0 +1,219 @@
+//! Hybrid Public Key Encryption (RFC 9180) in base and auth modes.
+//!
+//! Suites use DHKEM(X25519, HKDF-SHA256) or DHKEM(P-256, HKDF-SHA256), the
+//! HKDF-SHA256 KDF, and AES-256-GCM or ChaCha20-Poly1305. The KEM follows the
+//! recipient key: a raw 32-byte X25519 key or an uncompressed SEC1 P-256 point
+//! from [`ElasticCrypto::get_public_key`].
+//!
+//! Messages are single-shot and serialised as the encapsulated key followed by
+//! the ciphertext; the encapsulated key is 32 bytes for X25519 and 65 bytes for
+//! P-256. Opening runs with the recipient's private key inside the key store,
+//! so a secret sealed to an attested workload can only be read there.
+
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use ::hpke::aead::{Aead, AesGcm256, ChaCha20Poly1305};
+use ::hpke::kdf::HkdfSha256;
+use ::hpke::kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256};
+use ::hpke::{Deserializable, HpkeError, OpModeR, OpModeS, Serializable};
+use serde::{Deserialize, Serialize};
+
+/// AEAD used for HPKE content encryption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HpkeAead {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+fn hpke_error(error: HpkeError) -> Error {
+    match error {
+        HpkeError::OpenError => Error::DecryptionFailed,
+        HpkeError::ValidationError | HpkeError::IncorrectInputLength(..) => {
+            Error::InvalidEncoding(error.to_string())
+        }
+        _ => Error::EncryptionError(error.to_string()),
+    }
+}
+
+/// KEM for a recipient or sender public key, by its encoding.
+fn kem_for_public_key(public_key: &[u8]) -> Result<KeyAlgorithm> {
+    match public_key.len() {
+        32 => Ok(KeyAlgorithm::X25519),
+        65 => Ok(KeyAlgorithm::EcdhP256),
+        _ => Err(Error::InvalidEncoding("HPKE needs an X25519 or uncompressed P-256 public key".to_string())),
+    }
+}
+
+fn seal<A: Aead, K: KemTrait>(
+    sender_key: Option<&[u8]>,
+    recipient_public_key: &[u8],
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let recipient = K::PublicKey::from_bytes(recipient_public_key).map_err(hpke_error)?;
+    let mode = match sender_key {
+        Some(sender_key) => {
+            let private_key = K::PrivateKey::from_bytes(sender_key).map_err(hpke_error)?;
+            let public_key = K::sk_to_pk(&private_key);
+            OpModeS::Auth((private_key, public_key))
+        }
+        None => OpModeS::Base,
+    };
+    let (enc, ciphertext) = ::hpke::single_shot_seal::<A, HkdfSha256, K, _>(
+        &mode,
+        &recipient,
+        info,
+        plaintext,
+        aad,
+        &mut ElasticRng::new()?,
+    )
+    .map_err(hpke_error)?;
+    let mut sealed = enc.to_bytes().to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub(crate) fn open<A: Aead, K: KemTrait>(
+    private_key: &[u8],
+    sender_public_key: Option<&[u8]>,
+    sealed: &[u8],
+    info: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    if sealed.len() < K::EncappedKey::size() {
+        return Err(Error::InvalidCiphertext);
+    }
+    let (enc, ciphertext) = sealed.split_at(K::EncappedKey::size());
+    let enc = K::EncappedKey::from_bytes(enc).map_err(hpke_error)?;
+    let private_key = K::PrivateKey::from_bytes(private_key).map_err(hpke_error)?;
+    let mode = match sender_public_key {
+        Some(sender) => OpModeR::Auth(K::PublicKey::from_bytes(sender).map_err(hpke_error)?),
+        None => OpModeR::Base,
+    };
+    ::hpke::single_shot_open::<A, HkdfSha256, K>(&mode, &private_key, &enc, info, ciphertext, aad)
+        .map_err(hpke_error)
+}
+
+pub(crate) fn seal_suite(
+    kem: KeyAlgorithm,
+    aead: HpkeAead,
+    sender: Option<&[u8]>,
+    recipient: &[u8],
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    match (kem, aead) {
+        (KeyAlgorithm::X25519, HpkeAead::Aes256Gcm) => {
+            seal::<AesGcm256, X25519HkdfSha256>(sender, recipient, info, aad, plaintext)
+        }
+        (KeyAlgorithm::X25519, HpkeAead::ChaCha20Poly1305) => {
+            seal::<ChaCha20Poly1305, X25519HkdfSha256>(sender, recipient, info, aad, plaintext)
+        }
+        (KeyAlgorithm::EcdhP256, HpkeAead::Aes256Gcm) => {
+            seal::<AesGcm256, DhP256HkdfSha256>(sender, recipient, info, aad, plaintext)
+        }
+        (KeyAlgorithm::EcdhP256, HpkeAead::ChaCha20Poly1305) => {
+            seal::<ChaCha20Poly1305, DhP256HkdfSha256>(sender, recipient, info, aad, plaintext)
+        }
+        _ => Err(Error::UnsupportedOperation),
+    }
+}
+
+impl Key {
+    pub(crate) fn hpke_open(
+        &self,
+        sender_public_key: Option<&[u8]>,
+        sealed: &[u8],
+        info: &[u8],
+        aad: &[u8],
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        if let Some(sender) = sender_public_key {
+            if kem_for_public_key(sender)? != self.algorithm {
+                return Err(Error::UnsupportedOperation);
+            }
+        }
+        let key = self.data.as_slice();
+        match (self.algorithm, aead) {
+            (KeyAlgorithm::X25519, HpkeAead::Aes256Gcm) => {
+                open::<AesGcm256, X25519HkdfSha256>(key, sender_public_key, sealed, info, aad)
+            }
+            (KeyAlgorithm::X25519, HpkeAead::ChaCha20Poly1305) => {
+                open::<ChaCha20Poly1305, X25519HkdfSha256>(key, sender_public_key, sealed, info, aad)
+            }
+            (KeyAlgorithm::EcdhP256, HpkeAead::Aes256Gcm) => {
+                open::<AesGcm256, DhP256HkdfSha256>(key, sender_public_key, sealed, info, aad)
+            }
+            (KeyAlgorithm::EcdhP256, HpkeAead::ChaCha20Poly1305) => {
+                open::<ChaCha20Poly1305, DhP256HkdfSha256>(key, sender_public_key, sealed, info, aad)
+            }
+            _ => Err(Error::UnsupportedOperation),
+        }
+    }
+}
+
+impl ElasticCrypto {
+    /// Seals `plaintext` to a recipient public key in HPKE base mode.
+    pub fn hpke_seal(
+        &self,
+        recipient_public_key: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        plaintext: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let kem = kem_for_public_key(&recipient_public_key)?;
+        seal_suite(kem, aead, None, &recipient_public_key, &info, &aad, &plaintext)
+    }
+
+    /// Seals `plaintext` in HPKE auth mode, authenticating the sender with an
+    /// X25519 or ECDH P-256 key of the same type as the recipient's.
+    pub fn hpke_seal_auth(
+        &self,
+        sender_handle: u32,
+        recipient_public_key: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        plaintext: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let sender = self.get_key(sender_handle)?;
+        let kem = kem_for_public_key(&recipient_public_key)?;
+        if sender.algorithm != kem {
+            return Err(Error::UnsupportedOperation);
+        }
+        seal_suite(kem, aead, Some(&sender.data), &recipient_public_key, &info, &aad, &plaintext)
+    }
+
+    /// Opens an HPKE base-mode message sealed to this X25519 or ECDH P-256 key.
+    pub fn hpke_open(
+        &self,
+        handle: u32,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.hpke_open(None, &sealed, &info, &aad, aead)
+    }
+
+    /// Opens an HPKE auth-mode message, checking it came from the holder of
+    /// `sender_public_key`.
+    pub fn hpke_open_auth(
+        &self,
+        handle: u32,
+        sender_public_key: Vec<u8>,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.hpke_open(Some(&sender_public_key), &sealed, &info, &aad, aead)
+    }
+}
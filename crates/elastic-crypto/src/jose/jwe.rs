@@ -0,0 +1,207 @@
+//! JSON Web Encryption (RFC 7516) in compact serialisation with A256GCM.
+
+use super::{b64_decode, b64_encode, Header, Jwk};
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_kw::KekAes256;
+use sha2::{Digest, Sha256};
+
+const ENC: &str = "A256GCM";
+const CEK_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// JWE key management algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JweAlgorithm {
+    /// `dir`: the AES-256 key encrypts the content directly.
+    Dir,
+    /// `A256KW`: a random content key is wrapped with the AES-256 key.
+    A256Kw,
+    /// `ECDH-ES`: the content key is agreed with the recipient's P-256 key
+    /// through an ephemeral key.
+    EcdhEs,
+}
+
+impl JweAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            JweAlgorithm::Dir => "dir",
+            JweAlgorithm::A256Kw => "A256KW",
+            JweAlgorithm::EcdhEs => "ECDH-ES",
+        }
+    }
+}
+
+/// Concat KDF (NIST SP 800-56A) as profiled for ECDH-ES in RFC 7518 section
+/// 4.6.2, deriving a 256-bit key for `A256GCM`.
+fn concat_kdf(shared_secret: &[u8], apu: &[u8], apv: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(1u32.to_be_bytes());
+    hasher.update(shared_secret);
+    for field in [ENC.as_bytes(), apu, apv] {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.update(((CEK_LEN * 8) as u32).to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn optional_b64(value: &Option<String>) -> Result<Vec<u8>> {
+    value.as_deref().map(b64_decode).transpose().map(Option::unwrap_or_default)
+}
+
+/// Encrypts `plaintext` under `cek` and assembles the compact JWE.
+fn seal(header: &Header, cek: &[u8], encrypted_key: &[u8], plaintext: &[u8]) -> Result<String> {
+    let protected = header.encode()?;
+    let iv = ElasticRng::new()?.get_random_bytes(IV_LEN)?;
+    let cipher = Aes256Gcm::new_from_slice(cek).map_err(|_| Error::InvalidKeyLength)?;
+    let mut ciphertext = cipher
+        .encrypt(Nonce::from_slice(&iv), Payload { msg: plaintext, aad: protected.as_bytes() })
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_LEN);
+    Ok([
+        protected,
+        b64_encode(encrypted_key),
+        b64_encode(iv),
+        b64_encode(ciphertext),
+        b64_encode(tag),
+    ]
+    .join("."))
+}
+
+/// ECDH-ES to a SEC1 P-256 public key, with `kid` naming the recipient.
+fn seal_ecdh_es(public_key: &[u8], kid: Option<String>, plaintext: &[u8]) -> Result<String> {
+    let recipient = p256::PublicKey::from_sec1_bytes(public_key).map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+    let ephemeral = p256::ecdh::EphemeralSecret::random(&mut ElasticRng::new()?);
+    let shared = ephemeral.diffie_hellman(&recipient);
+    let point = ephemeral.public_key().to_sec1_bytes();
+    let (x, y) = point[1..].split_at(CEK_LEN);
+    let header = Header {
+        alg: JweAlgorithm::EcdhEs.name().to_string(),
+        enc: Some(ENC.to_string()),
+        kid,
+        epk: Some(Jwk {
+            kty: "EC".to_string(),
+            crv: Some("P-256".to_string()),
+            x: Some(b64_encode(x)),
+            y: Some(b64_encode(y)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let cek = concat_kdf(shared.raw_secret_bytes(), &[], &[]);
+    seal(&header, &cek, &[], plaintext)
+}
+
+/// Key id (`kid`) a compact JWE was encrypted to, if its header names one.
+pub(crate) fn jwe_key_id(token: &str) -> Result<Option<Vec<u8>>> {
+    let protected = token.trim().split('.').next().unwrap_or_default();
+    Header::decode(protected)?.kid.as_deref().map(b64_decode).transpose()
+}
+
+/// Encrypts `plaintext` with ECDH-ES + A256GCM to a recipient's public P-256
+/// JWK, such as one exported by [`ElasticCrypto::get_public_jwk`].
+pub fn jwe_encrypt_to_jwk(jwk: &str, plaintext: &[u8]) -> Result<String> {
+    let jwk = Jwk::from_json(jwk)?;
+    match jwk.public_key()? {
+        (KeyAlgorithm::EcdhP256, public_key) => seal_ecdh_es(&public_key, jwk.kid, plaintext),
+        // A bare P-256 key with no alg or use may be used for key agreement.
+        (KeyAlgorithm::EcdsaP256, public_key) if jwk.alg.is_none() && jwk.key_use.is_none() => {
+            seal_ecdh_es(&public_key, jwk.kid, plaintext)
+        }
+        _ => Err(Error::UnsupportedOperation),
+    }
+}
+
+impl Key {
+    /// Content key for an incoming JWE, derived or unwrapped with this key.
+    fn jwe_content_key(&self, header: &Header, encrypted_key: &[u8]) -> Result<Vec<u8>> {
+        match (header.alg.as_str(), self.algorithm) {
+            ("dir", KeyAlgorithm::Aes256Gcm) if encrypted_key.is_empty() => Ok(self.data.clone()),
+            ("A256KW", KeyAlgorithm::Aes256Gcm) => {
+                let kek = KekAes256::try_from(self.data.as_slice()).map_err(|_| Error::InvalidKeyLength)?;
+                let cek = kek.unwrap_vec(encrypted_key).map_err(|_| Error::DecryptionFailed)?;
+                if cek.len() != CEK_LEN {
+                    return Err(Error::DecryptionFailed);
+                }
+                Ok(cek)
+            }
+            ("ECDH-ES", KeyAlgorithm::EcdhP256) if encrypted_key.is_empty() => {
+                let epk = header
+                    .epk
+                    .as_ref()
+                    .ok_or_else(|| Error::InvalidEncoding("ECDH-ES header is missing \"epk\"".to_string()))?;
+                let (_, point) = epk.public_key()?;
+                let shared = self.diffie_hellman(&point)?;
+                Ok(concat_kdf(&shared, &optional_b64(&header.apu)?, &optional_b64(&header.apv)?))
+            }
+            _ => Err(Error::DecryptionFailed),
+        }
+    }
+
+    pub(crate) fn jwe_decrypt(&self, token: &str) -> Result<Vec<u8>> {
+        let parts: Vec<&str> = token.trim().split('.').collect();
+        let [protected, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+            return Err(Error::InvalidEncoding("JWE compact form needs five parts".to_string()));
+        };
+        let header = Header::decode(protected)?;
+        if header.enc.as_deref() != Some(ENC) {
+            return Err(Error::UnsupportedOperation);
+        }
+        if !header.matches_kid(Some(&b64_encode(self.key_id()?))) {
+            return Err(Error::DecryptionFailed);
+        }
+        let iv = b64_decode(iv)?;
+        if iv.len() != IV_LEN {
+            return Err(Error::InvalidCiphertext);
+        }
+
+        let cek = self.jwe_content_key(&header, &b64_decode(encrypted_key)?)?;
+        let cipher = Aes256Gcm::new_from_slice(&cek).map_err(|_| Error::InvalidKeyLength)?;
+        let mut msg = b64_decode(ciphertext)?;
+        msg.extend_from_slice(&b64_decode(tag)?);
+        cipher
+            .decrypt(Nonce::from_slice(&iv), Payload { msg: &msg, aad: protected.as_bytes() })
+            .map_err(|_| Error::DecryptionFailed)
+    }
+}
+
+impl ElasticCrypto {
+    /// Encrypts `plaintext` into a compact JWE with A256GCM content encryption.
+    ///
+    /// `Dir` and `A256Kw` need an AES-256-GCM key. `EcdhEs` needs an ECDH
+    /// P-256 key and encrypts to its public half; use [`jwe_encrypt_to_jwk`]
+    /// for recipients whose private key lives elsewhere.
+    pub fn jwe_encrypt(&self, handle: u32, plaintext: Vec<u8>, algorithm: JweAlgorithm) -> Result<String> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let kid = Some(b64_encode(key.key_id()?));
+        let header = Header {
+            alg: algorithm.name().to_string(),
+            enc: Some(ENC.to_string()),
+            kid: kid.clone(),
+            ..Default::default()
+        };
+        match (algorithm, key.algorithm) {
+            (JweAlgorithm::Dir, KeyAlgorithm::Aes256Gcm) => seal(&header, &key.data, &[], &plaintext),
+            (JweAlgorithm::A256Kw, KeyAlgorithm::Aes256Gcm) => {
+                let cek = ElasticRng::new()?.get_random_bytes(CEK_LEN)?;
+                let kek = KekAes256::try_from(key.data.as_slice()).map_err(|_| Error::InvalidKeyLength)?;
+                let wrapped = kek.wrap_vec(&cek).map_err(|e| Error::EncryptionError(e.to_string()))?;
+                seal(&header, &cek, &wrapped, &plaintext)
+            }
+            (JweAlgorithm::EcdhEs, KeyAlgorithm::EcdhP256) => seal_ecdh_es(&key.public_key()?, kid, &plaintext),
+            _ => Err(Error::UnsupportedOperation),
+        }
+    }
+
+    /// Decrypts a compact JWE made for this key (`dir`, `A256KW` or `ECDH-ES`
+    /// with A256GCM).
+    pub fn jwe_decrypt(&self, handle: u32, token: &str) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.jwe_decrypt(token)
+    }
+}
@@ -0,0 +1,128 @@
+//! Public keys as JSON Web Keys (RFC 7517, RFC 8037 for Ed25519).
+
+use super::{b64_decode, b64_encode};
+use crate::signature::{rsa_public_key_der, rsa_public_key_parts};
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use serde::{Deserialize, Serialize};
+
+const P256_COORDINATE_LEN: usize = 32;
+
+/// A public JSON Web Key. Private key members are never produced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    #[serde(rename = "use", skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+}
+
+impl Jwk {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| Error::InvalidEncoding(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| Error::InvalidEncoding(e.to_string()))
+    }
+
+    /// Builds the JWK for the public half of `key`, with `kid` set to the
+    /// key's id.
+    pub(crate) fn from_key(key: &Key) -> Result<Self> {
+        let public_key = key.public_key()?;
+        let mut jwk = match key.algorithm {
+            KeyAlgorithm::Ed25519 => Jwk {
+                kty: "OKP".to_string(),
+                crv: Some("Ed25519".to_string()),
+                x: Some(b64_encode(&public_key)),
+                ..Default::default()
+            },
+            KeyAlgorithm::EcdsaP256 | KeyAlgorithm::EcdhP256 => {
+                let (x, y) = public_key[1..].split_at(P256_COORDINATE_LEN);
+                Jwk {
+                    kty: "EC".to_string(),
+                    crv: Some("P-256".to_string()),
+                    x: Some(b64_encode(x)),
+                    y: Some(b64_encode(y)),
+                    ..Default::default()
+                }
+            }
+            KeyAlgorithm::RsaPssSha256 => {
+                let (n, e) = rsa_public_key_parts(&public_key)?;
+                Jwk {
+                    kty: "RSA".to_string(),
+                    n: Some(b64_encode(n)),
+                    e: Some(b64_encode(e)),
+                    ..Default::default()
+                }
+            }
+            _ => return Err(Error::UnsupportedOperation),
+        };
+        let (alg, key_use) = match key.algorithm {
+            KeyAlgorithm::EcdhP256 => ("ECDH-ES", "enc"),
+            algorithm => (super::jws::jws_algorithm(algorithm)?, "sig"),
+        };
+        jwk.kid = Some(b64_encode(key.key_id()?));
+        jwk.alg = Some(alg.to_string());
+        jwk.key_use = Some(key_use.to_string());
+        Ok(jwk)
+    }
+
+    /// Returns the key algorithm and the public key in the form
+    /// [`crate::verify_with_public_key`] expects.
+    ///
+    /// P-256 keys are treated as ECDH keys when `alg` is `ECDH-ES` or `use`
+    /// is `enc`, and as ECDSA keys otherwise.
+    pub fn public_key(&self) -> Result<(KeyAlgorithm, Vec<u8>)> {
+        let member = |value: &Option<String>, name: &str| -> Result<Vec<u8>> {
+            let value = value
+                .as_deref()
+                .ok_or_else(|| Error::InvalidEncoding(format!("JWK is missing \"{}\"", name)))?;
+            b64_decode(value)
+        };
+        match (self.kty.as_str(), self.crv.as_deref()) {
+            ("OKP", Some("Ed25519")) => Ok((KeyAlgorithm::Ed25519, member(&self.x, "x")?)),
+            ("EC", Some("P-256")) => {
+                let (x, y) = (member(&self.x, "x")?, member(&self.y, "y")?);
+                if x.len() != P256_COORDINATE_LEN || y.len() != P256_COORDINATE_LEN {
+                    return Err(Error::InvalidKeyLength);
+                }
+                let point = [&[0x04][..], &x, &y].concat();
+                let is_ecdh = self.alg.as_deref() == Some("ECDH-ES") || self.key_use.as_deref() == Some("enc");
+                let algorithm = if is_ecdh { KeyAlgorithm::EcdhP256 } else { KeyAlgorithm::EcdsaP256 };
+                Ok((algorithm, point))
+            }
+            ("RSA", _) => {
+                let der = rsa_public_key_der(&member(&self.n, "n")?, &member(&self.e, "e")?)?;
+                Ok((KeyAlgorithm::RsaPssSha256, der))
+            }
+            (kty, crv) => Err(Error::InvalidEncoding(format!(
+                "unsupported JWK type {}{}",
+                kty,
+                crv.map(|c| format!("/{}", c)).unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+impl ElasticCrypto {
+    /// Exports the public half of an asymmetric key as a JWK (JSON).
+    /// Allowed for secure-storage keys.
+    pub fn get_public_jwk(&self, handle: u32) -> Result<String> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        Jwk::from_key(&key)?.to_json()
+    }
+}
@@ -0,0 +1,173 @@
+//! JSON Web Signatures (RFC 7515).
+
+use super::{b64_decode, b64_encode, Header, Jwk};
+use crate::signature::verify_with_public_key;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use serde::{Deserialize, Serialize};
+
+/// One signature of a JSON-serialised JWS.
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonSignature {
+    protected: String,
+    signature: String,
+}
+
+/// General JSON serialisation. The flattened form is accepted on input by
+/// reading `protected`/`signature` from the top level.
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonJws {
+    payload: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    signatures: Vec<JsonSignature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+/// A parsed JWS: the encoded payload and each (protected header, signature) pair.
+struct Parsed {
+    payload: String,
+    signatures: Vec<(String, Vec<u8>)>,
+}
+
+pub(crate) fn jws_algorithm(algorithm: KeyAlgorithm) -> Result<&'static str> {
+    match algorithm {
+        KeyAlgorithm::Ed25519 => Ok("EdDSA"),
+        KeyAlgorithm::EcdsaP256 => Ok("ES256"),
+        KeyAlgorithm::RsaPssSha256 => Ok("PS256"),
+        KeyAlgorithm::HmacSha256 => Ok("HS256"),
+        _ => Err(Error::UnsupportedOperation),
+    }
+}
+
+fn parse(token: &str) -> Result<Parsed> {
+    let token = token.trim();
+    if token.starts_with('{') {
+        let json: JsonJws = serde_json::from_str(token).map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+        let mut signatures = json
+            .signatures
+            .into_iter()
+            .map(|s| (s.protected, s.signature))
+            .collect::<Vec<_>>();
+        if let (Some(protected), Some(signature)) = (json.protected, json.signature) {
+            signatures.push((protected, signature));
+        }
+        let signatures = signatures
+            .into_iter()
+            .map(|(protected, signature)| Ok((protected, b64_decode(&signature)?)))
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Parsed { payload: json.payload, signatures });
+    }
+
+    let parts: Vec<&str> = token.split('.').collect();
+    let [protected, payload, signature] = parts[..] else {
+        return Err(Error::InvalidEncoding("JWS compact form needs three parts".to_string()));
+    };
+    Ok(Parsed {
+        payload: payload.to_string(),
+        signatures: vec![(protected.to_string(), b64_decode(signature)?)],
+    })
+}
+
+/// Checks the signatures of `parsed` whose header names `algorithm` and, if
+/// both it and the caller have a `kid`, the id `kid`. Returns the decoded
+/// payload on the first match.
+fn verify_parsed(
+    parsed: &Parsed,
+    algorithm: KeyAlgorithm,
+    kid: Option<&str>,
+    verify: impl Fn(&[u8], &[u8]) -> Result<bool>,
+) -> Result<Vec<u8>> {
+    let alg = jws_algorithm(algorithm)?;
+    for (protected, signature) in &parsed.signatures {
+        let header = Header::decode(protected)?;
+        if header.alg != alg || !header.matches_kid(kid) {
+            continue;
+        }
+        let signing_input = format!("{}.{}", protected, parsed.payload);
+        if verify(signing_input.as_bytes(), signature)? {
+            return b64_decode(&parsed.payload);
+        }
+    }
+    Err(Error::VerificationFailed)
+}
+
+/// Verifies a JWS (compact or JSON) with a public JWK and returns its payload.
+pub fn jws_verify_with_jwk(jwk: &str, token: &str) -> Result<Vec<u8>> {
+    let jwk = Jwk::from_json(jwk)?;
+    let (algorithm, public_key) = jwk.public_key()?;
+    let parsed = parse(token)?;
+    verify_parsed(&parsed, algorithm, jwk.kid.as_deref(), |data, signature| {
+        verify_with_public_key(algorithm, &public_key, data, signature)
+    })
+}
+
+impl Key {
+    fn jws_protected_header(&self) -> Result<String> {
+        Header {
+            alg: jws_algorithm(self.algorithm)?.to_string(),
+            kid: Some(b64_encode(self.key_id()?)),
+            ..Default::default()
+        }
+        .encode()
+    }
+
+    fn jws_sign(&self, signing_input: &[u8]) -> Result<Vec<u8>> {
+        match self.algorithm {
+            KeyAlgorithm::HmacSha256 => self.mac(signing_input),
+            _ => self.sign(signing_input),
+        }
+    }
+}
+
+impl ElasticCrypto {
+    /// Signs `payload` as a compact JWS (`header.payload.signature`).
+    pub fn jws_sign(&self, handle: u32, payload: Vec<u8>) -> Result<String> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let signing_input = format!("{}.{}", key.jws_protected_header()?, b64_encode(payload));
+        let signature = key.jws_sign(signing_input.as_bytes())?;
+        Ok(format!("{}.{}", signing_input, b64_encode(signature)))
+    }
+
+    /// Signs `payload` with each key into a JWS in general JSON serialisation.
+    pub fn jws_sign_json(&self, handles: Vec<u32>, payload: Vec<u8>) -> Result<String> {
+        self.self_test.check()?;
+        if handles.is_empty() {
+            return Err(Error::UnsupportedOperation);
+        }
+        let payload = b64_encode(payload);
+        let mut signatures = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let key = self.get_key(handle)?;
+            let protected = key.jws_protected_header()?;
+            let signature = key.jws_sign(format!("{}.{}", protected, payload).as_bytes())?;
+            signatures.push(JsonSignature {
+                protected,
+                signature: b64_encode(signature),
+            });
+        }
+        let jws = JsonJws {
+            payload,
+            signatures,
+            protected: None,
+            signature: None,
+        };
+        serde_json::to_string(&jws).map_err(|e| Error::InvalidEncoding(e.to_string()))
+    }
+
+    /// Verifies a compact or JSON JWS and returns its payload.
+    ///
+    /// The header `alg` must be the key's algorithm. A JSON JWS passes if any
+    /// of its signatures made with this key verifies.
+    pub fn jws_verify(&self, handle: u32, token: &str) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let kid = b64_encode(key.key_id()?);
+        verify_parsed(&parse(token)?, key.algorithm, Some(&kid), |data, signature| match key.algorithm {
+            KeyAlgorithm::HmacSha256 => key.verify_mac(data, signature),
+            _ => key.verify(data, signature),
+        })
+    }
+}
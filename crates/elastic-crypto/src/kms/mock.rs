@@ -0,0 +1,422 @@
+//! In-process KMS serving the [`KmsClient`](super::KmsClient) API over HTTPS
+//! on localhost, for tests.
+//!
+//! Reports are read with the SEV-SNP layout (report data at offset 0x50,
+//! measurement at 0x90) and accepted when the report data matches the
+//! challenge and the measurement is on the allow list. Report signatures are
+//! not checked, so [`MockAttester`] can stand in for the guest firmware; a
+//! real KMS must also verify the VCEK signature chain.
+
+use super::{report_data, Attester, KmsConfig, KEY_RELEASE_INFO, REPORT_DATA_LEN};
+use crate::hpke::{self, HpkeAead};
+use crate::rng::ElasticRng;
+use crate::x509::{CertificateParams, SubjectAltName};
+use crate::{ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType, Result};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use p256::pkcs8::EncodePrivateKey;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Size of a SEV-SNP attestation report.
+pub const SNP_REPORT_LEN: usize = 0x4a0;
+pub const MEASUREMENT_LEN: usize = 48;
+
+const REPORT_DATA_OFFSET: usize = 0x50;
+const MEASUREMENT_OFFSET: usize = 0x90;
+const GCM_NONCE_LEN: usize = 12;
+const MAX_BODY_LEN: usize = 1 << 20;
+
+/// Produces unsigned reports in the SEV-SNP layout for a fixed measurement.
+pub struct MockAttester {
+    measurement: [u8; MEASUREMENT_LEN],
+}
+
+impl MockAttester {
+    pub fn new(measurement: [u8; MEASUREMENT_LEN]) -> Self {
+        Self { measurement }
+    }
+}
+
+impl Attester for MockAttester {
+    fn attestation_report(&self, report_data: [u8; REPORT_DATA_LEN]) -> Result<Vec<u8>> {
+        let mut report = vec![0u8; SNP_REPORT_LEN];
+        report[..4].copy_from_slice(&2u32.to_le_bytes());
+        report[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + REPORT_DATA_LEN].copy_from_slice(&report_data);
+        report[MEASUREMENT_OFFSET..MEASUREMENT_OFFSET + MEASUREMENT_LEN].copy_from_slice(&self.measurement);
+        Ok(report)
+    }
+}
+
+struct SessionState {
+    public_key: Vec<u8>,
+    measurement: [u8; MEASUREMENT_LEN],
+}
+
+#[derive(Default)]
+struct State {
+    allowed_measurements: Mutex<HashSet<[u8; MEASUREMENT_LEN]>>,
+    master_keys: Mutex<HashMap<String, Vec<u8>>>,
+    challenges: Mutex<HashSet<Vec<u8>>>,
+    sessions: Mutex<HashMap<String, SessionState>>,
+}
+
+type Reply = std::result::Result<Value, (u16, String)>;
+
+fn reject(status: u16, message: &str) -> (u16, String) {
+    (status, message.to_string())
+}
+
+fn field(body: &Value, name: &str) -> std::result::Result<Vec<u8>, (u16, String)> {
+    body[name]
+        .as_str()
+        .and_then(|value| STANDARD.decode(value).ok())
+        .ok_or_else(|| reject(400, &format!("missing or invalid field {}", name)))
+}
+
+fn key_id(body: &Value) -> std::result::Result<&str, (u16, String)> {
+    body["key_id"].as_str().ok_or_else(|| reject(400, "missing field key_id"))
+}
+
+fn random_bytes(len: usize) -> std::result::Result<Vec<u8>, (u16, String)> {
+    ElasticRng::new()
+        .and_then(|mut rng| rng.get_random_bytes(len))
+        .map_err(|e| reject(500, &e.to_string()))
+}
+
+impl State {
+    fn handle(&self, path: &str, token: Option<&str>, body: &Value) -> Reply {
+        match path {
+            "/v1/challenge" => self.challenge(),
+            "/v1/attest" => self.attest(body),
+            "/v1/wrap" | "/v1/unwrap" | "/v1/generate-data-key" => {
+                let recipient = self.session_key(token)?;
+                match path {
+                    "/v1/wrap" => self.wrap(body),
+                    "/v1/unwrap" => self.unwrap(body, &recipient),
+                    _ => self.generate_data_key(body, &recipient),
+                }
+            }
+            _ => Err(reject(404, "unknown endpoint")),
+        }
+    }
+
+    fn challenge(&self) -> Reply {
+        let challenge = random_bytes(32)?;
+        self.challenges.lock().unwrap().insert(challenge.clone());
+        Ok(json!({"challenge": STANDARD.encode(challenge)}))
+    }
+
+    fn attest(&self, body: &Value) -> Reply {
+        let challenge = field(body, "challenge")?;
+        let public_key = field(body, "public_key")?;
+        let report = field(body, "report")?;
+        if !self.challenges.lock().unwrap().remove(&challenge) {
+            return Err(reject(403, "unknown or reused challenge"));
+        }
+        if public_key.len() != 32 || report.len() != SNP_REPORT_LEN {
+            return Err(reject(400, "malformed attestation"));
+        }
+        let expected = report_data(&challenge, &public_key);
+        if report[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + REPORT_DATA_LEN] != expected {
+            return Err(reject(403, "report data does not match challenge"));
+        }
+        let mut measurement = [0u8; MEASUREMENT_LEN];
+        measurement.copy_from_slice(&report[MEASUREMENT_OFFSET..MEASUREMENT_OFFSET + MEASUREMENT_LEN]);
+        if !self.allowed_measurements.lock().unwrap().contains(&measurement) {
+            return Err(reject(403, "measurement not allowed"));
+        }
+        let token = hex::encode(random_bytes(16)?);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(token.clone(), SessionState { public_key, measurement });
+        Ok(json!({"token": token}))
+    }
+
+    fn session_key(&self, token: Option<&str>) -> std::result::Result<Vec<u8>, (u16, String)> {
+        let sessions = self.sessions.lock().unwrap();
+        token
+            .and_then(|token| sessions.get(token))
+            .map(|session| session.public_key.clone())
+            .ok_or_else(|| reject(401, "missing or expired session token"))
+    }
+
+    fn cipher(&self, key_id: &str) -> std::result::Result<Aes256Gcm, (u16, String)> {
+        let keys = self.master_keys.lock().unwrap();
+        let key = keys.get(key_id).ok_or_else(|| reject(404, "unknown key id"))?;
+        Aes256Gcm::new_from_slice(key).map_err(|e| reject(500, &e.to_string()))
+    }
+
+    fn seal(&self, key_id: &str, plaintext: &[u8]) -> std::result::Result<Vec<u8>, (u16, String)> {
+        let cipher = self.cipher(key_id)?;
+        let nonce = random_bytes(GCM_NONCE_LEN)?;
+        let payload = Payload { msg: plaintext, aad: key_id.as_bytes() };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|e| reject(500, &e.to_string()))?;
+        Ok([nonce, ciphertext].concat())
+    }
+
+    fn release(&self, key_id: &str, recipient: &[u8], key_data: &[u8]) -> std::result::Result<String, (u16, String)> {
+        let sealed = hpke::seal_suite(
+            KeyAlgorithm::X25519,
+            HpkeAead::ChaCha20Poly1305,
+            None,
+            recipient,
+            KEY_RELEASE_INFO,
+            key_id.as_bytes(),
+            key_data,
+        )
+        .map_err(|e| reject(500, &e.to_string()))?;
+        Ok(STANDARD.encode(sealed))
+    }
+
+    fn wrap(&self, body: &Value) -> Reply {
+        let key_id = key_id(body)?;
+        let plaintext = field(body, "plaintext")?;
+        Ok(json!({"ciphertext": STANDARD.encode(self.seal(key_id, &plaintext)?)}))
+    }
+
+    fn unwrap(&self, body: &Value, recipient: &[u8]) -> Reply {
+        let key_id = key_id(body)?;
+        let ciphertext = field(body, "ciphertext")?;
+        if ciphertext.len() < GCM_NONCE_LEN {
+            return Err(reject(400, "ciphertext too short"));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(GCM_NONCE_LEN);
+        let payload = Payload { msg: ciphertext, aad: key_id.as_bytes() };
+        let key_data = self
+            .cipher(key_id)?
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| reject(400, "ciphertext does not unwrap under this key"))?;
+        Ok(json!({"sealed_key": self.release(key_id, recipient, &key_data)?}))
+    }
+
+    fn generate_data_key(&self, body: &Value, recipient: &[u8]) -> Reply {
+        let key_id = key_id(body)?;
+        let key_size = body["key_size"].as_u64().unwrap_or(0);
+        if key_size == 0 || !key_size.is_multiple_of(8) || key_size > 4096 {
+            return Err(reject(400, "invalid key_size"));
+        }
+        let key_data = random_bytes(key_size as usize / 8)?;
+        Ok(json!({
+            "ciphertext": STANDARD.encode(self.seal(key_id, &key_data)?),
+            "sealed_key": self.release(key_id, recipient, &key_data)?,
+        }))
+    }
+}
+
+/// Mock KMS listening on a random localhost port until dropped.
+pub struct MockKms {
+    address: SocketAddr,
+    certificate: Vec<u8>,
+    state: Arc<State>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MockKms {
+    /// Starts the server with a fresh self-signed certificate for `localhost`.
+    pub fn start() -> Result<Self> {
+        let (certificate, private_key) = server_identity()?;
+        let tls = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| {
+                builder.with_no_client_auth().with_single_cert(
+                    vec![CertificateDer::from(certificate.clone())],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(private_key)),
+                )
+            })
+            .map_err(|e| Error::Kms(e.to_string()))?;
+        let tls = Arc::new(tls);
+
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| Error::Kms(e.to_string()))?;
+        let address = listener.local_addr().map_err(|e| Error::Kms(e.to_string()))?;
+        let state = Arc::new(State::default());
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let server_state = Arc::clone(&state);
+        let server_shutdown = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let tls = Arc::clone(&tls);
+                let state = Arc::clone(&server_state);
+                thread::spawn(move || serve_connection(stream, tls, &state));
+            }
+        });
+
+        Ok(Self {
+            address,
+            certificate,
+            state,
+            shutdown,
+        })
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("https://localhost:{}", self.address.port())
+    }
+
+    /// DER certificate the server presents, to trust as the root.
+    pub fn root_certificate(&self) -> Vec<u8> {
+        self.certificate.clone()
+    }
+
+    /// Client configuration for this server.
+    pub fn config(&self) -> KmsConfig {
+        KmsConfig {
+            endpoint: self.endpoint(),
+            root_certificate: Some(self.root_certificate()),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a random master key under `key_id`.
+    pub fn create_key(&self, key_id: &str) -> Result<()> {
+        let key = ElasticRng::new()?.get_random_bytes(32)?;
+        self.state.master_keys.lock().unwrap().insert(key_id.to_string(), key);
+        Ok(())
+    }
+
+    pub fn allow_measurement(&self, measurement: [u8; MEASUREMENT_LEN]) {
+        self.state.allowed_measurements.lock().unwrap().insert(measurement);
+    }
+
+    /// Stops accepting `measurement` and ends the sessions it attested.
+    pub fn revoke_measurement(&self, measurement: [u8; MEASUREMENT_LEN]) {
+        self.state.allowed_measurements.lock().unwrap().remove(&measurement);
+        self.state
+            .sessions
+            .lock()
+            .unwrap()
+            .retain(|_, session| session.measurement != measurement);
+    }
+
+    /// Ends all sessions, as if their tokens had expired.
+    pub fn expire_sessions(&self) {
+        self.state.sessions.lock().unwrap().clear();
+    }
+}
+
+impl Drop for MockKms {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+/// Self-signed P-256 certificate for `localhost` and its PKCS#8 key.
+fn server_identity() -> Result<(Vec<u8>, Vec<u8>)> {
+    let crypto = ElasticCrypto::new()?;
+    let handle = crypto.generate_key(KeyConfig {
+        key_type: KeyType::Asymmetric,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: Some(KeyAlgorithm::EcdsaP256),
+    })?;
+    let params = CertificateParams {
+        subject: "CN=elastic mock kms".to_string(),
+        subject_alt_names: vec![SubjectAltName::Dns("localhost".to_string())],
+        validity_days: 1,
+        ..Default::default()
+    };
+    let certificate = crypto.create_self_signed_certificate(handle, params)?;
+    let secret = p256::SecretKey::from_slice(&crypto.export_key(handle)?).map_err(|_| Error::InvalidKeyLength)?;
+    let private_key = secret.to_pkcs8_der().map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+    Ok((certificate, private_key.as_bytes().to_vec()))
+}
+
+fn serve_connection(stream: TcpStream, tls: Arc<ServerConfig>, state: &State) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+    let Ok(connection) = ServerConnection::new(tls) else { return };
+    let mut reader = BufReader::new(StreamOwned::new(connection, stream));
+
+    let reply = match read_request(&mut reader) {
+        Ok((path, token, body)) => state.handle(&path, token.as_deref(), &body),
+        Err(rejection) => Err(rejection),
+    };
+    let (status, body) = match reply {
+        Ok(body) => (200, body),
+        Err((status, message)) => (status, json!({"error": message})),
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    let stream = reader.get_mut();
+    let _ = stream.write_all(response.as_bytes());
+    stream.conn.send_close_notify();
+    let _ = stream.flush();
+}
+
+type Request = (String, Option<String>, Value);
+
+fn read_request(reader: &mut impl BufRead) -> std::result::Result<Request, (u16, String)> {
+    let bad_request = |_| reject(400, "malformed request");
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(bad_request)?;
+    let mut parts = line.split_whitespace();
+    if parts.next() != Some("POST") {
+        return Err(reject(405, "only POST is supported"));
+    }
+    let path = parts.next().ok_or_else(|| reject(400, "missing path"))?.to_string();
+
+    let mut content_length = 0;
+    let mut token = None;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(bad_request)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(reject(400, "malformed header"));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| reject(400, "invalid content-length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            token = value.strip_prefix("Bearer ").map(str::to_string);
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        return Err(reject(413, "request too large"));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).map_err(bad_request)?;
+    let body = serde_json::from_slice(&body).map_err(|_| reject(400, "invalid JSON"))?;
+    Ok((path, token, body))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
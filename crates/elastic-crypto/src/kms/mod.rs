@@ -0,0 +1,354 @@
+//! Keys held by an external KMS and released only to attested workloads.
+//!
+//! [`KmsClient`] delegates wrapping, unwrapping and data-key generation to a
+//! remote KMS over HTTPS. Requests and responses are JSON objects with binary
+//! fields in standard base64:
+//!
+//! | Endpoint                     | Request                               | Response                      |
+//! |------------------------------|---------------------------------------|-------------------------------|
+//! | `POST /v1/challenge`         | `{}`                                  | `{challenge}`                 |
+//! | `POST /v1/attest`            | `{challenge, public_key, report}`     | `{token}`                     |
+//! | `POST /v1/wrap`              | `{key_id, plaintext}`                 | `{ciphertext}`                |
+//! | `POST /v1/unwrap`            | `{key_id, ciphertext}`                | `{sealed_key}`                |
+//! | `POST /v1/generate-data-key` | `{key_id, key_size}`                  | `{ciphertext, sealed_key}`    |
+//!
+//! Errors are returned as `{error}` with a non-2xx status: 401 for a missing
+//! or expired session token, 403 for a rejected attestation.
+//!
+//! Before its first key operation the client fetches a single-use challenge,
+//! creates an ephemeral X25519 key and sends an attestation report whose
+//! report data is [`report_data`] over the two. The KMS verifies the report
+//! and issues a bearer token for the other endpoints. Released keys come back
+//! HPKE-sealed (X25519, HKDF-SHA256, ChaCha20-Poly1305) to the attested
+//! ephemeral key with the KMS key id as associated data, so only the attested
+//! guest can read them. An expired token is renewed by attesting again.
+//!
+//! Unwrapped keys are never returned to callers:
+//! [`ElasticCrypto::load_remote_key`] and
+//! [`ElasticCrypto::generate_remote_data_key`] put them straight into the key
+//! store as secure-storage keys.
+
+use crate::hpke;
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, KeyConfig, Result};
+use ::hpke::aead::ChaCha20Poly1305;
+use ::hpke::kem::X25519HkdfSha256;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rustls::pki_types::CertificateDer;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha512};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub mod mock;
+
+/// Length of the report data field bound into an attestation report.
+pub const REPORT_DATA_LEN: usize = 64;
+
+const REPORT_DATA_LABEL: &[u8] = b"elastic-kms attestation";
+
+/// HPKE info used when the KMS releases a key to an attested session.
+pub const KEY_RELEASE_INFO: &[u8] = b"elastic-kms key release";
+
+/// Report data binding a KMS challenge and the session's ephemeral public
+/// key: `SHA-512(label || challenge || public_key)`.
+pub fn report_data(challenge: &[u8], public_key: &[u8]) -> [u8; REPORT_DATA_LEN] {
+    let mut hasher = Sha512::new();
+    hasher.update(REPORT_DATA_LABEL);
+    hasher.update(challenge);
+    hasher.update(public_key);
+    hasher.finalize().into()
+}
+
+/// Source of attestation reports for the current guest.
+pub trait Attester: Send + Sync {
+    /// Returns a report with `report_data` in its report data field.
+    fn attestation_report(&self, report_data: [u8; REPORT_DATA_LEN]) -> Result<Vec<u8>>;
+}
+
+/// SEV-SNP attestation reports from the guest firmware (`/dev/sev-guest`).
+#[cfg(all(feature = "sevsnp", target_os = "linux"))]
+pub struct SevSnpAttester;
+
+#[cfg(all(feature = "sevsnp", target_os = "linux"))]
+impl Attester for SevSnpAttester {
+    fn attestation_report(&self, report_data: [u8; REPORT_DATA_LEN]) -> Result<Vec<u8>> {
+        let mut firmware = sev::firmware::guest::Firmware::open()
+            .map_err(|e| Error::SevSnpOperationFailed(e.to_string()))?;
+        firmware
+            .get_report(None, Some(report_data), None)
+            .map_err(|e| Error::SevSnpOperationFailed(e.to_string()))
+    }
+}
+
+/// A key wrapped by the KMS, safe to store next to the data it protects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteWrappedKey {
+    pub kms_key_id: String,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KmsConfig {
+    /// Base URL of the KMS, e.g. `https://kms.example.com`.
+    pub endpoint: String,
+    /// DER certificate to trust as the only root for the KMS connection;
+    /// `None` trusts the Mozilla root store.
+    pub root_certificate: Option<Vec<u8>>,
+    pub timeout: Duration,
+}
+
+impl Default for KmsConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            root_certificate: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+struct Session {
+    token: String,
+    secret: StaticSecret,
+}
+
+enum Failure {
+    Status(u16, String),
+    Transport(String),
+}
+
+impl From<Failure> for Error {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::Status(403, message) => Error::AttestationFailed(message),
+            Failure::Status(status, message) => Error::Kms(format!("HTTP {}: {}", status, message)),
+            Failure::Transport(message) => Error::Kms(message),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChallengeResponse {
+    challenge: String,
+}
+
+#[derive(Deserialize)]
+struct AttestResponse {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct WrapResponse {
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct UnwrapResponse {
+    sealed_key: String,
+}
+
+#[derive(Deserialize)]
+struct DataKeyResponse {
+    ciphertext: String,
+    sealed_key: String,
+}
+
+fn decode(field: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(field)
+        .map_err(|e| Error::Kms(format!("invalid base64 in response: {}", e)))
+}
+
+fn kms_error(message: impl ToString) -> Error {
+    Error::Kms(message.to_string())
+}
+
+/// HTTPS client for the KMS API, attesting on first use.
+pub struct KmsClient {
+    agent: ureq::Agent,
+    endpoint: String,
+    attester: Box<dyn Attester>,
+    session: Mutex<Option<Arc<Session>>>,
+}
+
+impl KmsClient {
+    pub fn new(config: KmsConfig, attester: Box<dyn Attester>) -> Result<Self> {
+        if !config.endpoint.starts_with("https://") {
+            return Err(kms_error("KMS endpoint must use https"));
+        }
+        let mut roots = rustls::RootCertStore::empty();
+        match config.root_certificate {
+            Some(der) => roots.add(CertificateDer::from(der)).map_err(kms_error)?,
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let tls = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(kms_error)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let agent = ureq::AgentBuilder::new()
+            .tls_config(Arc::new(tls))
+            .timeout(config.timeout)
+            .build();
+        Ok(Self {
+            agent,
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+            attester,
+            session: Mutex::new(None),
+        })
+    }
+
+    /// Attests to the KMS now, replacing any current session.
+    pub fn attest(&self) -> Result<()> {
+        let session = Arc::new(self.new_session()?);
+        *self.lock()? = Some(session);
+        Ok(())
+    }
+
+    /// Wraps existing key material under `kms_key_id`, e.g. to migrate a key
+    /// into the KMS.
+    pub fn wrap_key(&self, kms_key_id: &str, plaintext: &[u8]) -> Result<RemoteWrappedKey> {
+        let body = json!({"key_id": kms_key_id, "plaintext": STANDARD.encode(plaintext)});
+        let (response, _): (WrapResponse, _) = self.call("/v1/wrap", &body)?;
+        Ok(RemoteWrappedKey {
+            kms_key_id: kms_key_id.to_string(),
+            ciphertext: decode(&response.ciphertext)?,
+        })
+    }
+
+    pub(crate) fn unwrap_key(&self, wrapped: &RemoteWrappedKey) -> Result<Vec<u8>> {
+        let body = json!({
+            "key_id": wrapped.kms_key_id,
+            "ciphertext": STANDARD.encode(&wrapped.ciphertext),
+        });
+        let (response, session): (UnwrapResponse, _) = self.call("/v1/unwrap", &body)?;
+        open_released_key(&session, &wrapped.kms_key_id, &response.sealed_key)
+    }
+
+    pub(crate) fn generate_data_key(&self, kms_key_id: &str, key_size: u32) -> Result<(Vec<u8>, RemoteWrappedKey)> {
+        let body = json!({"key_id": kms_key_id, "key_size": key_size});
+        let (response, session): (DataKeyResponse, _) = self.call("/v1/generate-data-key", &body)?;
+        let key_data = open_released_key(&session, kms_key_id, &response.sealed_key)?;
+        let wrapped = RemoteWrappedKey {
+            kms_key_id: kms_key_id.to_string(),
+            ciphertext: decode(&response.ciphertext)?,
+        };
+        Ok((key_data, wrapped))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Option<Arc<Session>>>> {
+        self.session.lock().map_err(kms_error)
+    }
+
+    fn new_session(&self) -> Result<Session> {
+        let challenge: ChallengeResponse = self.post("/v1/challenge", None, &json!({}))?;
+        let challenge = decode(&challenge.challenge)?;
+        let secret = StaticSecret::random_from_rng(ElasticRng::new()?);
+        let public_key = PublicKey::from(&secret);
+        let report = self
+            .attester
+            .attestation_report(report_data(&challenge, public_key.as_bytes()))?;
+        let body = json!({
+            "challenge": STANDARD.encode(&challenge),
+            "public_key": STANDARD.encode(public_key.as_bytes()),
+            "report": STANDARD.encode(&report),
+        });
+        let response: AttestResponse = self.post("/v1/attest", None, &body)?;
+        Ok(Session {
+            token: response.token,
+            secret,
+        })
+    }
+
+    /// Calls a key endpoint, attesting first if there is no session and once
+    /// more if the KMS no longer accepts the current one.
+    fn call<T: DeserializeOwned>(&self, path: &str, body: &Value) -> Result<(T, Arc<Session>)> {
+        let current = self.lock()?.clone();
+        if let Some(session) = current {
+            match self.post(path, Some(&session.token), body) {
+                Ok(response) => return Ok((response, session)),
+                Err(Failure::Status(401, _)) => {}
+                Err(failure) => return Err(failure.into()),
+            }
+        }
+        let session = Arc::new(self.new_session()?);
+        *self.lock()? = Some(Arc::clone(&session));
+        let response = self.post(path, Some(&session.token), body)?;
+        Ok((response, session))
+    }
+
+    fn post<T: DeserializeOwned>(&self, path: &str, token: Option<&str>, body: &Value) -> std::result::Result<T, Failure> {
+        let mut request = self.agent.post(&format!("{}{}", self.endpoint, path));
+        if let Some(token) = token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        match request.send_json(body) {
+            Ok(response) => response
+                .into_json()
+                .map_err(|e| Failure::Transport(format!("invalid response: {}", e))),
+            Err(ureq::Error::Status(status, response)) => {
+                let message = response
+                    .into_json::<Value>()
+                    .ok()
+                    .and_then(|body| body["error"].as_str().map(str::to_string))
+                    .unwrap_or_default();
+                Err(Failure::Status(status, message))
+            }
+            Err(e) => Err(Failure::Transport(e.to_string())),
+        }
+    }
+}
+
+fn open_released_key(session: &Session, kms_key_id: &str, sealed_key: &str) -> Result<Vec<u8>> {
+    let sealed = decode(sealed_key)?;
+    hpke::open::<ChaCha20Poly1305, X25519HkdfSha256>(
+        &session.secret.to_bytes(),
+        None,
+        &sealed,
+        KEY_RELEASE_INFO,
+        kms_key_id.as_bytes(),
+    )
+}
+
+/// Remote keys stay in the store; they were never the workload's to export.
+fn secure(config: KeyConfig) -> KeyConfig {
+    KeyConfig {
+        secure_storage: true,
+        ..config
+    }
+}
+
+impl ElasticCrypto {
+    /// Has the KMS unwrap `wrapped`, attesting first if needed, and holds the
+    /// key as a secure-storage key.
+    pub fn load_remote_key(&self, kms: &KmsClient, wrapped: &RemoteWrappedKey, config: KeyConfig) -> Result<u32> {
+        self.self_test.check()?;
+        let key_data = kms.unwrap_key(wrapped)?;
+        Ok(self.insert_key(Key::new(key_data, secure(config))?))
+    }
+
+    /// Has the KMS generate a symmetric or HMAC data key under `kms_key_id`.
+    /// Returns the handle of the key, held as a secure-storage key, and its
+    /// wrapped form for [`ElasticCrypto::load_remote_key`].
+    pub fn generate_remote_data_key(
+        &self,
+        kms: &KmsClient,
+        kms_key_id: &str,
+        config: KeyConfig,
+    ) -> Result<(u32, RemoteWrappedKey)> {
+        self.self_test.check()?;
+        let key_size = match config.algorithm() {
+            KeyAlgorithm::Aes256Gcm => 256,
+            KeyAlgorithm::AesSiv | KeyAlgorithm::HmacSha256 => config.key_size,
+            _ => return Err(Error::UnsupportedOperation),
+        };
+        let (key_data, wrapped) = kms.generate_data_key(kms_key_id, key_size)?;
+        let handle = self.insert_key(Key::new(key_data, secure(config))?);
+        Ok((handle, wrapped))
+    }
+}
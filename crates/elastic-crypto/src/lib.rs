@@ -7,8 +7,33 @@ mod sev;
 
 mod error;
 pub mod aes;
+pub mod audit;
+pub mod backend;
+pub mod cose;
+pub mod hpke;
+pub mod jose;
+#[cfg(feature = "kms")]
+pub mod kms;
+pub mod noise;
+pub mod password;
+pub mod rng;
+pub mod selftest;
+pub mod shamir;
+pub mod signature;
+pub mod siv;
+pub mod x509;
 
 pub use aes::AesKey;
+pub use audit::{AuditEvent, AuditSink, StderrAuditSink};
+pub use backend::CryptoBackend;
+pub use self::hpke::HpkeAead;
+pub use jose::JweAlgorithm;
+pub use noise::{NoiseCipherState, NoiseHandshake, NoisePattern, NoiseSuite};
+pub use password::PasswordParams;
+pub use rng::ElasticRng;
+pub use selftest::{SelfTestAlgorithm, SelfTestReport, SelfTestResult};
+pub use signature::verify_with_public_key;
+pub use x509::CertificateParams;
 
 #[cfg(feature = "linux")]
 pub use linux::*;
@@ -17,14 +42,27 @@ pub use wasm::*;
 #[cfg(feature = "sevsnp")]
 pub use sev::{SevsnpRng, SevsnpAes};
 
-use std::collections::HashMap;
-use std::sync::Mutex;
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
 use aes_gcm::aead::Aead;
-use aes_gcm::KeyInit;
+use aes_gcm::{Aes256Gcm, KeyInit};
 use std::env;
 
-#[derive(Debug, Error)]
+/// Leading byte of AES-256-GCM ciphertexts: `GCM_FORMAT_V1 || nonce || ciphertext || tag`.
+///
+/// Ciphertexts from earlier releases have no format byte and were sealed under
+/// the fixed nonce `GCM_LEGACY_NONCE`. `decrypt` still opens them; re-encrypt
+/// such data to move it to the current format.
+const GCM_FORMAT_V1: u8 = 1;
+const GCM_NONCE_LEN: usize = 12;
+const GCM_LEGACY_NONCE: &[u8; GCM_NONCE_LEN] = b"elastic-nc12";
+
+#[derive(Debug, Error, Serialize, Deserialize)]
 pub enum Error {
     #[error("Invalid key length")]
     InvalidKeyLength,
@@ -56,11 +94,37 @@ pub enum Error {
     DecryptionFailed,
     #[error("Invalid ciphertext")]
     InvalidCiphertext,
+    #[error("RNG error: {0}")]
+    RngError(String),
+    #[error("Self-test failed: {0}")]
+    SelfTestFailed(String),
+    #[error("Invalid password hashing parameters: {0}")]
+    InvalidPasswordParams(String),
+    #[error("Invalid password hash: {0}")]
+    InvalidPasswordHash(String),
+    #[error("Signature verification failed")]
+    VerificationFailed,
+    #[error("Invalid encoding: {0}")]
+    InvalidEncoding(String),
+    #[error("Invalid certificate parameters: {0}")]
+    InvalidCertificateParams(String),
+    #[error("Invalid key share: {0}")]
+    InvalidKeyShare(String),
+    #[error("Noise protocol error: {0}")]
+    NoiseProtocol(String),
+    #[error("Key server error: {0}")]
+    KeyServer(String),
+    #[error("KMS error: {0}")]
+    Kms(String),
+    #[error("Attestation failed: {0}")]
+    AttestationFailed(String),
+    #[error("Access denied to key {0}")]
+    KeyAccessDenied(u32),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum KeyType {
     Symmetric,
     Asymmetric,
@@ -73,23 +137,146 @@ pub enum AesMode {
     Gcm,
 }
 
-#[derive(Debug, Clone)]
+/// Algorithm a key is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyAlgorithm {
+    Aes256Gcm,
+    HmacSha256,
+    Ed25519,
+    EcdsaP256,
+    /// RSASSA-PSS with SHA-256; `key_size` is the modulus length (at least 2048)
+    RsaPssSha256,
+    /// ECDH key agreement over P-256
+    EcdhP256,
+    /// X25519 key agreement
+    X25519,
+    /// Deterministic AES-SIV (RFC 5297); `key_size` is the SIV key length,
+    /// 256 (AES-128) or 512 (AES-256)
+    AesSiv,
+}
+
+impl KeyAlgorithm {
+    fn key_type(self) -> KeyType {
+        match self {
+            KeyAlgorithm::Aes256Gcm | KeyAlgorithm::AesSiv => KeyType::Symmetric,
+            KeyAlgorithm::HmacSha256 => KeyType::Hmac,
+            KeyAlgorithm::Ed25519
+            | KeyAlgorithm::EcdsaP256
+            | KeyAlgorithm::RsaPssSha256
+            | KeyAlgorithm::EcdhP256
+            | KeyAlgorithm::X25519 => KeyType::Asymmetric,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyConfig {
     pub key_type: KeyType,
     pub key_size: u32,
     pub secure_storage: bool,
+    /// Specific algorithm; `None` uses the default for `key_type`
+    /// (AES-256-GCM, Ed25519 or HMAC-SHA256).
+    pub algorithm: Option<KeyAlgorithm>,
+}
+
+impl KeyConfig {
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.algorithm.unwrap_or(match self.key_type {
+            KeyType::Symmetric => KeyAlgorithm::Aes256Gcm,
+            KeyType::Asymmetric => KeyAlgorithm::Ed25519,
+            KeyType::Hmac => KeyAlgorithm::HmacSha256,
+        })
+    }
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        Self {
+            key_type: KeyType::Symmetric,
+            key_size: 256,
+            secure_storage: false,
+            algorithm: None,
+        }
+    }
 }
 
 pub struct Key {
     data: Vec<u8>,
     config: KeyConfig,
+    algorithm: KeyAlgorithm,
+    // Cipher instance built once at import, shared by all concurrent users of the key.
+    cipher: Option<Aes256Gcm>,
+    // Workload namespace the key belongs to; `None` for host keys.
+    owner: Option<Arc<str>>,
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+impl Key {
+    fn new(data: Vec<u8>, config: KeyConfig) -> Result<Self> {
+        let mut data = Zeroizing::new(data);
+        let algorithm = config.algorithm();
+        if algorithm.key_type() != config.key_type {
+            return Err(Error::UnsupportedOperation);
+        }
+        let cipher = match algorithm {
+            KeyAlgorithm::Aes256Gcm => {
+                Some(Aes256Gcm::new_from_slice(&data).map_err(|_| Error::InvalidKeyLength)?)
+            }
+            KeyAlgorithm::AesSiv => {
+                siv::check_key(&data)?;
+                None
+            }
+            _ => {
+                signature::check_key(algorithm, &data)?;
+                None
+            }
+        };
+        Ok(Self { data: std::mem::take(&mut *data), config, algorithm, cipher, owner: None })
+    }
+
+    fn cipher(&self) -> Result<&Aes256Gcm> {
+        self.cipher.as_ref().ok_or(Error::UnsupportedOperation)
+    }
 }
 
+/// Key table shared by all threads using a context.
+///
+/// Lookups load the current snapshot without taking a lock and clone the
+/// `Arc<Key>` they need, so no lock is held while a cipher runs. Inserts and
+/// deletes publish a new snapshot (copy-on-write), which suits the read-mostly
+/// access pattern of a key store.
+type KeyTable = HashMap<u32, Arc<Key>>;
+
+/// State shared by a host context and the workload contexts made from it.
+struct KeyStore {
+    keys: ArcSwap<KeyTable>,
+    next_handle: AtomicU32,
+    audit: RwLock<Arc<dyn AuditSink>>,
+    // Public points of the custodian keys shares may be encrypted to.
+    custodians: RwLock<HashSet<Vec<u8>>>,
+}
+
+/// Key store context.
+///
+/// A context created with [`ElasticCrypto::new`] acts for the host. Contexts
+/// from [`ElasticCrypto::for_workload`] share its key store but act for one
+/// workload: keys they create are owned by that workload, and every handle
+/// lookup checks the owner, so a workload cannot use another workload's or
+/// the host's keys even if it guesses their handles. Denied lookups fail with
+/// [`Error::KeyAccessDenied`] and are reported to the audit sink.
 pub struct ElasticCrypto {
-    keys: Mutex<HashMap<u32, Key>>,
-    next_handle: Mutex<u32>,
+    store: Arc<KeyStore>,
+    workload: Option<Arc<str>>,
+    #[cfg(feature = "sevsnp")]
     aes: Mutex<Option<SevsnpAes>>,
     is_sevsnp: bool,
+    self_test: SelfTestReport,
+    rng: Mutex<ElasticRng>,
 }
 
 impl ElasticCrypto {
@@ -97,6 +284,7 @@ impl ElasticCrypto {
         println!("Initializing ElasticCrypto...");
         println!("Checking for SEV-SNP support...");
         
+        #[cfg(feature = "sevsnp")]
         let mut aes = None;
         let is_sevsnp = env::var("ELASTIC_SEV_SNP").unwrap_or_default() == "1";
         
@@ -119,12 +307,26 @@ impl ElasticCrypto {
         {
             println!("SEV-SNP feature is not enabled in build");
         }
+
+        println!("Running crypto self-tests...");
+        let self_test = selftest::run_self_tests();
+        if let Err(e) = self_test.check() {
+            println!("{}, refusing all crypto operations", e);
+        }
         
         Ok(Self {
-            keys: Mutex::new(HashMap::new()),
-            next_handle: Mutex::new(1),
+            store: Arc::new(KeyStore {
+                keys: ArcSwap::from_pointee(HashMap::new()),
+                next_handle: AtomicU32::new(1),
+                audit: RwLock::new(Arc::new(StderrAuditSink)),
+                custodians: RwLock::new(HashSet::new()),
+            }),
+            workload: None,
+            #[cfg(feature = "sevsnp")]
             aes: Mutex::new(aes),
             is_sevsnp,
+            self_test,
+            rng: Mutex::new(ElasticRng::new()?),
         })
     }
 
@@ -132,37 +334,128 @@ impl ElasticCrypto {
         self.is_sevsnp
     }
 
-    fn get_next_handle(&self) -> u32 {
-        let mut handle = self.next_handle.lock().unwrap();
-        let current = *handle;
-        *handle += 1;
-        current
+    /// Results of the known-answer tests run when this context was created.
+    pub fn self_test_report(&self) -> &SelfTestReport {
+        &self.self_test
     }
 
-    pub fn generate_key(&self, config: KeyConfig) -> Result<u32> {
-        let key_data = match config.key_type {
-            KeyType::Symmetric => {
-                // Use a fixed key for demo to ensure consistent results
-                let demo_key = [0x42u8; 32]; // Same key as SEV-SNP initialization
-                demo_key.to_vec()
+    /// Context acting for `workload_id` on this context's key store.
+    /// Only the host context can create one.
+    pub fn for_workload(&self, workload_id: &str) -> Result<ElasticCrypto> {
+        self.check_host("for_workload")?;
+        if workload_id.is_empty() {
+            return Err(Error::OperationNotPermitted);
+        }
+        Ok(Self {
+            store: Arc::clone(&self.store),
+            workload: Some(Arc::from(workload_id)),
+            #[cfg(feature = "sevsnp")]
+            aes: Mutex::new(self.aes.lock().ok().and_then(|aes| aes.clone())),
+            is_sevsnp: self.is_sevsnp,
+            self_test: self.self_test.clone(),
+            rng: Mutex::new(ElasticRng::new()?),
+        })
+    }
+
+    /// Workload this context acts for; `None` for the host.
+    pub fn workload_id(&self) -> Option<&str> {
+        self.workload.as_deref()
+    }
+
+    /// Replaces the sink that receives audit events. Host only.
+    pub fn set_audit_sink(&self, sink: Arc<dyn AuditSink>) -> Result<()> {
+        self.check_host("set_audit_sink")?;
+        *self.store.audit.write().map_err(|_| Error::OperationNotPermitted)? = sink;
+        Ok(())
+    }
+
+    /// Deletes every key owned by `workload_id`, e.g. when a tenant is torn
+    /// down, and returns how many were removed. Host only.
+    pub fn delete_workload_keys(&self, workload_id: &str) -> Result<usize> {
+        self.check_host("delete_workload_keys")?;
+        let mut removed = 0;
+        self.store.keys.rcu(|keys| {
+            let mut remaining = KeyTable::clone(keys);
+            remaining.retain(|_, key| key.owner.as_deref() != Some(workload_id));
+            removed = keys.len() - remaining.len();
+            remaining
+        });
+        Ok(removed)
+    }
+
+    fn audit(&self, event: AuditEvent) {
+        if let Ok(sink) = self.store.audit.read() {
+            sink.record(event);
+        }
+    }
+
+    fn check_host(&self, operation: &'static str) -> Result<()> {
+        match &self.workload {
+            None => Ok(()),
+            Some(workload) => {
+                self.audit(AuditEvent::HostOperationDenied {
+                    caller: workload.to_string(),
+                    operation,
+                });
+                Err(Error::OperationNotPermitted)
             }
-            _ => return Err(Error::NotImplemented),
-        };
+        }
+    }
+
+    fn get_next_handle(&self) -> u32 {
+        self.store.next_handle.fetch_add(1, Ordering::Relaxed)
+    }
 
+    fn get_key(&self, handle: u32) -> Result<Arc<Key>> {
+        let key = self.store.keys.load().get(&handle).cloned().ok_or(Error::KeyNotFound)?;
+        if key.owner != self.workload {
+            self.audit(AuditEvent::KeyAccessDenied {
+                caller: self.workload.as_deref().map(str::to_string),
+                handle,
+                owner: key.owner.as_deref().map(str::to_string),
+            });
+            return Err(Error::KeyAccessDenied(handle));
+        }
+        Ok(key)
+    }
+
+    fn insert_key(&self, mut key: Key) -> u32 {
         let handle = self.get_next_handle();
-        self.keys.lock().unwrap().insert(handle, Key { data: key_data, config });
-        Ok(handle)
+        key.owner = self.workload.clone();
+        let key = Arc::new(key);
+        self.store.keys.rcu(|keys| {
+            let mut keys = KeyTable::clone(keys);
+            keys.insert(handle, Arc::clone(&key));
+            keys
+        });
+        handle
+    }
+
+    fn remove_key(&self, handle: u32) -> Result<Arc<Key>> {
+        let key = self.get_key(handle)?;
+        self.store.keys.rcu(|keys| {
+            let mut keys = KeyTable::clone(keys);
+            keys.remove(&handle);
+            keys
+        });
+        Ok(key)
+    }
+
+    pub fn generate_key(&self, config: KeyConfig) -> Result<u32> {
+        self.self_test.check()?;
+        let key_data = signature::generate_key_data(&config)?;
+
+        Ok(self.insert_key(Key::new(key_data, config)?))
     }
 
     pub fn import_key(&self, key_data: Vec<u8>, config: KeyConfig) -> Result<u32> {
-        let handle = self.get_next_handle();
-        self.keys.lock().unwrap().insert(handle, Key { data: key_data, config });
-        Ok(handle)
+        self.self_test.check()?;
+        Ok(self.insert_key(Key::new(key_data, config)?))
     }
 
     pub fn export_key(&self, handle: u32) -> Result<Vec<u8>> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
         if key.config.secure_storage {
             return Err(Error::OperationNotPermitted);
         }
@@ -170,45 +463,50 @@ impl ElasticCrypto {
     }
 
     pub fn delete_key(&self, handle: u32) -> Result<()> {
-        self.keys.lock().unwrap().remove(&handle).ok_or(Error::KeyNotFound)?;
+        self.self_test.check()?;
+        self.remove_key(handle)?;
         Ok(())
     }
 
     pub fn encrypt(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
         
-        match key.config.key_type {
-            KeyType::Symmetric => {
-                // Use AES-GCM for symmetric encryption
-                let cipher = aes_gcm::Aes256Gcm::new_from_slice(&key.data)
+        match key.algorithm {
+            KeyAlgorithm::AesSiv => key.siv_encrypt(&data, &[], None),
+            KeyAlgorithm::Aes256Gcm => {
+                // Use AES-GCM with a fresh random nonce, sent ahead of the ciphertext
+                let cipher = key.cipher()?;
+                let mut nonce = [0u8; GCM_NONCE_LEN];
+                self.rng.lock().map_err(|_| Error::RngError("RNG lock poisoned".into()))?.fill(&mut nonce)?;
+                let ciphertext = cipher.encrypt(aes_gcm::Nonce::from_slice(&nonce), data.as_ref())
                     .map_err(|e| Error::EncryptionError(e.to_string()))?;
-                
-                // Use a fixed nonce for consistent results across platforms
-                // In production, you would use a random nonce
-                let nonce = aes_gcm::Nonce::from_slice(b"elastic-nc12"); // 12 bytes
-                
-                // Always use software implementation for consistent results
-                cipher.encrypt(nonce, data.as_ref())
-                    .map_err(|e| Error::EncryptionError(e.to_string()))
+                Ok([&[GCM_FORMAT_V1][..], &nonce, &ciphertext].concat())
             }
             _ => Err(Error::UnsupportedOperation),
         }
     }
 
     pub fn decrypt(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
         
-        match key.config.key_type {
-            KeyType::Symmetric => {
-                // Use AES-GCM for symmetric decryption
-                let cipher = aes_gcm::Aes256Gcm::new_from_slice(&key.data)
-                    .map_err(|e| Error::DecryptionError(e.to_string()))?;
-                
-                // Use the same fixed nonce as encryption
-                let nonce = aes_gcm::Nonce::from_slice(b"elastic-nc12"); // 12 bytes
-                cipher.decrypt(nonce, data.as_ref())
+        match key.algorithm {
+            KeyAlgorithm::AesSiv => key.siv_decrypt(&data, &[], None),
+            KeyAlgorithm::Aes256Gcm => {
+                // Use AES-GCM for symmetric decryption; the nonce follows the format byte
+                let cipher = key.cipher()?;
+                if let Some((&GCM_FORMAT_V1, rest)) = data.split_first() {
+                    if rest.len() >= GCM_NONCE_LEN {
+                        let (nonce, ciphertext) = rest.split_at(GCM_NONCE_LEN);
+                        if let Ok(plaintext) = cipher.decrypt(aes_gcm::Nonce::from_slice(nonce), ciphertext) {
+                            return Ok(plaintext);
+                        }
+                    }
+                }
+
+                // Legacy ciphertexts sealed under the fixed nonce
+                cipher.decrypt(aes_gcm::Nonce::from_slice(GCM_LEGACY_NONCE), data.as_ref())
                     .map_err(|e| Error::DecryptionError(e.to_string()))
             }
             _ => Err(Error::UnsupportedOperation),
@@ -216,6 +514,7 @@ impl ElasticCrypto {
     }
 
     pub fn hash(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
         use sha2::{Sha256, Digest};
         let mut hasher = Sha256::new();
         hasher.update(&data);
@@ -223,6 +522,7 @@ impl ElasticCrypto {
     }
 
     pub fn hash_sha512(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
         use sha2::{Sha512, Digest};
         let mut hasher = Sha512::new();
         hasher.update(&data);
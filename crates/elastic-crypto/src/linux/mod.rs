@@ -2,6 +2,7 @@
 pub use crate::aes::AesMode;
 
 use crate::Error;
+use crate::selftest::{self, SelfTestReport};
 use rand::RngCore;
 use std::collections::HashMap;
 use std::sync::Mutex;
@@ -39,6 +40,7 @@ pub struct Key {
 pub struct CryptoContext {
     keys: AsyncMutex<HashMap<u32, Key>>,
     next_handle: Mutex<u32>,
+    self_test: SelfTestReport,
 }
 
 impl CryptoContext {
@@ -46,10 +48,17 @@ impl CryptoContext {
         Self {
             keys: AsyncMutex::new(HashMap::new()),
             next_handle: Mutex::new(1),
+            self_test: selftest::run_self_tests(),
         }
     }
 
+    /// Results of the known-answer tests run when this context was created.
+    pub fn self_test_report(&self) -> &SelfTestReport {
+        &self.self_test
+    }
+
     pub async fn generate_key(&self, config: &KeyConfig) -> Result<u32, Error> {
+        self.self_test.check()?;
         let mut keys = self.keys.lock().await;
         let mut next_handle = self.next_handle.lock().unwrap();
         
@@ -86,6 +95,7 @@ impl CryptoContext {
     }
 
     pub async fn export_key(&self, handle: u32) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -97,6 +107,7 @@ impl CryptoContext {
     }
 
     pub async fn import_key(&self, key_data: &[u8], config: &KeyConfig) -> Result<u32, Error> {
+        self.self_test.check()?;
         let mut keys = self.keys.lock().await;
         let mut next_handle = self.next_handle.lock().unwrap();
         
@@ -113,12 +124,14 @@ impl CryptoContext {
     }
 
     pub async fn delete_key(&self, handle: u32) -> Result<(), Error> {
+        self.self_test.check()?;
         let mut keys = self.keys.lock().await;
         keys.remove(&handle).ok_or(Error::KeyNotFound)?;
         Ok(())
     }
 
     pub async fn encrypt(&self, handle: u32, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -132,6 +145,7 @@ impl CryptoContext {
     }
 
     pub async fn decrypt(&self, handle: u32, encrypted_data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -145,6 +159,7 @@ impl CryptoContext {
     }
 
     pub async fn sign(&self, handle: u32, _data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -159,6 +174,7 @@ impl CryptoContext {
     }
 
     pub async fn verify(&self, handle: u32, _data: &[u8], _signature: &[u8]) -> Result<bool, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -173,6 +189,7 @@ impl CryptoContext {
     }
 
     pub async fn calculate_mac(&self, handle: u32, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -189,6 +206,7 @@ impl CryptoContext {
     }
 
     pub async fn verify_mac(&self, handle: u32, data: &[u8], mac: &[u8]) -> Result<bool, Error> {
+        self.self_test.check()?;
         let keys = self.keys.lock().await;
         let key = keys.get(&handle).ok_or(Error::KeyNotFound)?;
         
@@ -202,6 +220,7 @@ impl CryptoContext {
     }
 
     pub async fn hash(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         use sha2::{Sha256, Digest};
         let mut hasher = Sha256::new();
         hasher.update(data);
@@ -209,6 +228,7 @@ impl CryptoContext {
     }
 
     pub async fn hash_sha512(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         use sha2::{Sha512, Digest};
         let mut hasher = Sha512::new();
         hasher.update(data);
@@ -0,0 +1,558 @@
+//! Noise protocol framework (revision 34) secure channels.
+//!
+//! Supports the XX, IK and NK handshake patterns with
+//! `25519_ChaChaPoly_BLAKE2s` and `25519_AESGCM_SHA256`. Static keys are X25519
+//! key handles, so the long-term private key never leaves the key store;
+//! ephemeral keys live only for the handshake. Everything is pure Rust with no
+//! platform dependencies, so the same code runs in the WASM backend.
+//!
+//! A finished handshake is split into a pair of [`NoiseCipherState`]s for
+//! transport messages. Noise messages are at most 65535 bytes; framing them on
+//! the wire (e.g. with a 2-byte length prefix) is left to the caller.
+
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use aes_gcm::Aes256Gcm;
+use blake2::Blake2s256;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac, SimpleHmac};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Largest Noise message, handshake or transport.
+pub const MAX_MESSAGE_LEN: usize = 65535;
+
+const HASH_LEN: usize = 32;
+const DH_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Handshake pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Both sides send their static keys during the handshake.
+    Xx,
+    /// The initiator knows the responder's static key and sends its own.
+    Ik,
+    /// The initiator knows the responder's static key and stays anonymous.
+    Nk,
+}
+
+/// DH, cipher and hash functions; DH is always X25519.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseSuite {
+    ChaChaPolyBlake2s,
+    AesGcmSha256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    E,
+    S,
+    Ee,
+    Es,
+    Se,
+    Ss,
+}
+
+impl NoisePattern {
+    fn name(self) -> &'static str {
+        match self {
+            NoisePattern::Xx => "XX",
+            NoisePattern::Ik => "IK",
+            NoisePattern::Nk => "NK",
+        }
+    }
+
+    fn messages(self) -> &'static [&'static [Token]] {
+        use Token::*;
+        match self {
+            NoisePattern::Xx => &[&[E], &[E, Ee, S, Es], &[S, Se]],
+            NoisePattern::Ik => &[&[E, Es, S, Ss], &[E, Ee, Se]],
+            NoisePattern::Nk => &[&[E, Es], &[E, Ee]],
+        }
+    }
+
+    /// Whether the responder's static key is known in advance (`<- s`).
+    fn responder_static_premessage(self) -> bool {
+        matches!(self, NoisePattern::Ik | NoisePattern::Nk)
+    }
+
+    /// Whether the initiator has a static key.
+    fn initiator_static(self) -> bool {
+        matches!(self, NoisePattern::Xx | NoisePattern::Ik)
+    }
+}
+
+impl NoiseSuite {
+    fn name(self) -> &'static str {
+        match self {
+            NoiseSuite::ChaChaPolyBlake2s => "ChaChaPoly_BLAKE2s",
+            NoiseSuite::AesGcmSha256 => "AESGCM_SHA256",
+        }
+    }
+
+    fn hash(self, parts: &[&[u8]]) -> [u8; HASH_LEN] {
+        match self {
+            NoiseSuite::ChaChaPolyBlake2s => {
+                let mut hasher = Blake2s256::new();
+                parts.iter().for_each(|p| hasher.update(p));
+                hasher.finalize().into()
+            }
+            NoiseSuite::AesGcmSha256 => {
+                let mut hasher = Sha256::new();
+                parts.iter().for_each(|p| hasher.update(p));
+                hasher.finalize().into()
+            }
+        }
+    }
+
+    fn hmac(self, key: &[u8], parts: &[&[u8]]) -> [u8; HASH_LEN] {
+        match self {
+            NoiseSuite::ChaChaPolyBlake2s => {
+                let mut mac = <SimpleHmac<Blake2s256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+                parts.iter().for_each(|p| mac.update(p));
+                mac.finalize().into_bytes().into()
+            }
+            NoiseSuite::AesGcmSha256 => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+                parts.iter().for_each(|p| mac.update(p));
+                mac.finalize().into_bytes().into()
+            }
+        }
+    }
+
+    /// Noise `HKDF` with two outputs.
+    fn hkdf(self, chaining_key: &[u8], input_key_material: &[u8]) -> ([u8; HASH_LEN], [u8; HASH_LEN]) {
+        let temp_key = self.hmac(chaining_key, &[input_key_material]);
+        let output1 = self.hmac(&temp_key, &[&[1]]);
+        let output2 = self.hmac(&temp_key, &[&output1, &[2]]);
+        (output1, output2)
+    }
+
+    /// ChaChaPoly encodes the counter little-endian, AESGCM big-endian, both
+    /// after 32 zero bits.
+    fn nonce(self, counter: u64) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        match self {
+            NoiseSuite::ChaChaPolyBlake2s => nonce[4..].copy_from_slice(&counter.to_le_bytes()),
+            NoiseSuite::AesGcmSha256 => nonce[4..].copy_from_slice(&counter.to_be_bytes()),
+        }
+        nonce
+    }
+
+    fn encrypt(self, key: &[u8; 32], counter: u64, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.nonce(counter);
+        let payload = Payload { msg: plaintext, aad: ad };
+        let result = match self {
+            NoiseSuite::ChaChaPolyBlake2s => ChaCha20Poly1305::new(key.into()).encrypt(&nonce.into(), payload),
+            NoiseSuite::AesGcmSha256 => Aes256Gcm::new(key.into()).encrypt(&nonce.into(), payload),
+        };
+        result.map_err(|e| Error::EncryptionError(e.to_string()))
+    }
+
+    fn decrypt(self, key: &[u8; 32], counter: u64, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.nonce(counter);
+        let payload = Payload { msg: ciphertext, aad: ad };
+        let result = match self {
+            NoiseSuite::ChaChaPolyBlake2s => ChaCha20Poly1305::new(key.into()).decrypt(&nonce.into(), payload),
+            NoiseSuite::AesGcmSha256 => Aes256Gcm::new(key.into()).decrypt(&nonce.into(), payload),
+        };
+        result.map_err(|_| Error::DecryptionFailed)
+    }
+}
+
+fn protocol_error(message: impl ToString) -> Error {
+    Error::NoiseProtocol(message.to_string())
+}
+
+/// Transport cipher state for one direction of a channel.
+///
+/// Each message uses the next nonce, so messages must be decrypted in the
+/// order they were encrypted.
+pub struct NoiseCipherState {
+    suite: NoiseSuite,
+    key: Option<[u8; 32]>,
+    nonce: u64,
+}
+
+impl NoiseCipherState {
+    fn new(suite: NoiseSuite) -> Self {
+        Self { suite, key: None, nonce: 0 }
+    }
+
+    fn initialize_key(&mut self, key: [u8; 32]) {
+        self.key = Some(key);
+        self.nonce = 0;
+    }
+
+    fn next_nonce(&mut self) -> Result<u64> {
+        // 2^64 - 1 is reserved for rekeying.
+        if self.nonce == u64::MAX {
+            return Err(protocol_error("nonce exhausted"));
+        }
+        let nonce = self.nonce;
+        self.nonce += 1;
+        Ok(nonce)
+    }
+
+    fn encrypt_with_ad(&mut self, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        match self.key {
+            Some(key) => {
+                let nonce = self.next_nonce()?;
+                self.suite.encrypt(&key, nonce, ad, plaintext)
+            }
+            None => Ok(plaintext.to_vec()),
+        }
+    }
+
+    fn decrypt_with_ad(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        match self.key {
+            Some(key) => {
+                if self.nonce == u64::MAX {
+                    return Err(protocol_error("nonce exhausted"));
+                }
+                let plaintext = self.suite.decrypt(&key, self.nonce, ad, ciphertext)?;
+                // Only advance once the message authenticated.
+                self.nonce += 1;
+                Ok(plaintext)
+            }
+            None => Ok(ciphertext.to_vec()),
+        }
+    }
+
+    /// Encrypts a transport message.
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if plaintext.len() + TAG_LEN > MAX_MESSAGE_LEN {
+            return Err(protocol_error("message too long"));
+        }
+        self.encrypt_with_ad(&[], plaintext)
+    }
+
+    /// Decrypts the next transport message.
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() > MAX_MESSAGE_LEN {
+            return Err(protocol_error("message too long"));
+        }
+        self.decrypt_with_ad(&[], ciphertext)
+    }
+
+    /// Replaces the key with one derived from it, as agreed by both sides.
+    pub fn rekey(&mut self) -> Result<()> {
+        let key = self.key.ok_or_else(|| protocol_error("cipher state has no key"))?;
+        let derived = self.suite.encrypt(&key, u64::MAX, &[], &[0u8; 32])?;
+        let mut new_key = [0u8; 32];
+        new_key.copy_from_slice(&derived[..32]);
+        self.key = Some(new_key);
+        Ok(())
+    }
+
+    /// Number of messages processed with the current key.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+struct SymmetricState {
+    suite: NoiseSuite,
+    cipher: NoiseCipherState,
+    chaining_key: [u8; HASH_LEN],
+    hash: [u8; HASH_LEN],
+}
+
+impl SymmetricState {
+    fn new(suite: NoiseSuite, protocol_name: &str) -> Self {
+        let mut hash = [0u8; HASH_LEN];
+        if protocol_name.len() <= HASH_LEN {
+            hash[..protocol_name.len()].copy_from_slice(protocol_name.as_bytes());
+        } else {
+            hash = suite.hash(&[protocol_name.as_bytes()]);
+        }
+        Self { suite, cipher: NoiseCipherState::new(suite), chaining_key: hash, hash }
+    }
+
+    fn mix_key(&mut self, input_key_material: &[u8]) {
+        let (chaining_key, key) = self.suite.hkdf(&self.chaining_key, input_key_material);
+        self.chaining_key = chaining_key;
+        self.cipher.initialize_key(key);
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = self.suite.hash(&[&self.hash, data]);
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let ciphertext = self.cipher.encrypt_with_ad(&self.hash, plaintext)?;
+        self.mix_hash(&ciphertext);
+        Ok(ciphertext)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let plaintext = self.cipher.decrypt_with_ad(&self.hash, ciphertext)?;
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    fn split(&self) -> (NoiseCipherState, NoiseCipherState) {
+        let (key1, key2) = self.suite.hkdf(&self.chaining_key, &[]);
+        let mut first = NoiseCipherState::new(self.suite);
+        first.initialize_key(key1);
+        let mut second = NoiseCipherState::new(self.suite);
+        second.initialize_key(key2);
+        (first, second)
+    }
+}
+
+/// An in-progress Noise handshake.
+///
+/// Call [`write_message`](Self::write_message) and
+/// [`read_message`](Self::read_message) in turn, starting with the initiator,
+/// until [`is_finished`](Self::is_finished), then [`split`](Self::split).
+pub struct NoiseHandshake {
+    pattern: NoisePattern,
+    initiator: bool,
+    symmetric: SymmetricState,
+    local_static: Option<Arc<Key>>,
+    local_ephemeral: Option<StaticSecret>,
+    remote_static: Option<[u8; DH_LEN]>,
+    remote_ephemeral: Option<[u8; DH_LEN]>,
+    message_index: usize,
+}
+
+impl NoiseHandshake {
+    fn new(
+        pattern: NoisePattern,
+        suite: NoiseSuite,
+        initiator: bool,
+        local_static: Option<Arc<Key>>,
+        remote_static: Option<Vec<u8>>,
+        prologue: &[u8],
+    ) -> Result<Self> {
+        let needs_local_static = !initiator || pattern.initiator_static();
+        if needs_local_static != local_static.is_some() {
+            return Err(protocol_error(format!(
+                "{} {} {} a static key",
+                pattern.name(),
+                if initiator { "initiator" } else { "responder" },
+                if needs_local_static { "needs" } else { "does not use" }
+            )));
+        }
+        if let Some(key) = &local_static {
+            if key.algorithm != KeyAlgorithm::X25519 {
+                return Err(Error::UnsupportedOperation);
+            }
+        }
+        let needs_remote_static = initiator && pattern.responder_static_premessage();
+        if needs_remote_static != remote_static.is_some() {
+            return Err(protocol_error(format!(
+                "{} {} the responder's static key in advance",
+                pattern.name(),
+                if needs_remote_static { "needs" } else { "does not take" }
+            )));
+        }
+        let remote_static = remote_static
+            .map(|key| <[u8; DH_LEN]>::try_from(key.as_slice()).map_err(|_| Error::InvalidKeyLength))
+            .transpose()?;
+
+        let protocol_name = format!("Noise_{}_25519_{}", pattern.name(), suite.name());
+        let mut symmetric = SymmetricState::new(suite, &protocol_name);
+        symmetric.mix_hash(prologue);
+        if pattern.responder_static_premessage() {
+            let responder_static = match (&remote_static, &local_static) {
+                (Some(remote), _) if initiator => remote.to_vec(),
+                (_, Some(local)) => local.public_key()?,
+                _ => unreachable!("checked above"),
+            };
+            symmetric.mix_hash(&responder_static);
+        }
+
+        Ok(Self {
+            pattern,
+            initiator,
+            symmetric,
+            local_static,
+            local_ephemeral: None,
+            remote_static,
+            remote_ephemeral: None,
+            message_index: 0,
+        })
+    }
+
+    fn check_turn(&self, writing: bool) -> Result<&'static [Token]> {
+        let messages = self.pattern.messages();
+        let tokens = messages.get(self.message_index).ok_or_else(|| protocol_error("handshake already finished"))?;
+        let initiator_turn = self.message_index.is_multiple_of(2);
+        if (initiator_turn == self.initiator) != writing {
+            return Err(protocol_error(if writing { "not our turn to write" } else { "not our turn to read" }));
+        }
+        Ok(tokens)
+    }
+
+    fn local_static(&self) -> Result<&Arc<Key>> {
+        self.local_static.as_ref().ok_or_else(|| protocol_error("missing local static key"))
+    }
+
+    fn local_ephemeral(&self) -> Result<&StaticSecret> {
+        self.local_ephemeral.as_ref().ok_or_else(|| protocol_error("missing local ephemeral key"))
+    }
+
+    fn remote(key: Option<[u8; DH_LEN]>) -> Result<PublicKey> {
+        key.map(PublicKey::from).ok_or_else(|| protocol_error("missing remote key"))
+    }
+
+    fn dh_ephemeral(&self, remote: Option<[u8; DH_LEN]>) -> Result<Vec<u8>> {
+        let shared = self.local_ephemeral()?.diffie_hellman(&Self::remote(remote)?);
+        if !shared.was_contributory() {
+            return Err(protocol_error("remote key has low order"));
+        }
+        Ok(shared.as_bytes().to_vec())
+    }
+
+    fn dh_static(&self, remote: Option<[u8; DH_LEN]>) -> Result<Vec<u8>> {
+        self.local_static()?.diffie_hellman(Self::remote(remote)?.as_bytes())
+    }
+
+    /// Mixes the DH for a two-letter token. The first letter is the
+    /// initiator's key, the second the responder's.
+    fn mix_dh(&mut self, token: Token) -> Result<()> {
+        let (re, rs) = (self.remote_ephemeral, self.remote_static);
+        let shared = match (token, self.initiator) {
+            (Token::Ee, _) => self.dh_ephemeral(re)?,
+            (Token::Es, true) | (Token::Se, false) => self.dh_ephemeral(rs)?,
+            (Token::Es, false) | (Token::Se, true) => self.dh_static(re)?,
+            (Token::Ss, _) => self.dh_static(rs)?,
+            (Token::E | Token::S, _) => unreachable!("not a DH token"),
+        };
+        self.symmetric.mix_key(&shared);
+        Ok(())
+    }
+
+    /// Produces the next handshake message carrying `payload`.
+    pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let tokens = self.check_turn(true)?;
+        let mut message = Vec::new();
+        for &token in tokens {
+            match token {
+                Token::E => {
+                    let ephemeral = StaticSecret::random_from_rng(ElasticRng::new()?);
+                    let public = PublicKey::from(&ephemeral);
+                    message.extend_from_slice(public.as_bytes());
+                    self.symmetric.mix_hash(public.as_bytes());
+                    self.local_ephemeral = Some(ephemeral);
+                }
+                Token::S => {
+                    let public = self.local_static()?.public_key()?;
+                    let encrypted = self.symmetric.encrypt_and_hash(&public)?;
+                    message.extend_from_slice(&encrypted);
+                }
+                _ => self.mix_dh(token)?,
+            }
+        }
+        message.extend_from_slice(&self.symmetric.encrypt_and_hash(payload)?);
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(protocol_error("message too long"));
+        }
+        self.message_index += 1;
+        Ok(message)
+    }
+
+    /// Processes the peer's next handshake message and returns its payload.
+    pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(protocol_error("message too long"));
+        }
+        let tokens = self.check_turn(false)?;
+        let mut rest = message;
+        let mut take = |len: usize| -> Result<&[u8]> {
+            if rest.len() < len {
+                return Err(protocol_error("handshake message truncated"));
+            }
+            let (head, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(head)
+        };
+        for &token in tokens {
+            match token {
+                Token::E => {
+                    let remote: [u8; DH_LEN] = take(DH_LEN)?.try_into().expect("length checked");
+                    self.symmetric.mix_hash(&remote);
+                    self.remote_ephemeral = Some(remote);
+                }
+                Token::S => {
+                    let len = if self.symmetric.cipher.key.is_some() { DH_LEN + TAG_LEN } else { DH_LEN };
+                    let remote = self.symmetric.decrypt_and_hash(take(len)?)?;
+                    self.remote_static = Some(remote.try_into().expect("length checked"));
+                }
+                _ => self.mix_dh(token)?,
+            }
+        }
+        let payload = self.symmetric.decrypt_and_hash(rest)?;
+        self.message_index += 1;
+        Ok(payload)
+    }
+
+    /// Whether all handshake messages have been processed.
+    pub fn is_finished(&self) -> bool {
+        self.message_index == self.pattern.messages().len()
+    }
+
+    /// The peer's static public key, once known.
+    pub fn remote_static(&self) -> Option<Vec<u8>> {
+        self.remote_static.map(|key| key.to_vec())
+    }
+
+    /// Handshake hash, usable for channel binding.
+    pub fn handshake_hash(&self) -> Vec<u8> {
+        self.symmetric.hash.to_vec()
+    }
+
+    /// Finishes the handshake and returns the `(send, receive)` cipher states
+    /// for this side.
+    pub fn split(self) -> Result<(NoiseCipherState, NoiseCipherState)> {
+        if !self.is_finished() {
+            return Err(protocol_error("handshake not finished"));
+        }
+        let (initiator_to_responder, responder_to_initiator) = self.symmetric.split();
+        Ok(if self.initiator {
+            (initiator_to_responder, responder_to_initiator)
+        } else {
+            (responder_to_initiator, initiator_to_responder)
+        })
+    }
+}
+
+impl ElasticCrypto {
+    fn noise_static(&self, handle: Option<u32>) -> Result<Option<Arc<Key>>> {
+        handle.map(|handle| self.get_key(handle)).transpose()
+    }
+
+    /// Starts a handshake as initiator.
+    ///
+    /// `static_handle` is an X25519 key, required for XX and IK and unused for
+    /// NK. `responder_static` is the responder's public key, required for IK
+    /// and NK.
+    pub fn noise_initiator(
+        &self,
+        pattern: NoisePattern,
+        suite: NoiseSuite,
+        static_handle: Option<u32>,
+        responder_static: Option<Vec<u8>>,
+        prologue: Vec<u8>,
+    ) -> Result<NoiseHandshake> {
+        self.self_test.check()?;
+        NoiseHandshake::new(pattern, suite, true, self.noise_static(static_handle)?, responder_static, &prologue)
+    }
+
+    /// Starts a handshake as responder with an X25519 static key.
+    pub fn noise_responder(
+        &self,
+        pattern: NoisePattern,
+        suite: NoiseSuite,
+        static_handle: u32,
+        prologue: Vec<u8>,
+    ) -> Result<NoiseHandshake> {
+        self.self_test.check()?;
+        NoiseHandshake::new(pattern, suite, false, self.noise_static(Some(static_handle))?, None, &prologue)
+    }
+}
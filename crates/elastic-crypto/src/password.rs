@@ -0,0 +1,101 @@
+//! Password hashing with PHC-format strings.
+//!
+//! New hashes should use Argon2id. scrypt and PBKDF2 are supported so that
+//! password databases migrated into the CVM keep verifying.
+
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Version};
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+
+/// Algorithm and cost parameters for new password hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordParams {
+    /// Argon2id (RFC 9106) with memory cost in KiB, iteration count and lanes.
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    /// scrypt with CPU/memory cost `2^log_n`, block size `r` and parallelism `p`.
+    Scrypt { log_n: u8, r: u32, p: u32 },
+    /// PBKDF2-HMAC-SHA256 with the given iteration count.
+    Pbkdf2Sha256 { iterations: u32 },
+}
+
+impl Default for PasswordParams {
+    /// Argon2id with the OWASP-recommended minimum cost (19 MiB, 2 passes, 1 lane).
+    fn default() -> Self {
+        PasswordParams::Argon2id {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Hashes `password` with a fresh random salt and returns a PHC string.
+pub fn password_hash(password: &[u8], params: &PasswordParams) -> Result<String> {
+    let mut rng = ElasticRng::new()?;
+    let salt = SaltString::generate(&mut rng);
+    let invalid = |e: argon2::password_hash::Error| Error::InvalidPasswordParams(e.to_string());
+
+    let hash = match *params {
+        PasswordParams::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            let params = argon2::Params::new(memory_kib, iterations, parallelism, None)
+                .map_err(|e| Error::InvalidPasswordParams(e.to_string()))?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password(password, &salt)
+                .map_err(invalid)?
+        }
+        PasswordParams::Scrypt { log_n, r, p } => {
+            let params = scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+                .map_err(|e| Error::InvalidPasswordParams(e.to_string()))?;
+            Scrypt
+                .hash_password_customized(password, None, None, params, &salt)
+                .map_err(invalid)?
+        }
+        PasswordParams::Pbkdf2Sha256 { iterations } => {
+            let params = pbkdf2::Params {
+                rounds: iterations,
+                output_length: 32,
+            };
+            Pbkdf2
+                .hash_password_customized(password, Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()), None, params, &salt)
+                .map_err(invalid)?
+        }
+    };
+    Ok(hash.to_string())
+}
+
+/// Checks `password` against a PHC string produced by Argon2, scrypt or PBKDF2.
+///
+/// Returns `Ok(false)` for a wrong password and an error for a malformed or
+/// unsupported hash string.
+pub fn password_verify(password: &[u8], phc: &str) -> Result<bool> {
+    let hash = PasswordHash::new(phc).map_err(|e| Error::InvalidPasswordHash(e.to_string()))?;
+    let verifiers: [&dyn PasswordVerifier; 3] = [&Argon2::default(), &Scrypt, &Pbkdf2];
+    match hash.verify_password(&verifiers, password) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(Error::InvalidPasswordHash(e.to_string())),
+    }
+}
+
+impl ElasticCrypto {
+    pub fn password_hash(&self, password: Vec<u8>, params: PasswordParams) -> Result<String> {
+        self.self_test.check()?;
+        password_hash(&password, &params)
+    }
+
+    pub fn password_verify(&self, password: Vec<u8>, phc: &str) -> Result<bool> {
+        self.self_test.check()?;
+        password_verify(&password, phc)
+    }
+}
@@ -0,0 +1,219 @@
+//! Random number generation backed by the platform entropy source.
+//!
+//! `ElasticRng` runs an HMAC-DRBG (NIST SP 800-90A, SHA-256) that is seeded and
+//! periodically reseeded from the SEV-SNP RNG when SEV-SNP mode is enabled, and
+//! from the operating system RNG otherwise.
+
+use crate::{Error, Result};
+use hmac::{Hmac, Mac};
+use rand_core::{CryptoRng, RngCore};
+use sha2::Sha256;
+#[cfg(feature = "sevsnp")]
+use std::env;
+
+#[cfg(feature = "sevsnp")]
+use crate::sev::SevsnpRng;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const OUT_LEN: usize = 32;
+const ENTROPY_LEN: usize = 32;
+const NONCE_LEN: usize = 16;
+const PERSONALIZATION: &[u8] = b"elastic-crypto ElasticRng";
+
+/// Number of generate calls allowed before the DRBG must be reseeded.
+const RESEED_INTERVAL: u64 = 1 << 20;
+
+/// Largest request served by a single generate call (2^19 bits).
+const MAX_BYTES_PER_REQUEST: usize = 1 << 16;
+
+/// HMAC-DRBG with SHA-256 as specified in NIST SP 800-90A section 10.1.2.
+pub struct HmacDrbg {
+    key: [u8; OUT_LEN],
+    v: [u8; OUT_LEN],
+    reseed_counter: u64,
+}
+
+impl HmacDrbg {
+    pub fn new(entropy: &[u8], nonce: &[u8], personalization: &[u8]) -> Self {
+        let mut drbg = Self {
+            key: [0x00; OUT_LEN],
+            v: [0x01; OUT_LEN],
+            reseed_counter: 1,
+        };
+        drbg.update(&[entropy, nonce, personalization]);
+        drbg
+    }
+
+    pub fn reseed(&mut self, entropy: &[u8], additional_input: &[u8]) {
+        self.update(&[entropy, additional_input]);
+        self.reseed_counter = 1;
+    }
+
+    pub fn needs_reseed(&self) -> bool {
+        self.reseed_counter > RESEED_INTERVAL
+    }
+
+    /// Fills `out` with pseudo-random bytes. Fails once the reseed interval is exhausted.
+    pub fn generate(&mut self, out: &mut [u8], additional_input: &[u8]) -> Result<()> {
+        if out.len() > MAX_BYTES_PER_REQUEST {
+            return Err(Error::RngError("Request exceeds DRBG output limit".to_string()));
+        }
+        if self.needs_reseed() {
+            return Err(Error::RngError("DRBG reseed required".to_string()));
+        }
+        if !additional_input.is_empty() {
+            self.update(&[additional_input]);
+        }
+        for chunk in out.chunks_mut(OUT_LEN) {
+            self.v = self.hmac(&[&self.v]);
+            chunk.copy_from_slice(&self.v[..chunk.len()]);
+        }
+        self.update(&[additional_input]);
+        self.reseed_counter += 1;
+        Ok(())
+    }
+
+    fn update(&mut self, provided: &[&[u8]]) {
+        let has_data = provided.iter().any(|part| !part.is_empty());
+        self.key = self.hmac(&[&self.v, &[0x00], &provided.concat()]);
+        self.v = self.hmac(&[&self.v]);
+        if has_data {
+            self.key = self.hmac(&[&self.v, &[0x01], &provided.concat()]);
+            self.v = self.hmac(&[&self.v]);
+        }
+    }
+
+    fn hmac(&self, parts: &[&[u8]]) -> [u8; OUT_LEN] {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.key)
+            .expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes().into()
+    }
+}
+
+enum EntropySource {
+    Os,
+    #[cfg(feature = "sevsnp")]
+    Sevsnp(SevsnpRng),
+}
+
+impl EntropySource {
+    fn fill(&mut self, dest: &mut [u8]) -> Result<()> {
+        match self {
+            EntropySource::Os => getrandom::getrandom(dest)
+                .map_err(|e| Error::RngError(e.to_string())),
+            #[cfg(feature = "sevsnp")]
+            EntropySource::Sevsnp(rng) => rng
+                .try_fill_bytes(dest)
+                .map_err(|e| Error::SevSnpRngError(e.to_string())),
+        }
+    }
+}
+
+/// Cryptographically secure RNG used by ELASTIC components.
+pub struct ElasticRng {
+    drbg: HmacDrbg,
+    source: EntropySource,
+}
+
+impl ElasticRng {
+    /// Largest buffer `get_random_bytes` will allocate.
+    pub const MAX_RANDOM_BYTES: usize = 1 << 20;
+
+    pub fn new() -> Result<Self> {
+        let mut source = Self::select_source();
+        let mut entropy = [0u8; ENTROPY_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        source.fill(&mut entropy)?;
+        source.fill(&mut nonce)?;
+        Ok(Self {
+            drbg: HmacDrbg::new(&entropy, &nonce, PERSONALIZATION),
+            source,
+        })
+    }
+
+    #[cfg(feature = "sevsnp")]
+    fn select_source() -> EntropySource {
+        let is_sevsnp = env::var("ELASTIC_SEV_SNP").unwrap_or_default() == "1";
+        if is_sevsnp {
+            if let Ok(rng) = SevsnpRng::new() {
+                return EntropySource::Sevsnp(rng);
+            }
+        }
+        EntropySource::Os
+    }
+
+    #[cfg(not(feature = "sevsnp"))]
+    fn select_source() -> EntropySource {
+        EntropySource::Os
+    }
+
+    /// Returns true when entropy is drawn from the SEV-SNP RNG.
+    pub fn is_sevsnp(&self) -> bool {
+        match self.source {
+            EntropySource::Os => false,
+            #[cfg(feature = "sevsnp")]
+            EntropySource::Sevsnp(_) => true,
+        }
+    }
+
+    /// Pulls fresh entropy from the platform source into the DRBG.
+    pub fn reseed(&mut self) -> Result<()> {
+        let mut entropy = [0u8; ENTROPY_LEN];
+        self.source.fill(&mut entropy)?;
+        self.drbg.reseed(&entropy, &[]);
+        Ok(())
+    }
+
+    pub fn fill(&mut self, dest: &mut [u8]) -> Result<()> {
+        for chunk in dest.chunks_mut(MAX_BYTES_PER_REQUEST) {
+            if self.drbg.needs_reseed() {
+                self.reseed()?;
+            }
+            self.drbg.generate(chunk, &[])?;
+        }
+        Ok(())
+    }
+
+    pub fn get_random_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        if len > Self::MAX_RANDOM_BYTES {
+            return Err(Error::RngError("Requested length too large".to_string()));
+        }
+        let mut bytes = vec![0u8; len];
+        self.fill(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn get_random_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0u8; 8];
+        self.fill(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl RngCore for ElasticRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).unwrap();
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
+        self.fill(dest).map_err(rand_core::Error::new)
+    }
+}
+
+impl CryptoRng for ElasticRng {}
@@ -0,0 +1,280 @@
+//! Power-on known-answer self-tests.
+//!
+//! Every crypto context runs these tests when it is created. A context whose
+//! report contains a failure enters an error state and refuses all operations
+//! with `Error::SelfTestFailed`.
+//!
+//! Setting `ELASTIC_SELFTEST_FAULT` to a comma-separated list of algorithm names
+//! (for example `aes-256-gcm,hmac-drbg`) corrupts the computed output of those
+//! tests, so the error state can be demonstrated during certification.
+
+use crate::rng::HmacDrbg;
+use crate::{Error, Result};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use ed25519_dalek::{Signer as _, Verifier as _};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use std::env;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FAULT_ENV: &str = "ELASTIC_SELFTEST_FAULT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestAlgorithm {
+    Aes256Gcm,
+    Sha256,
+    Sha512,
+    HmacSha256,
+    HkdfSha256,
+    Ed25519,
+    EcdsaP256,
+    HmacDrbg,
+}
+
+impl SelfTestAlgorithm {
+    pub const ALL: [SelfTestAlgorithm; 8] = [
+        SelfTestAlgorithm::Aes256Gcm,
+        SelfTestAlgorithm::Sha256,
+        SelfTestAlgorithm::Sha512,
+        SelfTestAlgorithm::HmacSha256,
+        SelfTestAlgorithm::HkdfSha256,
+        SelfTestAlgorithm::Ed25519,
+        SelfTestAlgorithm::EcdsaP256,
+        SelfTestAlgorithm::HmacDrbg,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelfTestAlgorithm::Aes256Gcm => "aes-256-gcm",
+            SelfTestAlgorithm::Sha256 => "sha-256",
+            SelfTestAlgorithm::Sha512 => "sha-512",
+            SelfTestAlgorithm::HmacSha256 => "hmac-sha-256",
+            SelfTestAlgorithm::HkdfSha256 => "hkdf-sha-256",
+            SelfTestAlgorithm::Ed25519 => "ed25519",
+            SelfTestAlgorithm::EcdsaP256 => "ecdsa-p256-sha-256",
+            SelfTestAlgorithm::HmacDrbg => "hmac-drbg-sha-256",
+        }
+    }
+
+    /// Source of the known-answer vector, quoted in the report for auditors.
+    pub fn vector_source(&self) -> &'static str {
+        match self {
+            SelfTestAlgorithm::Aes256Gcm => "GCM specification, test case 15",
+            SelfTestAlgorithm::Sha256 => "FIPS 180-2, appendix B.1",
+            SelfTestAlgorithm::Sha512 => "FIPS 180-2, appendix C.1",
+            SelfTestAlgorithm::HmacSha256 => "RFC 4231, test case 2",
+            SelfTestAlgorithm::HkdfSha256 => "RFC 5869, test case 1",
+            SelfTestAlgorithm::Ed25519 => "RFC 8032, section 7.1, test 1",
+            SelfTestAlgorithm::EcdsaP256 => "RFC 6979, appendix A.2.5",
+            SelfTestAlgorithm::HmacDrbg => "NIST CAVP HMAC_DRBG SHA-256, count 0",
+        }
+    }
+}
+
+impl fmt::Display for SelfTestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTestResult {
+    pub algorithm: SelfTestAlgorithm,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+/// Outcome of one power-on self-test run.
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    /// Version of the elastic-crypto build that produced the report.
+    pub module_version: &'static str,
+    /// Seconds since the UNIX epoch when the run finished.
+    pub completed_at: u64,
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+
+    pub fn failures(&self) -> Vec<&SelfTestResult> {
+        self.results.iter().filter(|result| !result.passed).collect()
+    }
+
+    /// Returns `Error::SelfTestFailed` naming the failed algorithms, if any.
+    pub fn check(&self) -> Result<()> {
+        if self.passed() {
+            return Ok(());
+        }
+        let failed: Vec<&str> = self.failures().iter().map(|r| r.algorithm.name()).collect();
+        Err(Error::SelfTestFailed(failed.join(", ")))
+    }
+}
+
+/// Runs every known-answer test and collects the results.
+pub fn run_self_tests() -> SelfTestReport {
+    let faults = env::var(FAULT_ENV).unwrap_or_default();
+    let faults: Vec<&str> = faults.split(',').map(str::trim).collect();
+
+    let results = SelfTestAlgorithm::ALL
+        .iter()
+        .map(|&algorithm| {
+            let inject_fault = faults.contains(&algorithm.name());
+            let outcome = run_known_answer_test(algorithm, inject_fault);
+            SelfTestResult {
+                algorithm,
+                passed: outcome.is_ok(),
+                error: outcome.err(),
+            }
+        })
+        .collect();
+
+    SelfTestReport {
+        module_version: env!("CARGO_PKG_VERSION"),
+        completed_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        results,
+    }
+}
+
+fn run_known_answer_test(algorithm: SelfTestAlgorithm, inject_fault: bool) -> std::result::Result<(), String> {
+    let (mut actual, expected) = match algorithm {
+        SelfTestAlgorithm::Aes256Gcm => aes_gcm_kat()?,
+        SelfTestAlgorithm::Sha256 => (
+            Sha256::digest(b"abc").to_vec(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        SelfTestAlgorithm::Sha512 => (
+            Sha512::digest(b"abc").to_vec(),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ),
+        SelfTestAlgorithm::HmacSha256 => hmac_kat()?,
+        SelfTestAlgorithm::HkdfSha256 => hkdf_kat()?,
+        SelfTestAlgorithm::Ed25519 => ed25519_kat()?,
+        SelfTestAlgorithm::EcdsaP256 => ecdsa_p256_kat()?,
+        SelfTestAlgorithm::HmacDrbg => drbg_kat()?,
+    };
+
+    if inject_fault {
+        if let Some(byte) = actual.first_mut() {
+            *byte ^= 0x01;
+        }
+    }
+
+    if actual != unhex(expected) {
+        return Err(format!("{} known-answer test mismatch", algorithm));
+    }
+    Ok(())
+}
+
+type KatOutput = std::result::Result<(Vec<u8>, &'static str), String>;
+
+fn unhex(data: &str) -> Vec<u8> {
+    hex::decode(data).expect("self-test vectors are valid hex")
+}
+
+fn aes_gcm_kat() -> KatOutput {
+    let key = unhex("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308");
+    let iv = unhex("cafebabefacedbaddecaf888");
+    let plaintext = unhex(
+        "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+         1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+    );
+    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| e.to_string())?;
+    let sealed = cipher
+        .encrypt(Nonce::from_slice(&iv), Payload { msg: &plaintext, aad: &[] })
+        .map_err(|e| e.to_string())?;
+    let opened = cipher
+        .decrypt(Nonce::from_slice(&iv), Payload { msg: &sealed, aad: &[] })
+        .map_err(|e| e.to_string())?;
+    if opened != plaintext {
+        return Err("aes-256-gcm round trip mismatch".to_string());
+    }
+    Ok((
+        sealed,
+        "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+         8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad\
+         b094dac5d93471bdec1a502270e3cc6c",
+    ))
+}
+
+fn hmac_kat() -> KatOutput {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(b"Jefe").map_err(|e| e.to_string())?;
+    mac.update(b"what do ya want for nothing?");
+    Ok((
+        mac.finalize().into_bytes().to_vec(),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+    ))
+}
+
+fn hkdf_kat() -> KatOutput {
+    let ikm = [0x0bu8; 22];
+    let salt = unhex("000102030405060708090a0b0c");
+    let info = unhex("f0f1f2f3f4f5f6f7f8f9");
+    let mut okm = [0u8; 42];
+    hkdf::Hkdf::<Sha256>::new(Some(&salt), &ikm)
+        .expand(&info, &mut okm)
+        .map_err(|e| e.to_string())?;
+    Ok((
+        okm.to_vec(),
+        "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865",
+    ))
+}
+
+fn ed25519_kat() -> KatOutput {
+    let secret: [u8; 32] = unhex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+        .try_into()
+        .map_err(|_| "invalid ed25519 vector".to_string())?;
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&secret);
+    let signature = signing_key.sign(b"");
+    signing_key
+        .verifying_key()
+        .verify(b"", &signature)
+        .map_err(|e| e.to_string())?;
+    Ok((
+        signature.to_bytes().to_vec(),
+        "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555\
+         fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+    ))
+}
+
+fn ecdsa_p256_kat() -> KatOutput {
+    use p256::ecdsa::signature::{Signer, Verifier};
+    use p256::ecdsa::{Signature, SigningKey};
+
+    let secret = unhex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+    let signing_key = SigningKey::from_slice(&secret).map_err(|e| e.to_string())?;
+    let signature: Signature = signing_key.sign(b"sample");
+    signing_key
+        .verifying_key()
+        .verify(b"sample", &signature)
+        .map_err(|e| e.to_string())?;
+    Ok((
+        signature.to_bytes().to_vec(),
+        "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716\
+         f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8",
+    ))
+}
+
+fn drbg_kat() -> KatOutput {
+    let entropy = unhex("ca851911349384bffe89de1cbdc46e6831e44d34a4fb935ee285dd14b71a7488");
+    let nonce = unhex("659ba96c601dc69fc902940805ec0ca8");
+    let mut drbg = HmacDrbg::new(&entropy, &nonce, &[]);
+    let mut output = [0u8; 128];
+    drbg.generate(&mut output, &[]).map_err(|e| e.to_string())?;
+    drbg.generate(&mut output, &[]).map_err(|e| e.to_string())?;
+    Ok((
+        output.to_vec(),
+        "e528e9abf2dece54d47c7e75e5fe302149f817ea9fb4bee6f4199697d04d5b89\
+         d54fbb978a15b5c443c9ec21036d2460b6f73ebad0dc2aba6e624abf07745bc1\
+         07694bb7547bb0995f70de25d6b29e2d3011bb19d27676c07162c8b5ccde0668\
+         961df86803482cb37ed6d5c0bb8d50cf1f50d476aa0458bdaba806f48be9dcb8",
+    ))
+}
@@ -7,17 +7,17 @@ use aes_gcm::{
 use crate::Error;
 use std::fmt;
 use rand::{RngCore};
-use rand::rngs::ThreadRng;
+use rand::rngs::OsRng;
 
 // SEV-SNP specific implementation
 #[derive(Debug, Clone)]
 pub struct SevsnpRng {
-    rng: ThreadRng,
+    rng: OsRng,
 }
 
 impl SevsnpRng {
     pub fn new() -> Result<Self, Error> {
-        Ok(Self { rng: rand::thread_rng() })
+        Ok(Self { rng: OsRng })
     }
 
     pub fn get_random_bytes(&mut self, len: usize) -> Result<Vec<u8>, Error> {
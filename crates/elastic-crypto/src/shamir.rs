@@ -0,0 +1,264 @@
+//! Shamir secret sharing of keys for split backup and recovery.
+//!
+//! Key material is split byte-wise over GF(2^8). Each share is sealed as an
+//! ECDH-ES JWE to a custodian's public P-256 JWK, so neither the shares nor the
+//! key appear in plaintext outside a key store:
+//!
+//! - the host registers the custodians with
+//!   [`ElasticCrypto::register_custodian`], and shares are only ever
+//!   encrypted to registered keys;
+//! - custodians whose keys live in another context move their share to the
+//!   recovery context with [`ElasticCrypto::rewrap_key_share`];
+//! - [`ElasticCrypto::recover_key`] opens the shares with the matching keys in
+//!   its own store and imports the rebuilt key, returning only a handle.
+//!
+//! Every share records the id and algorithm of the original key, which are
+//! checked to catch shares from different splits or a wrong `KeyConfig`.
+
+use crate::jose::{jwe_encrypt_to_jwk, jwe_key_id, Jwk};
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, KeyConfig, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use zeroize::{Zeroize, Zeroizing};
+
+const SHARE_VERSION: u8 = 1;
+
+/// Plaintext of one share, as carried inside its JWE.
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyShare {
+    version: u8,
+    /// Id of the split key, base64url.
+    key_id: String,
+    algorithm: KeyAlgorithm,
+    threshold: u8,
+    /// x coordinate, 1..=255.
+    index: u8,
+    /// y values for each key byte, base64url.
+    share: String,
+}
+
+impl Drop for KeyShare {
+    fn drop(&mut self) {
+        self.share.zeroize();
+    }
+}
+
+/// Multiplication in GF(2^8) with the AES polynomial, without data-dependent branches.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse (a^254); `a` must be non-zero.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Splits `secret` into `count` shares, any `threshold` of which rebuild it.
+/// Share `i` is evaluated at x = i + 1.
+fn split(secret: &[u8], threshold: u8, count: u8, rng: &mut ElasticRng) -> Result<Vec<Zeroizing<Vec<u8>>>> {
+    let mut shares = vec![Zeroizing::new(vec![0u8; secret.len()]); count as usize];
+    let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize - 1]);
+    for (position, &byte) in secret.iter().enumerate() {
+        rng.fill(&mut coefficients)?;
+        for (i, share) in shares.iter_mut().enumerate() {
+            let x = i as u8 + 1;
+            // Horner's rule, highest coefficient first.
+            let y = coefficients.iter().rev().fold(0u8, |acc, &c| gf_mul(acc, x) ^ c);
+            share[position] = gf_mul(y, x) ^ byte;
+        }
+    }
+    Ok(shares)
+}
+
+/// Lagrange interpolation at x = 0 over `(x, y-bytes)` points with distinct
+/// non-zero x.
+fn combine(points: &[(u8, Zeroizing<Vec<u8>>)]) -> Zeroizing<Vec<u8>> {
+    let len = points[0].1.len();
+    let mut secret = Zeroizing::new(vec![0u8; len]);
+    for (j, (xj, yj)) in points.iter().enumerate() {
+        // l_j(0) = prod_{m != j} x_m / (x_m - x_j); subtraction is XOR.
+        let mut basis = 1u8;
+        for (m, (xm, _)) in points.iter().enumerate() {
+            if m != j {
+                basis = gf_mul(basis, gf_mul(*xm, gf_inv(xm ^ xj)));
+            }
+        }
+        for (out, &y) in secret.iter_mut().zip(yj.iter()) {
+            *out ^= gf_mul(y, basis);
+        }
+    }
+    secret
+}
+
+fn share_error(message: impl ToString) -> Error {
+    Error::InvalidKeyShare(message.to_string())
+}
+
+/// Public point of a custodian JWK that shares can be encrypted to.
+fn custodian_point(custodian_jwk: &str) -> Result<Vec<u8>> {
+    match Jwk::from_json(custodian_jwk)?.public_key()? {
+        (KeyAlgorithm::EcdhP256 | KeyAlgorithm::EcdsaP256, point) => Ok(point),
+        _ => Err(Error::UnsupportedOperation),
+    }
+}
+
+impl ElasticCrypto {
+    /// Finds the key a share was encrypted to by the JWE `kid`.
+    fn share_recipient(&self, share: &str) -> Result<Arc<Key>> {
+        let kid = jwe_key_id(share)?.ok_or_else(|| share_error("share does not name its custodian key"))?;
+        for key in self.store.keys.load().values() {
+            if key.owner == self.workload && key.key_id().is_ok_and(|id| id == kid) {
+                return Ok(Arc::clone(key));
+            }
+        }
+        Err(Error::KeyNotFound)
+    }
+
+    /// Fails unless `custodian_jwk` was registered by the host.
+    fn check_custodian(&self, custodian_jwk: &str) -> Result<()> {
+        let point = custodian_point(custodian_jwk)?;
+        let custodians = self.store.custodians.read().map_err(|_| Error::OperationNotPermitted)?;
+        if !custodians.contains(&point) {
+            return Err(share_error("custodian key is not registered"));
+        }
+        Ok(())
+    }
+
+    /// Registers a custodian's public ECDH P-256 JWK, e.g. from
+    /// [`ElasticCrypto::get_public_jwk`]. Host only.
+    ///
+    /// Registrations are shared by all contexts on this key store.
+    pub fn register_custodian(&self, custodian_jwk: &str) -> Result<()> {
+        self.self_test.check()?;
+        self.check_host("register_custodian")?;
+        let point = custodian_point(custodian_jwk)?;
+        self.store.custodians.write().map_err(|_| Error::OperationNotPermitted)?.insert(point);
+        Ok(())
+    }
+
+    /// Splits a key into one share per custodian, any `threshold` of which
+    /// can rebuild it.
+    ///
+    /// `custodian_keys` are public ECDH P-256 JWKs registered with
+    /// [`ElasticCrypto::register_custodian`]; each returned share is a compact
+    /// JWE to the custodian at the same position. Works for `secure_storage`
+    /// keys, since the key only leaves the store encrypted.
+    pub fn split_key(&self, handle: u32, threshold: u8, custodian_keys: Vec<String>) -> Result<Vec<String>> {
+        self.self_test.check()?;
+        let count = u8::try_from(custodian_keys.len()).map_err(|_| share_error("at most 255 shares are supported"))?;
+        if threshold < 2 || threshold > count {
+            return Err(share_error(format!(
+                "threshold must be between 2 and the number of custodians ({}), got {}",
+                count, threshold
+            )));
+        }
+
+        for custodian in &custodian_keys {
+            self.check_custodian(custodian)?;
+        }
+
+        let key = self.get_key(handle)?;
+        let key_id = URL_SAFE_NO_PAD.encode(key.key_id()?);
+        let shares = split(&key.data, threshold, count, &mut ElasticRng::new()?)?;
+
+        shares
+            .into_iter()
+            .zip(&custodian_keys)
+            .enumerate()
+            .map(|(i, (share, custodian))| {
+                let share = KeyShare {
+                    version: SHARE_VERSION,
+                    key_id: key_id.clone(),
+                    algorithm: key.algorithm,
+                    threshold,
+                    index: i as u8 + 1,
+                    share: URL_SAFE_NO_PAD.encode(&share),
+                };
+                let plaintext = Zeroizing::new(
+                    serde_json::to_vec(&share).map_err(|e| Error::InvalidEncoding(e.to_string()))?,
+                );
+                jwe_encrypt_to_jwk(custodian, &plaintext)
+            })
+            .collect()
+    }
+
+    /// Re-encrypts a share held by the custodian key `handle` to another
+    /// registered custodian, such as the recovery context's key, without
+    /// revealing it.
+    pub fn rewrap_key_share(&self, handle: u32, share: &str, recipient_jwk: &str) -> Result<String> {
+        self.self_test.check()?;
+        self.check_custodian(recipient_jwk)?;
+        let plaintext = Zeroizing::new(self.get_key(handle)?.jwe_decrypt(share)?);
+        jwe_encrypt_to_jwk(recipient_jwk, &plaintext)
+    }
+
+    /// Rebuilds a split key inside this key store and returns its handle.
+    ///
+    /// Each share must be encrypted to a key held in this context. `config`
+    /// must describe the original key; `secure_storage` applies to the
+    /// recovered key as usual.
+    pub fn recover_key(&self, shares: Vec<String>, config: KeyConfig) -> Result<u32> {
+        self.self_test.check()?;
+        let mut opened = Vec::with_capacity(shares.len());
+        for share in &shares {
+            let plaintext = Zeroizing::new(self.share_recipient(share)?.jwe_decrypt(share)?);
+            let share: KeyShare = serde_json::from_slice(&plaintext).map_err(|e| share_error(e.to_string()))?;
+            if share.version != SHARE_VERSION {
+                return Err(share_error(format!("unsupported share version {}", share.version)));
+            }
+            opened.push(share);
+        }
+
+        let first = opened.first().ok_or_else(|| share_error("no shares given"))?;
+        let (key_id, threshold, algorithm) = (first.key_id.clone(), first.threshold, first.algorithm);
+        if opened.iter().any(|s| s.key_id != key_id || s.threshold != threshold) {
+            return Err(share_error("shares belong to different splits"));
+        }
+        if config.algorithm() != algorithm {
+            return Err(share_error(format!("shares are for a {:?} key", algorithm)));
+        }
+        let mut seen = HashSet::new();
+        let mut points = Vec::new();
+        for share in &opened {
+            if share.index == 0 || !seen.insert(share.index) {
+                return Err(share_error(format!("invalid or repeated share index {}", share.index)));
+            }
+            let y = Zeroizing::new(URL_SAFE_NO_PAD.decode(&share.share).map_err(|e| share_error(e.to_string()))?);
+            points.push((share.index, y));
+        }
+        if points.len() < threshold as usize {
+            return Err(share_error(format!("{} of {} required shares given", points.len(), threshold)));
+        }
+        points.truncate(threshold as usize);
+        if points.iter().any(|(_, y)| y.len() != points[0].1.len()) {
+            return Err(share_error("shares have different lengths"));
+        }
+
+        let key = Key::new(combine(&points).to_vec(), config)?;
+        if URL_SAFE_NO_PAD.encode(key.key_id()?) != key_id {
+            return Err(share_error("reconstructed key does not match the split key"));
+        }
+        Ok(self.insert_key(key))
+    }
+}
@@ -0,0 +1,292 @@
+//! Signing and MAC operations on key handles.
+//!
+//! Ed25519 signatures are 64 bytes, ECDSA P-256 signatures are the fixed-size
+//! `r || s` encoding (64 bytes) used by COSE and JOSE, and RSA-PSS uses a salt
+//! as long as the SHA-256 digest. Public keys are exported as the raw 32-byte
+//! Ed25519 or X25519 point, an uncompressed SEC1 P-256 point, or a DER
+//! SubjectPublicKeyInfo for RSA. RSA private keys are held as PKCS#1 DER.
+
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, KeyConfig, Result};
+use ed25519_dalek::{Signer, Verifier};
+use hmac::{Hmac, Mac};
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey};
+use rsa::pkcs8::{DecodePublicKey, EncodePublicKey};
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the key identifiers returned by [`ElasticCrypto::get_key_id`].
+pub const KEY_ID_LEN: usize = 16;
+
+/// Smallest RSA modulus accepted for new and imported keys.
+pub const MIN_RSA_BITS: usize = 2048;
+
+fn rsa_private_key(data: &[u8]) -> Result<RsaPrivateKey> {
+    let key = RsaPrivateKey::from_pkcs1_der(data).map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+    if key.size() * 8 < MIN_RSA_BITS {
+        return Err(Error::InvalidKeyLength);
+    }
+    Ok(key)
+}
+
+fn p256_secret_key(data: &[u8]) -> Result<p256::SecretKey> {
+    p256::SecretKey::from_slice(data).map_err(|_| Error::InvalidKeyLength)
+}
+
+/// Checks that `data` is valid private key material for `algorithm`.
+pub(crate) fn check_key(algorithm: KeyAlgorithm, data: &[u8]) -> Result<()> {
+    match algorithm {
+        KeyAlgorithm::Ed25519 | KeyAlgorithm::X25519 if data.len() != ed25519_dalek::SECRET_KEY_LENGTH => {
+            Err(Error::InvalidKeyLength)
+        }
+        KeyAlgorithm::EcdsaP256 | KeyAlgorithm::EcdhP256 => p256_secret_key(data).map(|_| ()),
+        KeyAlgorithm::RsaPssSha256 => rsa_private_key(data).map(|_| ()),
+        KeyAlgorithm::HmacSha256 if data.is_empty() => Err(Error::InvalidKeyLength),
+        _ => Ok(()),
+    }
+}
+
+/// Generates fresh private key material for `algorithm`.
+pub(crate) fn generate_key_data(config: &KeyConfig) -> Result<Vec<u8>> {
+    let mut rng = ElasticRng::new()?;
+    match config.algorithm() {
+        KeyAlgorithm::EcdsaP256 | KeyAlgorithm::EcdhP256 => {
+            Ok(p256::SecretKey::random(&mut rng).to_bytes().to_vec())
+        }
+        KeyAlgorithm::RsaPssSha256 => {
+            let bits = config.key_size as usize;
+            if bits < MIN_RSA_BITS {
+                return Err(Error::InvalidKeyLength);
+            }
+            let key = RsaPrivateKey::new(&mut rng, bits).map_err(|e| Error::RngError(e.to_string()))?;
+            let der = key.to_pkcs1_der().map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+            Ok(der.as_bytes().to_vec())
+        }
+        KeyAlgorithm::AesSiv => match config.key_size {
+            256 | 512 => rng.get_random_bytes(config.key_size as usize / 8),
+            _ => Err(Error::InvalidKeyLength),
+        },
+        _ => rng.get_random_bytes(32),
+    }
+}
+
+/// Verifies `signature` over `data` with a raw public key, for signatures
+/// produced outside this context.
+pub fn verify_with_public_key(
+    algorithm: KeyAlgorithm,
+    public_key: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool> {
+    match algorithm {
+        KeyAlgorithm::Ed25519 => {
+            let bytes: &[u8; 32] = public_key.try_into().map_err(|_| Error::InvalidKeyLength)?;
+            let key = ed25519_dalek::VerifyingKey::from_bytes(bytes)
+                .map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+            let Ok(signature) = ed25519_dalek::Signature::from_slice(signature) else {
+                return Ok(false);
+            };
+            Ok(key.verify(data, &signature).is_ok())
+        }
+        KeyAlgorithm::EcdsaP256 => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+                .map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+            let Ok(signature) = p256::ecdsa::Signature::from_slice(signature) else {
+                return Ok(false);
+            };
+            Ok(key.verify(data, &signature).is_ok())
+        }
+        KeyAlgorithm::RsaPssSha256 => {
+            let key = RsaPublicKey::from_public_key_der(public_key)
+                .map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+            let Ok(signature) = rsa::pss::Signature::try_from(signature) else {
+                return Ok(false);
+            };
+            let key = rsa::pss::VerifyingKey::<Sha256>::new(key);
+            Ok(rsa::signature::Verifier::verify(&key, data, &signature).is_ok())
+        }
+        _ => Err(Error::UnsupportedOperation),
+    }
+}
+
+/// Encodes an RSA public key given as big-endian modulus and exponent as a DER
+/// SubjectPublicKeyInfo, the form [`verify_with_public_key`] accepts.
+pub fn rsa_public_key_der(modulus: &[u8], exponent: &[u8]) -> Result<Vec<u8>> {
+    let key = RsaPublicKey::new(
+        rsa::BigUint::from_bytes_be(modulus),
+        rsa::BigUint::from_bytes_be(exponent),
+    )
+    .map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+    if key.size() * 8 < MIN_RSA_BITS {
+        return Err(Error::InvalidKeyLength);
+    }
+    let der = key.to_public_key_der().map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+    Ok(der.as_bytes().to_vec())
+}
+
+/// Splits a DER SubjectPublicKeyInfo RSA key into big-endian modulus and exponent.
+pub fn rsa_public_key_parts(der: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let key = RsaPublicKey::from_public_key_der(der).map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+    Ok((key.n().to_bytes_be(), key.e().to_bytes_be()))
+}
+
+impl Key {
+    pub(crate) fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.algorithm {
+            KeyAlgorithm::Ed25519 => {
+                let key = ed25519_dalek::SigningKey::from_bytes(self.raw_key_bytes()?);
+                Ok(key.sign(data).to_bytes().to_vec())
+            }
+            KeyAlgorithm::EcdsaP256 => {
+                let key = p256::ecdsa::SigningKey::from_slice(&self.data)
+                    .map_err(|_| Error::InvalidKeyLength)?;
+                let signature: p256::ecdsa::Signature = key.sign(data);
+                Ok(signature.to_bytes().to_vec())
+            }
+            KeyAlgorithm::RsaPssSha256 => {
+                let key = rsa::pss::BlindedSigningKey::<Sha256>::new(rsa_private_key(&self.data)?);
+                let signature = key.sign_with_rng(&mut ElasticRng::new()?, data);
+                Ok(signature.to_vec())
+            }
+            _ => Err(Error::UnsupportedOperation),
+        }
+    }
+
+    pub(crate) fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+        verify_with_public_key(self.algorithm, &self.public_key()?, data, signature)
+    }
+
+    pub(crate) fn public_key(&self) -> Result<Vec<u8>> {
+        match self.algorithm {
+            KeyAlgorithm::Ed25519 => {
+                let key = ed25519_dalek::SigningKey::from_bytes(self.raw_key_bytes()?);
+                Ok(key.verifying_key().to_bytes().to_vec())
+            }
+            KeyAlgorithm::EcdsaP256 | KeyAlgorithm::EcdhP256 => {
+                Ok(p256_secret_key(&self.data)?.public_key().to_sec1_bytes().to_vec())
+            }
+            KeyAlgorithm::X25519 => {
+                let secret = x25519_dalek::StaticSecret::from(*self.raw_key_bytes()?);
+                Ok(x25519_dalek::PublicKey::from(&secret).as_bytes().to_vec())
+            }
+            KeyAlgorithm::RsaPssSha256 => {
+                let key = rsa_private_key(&self.data)?.to_public_key();
+                let der = key.to_public_key_der().map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+                Ok(der.as_bytes().to_vec())
+            }
+            _ => Err(Error::UnsupportedOperation),
+        }
+    }
+
+    /// ECDH shared secret with a SEC1-encoded P-256 public key, or X25519
+    /// with a raw 32-byte public key.
+    pub(crate) fn diffie_hellman(&self, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        match self.algorithm {
+            KeyAlgorithm::EcdhP256 => {
+                let peer = p256::PublicKey::from_sec1_bytes(peer_public_key)
+                    .map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+                let secret =
+                    p256::ecdh::diffie_hellman(p256_secret_key(&self.data)?.to_nonzero_scalar(), peer.as_affine());
+                Ok(secret.raw_secret_bytes().to_vec())
+            }
+            KeyAlgorithm::X25519 => {
+                let peer: [u8; 32] = peer_public_key.try_into().map_err(|_| Error::InvalidKeyLength)?;
+                let secret = x25519_dalek::StaticSecret::from(*self.raw_key_bytes()?);
+                let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(peer));
+                // Reject low-order points, which give an all-zero secret.
+                if !shared.was_contributory() {
+                    return Err(Error::InvalidEncoding("X25519 public key has low order".to_string()));
+                }
+                Ok(shared.as_bytes().to_vec())
+            }
+            _ => Err(Error::UnsupportedOperation),
+        }
+    }
+
+    pub(crate) fn mac(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.hmac(data)?.finalize().into_bytes().to_vec())
+    }
+
+    pub(crate) fn verify_mac(&self, data: &[u8], mac: &[u8]) -> Result<bool> {
+        Ok(self.hmac(data)?.verify_slice(mac).is_ok())
+    }
+
+    /// Stable identifier for the key, safe to place in message headers.
+    ///
+    /// Asymmetric keys use a truncated SHA-256 of the public key so that peers
+    /// holding only the public key compute the same id. Secret keys use a
+    /// truncated HMAC under the key itself, which reveals nothing about it.
+    pub(crate) fn key_id(&self) -> Result<Vec<u8>> {
+        let digest = match self.algorithm {
+            KeyAlgorithm::Ed25519
+            | KeyAlgorithm::EcdsaP256
+            | KeyAlgorithm::RsaPssSha256
+            | KeyAlgorithm::EcdhP256
+            | KeyAlgorithm::X25519 => Sha256::digest(self.public_key()?).to_vec(),
+            _ => {
+                let mut mac = HmacSha256::new_from_slice(&self.data).map_err(|_| Error::InvalidKeyLength)?;
+                mac.update(b"elastic key id");
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+        Ok(digest[..KEY_ID_LEN].to_vec())
+    }
+
+    fn hmac(&self, data: &[u8]) -> Result<HmacSha256> {
+        if self.algorithm != KeyAlgorithm::HmacSha256 {
+            return Err(Error::UnsupportedOperation);
+        }
+        let mut mac = HmacSha256::new_from_slice(&self.data).map_err(|_| Error::InvalidKeyLength)?;
+        mac.update(data);
+        Ok(mac)
+    }
+
+    /// Raw 32-byte Ed25519 seed or X25519 scalar.
+    fn raw_key_bytes(&self) -> Result<&[u8; 32]> {
+        self.data.as_slice().try_into().map_err(|_| Error::InvalidKeyLength)
+    }
+}
+
+impl ElasticCrypto {
+    /// Signs `data` with an Ed25519, ECDSA P-256 or RSA-PSS key.
+    pub fn sign(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.sign(&data)
+    }
+
+    pub fn verify(&self, handle: u32, data: Vec<u8>, signature: Vec<u8>) -> Result<bool> {
+        self.self_test.check()?;
+        self.get_key(handle)?.verify(&data, &signature)
+    }
+
+    /// Computes HMAC-SHA256 over `data` with an HMAC key.
+    pub fn calculate_mac(&self, handle: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.mac(&data)
+    }
+
+    pub fn verify_mac(&self, handle: u32, data: Vec<u8>, mac: Vec<u8>) -> Result<bool> {
+        self.self_test.check()?;
+        self.get_key(handle)?.verify_mac(&data, &mac)
+    }
+
+    /// Public half of an asymmetric key. Allowed for secure-storage keys.
+    pub fn get_public_key(&self, handle: u32) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.public_key()
+    }
+
+    pub fn key_algorithm(&self, handle: u32) -> Result<KeyAlgorithm> {
+        Ok(self.get_key(handle)?.algorithm)
+    }
+
+    /// Identifier used as `kid` in COSE and JOSE headers.
+    pub fn get_key_id(&self, handle: u32) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?.key_id()
+    }
+}
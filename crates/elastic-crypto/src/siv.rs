@@ -0,0 +1,110 @@
+//! Deterministic authenticated encryption with AES-SIV (RFC 5297).
+//!
+//! The same key, associated data and plaintext always give the same
+//! ciphertext, so encrypted columns can be compared for equality without
+//! decrypting them. Passing a nonce turns this into the nonce-based,
+//! misuse-resistant mode: the nonce is the last associated-data component, and
+//! reusing one only reveals whether two messages are equal.
+//!
+//! Ciphertexts are the 16-byte synthetic IV followed by the encrypted
+//! plaintext. They are computed in software on every backend and are
+//! byte-identical across Linux, SEV-SNP and WASM.
+
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use aes_siv::siv::{Aes128Siv, Aes256Siv};
+use aes_siv::KeyInit;
+
+/// Checks the key is AES-SIV-CMAC-256 (32 bytes) or AES-SIV-CMAC-512 (64 bytes).
+pub(crate) fn check_key(data: &[u8]) -> Result<()> {
+    match data.len() {
+        32 | 64 => Ok(()),
+        _ => Err(Error::InvalidKeyLength),
+    }
+}
+
+/// Associated-data vector in RFC 5297 order, with the nonce last.
+fn headers<'a>(associated_data: &'a [Vec<u8>], nonce: Option<&'a [u8]>) -> Vec<&'a [u8]> {
+    associated_data
+        .iter()
+        .map(Vec::as_slice)
+        .chain(nonce)
+        .collect()
+}
+
+impl Key {
+    pub(crate) fn siv_encrypt(
+        &self,
+        plaintext: &[u8],
+        associated_data: &[Vec<u8>],
+        nonce: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        if self.algorithm != KeyAlgorithm::AesSiv {
+            return Err(Error::UnsupportedOperation);
+        }
+        let headers = headers(associated_data, nonce);
+        let result = match self.data.len() {
+            32 => Aes128Siv::new_from_slice(&self.data)
+                .map_err(|_| Error::InvalidKeyLength)?
+                .encrypt(&headers, plaintext),
+            _ => Aes256Siv::new_from_slice(&self.data)
+                .map_err(|_| Error::InvalidKeyLength)?
+                .encrypt(&headers, plaintext),
+        };
+        result.map_err(|e| Error::EncryptionError(e.to_string()))
+    }
+
+    pub(crate) fn siv_decrypt(
+        &self,
+        ciphertext: &[u8],
+        associated_data: &[Vec<u8>],
+        nonce: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        if self.algorithm != KeyAlgorithm::AesSiv {
+            return Err(Error::UnsupportedOperation);
+        }
+        let headers = headers(associated_data, nonce);
+        let result = match self.data.len() {
+            32 => Aes128Siv::new_from_slice(&self.data)
+                .map_err(|_| Error::InvalidKeyLength)?
+                .decrypt(&headers, ciphertext),
+            _ => Aes256Siv::new_from_slice(&self.data)
+                .map_err(|_| Error::InvalidKeyLength)?
+                .decrypt(&headers, ciphertext),
+        };
+        result.map_err(|e| Error::DecryptionError(e.to_string()))
+    }
+}
+
+impl ElasticCrypto {
+    /// Encrypts `plaintext` with an AES-SIV key, authenticating each
+    /// `associated_data` component separately.
+    ///
+    /// Without a `nonce` the output is deterministic. With one, the nonce must
+    /// be passed again to [`ElasticCrypto::decrypt_siv`]; it is not included
+    /// in the ciphertext.
+    pub fn encrypt_siv(
+        &self,
+        handle: u32,
+        plaintext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?
+            .siv_encrypt(&plaintext, &associated_data, nonce.as_deref())
+    }
+
+    /// Decrypts and authenticates an AES-SIV ciphertext made with the same
+    /// associated data and nonce.
+    pub fn decrypt_siv(
+        &self,
+        handle: u32,
+        ciphertext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        self.get_key(handle)?
+            .siv_decrypt(&ciphertext, &associated_data, nonce.as_deref())
+    }
+}
@@ -1,6 +1,7 @@
 // use crate::{Error, Crypto};
 use crate::Error;
 use crate::aes::AesMode;
+use crate::selftest::{self, SelfTestReport};
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
@@ -29,6 +30,7 @@ struct WasmCryptoInner {
 #[derive(Debug)]
 pub struct WasmCrypto {
     inner: UnsafeCell<WasmCryptoInner>,
+    self_test: SelfTestReport,
 }
 
 // SAFETY: WasmCrypto is only used in single-threaded WASM context
@@ -41,6 +43,12 @@ impl WasmCrypto {
         let is_sevsnp = env::var("ELASTIC_SEV_SNP").map(|v| v == "1").unwrap_or(false);
         println!("SEV-SNP environment variable: {}", is_sevsnp);
 
+        println!("Running crypto self-tests...");
+        let self_test = selftest::run_self_tests();
+        if let Err(e) = self_test.check() {
+            println!("{}, refusing all crypto operations", e);
+        }
+
         #[cfg(feature = "sevsnp")]
         {
             if is_sevsnp {
@@ -54,6 +62,7 @@ impl WasmCrypto {
                                 rng: Some(rng),
                                 aes: None,
                             }),
+                            self_test,
                         };
                     }
                     Err(e) => {
@@ -72,6 +81,7 @@ impl WasmCrypto {
                     rng: None,
                     aes: None,
                 }),
+                self_test,
             }
         }
 
@@ -83,6 +93,7 @@ impl WasmCrypto {
                 inner: UnsafeCell::new(WasmCryptoInner {
                     is_sevsnp: false,
                 }),
+                self_test,
             }
         }
     }
@@ -92,8 +103,14 @@ impl WasmCrypto {
         unsafe { (*self.inner.get()).is_sevsnp }
     }
 
+    /// Results of the known-answer tests run when this context was created.
+    pub fn self_test_report(&self) -> &SelfTestReport {
+        &self.self_test
+    }
+
     #[cfg(feature = "sevsnp")]
     fn generate_sevsnp_key(&self) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         // SAFETY: We're in a single-threaded WASM context
         let inner = unsafe { &mut *self.inner.get() };
         if let Some(rng) = inner.rng.as_mut() {
@@ -110,6 +127,7 @@ impl WasmCrypto {
 
     #[cfg(feature = "sevsnp")]
     fn encrypt_sevsnp(&self, key: &[u8], data: &[u8], _mode: AesMode) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         // SAFETY: We're in a single-threaded WASM context
         let inner = unsafe { &mut *self.inner.get() };
         if inner.aes.is_none() {
@@ -129,6 +147,7 @@ impl WasmCrypto {
 
     #[cfg(feature = "sevsnp")]
     fn decrypt_sevsnp(&self, key: &[u8], data: &[u8], _mode: AesMode) -> Result<Vec<u8>, Error> {
+        self.self_test.check()?;
         // SAFETY: We're in a single-threaded WASM context
         let inner = unsafe { &mut *self.inner.get() };
         if inner.aes.is_none() {
@@ -0,0 +1,320 @@
+//! X.509 certificate signing requests and self-signed certificates.
+//!
+//! The to-be-signed structures are assembled here and signed with a key handle,
+//! so a workload can mint its TLS identity at boot while the private key stays
+//! in the key store. Output is DER, which is what rustls' `Certificate` holds;
+//! [`pem_encode`] produces the PEM files `elastic-tls` loads from disk.
+
+use crate::rng::ElasticRng;
+use crate::{ElasticCrypto, Error, Key, KeyAlgorithm, Result};
+use const_oid::db::{rfc5280, rfc5912, rfc8410};
+use const_oid::ObjectIdentifier;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use x509_cert::der::asn1::{BitString, Ia5String, OctetString, SetOfVec};
+use x509_cert::der::pem::LineEnding;
+use x509_cert::der::referenced::OwnedToRef;
+use x509_cert::der::{Any, Decode, Encode};
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::{self, BasicConstraints, KeyUsages, SubjectKeyIdentifier};
+use x509_cert::ext::{AsExtension, Extension};
+use x509_cert::name::Name;
+use x509_cert::request::{CertReq, CertReqInfo, ExtensionReq};
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+use x509_cert::time::Validity;
+use x509_cert::{Certificate, TbsCertificate};
+
+const SERIAL_LEN: usize = 16;
+
+/// Key usage bits (RFC 5280 section 4.2.1.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyUsage {
+    DigitalSignature,
+    NonRepudiation,
+    KeyEncipherment,
+    DataEncipherment,
+    KeyAgreement,
+    KeyCertSign,
+    CrlSign,
+}
+
+/// Extended key usage purposes (RFC 5280 section 4.2.1.12).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtendedKeyUsage {
+    ServerAuth,
+    ClientAuth,
+    CodeSigning,
+}
+
+/// Subject alternative name entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+    Uri(String),
+    Email(String),
+}
+
+/// An extension passed through verbatim, e.g. attestation evidence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomExtension {
+    /// Dotted-decimal OID, e.g. "1.3.6.1.4.1.58270.1.1".
+    pub oid: String,
+    pub critical: bool,
+    /// DER-encoded extension value (the contents of `extnValue`).
+    pub value: Vec<u8>,
+}
+
+/// Contents of a CSR or self-signed certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateParams {
+    /// RFC 4514 distinguished name, e.g. "CN=workload,O=Elastic".
+    pub subject: String,
+    pub subject_alt_names: Vec<SubjectAltName>,
+    pub key_usage: Vec<KeyUsage>,
+    pub extended_key_usage: Vec<ExtendedKeyUsage>,
+    pub custom_extensions: Vec<CustomExtension>,
+    /// Sets the CA flag in basic constraints.
+    pub is_ca: bool,
+    /// Certificate lifetime from now. Ignored for CSRs.
+    pub validity_days: u32,
+}
+
+impl Default for CertificateParams {
+    /// A TLS server identity valid for 90 days.
+    fn default() -> Self {
+        Self {
+            subject: String::new(),
+            subject_alt_names: Vec::new(),
+            key_usage: vec![KeyUsage::DigitalSignature],
+            extended_key_usage: vec![ExtendedKeyUsage::ServerAuth],
+            custom_extensions: Vec::new(),
+            is_ca: false,
+            validity_days: 90,
+        }
+    }
+}
+
+/// Wraps DER in PEM with the given label ("CERTIFICATE" or
+/// "CERTIFICATE REQUEST").
+pub fn pem_encode(label: &str, der: &[u8]) -> Result<String> {
+    x509_cert::der::pem::encode_string(label, LineEnding::LF, der).map_err(|e| Error::InvalidEncoding(e.to_string()))
+}
+
+fn invalid_params(message: impl ToString) -> Error {
+    Error::InvalidCertificateParams(message.to_string())
+}
+
+fn der_error(e: x509_cert::der::Error) -> Error {
+    Error::InvalidEncoding(e.to_string())
+}
+
+fn ia5(value: &str) -> Result<Ia5String> {
+    Ia5String::new(value).map_err(invalid_params)
+}
+
+impl CertificateParams {
+    fn subject_name(&self) -> Result<Name> {
+        Name::from_str(&self.subject).map_err(invalid_params)
+    }
+
+    fn extensions(&self, subject: &Name, spki: &SubjectPublicKeyInfoOwned, is_certificate: bool) -> Result<Vec<Extension>> {
+        let mut extensions = Vec::new();
+        let mut push = |extension: std::result::Result<Extension, x509_cert::der::Error>| -> Result<()> {
+            extensions.push(extension.map_err(der_error)?);
+            Ok(())
+        };
+
+        let basic_constraints = BasicConstraints {
+            ca: self.is_ca,
+            path_len_constraint: None,
+        };
+        push(basic_constraints.to_extension(subject, &[]))?;
+
+        if !self.key_usage.is_empty() {
+            let usages = self.key_usage.iter().fold(x509_cert::der::flagset::FlagSet::default(), |set, usage| {
+                set | match usage {
+                    KeyUsage::DigitalSignature => KeyUsages::DigitalSignature,
+                    KeyUsage::NonRepudiation => KeyUsages::NonRepudiation,
+                    KeyUsage::KeyEncipherment => KeyUsages::KeyEncipherment,
+                    KeyUsage::DataEncipherment => KeyUsages::DataEncipherment,
+                    KeyUsage::KeyAgreement => KeyUsages::KeyAgreement,
+                    KeyUsage::KeyCertSign => KeyUsages::KeyCertSign,
+                    KeyUsage::CrlSign => KeyUsages::CRLSign,
+                }
+            });
+            push(pkix::KeyUsage(usages).to_extension(subject, &[]))?;
+        }
+
+        if !self.extended_key_usage.is_empty() {
+            let purposes = self
+                .extended_key_usage
+                .iter()
+                .map(|usage| match usage {
+                    ExtendedKeyUsage::ServerAuth => rfc5280::ID_KP_SERVER_AUTH,
+                    ExtendedKeyUsage::ClientAuth => rfc5280::ID_KP_CLIENT_AUTH,
+                    ExtendedKeyUsage::CodeSigning => rfc5280::ID_KP_CODE_SIGNING,
+                })
+                .collect();
+            push(pkix::ExtendedKeyUsage(purposes).to_extension(subject, &[]))?;
+        }
+
+        if !self.subject_alt_names.is_empty() {
+            let names = self
+                .subject_alt_names
+                .iter()
+                .map(|name| {
+                    Ok(match name {
+                        SubjectAltName::Dns(dns) => GeneralName::DnsName(ia5(dns)?),
+                        SubjectAltName::Uri(uri) => GeneralName::UniformResourceIdentifier(ia5(uri)?),
+                        SubjectAltName::Email(email) => GeneralName::Rfc822Name(ia5(email)?),
+                        SubjectAltName::Ip(ip) => {
+                            let octets = match ip {
+                                IpAddr::V4(ip) => ip.octets().to_vec(),
+                                IpAddr::V6(ip) => ip.octets().to_vec(),
+                            };
+                            GeneralName::IpAddress(OctetString::new(octets).map_err(der_error)?)
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            push(pkix::SubjectAltName(names).to_extension(subject, &[]))?;
+        }
+
+        if is_certificate {
+            let key_id = SubjectKeyIdentifier::try_from(spki.owned_to_ref()).map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+            push(key_id.to_extension(subject, &[]))?;
+        }
+
+        for custom in &self.custom_extensions {
+            let extn_id = ObjectIdentifier::new(&custom.oid).map_err(invalid_params)?;
+            if extensions.iter().any(|e| e.extn_id == extn_id) {
+                return Err(invalid_params(format!("duplicate extension {}", extn_id)));
+            }
+            extensions.push(Extension {
+                extn_id,
+                critical: custom.critical,
+                extn_value: OctetString::new(custom.value.clone()).map_err(der_error)?,
+            });
+        }
+        Ok(extensions)
+    }
+}
+
+impl Key {
+    fn subject_public_key_info(&self) -> Result<SubjectPublicKeyInfoOwned> {
+        let public_key = self.public_key()?;
+        let algorithm = match self.algorithm {
+            KeyAlgorithm::Ed25519 => AlgorithmIdentifierOwned {
+                oid: rfc8410::ID_ED_25519,
+                parameters: None,
+            },
+            KeyAlgorithm::EcdsaP256 => AlgorithmIdentifierOwned {
+                oid: rfc5912::ID_EC_PUBLIC_KEY,
+                parameters: Some(Any::from(&rfc5912::SECP_256_R_1)),
+            },
+            KeyAlgorithm::RsaPssSha256 => {
+                return SubjectPublicKeyInfoOwned::from_der(&public_key).map_err(der_error);
+            }
+            _ => return Err(Error::UnsupportedOperation),
+        };
+        Ok(SubjectPublicKeyInfoOwned {
+            algorithm,
+            subject_public_key: BitString::from_bytes(&public_key).map_err(der_error)?,
+        })
+    }
+
+    fn x509_signature_algorithm(&self) -> Result<AlgorithmIdentifierOwned> {
+        match self.algorithm {
+            KeyAlgorithm::Ed25519 => Ok(AlgorithmIdentifierOwned {
+                oid: rfc8410::ID_ED_25519,
+                parameters: None,
+            }),
+            KeyAlgorithm::EcdsaP256 => Ok(AlgorithmIdentifierOwned {
+                oid: rfc5912::ECDSA_WITH_SHA_256,
+                parameters: None,
+            }),
+            KeyAlgorithm::RsaPssSha256 => {
+                rsa::pss::get_default_pss_signature_algo_id::<sha2::Sha256>().map_err(|e| Error::InvalidEncoding(e.to_string()))
+            }
+            _ => Err(Error::UnsupportedOperation),
+        }
+    }
+
+    /// Signs `tbs` as an X.509 signature value (DER-encoded for ECDSA).
+    fn x509_sign(&self, tbs: &[u8]) -> Result<BitString> {
+        let mut signature = self.sign(tbs)?;
+        if self.algorithm == KeyAlgorithm::EcdsaP256 {
+            let raw = p256::ecdsa::Signature::from_slice(&signature).map_err(|e| Error::InvalidEncoding(e.to_string()))?;
+            signature = raw.to_der().as_bytes().to_vec();
+        }
+        BitString::from_bytes(&signature).map_err(der_error)
+    }
+}
+
+impl ElasticCrypto {
+    /// Builds a PKCS#10 certificate signing request (DER) signed by the key.
+    pub fn create_csr(&self, handle: u32, params: CertificateParams) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        let subject = params.subject_name()?;
+        let public_key = key.subject_public_key_info()?;
+        let extensions = params.extensions(&subject, &public_key, false)?;
+
+        let mut attributes = SetOfVec::new();
+        if !extensions.is_empty() {
+            let request = ExtensionReq(extensions).try_into().map_err(der_error)?;
+            attributes.insert(request).map_err(der_error)?;
+        }
+        let info = CertReqInfo {
+            version: x509_cert::request::Version::V1,
+            subject,
+            public_key,
+            attributes,
+        };
+        let algorithm = key.x509_signature_algorithm()?;
+        let signature = key.x509_sign(&info.to_der().map_err(der_error)?)?;
+        CertReq { info, algorithm, signature }.to_der().map_err(der_error)
+    }
+
+    /// Builds a self-signed X.509 v3 certificate (DER) for the key.
+    pub fn create_self_signed_certificate(&self, handle: u32, params: CertificateParams) -> Result<Vec<u8>> {
+        self.self_test.check()?;
+        let key = self.get_key(handle)?;
+        if params.validity_days == 0 {
+            return Err(invalid_params("validity must be at least one day"));
+        }
+        let subject = params.subject_name()?;
+        let public_key = key.subject_public_key_info()?;
+        let extensions = params.extensions(&subject, &public_key, true)?;
+
+        // Positive serial with 127 random bits.
+        let mut serial = ElasticRng::new()?.get_random_bytes(SERIAL_LEN)?;
+        serial[0] &= 0x7f;
+        let validity = Validity::from_now(Duration::from_secs(u64::from(params.validity_days) * 86_400)).map_err(der_error)?;
+        let signature_algorithm = key.x509_signature_algorithm()?;
+
+        let tbs_certificate = TbsCertificate {
+            version: x509_cert::Version::V3,
+            serial_number: SerialNumber::new(&serial).map_err(der_error)?,
+            signature: signature_algorithm.clone(),
+            issuer: subject.clone(),
+            validity,
+            subject,
+            subject_public_key_info: public_key,
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions: Some(extensions),
+        };
+        let signature = key.x509_sign(&tbs_certificate.to_der().map_err(der_error)?)?;
+        Certificate {
+            tbs_certificate,
+            signature_algorithm,
+            signature,
+        }
+        .to_der()
+        .map_err(der_error)
+    }
+}
@@ -0,0 +1,87 @@
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use elastic_crypto::{ElasticCrypto, Error, KeyConfig, KeyType};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+
+fn symmetric_config() -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Symmetric,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: None,
+    }
+}
+
+#[test]
+fn test_concurrent_encrypt_while_keys_change() {
+    let crypto = Arc::new(ElasticCrypto::new().unwrap());
+    let handle = crypto.generate_key(symmetric_config()).unwrap();
+
+    let workers: Vec<_> = (0..8)
+        .map(|i| {
+            let crypto = Arc::clone(&crypto);
+            thread::spawn(move || {
+                for n in 0..200 {
+                    let data = format!("worker {} message {}", i, n).into_bytes();
+                    let ciphertext = crypto.encrypt(handle, data.clone()).unwrap();
+                    assert_eq!(crypto.decrypt(handle, ciphertext).unwrap(), data);
+                }
+            })
+        })
+        .collect();
+
+    let churn = {
+        let crypto = Arc::clone(&crypto);
+        thread::spawn(move || {
+            (0..200)
+                .map(|_| {
+                    let temp = crypto.import_key(vec![7u8; 32], symmetric_config()).unwrap();
+                    crypto.delete_key(temp).unwrap();
+                    temp
+                })
+                .collect::<Vec<_>>()
+        })
+    };
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let temp_handles = churn.join().unwrap();
+
+    let unique: HashSet<_> = temp_handles.iter().collect();
+    assert_eq!(unique.len(), temp_handles.len());
+    assert!(!unique.contains(&handle));
+    assert!(matches!(crypto.export_key(temp_handles[0]), Err(Error::KeyNotFound)));
+}
+
+#[test]
+fn test_aes_gcm_ciphertext_format() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let key = [0x24u8; 32];
+    let handle = crypto.import_key(key.to_vec(), symmetric_config()).unwrap();
+
+    // Format byte, random nonce, then ciphertext and tag.
+    let first = crypto.encrypt(handle, b"same".to_vec()).unwrap();
+    let second = crypto.encrypt(handle, b"same".to_vec()).unwrap();
+    assert_eq!(first.len(), 1 + 12 + 4 + 16);
+    assert_eq!(first[0], 1);
+    assert_ne!(first[1..13], second[1..13]);
+    assert_eq!(crypto.decrypt(handle, first.clone()).unwrap(), b"same");
+    assert!(matches!(crypto.decrypt(handle, first[..8].to_vec()), Err(Error::DecryptionError(_))));
+
+    // Ciphertexts from before the format byte used a fixed nonce and still open.
+    let legacy = Aes256Gcm::new_from_slice(&key)
+        .unwrap()
+        .encrypt(Nonce::from_slice(b"elastic-nc12"), b"legacy".as_ref())
+        .unwrap();
+    assert_eq!(crypto.decrypt(handle, legacy).unwrap(), b"legacy");
+}
+
+#[test]
+fn test_import_rejects_invalid_symmetric_key() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let result = crypto.import_key(vec![0u8; 16], symmetric_config());
+    assert!(matches!(result, Err(Error::InvalidKeyLength)));
+}
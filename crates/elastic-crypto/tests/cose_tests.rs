@@ -0,0 +1,123 @@
+use coset::{iana, CoseEncrypt0, CoseSign1, RegisteredLabelWithPrivate, TaggedCborSerializable};
+use elastic_crypto::cose::cose_sign1_verify_with_public_key;
+use elastic_crypto::{ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType};
+
+fn config(key_type: KeyType, algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        key_type,
+        key_size: 256,
+        secure_storage: true,
+        algorithm: Some(algorithm),
+    }
+}
+
+#[test]
+fn test_cose_sign1_round_trip() {
+    let crypto = ElasticCrypto::new().unwrap();
+
+    for (algorithm, cose_alg) in [
+        (KeyAlgorithm::Ed25519, iana::Algorithm::EdDSA),
+        (KeyAlgorithm::EcdsaP256, iana::Algorithm::ES256),
+    ] {
+        let handle = crypto.generate_key(config(KeyType::Asymmetric, algorithm)).unwrap();
+        let message = crypto.cose_sign1(handle, b"claims".to_vec(), b"aad".to_vec()).unwrap();
+
+        let sign1 = CoseSign1::from_tagged_slice(&message).unwrap();
+        assert_eq!(sign1.protected.header.alg, Some(RegisteredLabelWithPrivate::Assigned(cose_alg)));
+        assert_eq!(sign1.protected.header.key_id, crypto.get_key_id(handle).unwrap());
+        assert_eq!(sign1.signature.len(), 64);
+
+        let payload = crypto.cose_sign1_verify(handle, message.clone(), b"aad".to_vec()).unwrap();
+        assert_eq!(payload, b"claims");
+
+        let public_key = crypto.get_public_key(handle).unwrap();
+        let payload = cose_sign1_verify_with_public_key(algorithm, &public_key, &message, b"aad").unwrap();
+        assert_eq!(payload, b"claims");
+
+        assert!(matches!(
+            crypto.cose_sign1_verify(handle, message, b"other".to_vec()),
+            Err(Error::VerificationFailed)
+        ));
+    }
+}
+
+#[test]
+fn test_cose_sign1_rejects_other_key() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let signer = crypto.generate_key(config(KeyType::Asymmetric, KeyAlgorithm::Ed25519)).unwrap();
+    let other = crypto.generate_key(config(KeyType::Asymmetric, KeyAlgorithm::Ed25519)).unwrap();
+
+    let message = crypto.cose_sign1(signer, b"claims".to_vec(), Vec::new()).unwrap();
+    assert!(matches!(
+        crypto.cose_sign1_verify(other, message.clone(), Vec::new()),
+        Err(Error::VerificationFailed)
+    ));
+
+    let mut tampered = message;
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    assert!(matches!(
+        crypto.cose_sign1_verify(signer, tampered, Vec::new()),
+        Err(Error::VerificationFailed)
+    ));
+}
+
+#[test]
+fn test_cose_encrypt0_round_trip() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto
+        .import_key(vec![7u8; 32], config(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm))
+        .unwrap();
+
+    let message = crypto.cose_encrypt0(handle, b"secret".to_vec(), b"aad".to_vec()).unwrap();
+    let encrypt0 = CoseEncrypt0::from_tagged_slice(&message).unwrap();
+    assert_eq!(
+        encrypt0.protected.header.alg,
+        Some(RegisteredLabelWithPrivate::Assigned(iana::Algorithm::A256GCM))
+    );
+    assert_eq!(encrypt0.protected.header.key_id, crypto.get_key_id(handle).unwrap());
+    assert_eq!(encrypt0.unprotected.iv.len(), 12);
+
+    // A fresh IV per message.
+    let again = crypto.cose_encrypt0(handle, b"secret".to_vec(), b"aad".to_vec()).unwrap();
+    assert_ne!(message, again);
+
+    let plaintext = crypto.cose_decrypt0(handle, message.clone(), b"aad".to_vec()).unwrap();
+    assert_eq!(plaintext, b"secret");
+    assert!(matches!(
+        crypto.cose_decrypt0(handle, message.clone(), b"other".to_vec()),
+        Err(Error::DecryptionFailed)
+    ));
+
+    let other = crypto
+        .import_key(vec![8u8; 32], config(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm))
+        .unwrap();
+    assert!(matches!(
+        crypto.cose_decrypt0(other, message, b"aad".to_vec()),
+        Err(Error::DecryptionFailed)
+    ));
+}
+
+#[test]
+fn test_cose_wrong_key_types() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let signing = crypto.generate_key(config(KeyType::Asymmetric, KeyAlgorithm::Ed25519)).unwrap();
+    let mac = crypto.generate_key(config(KeyType::Hmac, KeyAlgorithm::HmacSha256)).unwrap();
+
+    assert!(matches!(
+        crypto.cose_encrypt0(signing, b"x".to_vec(), Vec::new()),
+        Err(Error::UnsupportedOperation)
+    ));
+    assert!(matches!(
+        crypto.cose_sign1(mac, b"x".to_vec(), Vec::new()),
+        Err(Error::UnsupportedOperation)
+    ));
+    assert!(matches!(
+        crypto.cose_sign1_verify(signing, b"not cbor".to_vec(), Vec::new()),
+        Err(Error::InvalidEncoding(_))
+    ));
+    assert!(matches!(
+        crypto.generate_key(config(KeyType::Symmetric, KeyAlgorithm::Ed25519)),
+        Err(Error::UnsupportedOperation)
+    ));
+}
@@ -9,6 +9,7 @@ async fn test_key_operations() {
         key_type: KeyType::Symmetric,
         key_size: 256,
         secure_storage: false,
+        algorithm: None,
     };
     
     let handle = ctx.generate_key(&config).await.unwrap();
@@ -37,6 +38,7 @@ async fn test_asymmetric_operations() {
         key_type: KeyType::Asymmetric,
         key_size: 2048,
         secure_storage: false,
+        algorithm: None,
     };
     
     let handle = ctx.generate_key(&config).await.unwrap();
@@ -64,6 +66,7 @@ async fn test_error_handling() {
         key_type: KeyType::Symmetric,
         key_size: 256,
         secure_storage: false,
+        algorithm: None,
     };
     
     let handle = ctx.generate_key(&config).await.unwrap();
@@ -82,6 +85,7 @@ async fn test_hmac_operations() {
         key_type: KeyType::Hmac,
         key_size: 256,
         secure_storage: false,
+        algorithm: None,
     };
     
     let handle = ctx.generate_key(&config).await.unwrap();
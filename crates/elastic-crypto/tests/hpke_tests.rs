@@ -0,0 +1,197 @@
+use elastic_crypto::{ElasticCrypto, Error, HpkeAead, KeyAlgorithm, KeyConfig, KeyType};
+
+const INFO: &str = "4f6465206f6e2061204772656369616e2055726e";
+const AAD: &str = "436f756e742d30";
+const PT: &str = "4265617574792069732074727574682c20747275746820626561757479";
+
+fn agreement(algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Asymmetric,
+        key_size: 256,
+        secure_storage: true,
+        algorithm: Some(algorithm),
+    }
+}
+
+fn unhex(data: &str) -> Vec<u8> {
+    hex::decode(data).unwrap()
+}
+
+struct Vector {
+    algorithm: KeyAlgorithm,
+    aead: HpkeAead,
+    sk_r: &'static str,
+    pk_s: Option<&'static str>,
+    enc: &'static str,
+    ct: &'static str,
+}
+
+/// First encryption of each matching suite in the RFC 9180 test vectors.
+const VECTORS: [Vector; 8] = [
+    Vector {
+        algorithm: KeyAlgorithm::X25519,
+        aead: HpkeAead::Aes256Gcm,
+        sk_r: "497b4502664cfea5d5af0b39934dac72242a74f8480451e1aee7d6a53320333d",
+        pk_s: None,
+        enc: "6c93e09869df3402d7bf231bf540fadd35cd56be14f97178f0954db94b7fc256",
+        ct: "e5d84cd531cfb583096e7cfa9641bd3079cf3a91cda813c52deb5f512be9931980a41de125a925cdad859d5b7a",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::X25519,
+        aead: HpkeAead::Aes256Gcm,
+        sk_r: "47f1eee3670dfaaf27c30a83d06ee9f257af174727c17b35328ef730dfc1cd81",
+        pk_s: Some("4a91c3d0893433f5e31a79fc520f885527a1bc60bf2b0c72693dd7f0b2e41a5a"),
+        enc: "9e59f4b1fa5c876f684765290c34e51145894cc4f244342b9fb1a4bdfd8bb426",
+        ct: "10b964283ac2cc0bdc4c85ab617291b446bf3832e9359b2c3a0facc50ea75a3c1afd08aeaacd6041d02eb560ec",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::X25519,
+        aead: HpkeAead::ChaCha20Poly1305,
+        sk_r: "8057991eef8f1f1af18f4a9491d16a1ce333f695d4db8e38da75975c4478e0fb",
+        pk_s: None,
+        enc: "1afa08d3dec047a643885163f1180476fa7ddb54c6a8029ea33f95796bf2ac4a",
+        ct: "1c5250d8034ec2b784ba2cfd69dbdb8af406cfe3ff938e131f0def8c8b60b4db21993c62ce81883d2dd1b51a28",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::X25519,
+        aead: HpkeAead::ChaCha20Poly1305,
+        sk_r: "3ca22a6d1cda1bb9480949ec5329d3bf0b080ca4c45879c95eddb55c70b80b82",
+        pk_s: Some("f0f4f9e96c54aeed3f323de8534fffd7e0577e4ce269896716bcb95643c8712b"),
+        enc: "f7674cc8cd7baa5872d1f33dbaffe3314239f6197ddf5ded1746760bfc847e0e",
+        ct: "ab1a13c9d4f01a87ec3440dbd756e2677bd2ecf9df0ce7ed73869b98e00c09be111cb9fdf077347aeb88e61bdf",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::EcdhP256,
+        aead: HpkeAead::Aes256Gcm,
+        sk_r: "317f915db7bc629c48fe765587897e01e282d3e8445f79f27f65d031a88082b2",
+        pk_s: None,
+        enc: "04c06b4f6bebc7bb495cb797ab753f911aff80aefb86fd8b6fcc35525f3ab5f03e0b21bd31a86c6048af3cb2d98e0d3bf01da5cc4c39ff5370d331a4f1f7d5a4e0",
+        ct: "58c61a45059d0c5704560e9d88b564a8b63f1364b8d1fcb3c4c6ddc1d291742465e902cd216f8908da49f8f96f",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::EcdhP256,
+        aead: HpkeAead::Aes256Gcm,
+        sk_r: "d9f10996a02cd6c9dbda1d1f225f18f781ea3c893b8c2a6cb2e266e59f3cd9a9",
+        pk_s: Some("04ece9b48cc98ee03ba742fe1218a3fbec960cc34b6e1defdcd3285276f39028e95b90f9526607565888766a1101f429dc3ec87364b5c8c613f0a081881950427f"),
+        enc: "04a7aeac79fda402674ef247c12d6f5fdfd21498d896b67ff04ec181382d4516b7662be32b4a2ae817c2d57104ecb6fcaa527438939810612d1b3d0af36ffc66ce",
+        ct: "59b9890aabf94c1d502c39d8d356989ab0880ed43e984255db7b32a8d7b0ad5beba799a4ec326a0ddca3dd5e5d",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::EcdhP256,
+        aead: HpkeAead::ChaCha20Poly1305,
+        sk_r: "a4d1c55836aa30f9b3fbb6ac98d338c877c2867dd3a77396d13f68d3ab150d3b",
+        pk_s: None,
+        enc: "04c07836a0206e04e31d8ae99bfd549380b072a1b1b82e563c935c095827824fc1559eac6fb9e3c70cd3193968994e7fe9781aa103f5b50e934b5b2f387e381291",
+        ct: "6469c41c5c81d3aa85432531ecf6460ec945bde1eb428cb2fedf7a29f5a685b4ccb0d057f03ea2952a27bb458b",
+    },
+    Vector {
+        algorithm: KeyAlgorithm::EcdhP256,
+        aead: HpkeAead::ChaCha20Poly1305,
+        sk_r: "3cb2c125b8c5a81d165a333048f5dcae29a2ab2072625adad66dbb0f48689af9",
+        pk_s: Some("04265529a04d4f46ab6fa3af4943774a9f1127821656a75a35fade898a9a1b014f64d874e88cddb24c1c3d79004d3a587db67670ca357ff4fba7e8b56ec013b98b"),
+        enc: "040d5176aedba55bc41709261e9195c5146bb62d783031280775f32e507d79b5cbc5748b6be6359760c73cfe10ca19521af704ca6d91ff32fc0739527b9385d415",
+        ct: "25881f219935eec5ba70d7b421f13c35005734f3e4d959680270f55d71e2f5cb3bd2daced2770bf3d9d4916872",
+    },
+];
+
+#[test]
+fn test_rfc9180_vectors() {
+    let crypto = ElasticCrypto::new().unwrap();
+    for vector in &VECTORS {
+        let handle = crypto.import_key(unhex(vector.sk_r), agreement(vector.algorithm)).unwrap();
+        let sealed = [unhex(vector.enc), unhex(vector.ct)].concat();
+        let opened = match vector.pk_s {
+            Some(pk_s) => crypto.hpke_open_auth(handle, unhex(pk_s), sealed, unhex(INFO), unhex(AAD), vector.aead),
+            None => crypto.hpke_open(handle, sealed, unhex(INFO), unhex(AAD), vector.aead),
+        };
+        assert_eq!(opened.unwrap(), unhex(PT), "{:?} {:?}", vector.algorithm, vector.aead);
+    }
+}
+
+#[test]
+fn test_seal_to_workload() {
+    let crypto = ElasticCrypto::new().unwrap();
+    for algorithm in [KeyAlgorithm::X25519, KeyAlgorithm::EcdhP256] {
+        for aead in [HpkeAead::Aes256Gcm, HpkeAead::ChaCha20Poly1305] {
+            let workload = crypto.generate_key(agreement(algorithm)).unwrap();
+            let public_key = crypto.get_public_key(workload).unwrap();
+
+            let sealed = crypto
+                .hpke_seal(public_key.clone(), b"db-password".to_vec(), Vec::new(), b"hunter2".to_vec(), aead)
+                .unwrap();
+            let opened = crypto
+                .hpke_open(workload, sealed.clone(), b"db-password".to_vec(), Vec::new(), aead)
+                .unwrap();
+            assert_eq!(opened, b"hunter2");
+
+            // info and aad are bound to the message.
+            assert!(matches!(
+                crypto.hpke_open(workload, sealed.clone(), b"other".to_vec(), Vec::new(), aead),
+                Err(Error::DecryptionFailed)
+            ));
+            assert!(matches!(
+                crypto.hpke_open(workload, sealed, b"db-password".to_vec(), b"aad".to_vec(), aead),
+                Err(Error::DecryptionFailed)
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_auth_mode() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let sender = crypto.generate_key(agreement(KeyAlgorithm::X25519)).unwrap();
+    let impostor = crypto.generate_key(agreement(KeyAlgorithm::X25519)).unwrap();
+    let recipient = crypto.generate_key(agreement(KeyAlgorithm::X25519)).unwrap();
+    let recipient_pk = crypto.get_public_key(recipient).unwrap();
+    let aead = HpkeAead::ChaCha20Poly1305;
+
+    let sealed = crypto
+        .hpke_seal_auth(sender, recipient_pk.clone(), b"info".to_vec(), b"aad".to_vec(), b"secret".to_vec(), aead)
+        .unwrap();
+    let sender_pk = crypto.get_public_key(sender).unwrap();
+    let opened = crypto
+        .hpke_open_auth(recipient, sender_pk, sealed.clone(), b"info".to_vec(), b"aad".to_vec(), aead)
+        .unwrap();
+    assert_eq!(opened, b"secret");
+
+    let impostor_pk = crypto.get_public_key(impostor).unwrap();
+    assert!(matches!(
+        crypto.hpke_open_auth(recipient, impostor_pk, sealed.clone(), b"info".to_vec(), b"aad".to_vec(), aead),
+        Err(Error::DecryptionFailed)
+    ));
+    // An auth-mode message does not open in base mode.
+    assert!(matches!(
+        crypto.hpke_open(recipient, sealed, b"info".to_vec(), b"aad".to_vec(), aead),
+        Err(Error::DecryptionFailed)
+    ));
+
+    // The sender key must use the recipient's KEM.
+    let p256_sender = crypto.generate_key(agreement(KeyAlgorithm::EcdhP256)).unwrap();
+    assert!(matches!(
+        crypto.hpke_seal_auth(p256_sender, recipient_pk, Vec::new(), Vec::new(), Vec::new(), aead),
+        Err(Error::UnsupportedOperation)
+    ));
+}
+
+#[test]
+fn test_invalid_inputs() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let aead = HpkeAead::Aes256Gcm;
+    assert!(matches!(
+        crypto.hpke_seal(vec![0u8; 33], Vec::new(), Vec::new(), Vec::new(), aead),
+        Err(Error::InvalidEncoding(_))
+    ));
+
+    let signing = crypto.generate_key(agreement(KeyAlgorithm::Ed25519)).unwrap();
+    assert!(matches!(
+        crypto.hpke_open(signing, vec![0u8; 64], Vec::new(), Vec::new(), aead),
+        Err(Error::UnsupportedOperation)
+    ));
+
+    let workload = crypto.generate_key(agreement(KeyAlgorithm::X25519)).unwrap();
+    assert!(matches!(
+        crypto.hpke_open(workload, vec![0u8; 8], Vec::new(), Vec::new(), aead),
+        Err(Error::InvalidCiphertext)
+    ));
+}
@@ -0,0 +1,214 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use elastic_crypto::jose::{jwe_encrypt_to_jwk, jws_verify_with_jwk, Jwk};
+use elastic_crypto::{ElasticCrypto, Error, JweAlgorithm, KeyAlgorithm, KeyConfig, KeyType};
+
+fn config(key_type: KeyType, key_size: u32, algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        key_type,
+        key_size,
+        secure_storage: true,
+        algorithm: Some(algorithm),
+    }
+}
+
+fn b64(data: &str) -> Vec<u8> {
+    URL_SAFE_NO_PAD.decode(data).unwrap()
+}
+
+fn header(token: &str) -> serde_json::Value {
+    let protected = token.split('.').next().unwrap();
+    serde_json::from_slice(&b64(protected)).unwrap()
+}
+
+#[test]
+fn test_jws_compact_all_algorithms() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let keys = [
+        (config(KeyType::Asymmetric, 256, KeyAlgorithm::Ed25519), "EdDSA"),
+        (config(KeyType::Asymmetric, 256, KeyAlgorithm::EcdsaP256), "ES256"),
+        (config(KeyType::Asymmetric, 2048, KeyAlgorithm::RsaPssSha256), "PS256"),
+        (config(KeyType::Hmac, 256, KeyAlgorithm::HmacSha256), "HS256"),
+    ];
+
+    for (config, alg) in keys {
+        let handle = crypto.generate_key(config).unwrap();
+        let token = crypto.jws_sign(handle, b"{\"sub\":\"workload\"}".to_vec()).unwrap();
+        assert_eq!(token.split('.').count(), 3);
+
+        let header = header(&token);
+        assert_eq!(header["alg"], alg);
+        assert_eq!(header["kid"], URL_SAFE_NO_PAD.encode(crypto.get_key_id(handle).unwrap()));
+
+        assert_eq!(crypto.jws_verify(handle, &token).unwrap(), b"{\"sub\":\"workload\"}");
+        if alg != "HS256" {
+            let jwk = crypto.get_public_jwk(handle).unwrap();
+            assert_eq!(jws_verify_with_jwk(&jwk, &token).unwrap(), b"{\"sub\":\"workload\"}");
+        }
+
+        let mut tampered = token.clone();
+        tampered.insert_str(token.find('.').unwrap() + 1, "e30");
+        assert!(matches!(crypto.jws_verify(handle, &tampered), Err(Error::VerificationFailed)));
+    }
+}
+
+#[test]
+fn test_jws_json_serialisation() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let ed = crypto.generate_key(config(KeyType::Asymmetric, 256, KeyAlgorithm::Ed25519)).unwrap();
+    let es = crypto.generate_key(config(KeyType::Asymmetric, 256, KeyAlgorithm::EcdsaP256)).unwrap();
+    let other = crypto.generate_key(config(KeyType::Asymmetric, 256, KeyAlgorithm::Ed25519)).unwrap();
+
+    let jws = crypto.jws_sign_json(vec![ed, es], b"payload".to_vec()).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&jws).unwrap();
+    assert_eq!(json["signatures"].as_array().unwrap().len(), 2);
+
+    assert_eq!(crypto.jws_verify(ed, &jws).unwrap(), b"payload");
+    assert_eq!(crypto.jws_verify(es, &jws).unwrap(), b"payload");
+    assert!(matches!(crypto.jws_verify(other, &jws), Err(Error::VerificationFailed)));
+
+    // Flattened form built from the compact one.
+    let compact = crypto.jws_sign(ed, b"payload".to_vec()).unwrap();
+    let parts: Vec<&str> = compact.split('.').collect();
+    let flattened = serde_json::json!({"protected": parts[0], "payload": parts[1], "signature": parts[2]});
+    assert_eq!(crypto.jws_verify(ed, &flattened.to_string()).unwrap(), b"payload");
+}
+
+#[test]
+fn test_jws_kid_compared_only_when_both_sides_have_one() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto.generate_key(config(KeyType::Asymmetric, 256, KeyAlgorithm::Ed25519)).unwrap();
+    let token = crypto.jws_sign(handle, b"payload".to_vec()).unwrap();
+    assert!(header(&token)["kid"].is_string());
+
+    // A JWK without a kid still verifies a token that names one.
+    let mut jwk = Jwk::from_json(&crypto.get_public_jwk(handle).unwrap()).unwrap();
+    jwk.kid = None;
+    assert_eq!(jws_verify_with_jwk(&jwk.to_json().unwrap(), &token).unwrap(), b"payload");
+
+    // Both sides naming different keys is a mismatch.
+    jwk.kid = Some("other".to_string());
+    let jwk = jwk.to_json().unwrap();
+    assert!(matches!(jws_verify_with_jwk(&jwk, &token), Err(Error::VerificationFailed)));
+}
+
+#[test]
+fn test_jws_rfc_vectors() {
+    let crypto = ElasticCrypto::new().unwrap();
+
+    // RFC 8037 appendix A.4: Ed25519
+    let ed = crypto
+        .import_key(
+            b64("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A"),
+            config(KeyType::Asymmetric, 256, KeyAlgorithm::Ed25519),
+        )
+        .unwrap();
+    let jwk = Jwk::from_json(&crypto.get_public_jwk(ed).unwrap()).unwrap();
+    assert_eq!(jwk.kty, "OKP");
+    assert_eq!(jwk.x.as_deref(), Some("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"));
+    let token = "eyJhbGciOiJFZERTQSJ9.RXhhbXBsZSBvZiBFZDI1NTE5IHNpZ25pbmc.\
+                 hgyY0il_MGCjP0JzlnLWG1PPOt7-09PGcvMg3AIbQR6dWbhijcNR4ki4iylGjg5BhVsPt9g7sVvpAr_MuM0KAg";
+    assert_eq!(crypto.jws_verify(ed, token).unwrap(), b"Example of Ed25519 signing");
+    let public_jwk = r#"{"kty":"OKP","crv":"Ed25519","x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#;
+    assert_eq!(jws_verify_with_jwk(public_jwk, token).unwrap(), b"Example of Ed25519 signing");
+
+    // RFC 7515 appendix A.1: HS256
+    let hs = crypto
+        .import_key(
+            b64("AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow"),
+            config(KeyType::Hmac, 512, KeyAlgorithm::HmacSha256),
+        )
+        .unwrap();
+    let token = "eyJ0eXAiOiJKV1QiLA0KICJhbGciOiJIUzI1NiJ9.\
+                 eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290Ijp0cnVlfQ.\
+                 dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let payload = crypto.jws_verify(hs, token).unwrap();
+    assert!(payload.starts_with(b"{\"iss\":\"joe\""));
+
+    // An alg other than the key's is refused, whatever the signature.
+    assert!(matches!(crypto.jws_verify(ed, token), Err(Error::VerificationFailed)));
+}
+
+#[test]
+fn test_jwe_round_trips() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let aes = crypto.generate_key(config(KeyType::Symmetric, 256, KeyAlgorithm::Aes256Gcm)).unwrap();
+    let ecdh = crypto.generate_key(config(KeyType::Asymmetric, 256, KeyAlgorithm::EcdhP256)).unwrap();
+
+    for (handle, algorithm, alg) in [
+        (aes, JweAlgorithm::Dir, "dir"),
+        (aes, JweAlgorithm::A256Kw, "A256KW"),
+        (ecdh, JweAlgorithm::EcdhEs, "ECDH-ES"),
+    ] {
+        let token = crypto.jwe_encrypt(handle, b"secret".to_vec(), algorithm).unwrap();
+        assert_eq!(token.split('.').count(), 5);
+        assert_eq!(header(&token)["alg"], alg);
+        assert_eq!(header(&token)["enc"], "A256GCM");
+        assert_eq!(crypto.jwe_decrypt(handle, &token).unwrap(), b"secret");
+
+        let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+        parts[3] = URL_SAFE_NO_PAD.encode(b"forged");
+        assert!(matches!(crypto.jwe_decrypt(handle, &parts.join(".")), Err(Error::DecryptionFailed)));
+    }
+
+    // Encrypting to an exported public JWK needs no access to the private key.
+    let jwk = crypto.get_public_jwk(ecdh).unwrap();
+    let token = jwe_encrypt_to_jwk(&jwk, b"for the holder").unwrap();
+    assert_eq!(crypto.jwe_decrypt(ecdh, &token).unwrap(), b"for the holder");
+
+    assert!(matches!(
+        crypto.jwe_encrypt(ecdh, b"x".to_vec(), JweAlgorithm::Dir),
+        Err(Error::UnsupportedOperation)
+    ));
+    let dir = crypto.jwe_encrypt(aes, b"x".to_vec(), JweAlgorithm::Dir).unwrap();
+    assert!(matches!(crypto.jwe_decrypt(ecdh, &dir), Err(Error::DecryptionFailed)));
+}
+
+#[test]
+fn test_jwe_external_tokens() {
+    let crypto = ElasticCrypto::new().unwrap();
+
+    // ECDH-ES to Bob's key from RFC 7518 appendix C, with apu/apv, made with
+    // Python's cryptography package.
+    let bob = crypto
+        .import_key(
+            b64("VEmDZpDXXK8p8N0Cndsxs924q6nS1RXFASRl6BfUqdw"),
+            config(KeyType::Asymmetric, 256, KeyAlgorithm::EcdhP256),
+        )
+        .unwrap();
+    let token = "eyJhbGciOiJFQ0RILUVTIiwiZW5jIjoiQTI1NkdDTSIsImFwdSI6IlFXeHBZMlUiLCJhcHYiOiJRbTlpIiwiZXBrIjp7Imt0\
+                 eSI6IkVDIiwiY3J2IjoiUC0yNTYiLCJ4IjoiZ0kwR0FJTEJkdTdUNTNha3JGbU15R2NzRjNuNWRPN01td05CSEtXNVNWMCIs\
+                 InkiOiJTTFdfeFNmZnpsUFdySEVWSTMwREhNXzRlZ1Z3dDNOUXFlVUQ3bk1GcHBzIn19..AAECAwQFBgcICQoL.\
+                 12KqQfmSY80vllPGecu_LZo8sl2MCQ.30HFINbElAJpcyALhppx8Q";
+    assert_eq!(crypto.jwe_decrypt(bob, token).unwrap(), b"Live long and prosper.");
+
+    let kek = crypto
+        .import_key((0u8..32).collect(), config(KeyType::Symmetric, 256, KeyAlgorithm::Aes256Gcm))
+        .unwrap();
+    let token = "eyJhbGciOiJBMjU2S1ciLCJlbmMiOiJBMjU2R0NNIn0.\
+                 BPijw8MC07C36UsU3Pha0dppzXQFbteQfTy0n7J3maQQTbBY8pAa2w.AAECAwQFBgcICQoL.\
+                 CDo4hz5HnomciqChSFMxWjMZhxAPfcp4Q2i92d6housMsH1JO3Qqip0Xn5yrrgrTR33NlzcykOzIXHjAStNN.\
+                 SNw68SxSH5KLXYhcbUju_A";
+    assert_eq!(
+        crypto.jwe_decrypt(kek, token).unwrap(),
+        b"The true sign of intelligence is not knowledge but imagination."
+    );
+}
+
+#[test]
+fn test_jwk_export() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let es = crypto.generate_key(config(KeyType::Asymmetric, 256, KeyAlgorithm::EcdsaP256)).unwrap();
+    let jwk = Jwk::from_json(&crypto.get_public_jwk(es).unwrap()).unwrap();
+    assert_eq!((jwk.kty.as_str(), jwk.crv.as_deref()), ("EC", Some("P-256")));
+    assert_eq!(jwk.alg.as_deref(), Some("ES256"));
+    assert_eq!(jwk.key_use.as_deref(), Some("sig"));
+    assert_eq!(jwk.kid, Some(URL_SAFE_NO_PAD.encode(crypto.get_key_id(es).unwrap())));
+    assert_eq!(jwk.public_key().unwrap(), (KeyAlgorithm::EcdsaP256, crypto.get_public_key(es).unwrap()));
+
+    // Secure-storage keys refuse export but still publish their public half.
+    assert!(matches!(crypto.export_key(es), Err(Error::OperationNotPermitted)));
+
+    let aes = crypto.generate_key(config(KeyType::Symmetric, 256, KeyAlgorithm::Aes256Gcm)).unwrap();
+    assert!(matches!(crypto.get_public_jwk(aes), Err(Error::UnsupportedOperation)));
+}
@@ -0,0 +1,135 @@
+use elastic_crypto::kms::mock::{MockAttester, MockKms};
+use elastic_crypto::kms::{KmsClient, KmsConfig, RemoteWrappedKey};
+use elastic_crypto::{ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType};
+
+const PILOT_MEASUREMENT: [u8; 48] = [0xa5; 48];
+const MASTER_KEY: &str = "pilot-master";
+
+fn mock_kms() -> MockKms {
+    let kms = MockKms::start().unwrap();
+    kms.create_key(MASTER_KEY).unwrap();
+    kms.allow_measurement(PILOT_MEASUREMENT);
+    kms
+}
+
+fn client(kms: &MockKms, measurement: [u8; 48]) -> KmsClient {
+    KmsClient::new(kms.config(), Box::new(MockAttester::new(measurement))).unwrap()
+}
+
+fn hmac_config() -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Hmac,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: Some(KeyAlgorithm::HmacSha256),
+    }
+}
+
+#[test]
+fn test_data_key_round_trip() {
+    let kms = mock_kms();
+    let kms_client = client(&kms, PILOT_MEASUREMENT);
+
+    let crypto = ElasticCrypto::new().unwrap();
+    let (handle, wrapped) = crypto
+        .generate_remote_data_key(&kms_client, MASTER_KEY, KeyConfig::default())
+        .unwrap();
+    assert_eq!(wrapped.kms_key_id, MASTER_KEY);
+    let ciphertext = crypto.encrypt(handle, b"migrated record".to_vec()).unwrap();
+    // Remote keys are held in secure storage whatever the config asked for.
+    assert!(matches!(crypto.export_key(handle), Err(Error::OperationNotPermitted)));
+
+    // Another attested instance can load the stored wrapped key.
+    let wrapped: RemoteWrappedKey = serde_json::from_str(&serde_json::to_string(&wrapped).unwrap()).unwrap();
+    let other = ElasticCrypto::new().unwrap();
+    let loaded = other
+        .load_remote_key(&client(&kms, PILOT_MEASUREMENT), &wrapped, KeyConfig::default())
+        .unwrap();
+    assert_eq!(other.decrypt(loaded, ciphertext).unwrap(), b"migrated record");
+}
+
+#[test]
+fn test_wrap_existing_key() {
+    let kms = mock_kms();
+    let kms_client = client(&kms, PILOT_MEASUREMENT);
+    let crypto = ElasticCrypto::new().unwrap();
+
+    let key_data = vec![0x3c; 32];
+    let wrapped = kms_client.wrap_key(MASTER_KEY, &key_data).unwrap();
+    assert_ne!(wrapped.ciphertext, key_data);
+    let remote = crypto.load_remote_key(&kms_client, &wrapped, hmac_config()).unwrap();
+    let local = crypto.import_key(key_data, hmac_config()).unwrap();
+    assert_eq!(
+        crypto.calculate_mac(remote, b"data".to_vec()).unwrap(),
+        crypto.calculate_mac(local, b"data".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn test_unwrap_requires_attestation() {
+    let kms = mock_kms();
+    let crypto = ElasticCrypto::new().unwrap();
+    let wrapped = client(&kms, PILOT_MEASUREMENT).wrap_key(MASTER_KEY, &[0x11; 32]).unwrap();
+
+    // A guest with another measurement is refused and gets no key.
+    let untrusted = client(&kms, [0x00; 48]);
+    assert!(matches!(
+        crypto.load_remote_key(&untrusted, &wrapped, KeyConfig::default()),
+        Err(Error::AttestationFailed(_))
+    ));
+
+    // Expired sessions are renewed by attesting again.
+    let trusted = client(&kms, PILOT_MEASUREMENT);
+    trusted.attest().unwrap();
+    kms.expire_sessions();
+    crypto.load_remote_key(&trusted, &wrapped, KeyConfig::default()).unwrap();
+
+    // Revoking the measurement ends the session and blocks re-attestation.
+    kms.revoke_measurement(PILOT_MEASUREMENT);
+    assert!(matches!(
+        crypto.load_remote_key(&trusted, &wrapped, KeyConfig::default()),
+        Err(Error::AttestationFailed(_))
+    ));
+}
+
+#[test]
+fn test_kms_errors() {
+    let kms = mock_kms();
+    let kms_client = client(&kms, PILOT_MEASUREMENT);
+    let crypto = ElasticCrypto::new().unwrap();
+
+    let mut wrapped = kms_client.wrap_key(MASTER_KEY, &[0x11; 32]).unwrap();
+    wrapped.ciphertext[20] ^= 1;
+    assert!(matches!(
+        crypto.load_remote_key(&kms_client, &wrapped, KeyConfig::default()),
+        Err(Error::Kms(_))
+    ));
+    assert!(matches!(kms_client.wrap_key("unknown", &[0x11; 32]), Err(Error::Kms(_))));
+
+    // Only symmetric and HMAC data keys can be generated remotely.
+    let signing = KeyConfig {
+        key_type: KeyType::Asymmetric,
+        algorithm: Some(KeyAlgorithm::Ed25519),
+        ..KeyConfig::default()
+    };
+    assert!(matches!(
+        crypto.generate_remote_data_key(&kms_client, MASTER_KEY, signing),
+        Err(Error::UnsupportedOperation)
+    ));
+
+    // Plain HTTP and untrusted certificates are refused.
+    let insecure = KmsConfig {
+        endpoint: kms.endpoint().replace("https", "http"),
+        ..kms.config()
+    };
+    assert!(matches!(
+        KmsClient::new(insecure, Box::new(MockAttester::new(PILOT_MEASUREMENT))),
+        Err(Error::Kms(_))
+    ));
+    let public_roots = KmsConfig {
+        root_certificate: None,
+        ..kms.config()
+    };
+    let untrusted_tls = KmsClient::new(public_roots, Box::new(MockAttester::new(PILOT_MEASUREMENT))).unwrap();
+    assert!(matches!(untrusted_tls.attest(), Err(Error::Kms(_))));
+}
@@ -0,0 +1,254 @@
+use elastic_crypto::{
+    ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType, NoiseCipherState, NoiseHandshake, NoisePattern,
+    NoiseSuite,
+};
+
+const PATTERNS: [NoisePattern; 3] = [NoisePattern::Xx, NoisePattern::Ik, NoisePattern::Nk];
+const SUITES: [NoiseSuite; 2] = [NoiseSuite::ChaChaPolyBlake2s, NoiseSuite::AesGcmSha256];
+
+fn x25519() -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Asymmetric,
+        key_size: 256,
+        secure_storage: true,
+        algorithm: Some(KeyAlgorithm::X25519),
+    }
+}
+
+fn protocol_name(pattern: NoisePattern, suite: NoiseSuite) -> String {
+    let pattern = match pattern {
+        NoisePattern::Xx => "XX",
+        NoisePattern::Ik => "IK",
+        NoisePattern::Nk => "NK",
+    };
+    let suite = match suite {
+        NoiseSuite::ChaChaPolyBlake2s => "ChaChaPoly_BLAKE2s",
+        NoiseSuite::AesGcmSha256 => "AESGCM_SHA256",
+    };
+    format!("Noise_{}_25519_{}", pattern, suite)
+}
+
+fn message_count(pattern: NoisePattern) -> usize {
+    if pattern == NoisePattern::Xx {
+        3
+    } else {
+        2
+    }
+}
+
+fn snow_write(state: &mut snow::HandshakeState, payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0u8; 65535];
+    let len = state.write_message(payload, &mut buf).unwrap();
+    buf.truncate(len);
+    buf
+}
+
+fn snow_read(state: &mut snow::HandshakeState, message: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0u8; 65535];
+    let len = state.read_message(message, &mut buf).unwrap();
+    buf.truncate(len);
+    buf
+}
+
+fn check_transport(send: &mut NoiseCipherState, receive: &mut NoiseCipherState, peer: &mut snow::TransportState) {
+    let mut buf = vec![0u8; 65535];
+    for i in 0..3u8 {
+        let ciphertext = send.encrypt(&[i; 40]).unwrap();
+        let len = peer.read_message(&ciphertext, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[i; 40]);
+
+        let len = peer.write_message(&[i; 7], &mut buf).unwrap();
+        assert_eq!(receive.decrypt(&buf[..len]).unwrap(), [i; 7]);
+    }
+}
+
+#[test]
+fn test_initiator_interop() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let prologue = b"elastic device link".to_vec();
+    for pattern in PATTERNS {
+        for suite in SUITES {
+            let name = protocol_name(pattern, suite);
+            let builder = snow::Builder::new(name.parse().unwrap());
+            let responder_keys = builder.generate_keypair().unwrap();
+            let mut responder = snow::Builder::new(name.parse().unwrap())
+                .local_private_key(&responder_keys.private)
+                .prologue(&prologue)
+                .build_responder()
+                .unwrap();
+
+            let static_handle = match pattern {
+                NoisePattern::Nk => None,
+                _ => Some(crypto.generate_key(x25519()).unwrap()),
+            };
+            let responder_static = match pattern {
+                NoisePattern::Xx => None,
+                _ => Some(responder_keys.public.clone()),
+            };
+            let mut initiator = crypto
+                .noise_initiator(pattern, suite, static_handle, responder_static, prologue.clone())
+                .unwrap();
+
+            for i in 0..message_count(pattern) {
+                let payload = format!("message {}", i).into_bytes();
+                if i % 2 == 0 {
+                    let message = initiator.write_message(&payload).unwrap();
+                    assert_eq!(snow_read(&mut responder, &message), payload, "{}", name);
+                } else {
+                    let message = snow_write(&mut responder, &payload);
+                    assert_eq!(initiator.read_message(&message).unwrap(), payload, "{}", name);
+                }
+            }
+            assert!(initiator.is_finished());
+            assert_eq!(initiator.handshake_hash(), responder.get_handshake_hash());
+            assert_eq!(initiator.remote_static().unwrap(), responder_keys.public);
+            if let Some(handle) = static_handle {
+                assert_eq!(responder.get_remote_static().unwrap(), crypto.get_public_key(handle).unwrap());
+            }
+
+            let (mut send, mut receive) = initiator.split().unwrap();
+            let mut peer = responder.into_transport_mode().unwrap();
+            check_transport(&mut send, &mut receive, &mut peer);
+        }
+    }
+}
+
+#[test]
+fn test_responder_interop() {
+    let crypto = ElasticCrypto::new().unwrap();
+    for pattern in PATTERNS {
+        for suite in SUITES {
+            let name = protocol_name(pattern, suite);
+            let static_handle = crypto.generate_key(x25519()).unwrap();
+            let static_public = crypto.get_public_key(static_handle).unwrap();
+            let initiator_keys = snow::Builder::new(name.parse().unwrap()).generate_keypair().unwrap();
+
+            let mut builder = snow::Builder::new(name.parse().unwrap());
+            if pattern != NoisePattern::Nk {
+                builder = builder.local_private_key(&initiator_keys.private);
+            }
+            if pattern != NoisePattern::Xx {
+                builder = builder.remote_public_key(&static_public);
+            }
+            let mut initiator = builder.build_initiator().unwrap();
+            let mut responder = crypto.noise_responder(pattern, suite, static_handle, Vec::new()).unwrap();
+
+            for i in 0..message_count(pattern) {
+                let payload = format!("message {}", i).into_bytes();
+                if i % 2 == 0 {
+                    let message = snow_write(&mut initiator, &payload);
+                    assert_eq!(responder.read_message(&message).unwrap(), payload, "{}", name);
+                } else {
+                    let message = responder.write_message(&payload).unwrap();
+                    assert_eq!(snow_read(&mut initiator, &message), payload, "{}", name);
+                }
+            }
+            assert_eq!(responder.handshake_hash(), initiator.get_handshake_hash());
+            if pattern != NoisePattern::Nk {
+                assert_eq!(responder.remote_static().unwrap(), initiator_keys.public);
+            }
+
+            let (mut send, mut receive) = responder.split().unwrap();
+            let mut peer = initiator.into_transport_mode().unwrap();
+            check_transport(&mut send, &mut receive, &mut peer);
+        }
+    }
+}
+
+fn handshake(initiator: &mut NoiseHandshake, responder: &mut NoiseHandshake) -> Result<(), Error> {
+    while !initiator.is_finished() {
+        let message = initiator.write_message(b"")?;
+        responder.read_message(&message)?;
+        if responder.is_finished() {
+            break;
+        }
+        let message = responder.write_message(b"")?;
+        initiator.read_message(&message)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_handle_to_handle_channel() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let device = crypto.generate_key(x25519()).unwrap();
+    let cvm = crypto.generate_key(x25519()).unwrap();
+    let cvm_public = crypto.get_public_key(cvm).unwrap();
+
+    let mut initiator = crypto
+        .noise_initiator(NoisePattern::Ik, NoiseSuite::AesGcmSha256, Some(device), Some(cvm_public), Vec::new())
+        .unwrap();
+    let mut responder = crypto.noise_responder(NoisePattern::Ik, NoiseSuite::AesGcmSha256, cvm, Vec::new()).unwrap();
+    handshake(&mut initiator, &mut responder).unwrap();
+    assert_eq!(responder.remote_static().unwrap(), crypto.get_public_key(device).unwrap());
+
+    let (mut device_send, mut device_receive) = initiator.split().unwrap();
+    let (mut cvm_send, mut cvm_receive) = responder.split().unwrap();
+    let reading = device_send.encrypt(b"temperature=21.5").unwrap();
+    assert_eq!(cvm_receive.decrypt(&reading).unwrap(), b"temperature=21.5");
+    let ack = cvm_send.encrypt(b"ack").unwrap();
+    assert_eq!(device_receive.decrypt(&ack).unwrap(), b"ack");
+
+    // Both sides rekey in step.
+    device_send.rekey().unwrap();
+    cvm_receive.rekey().unwrap();
+    let reading = device_send.encrypt(b"temperature=21.6").unwrap();
+    assert_eq!(cvm_receive.decrypt(&reading).unwrap(), b"temperature=21.6");
+
+    // Tampered and replayed messages are rejected without advancing the nonce.
+    let reading = device_send.encrypt(b"temperature=21.7").unwrap();
+    let mut tampered = reading.clone();
+    tampered[0] ^= 1;
+    assert!(matches!(cvm_receive.decrypt(&tampered), Err(Error::DecryptionFailed)));
+    assert_eq!(cvm_receive.decrypt(&reading).unwrap(), b"temperature=21.7");
+    assert!(matches!(cvm_receive.decrypt(&reading), Err(Error::DecryptionFailed)));
+}
+
+#[test]
+fn test_handshake_failures() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let cvm = crypto.generate_key(x25519()).unwrap();
+    let other = crypto.generate_key(x25519()).unwrap();
+    let suite = NoiseSuite::ChaChaPolyBlake2s;
+
+    // NK to the wrong responder key fails on the first message.
+    let wrong_public = crypto.get_public_key(other).unwrap();
+    let mut initiator = crypto
+        .noise_initiator(NoisePattern::Nk, suite, None, Some(wrong_public), Vec::new())
+        .unwrap();
+    let mut responder = crypto.noise_responder(NoisePattern::Nk, suite, cvm, Vec::new()).unwrap();
+    assert!(matches!(handshake(&mut initiator, &mut responder), Err(Error::DecryptionFailed)));
+
+    // Mismatched prologues fail.
+    let mut initiator = crypto
+        .noise_initiator(NoisePattern::Xx, suite, Some(other), None, b"v1".to_vec())
+        .unwrap();
+    let mut responder = crypto.noise_responder(NoisePattern::Xx, suite, cvm, b"v2".to_vec()).unwrap();
+    assert!(matches!(handshake(&mut initiator, &mut responder), Err(Error::DecryptionFailed)));
+
+    // Out-of-turn and unfinished use.
+    let mut responder = crypto.noise_responder(NoisePattern::Xx, suite, cvm, Vec::new()).unwrap();
+    assert!(matches!(responder.write_message(b""), Err(Error::NoiseProtocol(_))));
+    assert!(matches!(responder.read_message(&[0u8; 8]), Err(Error::NoiseProtocol(_))));
+    assert!(matches!(responder.split(), Err(Error::NoiseProtocol(_))));
+
+    // Pattern requirements on keys.
+    assert!(matches!(
+        crypto.noise_initiator(NoisePattern::Ik, suite, Some(other), None, Vec::new()),
+        Err(Error::NoiseProtocol(_))
+    ));
+    assert!(matches!(
+        crypto.noise_initiator(NoisePattern::Xx, suite, None, None, Vec::new()),
+        Err(Error::NoiseProtocol(_))
+    ));
+    let signing = crypto
+        .generate_key(KeyConfig {
+            algorithm: Some(KeyAlgorithm::Ed25519),
+            ..x25519()
+        })
+        .unwrap();
+    assert!(matches!(
+        crypto.noise_responder(NoisePattern::Xx, suite, signing, Vec::new()),
+        Err(Error::UnsupportedOperation)
+    ));
+}
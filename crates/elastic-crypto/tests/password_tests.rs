@@ -0,0 +1,59 @@
+use elastic_crypto::password::{password_hash, password_verify};
+use elastic_crypto::{ElasticCrypto, Error, PasswordParams};
+
+// Low costs keep the tests fast in debug builds.
+const ARGON2_TEST: PasswordParams = PasswordParams::Argon2id {
+    memory_kib: 1024,
+    iterations: 1,
+    parallelism: 1,
+};
+
+#[test]
+fn test_argon2id_hash_and_verify() {
+    let crypto = ElasticCrypto::new().unwrap();
+
+    let phc = crypto.password_hash(b"hunter2".to_vec(), ARGON2_TEST).unwrap();
+    assert!(phc.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert!(crypto.password_verify(b"hunter2".to_vec(), &phc).unwrap());
+    assert!(!crypto.password_verify(b"hunter3".to_vec(), &phc).unwrap());
+
+    // Fresh salt per hash
+    let again = crypto.password_hash(b"hunter2".to_vec(), ARGON2_TEST).unwrap();
+    assert_ne!(phc, again);
+}
+
+#[test]
+fn test_legacy_algorithms() {
+    let scrypt = password_hash(b"secret", &PasswordParams::Scrypt { log_n: 10, r: 8, p: 1 }).unwrap();
+    assert!(scrypt.starts_with("$scrypt$ln=10,r=8,p=1$"));
+    assert!(password_verify(b"secret", &scrypt).unwrap());
+
+    let pbkdf2 = password_hash(b"secret", &PasswordParams::Pbkdf2Sha256 { iterations: 1000 }).unwrap();
+    assert!(pbkdf2.starts_with("$pbkdf2-sha256$i=1000,l=32$"));
+    assert!(password_verify(b"secret", &pbkdf2).unwrap());
+    assert!(!password_verify(b"Secret", &pbkdf2).unwrap());
+}
+
+#[test]
+fn test_verify_hashes_from_other_implementations() {
+    // Produced with Python's hashlib for the same password and salt.
+    let password = b"correct horse battery staple";
+    let pbkdf2 = "$pbkdf2-sha256$i=1000,l=32$ZWxhc3RpYy1zYWx0LTE2Yg$3V8Qo/VPWAbRqKEOAV1eCJ2VkqySn/1vwyHkozLDTfA";
+    let scrypt = "$scrypt$ln=10,r=8,p=1$ZWxhc3RpYy1zYWx0LTE2Yg$1jlY6JT48b3GoLikvsJfgFoFHdTn5BWmO+6Gyl2r7cw";
+
+    assert!(password_verify(password, pbkdf2).unwrap());
+    assert!(password_verify(password, scrypt).unwrap());
+    assert!(!password_verify(b"wrong", scrypt).unwrap());
+}
+
+#[test]
+fn test_invalid_inputs() {
+    assert!(matches!(password_verify(b"x", "not-a-phc-string"), Err(Error::InvalidPasswordHash(_))));
+    assert!(matches!(
+        password_verify(b"x", "$bcrypt$v=1$c2FsdHNhbHQ$aGFzaGhhc2g"),
+        Err(Error::InvalidPasswordHash(_))
+    ));
+
+    let params = PasswordParams::Argon2id { memory_kib: 1, iterations: 0, parallelism: 1 };
+    assert!(matches!(password_hash(b"x", &params), Err(Error::InvalidPasswordParams(_))));
+}
@@ -0,0 +1,48 @@
+use elastic_crypto::{ElasticCrypto, Error, KeyConfig, KeyType, SelfTestAlgorithm};
+use std::env;
+use std::sync::Mutex;
+
+// Fault injection goes through the environment, so tests creating contexts must not overlap.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn symmetric_config() -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Symmetric,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: None,
+    }
+}
+
+#[test]
+fn test_self_tests_pass() {
+    let _guard = ENV_LOCK.lock().unwrap();
+    let crypto = ElasticCrypto::new().unwrap();
+
+    let report = crypto.self_test_report();
+    assert!(report.passed(), "failures: {:?}", report.failures());
+    assert_eq!(report.results.len(), SelfTestAlgorithm::ALL.len());
+    assert!(report.completed_at > 0);
+
+    let handle = crypto.generate_key(symmetric_config()).unwrap();
+    let ciphertext = crypto.encrypt(handle, b"self-test".to_vec()).unwrap();
+    assert_eq!(crypto.decrypt(handle, ciphertext).unwrap(), b"self-test");
+}
+
+#[test]
+fn test_self_test_failure_refuses_operations() {
+    let _guard = ENV_LOCK.lock().unwrap();
+    env::set_var("ELASTIC_SELFTEST_FAULT", "aes-256-gcm,hmac-drbg-sha-256");
+    let crypto = ElasticCrypto::new();
+    env::remove_var("ELASTIC_SELFTEST_FAULT");
+    let crypto = crypto.unwrap();
+
+    let report = crypto.self_test_report();
+    assert!(!report.passed());
+    let failed: Vec<_> = report.failures().iter().map(|r| r.algorithm).collect();
+    assert_eq!(failed, vec![SelfTestAlgorithm::Aes256Gcm, SelfTestAlgorithm::HmacDrbg]);
+
+    assert!(matches!(crypto.generate_key(symmetric_config()), Err(Error::SelfTestFailed(_))));
+    assert!(matches!(crypto.hash(b"data".to_vec()), Err(Error::SelfTestFailed(_))));
+    assert!(matches!(crypto.encrypt(1, b"data".to_vec()), Err(Error::SelfTestFailed(_))));
+}
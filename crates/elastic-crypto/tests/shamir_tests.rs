@@ -0,0 +1,131 @@
+use elastic_crypto::{ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType};
+
+fn secure(key_type: KeyType, algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        key_type,
+        key_size: 256,
+        secure_storage: true,
+        algorithm: Some(algorithm),
+    }
+}
+
+fn custodians(crypto: &ElasticCrypto, count: usize) -> (Vec<u32>, Vec<String>) {
+    let handles: Vec<u32> = (0..count)
+        .map(|_| crypto.generate_key(secure(KeyType::Asymmetric, KeyAlgorithm::EcdhP256)).unwrap())
+        .collect();
+    let jwks: Vec<String> = handles.iter().map(|&h| crypto.get_public_jwk(h).unwrap()).collect();
+    for jwk in &jwks {
+        crypto.register_custodian(jwk).unwrap();
+    }
+    (handles, jwks)
+}
+
+#[test]
+fn test_split_and_recover_any_subset() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let (_, jwks) = custodians(&crypto, 5);
+    let master = crypto
+        .import_key((1u8..=32).collect(), secure(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm))
+        .unwrap();
+    let ciphertext = crypto.cose_encrypt0(master, b"backup me".to_vec(), Vec::new()).unwrap();
+
+    let shares = crypto.split_key(master, 3, jwks).unwrap();
+    assert_eq!(shares.len(), 5);
+
+    for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+        let chosen = subset.iter().map(|&i| shares[i].clone()).collect();
+        let recovered = crypto
+            .recover_key(chosen, secure(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm))
+            .unwrap();
+        assert_ne!(recovered, master);
+        assert_eq!(crypto.cose_decrypt0(recovered, ciphertext.clone(), Vec::new()).unwrap(), b"backup me");
+        assert!(matches!(crypto.export_key(recovered), Err(Error::OperationNotPermitted)));
+    }
+}
+
+#[test]
+fn test_recover_signing_key_in_another_context() {
+    let original = ElasticCrypto::new().unwrap();
+    let (custodian_handles, jwks) = custodians(&original, 3);
+    let signer = original.generate_key(secure(KeyType::Asymmetric, KeyAlgorithm::Ed25519)).unwrap();
+    let shares = original.split_key(signer, 2, jwks).unwrap();
+
+    // Custodians move their shares to the recovery context's key.
+    let recovery = ElasticCrypto::new().unwrap();
+    let recovery_key = recovery.generate_key(secure(KeyType::Asymmetric, KeyAlgorithm::EcdhP256)).unwrap();
+    let recovery_jwk = recovery.get_public_jwk(recovery_key).unwrap();
+    original.register_custodian(&recovery_jwk).unwrap();
+    let moved: Vec<String> = [0, 2]
+        .iter()
+        .map(|&i| original.rewrap_key_share(custodian_handles[i], &shares[i], &recovery_jwk).unwrap())
+        .collect();
+
+    let recovered = recovery
+        .recover_key(moved, secure(KeyType::Asymmetric, KeyAlgorithm::Ed25519))
+        .unwrap();
+    assert_eq!(recovery.get_public_key(recovered).unwrap(), original.get_public_key(signer).unwrap());
+    let signature = recovery.sign(recovered, b"still me".to_vec()).unwrap();
+    assert!(original.verify(signer, b"still me".to_vec(), signature).unwrap());
+}
+
+#[test]
+fn test_recover_rejects_bad_shares() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let (_, jwks) = custodians(&crypto, 3);
+    let config = secure(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm);
+    let first = crypto.import_key(vec![1u8; 32], config.clone()).unwrap();
+    let second = crypto.import_key(vec![2u8; 32], config.clone()).unwrap();
+    let shares = crypto.split_key(first, 2, jwks.clone()).unwrap();
+    let others = crypto.split_key(second, 2, jwks.clone()).unwrap();
+
+    let recover = |shares: Vec<String>, config: KeyConfig| crypto.recover_key(shares, config);
+    assert!(matches!(recover(vec![shares[0].clone()], config.clone()), Err(Error::InvalidKeyShare(_))));
+    assert!(matches!(
+        recover(vec![shares[1].clone(), shares[1].clone()], config.clone()),
+        Err(Error::InvalidKeyShare(_))
+    ));
+    assert!(matches!(
+        recover(vec![shares[0].clone(), others[1].clone()], config.clone()),
+        Err(Error::InvalidKeyShare(_))
+    ));
+    assert!(matches!(
+        recover(shares[..2].to_vec(), secure(KeyType::Hmac, KeyAlgorithm::HmacSha256)),
+        Err(Error::InvalidKeyShare(_))
+    ));
+
+    // Shares for custodians whose keys are not in this store cannot be opened.
+    let stranger = ElasticCrypto::new().unwrap();
+    assert!(matches!(stranger.recover_key(shares[..2].to_vec(), config), Err(Error::KeyNotFound)));
+
+    assert!(matches!(crypto.split_key(first, 1, jwks.clone()), Err(Error::InvalidKeyShare(_))));
+    assert!(matches!(crypto.split_key(first, 4, jwks), Err(Error::InvalidKeyShare(_))));
+}
+
+#[test]
+fn test_shares_only_go_to_registered_custodians() {
+    let host = ElasticCrypto::new().unwrap();
+    let (custodian_handles, mut jwks) = custodians(&host, 2);
+    let master = host.generate_key(secure(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm)).unwrap();
+
+    // A key the host never registered gets neither fresh nor rewrapped shares.
+    let outsider = ElasticCrypto::new().unwrap();
+    let outsider_key = outsider.generate_key(secure(KeyType::Asymmetric, KeyAlgorithm::EcdhP256)).unwrap();
+    let outsider_jwk = outsider.get_public_jwk(outsider_key).unwrap();
+    let shares = host.split_key(master, 2, jwks.clone()).unwrap();
+    assert!(matches!(
+        host.rewrap_key_share(custodian_handles[0], &shares[0], &outsider_jwk),
+        Err(Error::InvalidKeyShare(_))
+    ));
+    jwks.push(outsider_jwk.clone());
+    assert!(matches!(host.split_key(master, 2, jwks.clone()), Err(Error::InvalidKeyShare(_))));
+
+    // Workloads share the host's registrations but cannot add to them.
+    let tenant = host.for_workload("tenant").unwrap();
+    assert!(matches!(tenant.register_custodian(&outsider_jwk), Err(Error::OperationNotPermitted)));
+    let tenant_key = tenant.generate_key(secure(KeyType::Symmetric, KeyAlgorithm::Aes256Gcm)).unwrap();
+    assert!(matches!(tenant.split_key(tenant_key, 2, jwks.clone()), Err(Error::InvalidKeyShare(_))));
+    assert_eq!(tenant.split_key(tenant_key, 2, jwks[..2].to_vec()).unwrap().len(), 2);
+
+    host.register_custodian(&outsider_jwk).unwrap();
+    assert_eq!(host.split_key(master, 2, jwks).unwrap().len(), 3);
+}
@@ -0,0 +1,166 @@
+use elastic_crypto::{ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType};
+
+fn siv(key_size: u32) -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Symmetric,
+        key_size,
+        secure_storage: true,
+        algorithm: Some(KeyAlgorithm::AesSiv),
+    }
+}
+
+fn unhex(data: &str) -> Vec<u8> {
+    hex::decode(data).unwrap()
+}
+
+#[test]
+fn test_rfc5297_deterministic() {
+    // RFC 5297 appendix A.1
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto
+        .import_key(
+            unhex("fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"),
+            siv(256),
+        )
+        .unwrap();
+    let plaintext = unhex("112233445566778899aabbccddee");
+    let ad = vec![unhex("101112131415161718191a1b1c1d1e1f2021222324252627")];
+
+    let ciphertext = crypto
+        .encrypt_siv(handle, plaintext.clone(), ad.clone(), None)
+        .unwrap();
+    assert_eq!(
+        ciphertext,
+        unhex("85632d07c6e8f37f950acd320a2ecc9340c02b9690c4dc04daef7f6afe5c")
+    );
+    assert_eq!(
+        crypto.decrypt_siv(handle, ciphertext, ad, None).unwrap(),
+        plaintext
+    );
+}
+
+#[test]
+fn test_rfc5297_nonce_based() {
+    // RFC 5297 appendix A.2
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto
+        .import_key(
+            unhex("7f7e7d7c7b7a79787776757473727170404142434445464748494a4b4c4d4e4f"),
+            siv(256),
+        )
+        .unwrap();
+    let ad = vec![
+        unhex("00112233445566778899aabbccddeeffdeaddadadeaddadaffeeddccbbaa99887766554433221100"),
+        unhex("102030405060708090a0"),
+    ];
+    let nonce = Some(unhex("09f911029d74e35bd84156c5635688c0"));
+    let plaintext = unhex(
+        "7468697320697320736f6d6520706c61696e7465787420746f20656e6372797074207573696e67205349562d414553",
+    );
+
+    let ciphertext = crypto
+        .encrypt_siv(handle, plaintext.clone(), ad.clone(), nonce.clone())
+        .unwrap();
+    assert_eq!(
+        ciphertext,
+        unhex(
+            "7bdb6e3b432667eb06f4d14bff2fbd0fcb900f2fddbe404326601965c889bf17\
+             dba77ceb094fa663b7a3f748ba8af829ea64ad544a272e9c485b62a3fd5c0d"
+        )
+    );
+    assert_eq!(
+        crypto
+            .decrypt_siv(handle, ciphertext.clone(), ad.clone(), nonce)
+            .unwrap(),
+        plaintext
+    );
+
+    // The nonce is authenticated like any other component.
+    let other_nonce = Some(vec![0u8; 16]);
+    assert!(matches!(
+        crypto.decrypt_siv(handle, ciphertext, ad, other_nonce),
+        Err(Error::DecryptionError(_))
+    ));
+}
+
+#[test]
+fn test_searchable_column() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto.import_key((0u8..64).collect(), siv(512)).unwrap();
+    let vin = b"VIN WVWZZZ1JZXW000001".to_vec();
+    let column = vec![b"vehicles.vin".to_vec()];
+
+    // Fixed vector so every backend produces the same bytes for an index.
+    let first = crypto
+        .encrypt_siv(handle, vin.clone(), column.clone(), None)
+        .unwrap();
+    assert_eq!(
+        first,
+        unhex("6ec96868cf9e90a5b5ebf7de6b7e9445220a518ef4e6361af4bb0a6baa120f357dbef2f664")
+    );
+    assert_eq!(
+        crypto
+            .encrypt_siv(handle, vin.clone(), column.clone(), None)
+            .unwrap(),
+        first
+    );
+
+    // The same value in another column does not match.
+    let other_column = crypto
+        .encrypt_siv(handle, vin.clone(), vec![b"owners.vin".to_vec()], None)
+        .unwrap();
+    assert_ne!(other_column, first);
+
+    let with_nonce = crypto
+        .encrypt_siv(
+            handle,
+            vin.clone(),
+            column.clone(),
+            Some((0u8..16).collect()),
+        )
+        .unwrap();
+    assert_eq!(
+        with_nonce,
+        unhex("b9bb0c81941340c11506ef61f6c4a4c7cf54e2e68c5e3c7d1048cf78ff8ea830ddfd5677f1")
+    );
+
+    let mut tampered = first.clone();
+    tampered[20] ^= 1;
+    assert!(matches!(
+        crypto.decrypt_siv(handle, tampered, column, None),
+        Err(Error::DecryptionError(_))
+    ));
+}
+
+#[test]
+fn test_siv_through_encrypt_decrypt() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto.generate_key(siv(512)).unwrap();
+    let first = crypto.encrypt(handle, b"customer-1842".to_vec()).unwrap();
+    assert_eq!(
+        crypto.encrypt(handle, b"customer-1842".to_vec()).unwrap(),
+        first
+    );
+    assert_eq!(
+        first,
+        crypto
+            .encrypt_siv(handle, b"customer-1842".to_vec(), Vec::new(), None)
+            .unwrap()
+    );
+    assert_eq!(crypto.decrypt(handle, first).unwrap(), b"customer-1842");
+
+    assert!(matches!(
+        crypto.generate_key(siv(384)),
+        Err(Error::InvalidKeyLength)
+    ));
+    assert!(matches!(
+        crypto.import_key(vec![0u8; 48], siv(384)),
+        Err(Error::InvalidKeyLength)
+    ));
+
+    let gcm = crypto.generate_key(KeyConfig::default()).unwrap();
+    assert!(matches!(
+        crypto.encrypt_siv(gcm, b"data".to_vec(), Vec::new(), None),
+        Err(Error::UnsupportedOperation)
+    ));
+}
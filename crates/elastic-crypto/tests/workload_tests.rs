@@ -0,0 +1,126 @@
+use elastic_crypto::{AuditEvent, AuditSink, ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct RecordingSink(Mutex<Vec<AuditEvent>>);
+
+impl AuditSink for RecordingSink {
+    fn record(&self, event: AuditEvent) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+fn host_with_audit() -> (ElasticCrypto, Arc<RecordingSink>) {
+    let host = ElasticCrypto::new().unwrap();
+    let sink = Arc::new(RecordingSink::default());
+    host.set_audit_sink(sink.clone()).unwrap();
+    (host, sink)
+}
+
+fn hmac_config() -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Hmac,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: Some(KeyAlgorithm::HmacSha256),
+    }
+}
+
+#[test]
+fn test_tenants_cannot_use_each_others_keys() {
+    let (host, sink) = host_with_audit();
+    let tenant_a = host.for_workload("tenant-a").unwrap();
+    let tenant_b = host.for_workload("tenant-b").unwrap();
+    assert_eq!(tenant_a.workload_id(), Some("tenant-a"));
+    assert_eq!(host.workload_id(), None);
+
+    let key_a = tenant_a.generate_key(KeyConfig::default()).unwrap();
+    let ciphertext = tenant_a.encrypt(key_a, b"tenant a data".to_vec()).unwrap();
+
+    // Guessing the handle does not give tenant B, or the host, the key.
+    assert!(matches!(tenant_b.decrypt(key_a, ciphertext.clone()), Err(Error::KeyAccessDenied(h)) if h == key_a));
+    assert!(matches!(tenant_b.export_key(key_a), Err(Error::KeyAccessDenied(_))));
+    assert!(matches!(tenant_b.delete_key(key_a), Err(Error::KeyAccessDenied(_))));
+    assert!(matches!(host.encrypt(key_a, Vec::new()), Err(Error::KeyAccessDenied(_))));
+    assert_eq!(tenant_a.decrypt(key_a, ciphertext).unwrap(), b"tenant a data");
+
+    // Host keys are out of reach of workloads too.
+    let host_key = host.import_key(vec![0x11; 32], hmac_config()).unwrap();
+    assert!(matches!(tenant_a.calculate_mac(host_key, Vec::new()), Err(Error::KeyAccessDenied(_))));
+    assert!(host.calculate_mac(host_key, Vec::new()).is_ok());
+
+    // Missing keys are still reported as missing.
+    assert!(matches!(tenant_b.encrypt(9999, Vec::new()), Err(Error::KeyNotFound)));
+
+    let events = sink.0.lock().unwrap();
+    assert_eq!(events.len(), 5);
+    assert_eq!(
+        events[0],
+        AuditEvent::KeyAccessDenied {
+            caller: Some("tenant-b".to_string()),
+            handle: key_a,
+            owner: Some("tenant-a".to_string()),
+        }
+    );
+    assert_eq!(
+        events[3],
+        AuditEvent::KeyAccessDenied {
+            caller: None,
+            handle: key_a,
+            owner: Some("tenant-a".to_string()),
+        }
+    );
+}
+
+#[test]
+fn test_workload_aes_keys_are_independent() {
+    let host = ElasticCrypto::new().unwrap();
+    let tenant_a = host.for_workload("tenant-a").unwrap();
+    let tenant_b = host.for_workload("tenant-b").unwrap();
+    let key_a = tenant_a.generate_key(KeyConfig::default()).unwrap();
+    let key_b = tenant_b.generate_key(KeyConfig::default()).unwrap();
+    assert_ne!(tenant_a.export_key(key_a).unwrap(), tenant_b.export_key(key_b).unwrap());
+
+    // Each tenant's key only opens its own data.
+    let data_a = tenant_a.encrypt(key_a, b"tenant a data".to_vec()).unwrap();
+    let data_b = tenant_b.encrypt(key_b, b"tenant b data".to_vec()).unwrap();
+    assert!(matches!(tenant_b.decrypt(key_b, data_a.clone()), Err(Error::DecryptionError(_))));
+    assert!(matches!(tenant_a.decrypt(key_a, data_b.clone()), Err(Error::DecryptionError(_))));
+    assert_eq!(tenant_a.decrypt(key_a, data_a).unwrap(), b"tenant a data");
+    assert_eq!(tenant_b.decrypt(key_b, data_b).unwrap(), b"tenant b data");
+}
+
+#[test]
+fn test_same_workload_id_shares_namespace() {
+    let host = ElasticCrypto::new().unwrap();
+    let first = host.for_workload("billing").unwrap();
+    let second = host.for_workload("billing").unwrap();
+    let key = first.import_key(vec![0x22; 32], hmac_config()).unwrap();
+    let mac = first.calculate_mac(key, b"invoice".to_vec()).unwrap();
+    assert!(second.verify_mac(key, b"invoice".to_vec(), mac).unwrap());
+}
+
+#[test]
+fn test_host_only_operations() {
+    let (host, sink) = host_with_audit();
+    let tenant = host.for_workload("tenant-a").unwrap();
+
+    assert!(matches!(tenant.for_workload("tenant-b"), Err(Error::OperationNotPermitted)));
+    assert!(matches!(tenant.delete_workload_keys("tenant-b"), Err(Error::OperationNotPermitted)));
+    assert!(matches!(host.for_workload(""), Err(Error::OperationNotPermitted)));
+    assert_eq!(
+        sink.0.lock().unwrap()[0],
+        AuditEvent::HostOperationDenied {
+            caller: "tenant-a".to_string(),
+            operation: "for_workload",
+        }
+    );
+
+    // Tearing a tenant down removes only its keys.
+    let other = host.for_workload("tenant-b").unwrap();
+    let keys: Vec<u32> = (0..3).map(|_| tenant.generate_key(KeyConfig::default()).unwrap()).collect();
+    let kept = other.generate_key(KeyConfig::default()).unwrap();
+    assert_eq!(host.delete_workload_keys("tenant-a").unwrap(), 3);
+    assert!(matches!(tenant.encrypt(keys[0], Vec::new()), Err(Error::KeyNotFound)));
+    assert!(other.encrypt(kept, b"data".to_vec()).is_ok());
+}
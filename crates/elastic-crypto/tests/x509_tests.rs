@@ -0,0 +1,134 @@
+use elastic_crypto::x509::{
+    pem_encode, CertificateParams, CustomExtension, ExtendedKeyUsage, KeyUsage, SubjectAltName,
+};
+use elastic_crypto::{verify_with_public_key, ElasticCrypto, Error, KeyAlgorithm, KeyConfig, KeyType};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::SubjectAltName as SanExtension;
+use x509_cert::request::{CertReq, ExtensionReq};
+use x509_cert::Certificate;
+
+const ATTESTATION_OID: &str = "1.3.6.1.4.1.58270.1.1";
+
+fn asymmetric(algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        key_type: KeyType::Asymmetric,
+        key_size: 256,
+        secure_storage: true,
+        algorithm: Some(algorithm),
+    }
+}
+
+fn params() -> CertificateParams {
+    CertificateParams {
+        subject: "CN=workload.elastic.local,O=Elastic".to_string(),
+        subject_alt_names: vec![
+            SubjectAltName::Dns("workload.elastic.local".to_string()),
+            SubjectAltName::Ip("10.0.0.7".parse().unwrap()),
+        ],
+        key_usage: vec![KeyUsage::DigitalSignature, KeyUsage::KeyEncipherment],
+        extended_key_usage: vec![ExtendedKeyUsage::ServerAuth, ExtendedKeyUsage::ClientAuth],
+        custom_extensions: vec![CustomExtension {
+            oid: ATTESTATION_OID.to_string(),
+            critical: false,
+            value: vec![0x04, 0x03, 0xaa, 0xbb, 0xcc],
+        }],
+        ..Default::default()
+    }
+}
+
+/// X.509 carries ECDSA signatures DER-encoded; the handle API uses `r || s`.
+fn raw_signature(algorithm: KeyAlgorithm, signature: &[u8]) -> Vec<u8> {
+    match algorithm {
+        KeyAlgorithm::EcdsaP256 => p256::ecdsa::Signature::from_der(signature).unwrap().to_bytes().to_vec(),
+        _ => signature.to_vec(),
+    }
+}
+
+#[test]
+fn test_self_signed_certificate() {
+    let crypto = ElasticCrypto::new().unwrap();
+
+    for algorithm in [KeyAlgorithm::Ed25519, KeyAlgorithm::EcdsaP256] {
+        let handle = crypto.generate_key(asymmetric(algorithm)).unwrap();
+        let der = crypto.create_self_signed_certificate(handle, params()).unwrap();
+        let cert = Certificate::from_der(&der).unwrap();
+        let tbs = &cert.tbs_certificate;
+
+        assert_eq!(tbs.subject.to_string(), "CN=workload.elastic.local,O=Elastic");
+        assert_eq!(tbs.issuer, tbs.subject);
+        assert_eq!(
+            tbs.subject_public_key_info.subject_public_key.raw_bytes(),
+            crypto.get_public_key(handle).unwrap()
+        );
+
+        let extensions = tbs.extensions.as_ref().unwrap();
+        let san = extensions
+            .iter()
+            .find(|e| e.extn_id == const_oid::db::rfc5280::ID_CE_SUBJECT_ALT_NAME)
+            .unwrap();
+        let san = SanExtension::from_der(san.extn_value.as_bytes()).unwrap();
+        assert!(matches!(&san.0[0], GeneralName::DnsName(dns) if dns.as_str() == "workload.elastic.local"));
+        assert!(matches!(&san.0[1], GeneralName::IpAddress(ip) if ip.as_bytes() == [10, 0, 0, 7]));
+        let custom = extensions.iter().find(|e| e.extn_id.to_string() == ATTESTATION_OID).unwrap();
+        assert_eq!(custom.extn_value.as_bytes(), [0x04, 0x03, 0xaa, 0xbb, 0xcc]);
+
+        let signature = raw_signature(algorithm, cert.signature.raw_bytes());
+        let tbs_der = tbs.to_der().unwrap();
+        let public_key = crypto.get_public_key(handle).unwrap();
+        assert!(verify_with_public_key(algorithm, &public_key, &tbs_der, &signature).unwrap());
+
+        // The private key never left the store.
+        assert!(matches!(crypto.export_key(handle), Err(Error::OperationNotPermitted)));
+    }
+}
+
+#[test]
+fn test_certificate_signing_request() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto.generate_key(asymmetric(KeyAlgorithm::EcdsaP256)).unwrap();
+
+    let der = crypto.create_csr(handle, params()).unwrap();
+    let csr = CertReq::from_der(&der).unwrap();
+    assert_eq!(csr.info.subject.to_string(), "CN=workload.elastic.local,O=Elastic");
+
+    let attribute = csr.info.attributes.iter().next().unwrap();
+    let request = ExtensionReq::from_der(&attribute.values.get(0).unwrap().to_der().unwrap()).unwrap();
+    assert!(request.0.iter().any(|e| e.extn_id.to_string() == ATTESTATION_OID));
+
+    let signature = raw_signature(KeyAlgorithm::EcdsaP256, csr.signature.raw_bytes());
+    let public_key = crypto.get_public_key(handle).unwrap();
+    let info = csr.info.to_der().unwrap();
+    assert!(verify_with_public_key(KeyAlgorithm::EcdsaP256, &public_key, &info, &signature).unwrap());
+
+    let pem = pem_encode("CERTIFICATE REQUEST", &der).unwrap();
+    assert!(pem.starts_with("-----BEGIN CERTIFICATE REQUEST-----\n"));
+}
+
+#[test]
+fn test_invalid_certificate_params() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let handle = crypto.generate_key(asymmetric(KeyAlgorithm::Ed25519)).unwrap();
+
+    let bad_subject = CertificateParams {
+        subject: "not a name".to_string(),
+        ..params()
+    };
+    assert!(matches!(
+        crypto.create_csr(handle, bad_subject),
+        Err(Error::InvalidCertificateParams(_))
+    ));
+
+    let mut bad_oid = params();
+    bad_oid.custom_extensions[0].oid = "attestation".to_string();
+    assert!(matches!(
+        crypto.create_self_signed_certificate(handle, bad_oid),
+        Err(Error::InvalidCertificateParams(_))
+    ));
+
+    let ecdh = crypto.generate_key(asymmetric(KeyAlgorithm::EcdhP256)).unwrap();
+    assert!(matches!(
+        crypto.create_self_signed_certificate(ecdh, params()),
+        Err(Error::UnsupportedOperation)
+    ));
+}
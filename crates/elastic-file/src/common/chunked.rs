@@ -0,0 +1,524 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::sync::Arc;
+use super::keys::KeySlot;
+use super::{FileError, FileMode};
+
+// On-disk layout of an encrypted file:
+//
+//   header: magic(4) || version(1) || reserved(3) || key id(16) || salt(32)
+//           || chunk size(4, BE) || reserved(4) || wrapped object key(32 + 16)
+//   chunk:  nonce(12) || AES-256-GCM(plaintext[..chunk size]) || tag(16)
+//
+// Every object has its own key, derived with HKDF from the master key, the
+// random salt and the object path when the file is created. It is stored
+// wrapped under a key derived the same way from the object's current path,
+// so renaming rewrites only the wrapped key and overwriting the salt makes
+// the contents unrecoverable. Chunks are encrypted under the object key with
+// a random nonce drawn on every write, so nonces stay unique even if the host
+// rolls a file back. The associated data is the first 64 bytes of the header
+// || index(8, BE) || last(1), so truncating or reordering chunks fails
+// authentication.
+const MAGIC: &[u8; 4] = b"ESEC";
+const VERSION: u8 = 3;
+pub(crate) const HEADER_LEN: usize = 112;
+const AUTHENTICATED_LEN: usize = 64;
+const KEY_ID: std::ops::Range<usize> = 8..24;
+const SALT: std::ops::Range<usize> = 24..56;
+const CHUNK_SIZE: std::ops::Range<usize> = 56..60;
+const WRAPPED_KEY: std::ops::Range<usize> = 64..112;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const CHUNK_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 4096;
+const MAX_CHUNK_SIZE: usize = 1 << 20;
+pub(crate) const KEY_ID_LEN: usize = 16;
+
+/// Identifies the master key a file was written with, without revealing it.
+pub(crate) fn key_id(key: &[u8]) -> [u8; KEY_ID_LEN] {
+    let mut id = [0u8; KEY_ID_LEN];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(b"elastic-file key id", &mut id)
+        .expect("key id length is valid");
+    id
+}
+
+/// True if `header` starts like a file in this format.
+pub(crate) fn is_chunked(header: &[u8]) -> bool {
+    header.len() >= HEADER_LEN && &header[..MAGIC.len()] == MAGIC && header[MAGIC.len()] == VERSION
+}
+
+/// Plaintext length of a file in this format, from its header and size on
+/// disk. Fails if the size does not match a valid chunk layout.
+pub(crate) fn plaintext_len(header: &[u8], physical_len: u64) -> Result<u64, FileError> {
+    if !is_chunked(header) {
+        return Err(FileError::DecryptionError("not an encrypted file".to_string()));
+    }
+    let chunk_size = read_chunk_size(header)?;
+    layout(physical_len, chunk_size).map(|(len, _)| len)
+}
+
+fn read_chunk_size(header: &[u8]) -> Result<usize, FileError> {
+    let chunk_size = u32::from_be_bytes(header[CHUNK_SIZE].try_into().unwrap()) as usize;
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(FileError::DecryptionError(format!("invalid chunk size {}", chunk_size)));
+    }
+    Ok(chunk_size)
+}
+
+/// Returns the plaintext length and chunk count for a physical file length.
+fn layout(physical_len: u64, chunk_size: usize) -> Result<(u64, u64), FileError> {
+    let invalid = || FileError::DecryptionError("encrypted file is truncated or corrupt".to_string());
+    let body = physical_len.checked_sub(HEADER_LEN as u64).ok_or_else(invalid)?;
+    let stride = (chunk_size + CHUNK_OVERHEAD) as u64;
+    let full = body / stride;
+    let rest = body % stride;
+    let (chunks, last) = match rest {
+        0 if full > 0 => (full, chunk_size as u64),
+        0 => return Err(invalid()),
+        rest if rest >= CHUNK_OVERHEAD as u64 => (full + 1, rest - CHUNK_OVERHEAD as u64),
+        _ => return Err(invalid()),
+    };
+    // Only an empty file may end in an empty chunk.
+    if last == 0 && chunks > 1 {
+        return Err(invalid());
+    }
+    Ok(((chunks - 1) * chunk_size as u64 + last, chunks))
+}
+
+/// The bytes a file in the chunked format is stored in: a host file, or a
+/// file of the in-memory backend.
+pub(crate) trait Backing {
+    fn physical_len(&self) -> io::Result<u64>;
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+    fn sync(&self) -> io::Result<()>;
+}
+
+impl Backing for File {
+    fn physical_len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let mut file = self;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let mut file = self;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Bytes already read into memory, e.g. so they can be checked before they
+/// are decrypted. Read-only.
+impl Backing for &[u8] {
+    fn physical_len(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let start = usize::try_from(offset).map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let data = self
+            .get(start..)
+            .and_then(|rest| rest.get(..buf.len()))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write_all_at(&self, _buf: &[u8], _offset: u64) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::PermissionDenied))
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A file in the chunked format, read and written at plaintext offsets.
+pub(crate) struct ChunkedFile<F: Backing = File> {
+    file: F,
+    header: [u8; HEADER_LEN],
+    cipher: Aes256Gcm,
+    chunk_size: usize,
+    len: u64,
+    pos: u64,
+    append: bool,
+}
+
+impl<F: Backing> ChunkedFile<F> {
+    /// Opens `file`, or writes a new header to it if it is empty. `context`
+    /// is mixed into the object key, binding the contents to e.g. an object
+    /// path.
+    pub(crate) fn open(file: F, key: &[u8], context: &[u8], append: bool) -> Result<Self, FileError> {
+        let physical_len = file.physical_len()?;
+        if physical_len == 0 {
+            return Self::create(file, key, context, append);
+        }
+
+        let header = read_header(&file)?;
+        if header[KEY_ID] != key_id(key) {
+            return Err(FileError::WrongKey);
+        }
+        let chunk_size = read_chunk_size(&header)?;
+        let (len, _) = layout(physical_len, chunk_size)?;
+
+        let chunked = Self {
+            cipher: object_cipher(&unwrap_object_key(key, &header, context)?)?,
+            file,
+            header,
+            chunk_size,
+            len,
+            pos: 0,
+            append,
+        };
+        // Authenticating the last chunk proves it carries the final flag, so
+        // the file was not cut short at a chunk boundary.
+        chunked.read_chunk(chunked.last_index(len))?;
+        Ok(chunked)
+    }
+
+    /// Opens `file` for a handle in `mode`, under the key loaded into `keys`.
+    pub(crate) fn open_for(file: F, mode: FileMode, keys: &KeySlot) -> Result<Self, FileError> {
+        let key = keys.get()?;
+        if !mode.can_write() && file.physical_len()? == 0 {
+            return Err(FileError::DecryptionError("not an encrypted file".to_string()));
+        }
+        Self::open(file, &key, &[], mode == FileMode::Append)
+    }
+
+    fn create(file: F, key: &[u8], context: &[u8], append: bool) -> Result<Self, FileError> {
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()] = VERSION;
+        header[KEY_ID].copy_from_slice(&key_id(key));
+        OsRng.fill_bytes(&mut header[SALT]);
+        header[CHUNK_SIZE].copy_from_slice(&(DEFAULT_CHUNK_SIZE as u32).to_be_bytes());
+        let object_key = derive(key, &header[SALT], b"elastic-file object key", context)?;
+        wrap_object_key(key, &mut header, context, &object_key)?;
+        file.write_all_at(&header, 0)?;
+
+        let chunked = Self {
+            cipher: object_cipher(&object_key)?,
+            file,
+            header,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            len: 0,
+            pos: 0,
+            append,
+        };
+        chunked.write_chunk(0, &[], true)?;
+        Ok(chunked)
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn key_id(&self) -> &[u8] {
+        &self.header[KEY_ID]
+    }
+
+    fn last_index(&self, len: u64) -> u64 {
+        len.saturating_sub(1) / self.chunk_size as u64
+    }
+
+    fn chunk_offset(&self, index: u64) -> u64 {
+        HEADER_LEN as u64 + index * (self.chunk_size + CHUNK_OVERHEAD) as u64
+    }
+
+    fn associated_data(&self, index: u64, last: bool) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(AUTHENTICATED_LEN + 9);
+        aad.extend_from_slice(&self.header[..AUTHENTICATED_LEN]);
+        aad.extend_from_slice(&index.to_be_bytes());
+        aad.push(last as u8);
+        aad
+    }
+
+    /// Decrypts chunk `index` of the current file.
+    fn read_chunk(&self, index: u64) -> Result<Vec<u8>, FileError> {
+        let start = index * self.chunk_size as u64;
+        let plain_len = (self.len.saturating_sub(start)).min(self.chunk_size as u64) as usize;
+        let mut stored = vec![0u8; plain_len + CHUNK_OVERHEAD];
+        self.file.read_exact_at(&mut stored, self.chunk_offset(index))
+            .map_err(|_| FileError::DecryptionError("encrypted file is truncated or corrupt".to_string()))?;
+
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        let aad = self.associated_data(index, index == self.last_index(self.len));
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| FileError::DecryptionError(format!("chunk {} failed authentication", index)))
+    }
+
+    fn write_chunk(&self, index: u64, plaintext: &[u8], last: bool) -> Result<(), FileError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let aad = self.associated_data(index, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| FileError::EncryptionError("Encryption failed".to_string()))?;
+
+        let mut stored = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        self.file.write_all_at(&stored, self.chunk_offset(index))?;
+        Ok(())
+    }
+
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let mut read = 0;
+        while read < buf.len() && self.pos < self.len {
+            let index = self.pos / self.chunk_size as u64;
+            let within = (self.pos % self.chunk_size as u64) as usize;
+            let chunk = self.read_chunk(index)?;
+            let take = (chunk.len() - within).min(buf.len() - read);
+            buf[read..read + take].copy_from_slice(&chunk[within..within + take]);
+            read += take;
+            self.pos += take as u64;
+        }
+        Ok(read)
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8]) -> Result<usize, FileError> {
+        if self.append {
+            self.pos = self.len;
+        }
+        // Writing past the end fills the gap with zeros first.
+        while self.len < self.pos {
+            let gap = (self.pos - self.len).min(self.chunk_size as u64) as usize;
+            let at = self.len;
+            self.write_at(at, &vec![0u8; gap])?;
+        }
+        let pos = self.pos;
+        self.write_at(pos, data)?;
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), FileError> {
+        let mut written = 0;
+        while written < data.len() {
+            let pos = offset + written as u64;
+            let index = pos / self.chunk_size as u64;
+            let within = (pos % self.chunk_size as u64) as usize;
+            let take = (self.chunk_size - within).min(data.len() - written);
+
+            let old_last = self.last_index(self.len);
+            let mut chunk = if index <= old_last { self.read_chunk(index)? } else { Vec::new() };
+            if chunk.len() < within + take {
+                chunk.resize(within + take, 0);
+            }
+            chunk[within..within + take].copy_from_slice(&data[written..written + take]);
+
+            let new_len = self.len.max(pos + take as u64);
+            let new_last = self.last_index(new_len);
+            if new_last > old_last && index != old_last {
+                // The old last chunk is full and loses its final flag.
+                let previous = self.read_chunk(old_last)?;
+                self.write_chunk(old_last, &previous, false)?;
+            }
+            self.write_chunk(index, &chunk, index == new_last)?;
+            self.len = new_len;
+            written += take;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn seek(&mut self, pos: SeekFrom) -> Result<u64, FileError> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+        self.pos = target.ok_or_else(|| FileError::OperationFailed("Invalid seek position".to_string()))?;
+        Ok(self.pos)
+    }
+
+    pub(crate) fn sync(&self) -> Result<(), FileError> {
+        self.file.sync()?;
+        Ok(())
+    }
+}
+
+/// Returns the header of `file` with its object key re-wrapped for a new
+/// path; the contents stay valid under it. Fails if `file` does not belong
+/// at `from`.
+pub(crate) fn rewrap(file: &File, key: &[u8], from: &[u8], to: &[u8]) -> Result<Vec<u8>, FileError> {
+    let mut header = read_header(file)?;
+    if header[KEY_ID] != key_id(key) {
+        return Err(FileError::WrongKey);
+    }
+    let object_key = unwrap_object_key(key, &header, from)?;
+    wrap_object_key(key, &mut header, to, &object_key)?;
+    Ok(header.to_vec())
+}
+
+/// Replaces the header of `file` with one returned by [`rewrap`].
+pub(crate) fn write_header(file: &File, header: &[u8]) -> Result<(), FileError> {
+    if header.len() != HEADER_LEN || !is_chunked(header) {
+        return Err(FileError::InvalidOperation("not an encrypted file header".to_string()));
+    }
+    read_header(file)?;
+    file.write_all_at(header, 0)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Overwrites the salt and wrapped key of `file` with random bytes, after
+/// which no key can decrypt its contents.
+pub(crate) fn shred(file: &impl Backing) -> Result<(), FileError> {
+    let mut header = read_header(file)?;
+    OsRng.fill_bytes(&mut header[SALT]);
+    OsRng.fill_bytes(&mut header[WRAPPED_KEY]);
+    file.write_all_at(&header, 0)?;
+    file.sync()?;
+    Ok(())
+}
+
+fn read_header(file: &impl Backing) -> Result<[u8; HEADER_LEN], FileError> {
+    let mut header = [0u8; HEADER_LEN];
+    file.read_exact_at(&mut header, 0)
+        .map_err(|_| FileError::DecryptionError("encrypted file is truncated or corrupt".to_string()))?;
+    if !is_chunked(&header) {
+        return Err(FileError::DecryptionError("not an encrypted file".to_string()));
+    }
+    Ok(header)
+}
+
+fn derive(key: &[u8], salt: &[u8], label: &[u8], context: &[u8]) -> Result<[u8; 32], FileError> {
+    let mut derived = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(salt), key)
+        .expand_multi_info(&[label, context], &mut derived)
+        .map_err(|_| FileError::EncryptionError("Failed to derive object key".to_string()))?;
+    Ok(derived)
+}
+
+fn object_cipher(key: &[u8]) -> Result<Aes256Gcm, FileError> {
+    Aes256Gcm::new_from_slice(key).map_err(|_| FileError::EncryptionError("Failed to create cipher".to_string()))
+}
+
+// A wrapping key is unique to a salt and path and only ever wraps the one
+// object key, so a fixed nonce never encrypts two different messages.
+const WRAP_NONCE: [u8; 12] = [0; 12];
+
+fn wrap_object_key(
+    key: &[u8],
+    header: &mut [u8; HEADER_LEN],
+    context: &[u8],
+    object_key: &[u8],
+) -> Result<(), FileError> {
+    let cipher = object_cipher(&derive(key, &header[SALT], b"elastic-file wrapping key", context)?)?;
+    let wrapped = cipher
+        .encrypt(
+            Nonce::from_slice(&WRAP_NONCE),
+            Payload { msg: object_key, aad: &header[..AUTHENTICATED_LEN] },
+        )
+        .map_err(|_| FileError::EncryptionError("Failed to wrap object key".to_string()))?;
+    header[WRAPPED_KEY].copy_from_slice(&wrapped);
+    Ok(())
+}
+
+fn unwrap_object_key(key: &[u8], header: &[u8; HEADER_LEN], context: &[u8]) -> Result<Vec<u8>, FileError> {
+    let cipher = object_cipher(&derive(key, &header[SALT], b"elastic-file wrapping key", context)?)?;
+    cipher
+        .decrypt(
+            Nonce::from_slice(&WRAP_NONCE),
+            Payload { msg: &header[WRAPPED_KEY], aad: &header[..AUTHENTICATED_LEN] },
+        )
+        .map_err(|_| FileError::DecryptionError("object key failed authentication".to_string()))
+}
+
+/// The contents behind a file handle: plain, or encrypted in the chunked
+/// format under the key loaded into `keys`.
+pub(crate) enum Storage {
+    Plain(File),
+    Chunked {
+        file: Box<ChunkedFile>,
+        keys: Arc<KeySlot>,
+    },
+}
+
+impl Storage {
+    /// Wraps a freshly opened file, encrypting it under the key in `keys` if
+    /// given. An empty file opened for writing gets a new header.
+    pub(crate) fn open(file: File, mode: FileMode, keys: Option<&Arc<KeySlot>>) -> Result<Self, FileError> {
+        let Some(keys) = keys else {
+            return Ok(Storage::Plain(file));
+        };
+        let chunked = ChunkedFile::open_for(file, mode, keys)?;
+        Ok(Storage::Chunked {
+            file: Box::new(chunked),
+            keys: Arc::clone(keys),
+        })
+    }
+
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        match self {
+            Storage::Plain(file) => Ok(file.read(buf)?),
+            Storage::Chunked { file, keys } => {
+                keys.check(file.key_id())?;
+                file.read(buf)
+            }
+        }
+    }
+
+    pub(crate) fn write(&mut self, buf: &[u8]) -> Result<usize, FileError> {
+        match self {
+            Storage::Plain(file) => Ok(file.write(buf)?),
+            Storage::Chunked { file, keys } => {
+                keys.check(file.key_id())?;
+                file.write(buf)
+            }
+        }
+    }
+
+    pub(crate) fn seek(&mut self, offset: i64, whence: i32) -> Result<u64, FileError> {
+        let seek_from = seek_from(offset, whence)?;
+        match self {
+            Storage::Plain(file) => Ok(file.seek(seek_from)?),
+            Storage::Chunked { file, .. } => file.seek(seek_from),
+        }
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<(), FileError> {
+        match self {
+            Storage::Plain(file) => Ok(file.sync_all()?),
+            Storage::Chunked { file, .. } => file.sync(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> Result<u64, FileError> {
+        match self {
+            Storage::Plain(file) => Ok(file.metadata()?.len()),
+            Storage::Chunked { file, .. } => Ok(file.len()),
+        }
+    }
+}
+
+/// The position `seek(offset, whence)` asks for, with `whence` as in lseek.
+pub(crate) fn seek_from(offset: i64, whence: i32) -> Result<SeekFrom, FileError> {
+    match whence {
+        0 if offset < 0 => Err(FileError::OperationFailed("Invalid seek position".to_string())),
+        0 => Ok(SeekFrom::Start(offset as u64)),
+        1 => Ok(SeekFrom::Current(offset)),
+        2 => Ok(SeekFrom::End(offset)),
+        _ => Err(FileError::OperationFailed("Invalid whence value".to_string())),
+    }
+}
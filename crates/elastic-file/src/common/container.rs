@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::chunked::{self, ChunkedFile};
+use super::journal::{self, Op};
+use super::manifest::{Manifest, MANIFEST_NAME, RESERVED_PREFIX};
+use super::sandbox::{OpenFlags, Sandbox};
+use super::{FileError, FileMode, FileType, KeySource, ObjectMetadata, VersionStore};
+
+// Encrypted objects use the chunked format with the object path mixed into
+// the wrapping of the object key, so objects cannot be swapped or renamed on
+// disk; `rename_file` re-wraps the key for the new path instead. Object
+// paths are resolved beneath the container root, so neither `..` nor a
+// symbolic link can reach outside it.
+
+struct Container {
+    sandbox: Sandbox,
+    id: String,
+    mode: FileMode,
+    key: Option<Vec<u8>>,
+    manifest: Option<Manifest>,
+    // Whether the manifest was authenticated with the loaded key.
+    verified: bool,
+    // Changes staged by an open transaction.
+    transaction: Option<Vec<Op>>,
+}
+
+impl Container {
+    /// Returns the host path of an object and its normalized name. The last
+    /// component is only followed if it is a symbolic link when `follow` is
+    /// set.
+    fn resolve(&self, path: &str, allow_root: bool, follow: bool) -> Result<(PathBuf, String), FileError> {
+        let name = object_name(path, allow_root)?;
+        if name.starts_with(RESERVED_PREFIX) {
+            return Err(FileError::InvalidOperation(format!("{} is reserved", name)));
+        }
+        Ok((self.sandbox.resolve(Path::new(&name), follow)?, name))
+    }
+
+    fn open_object(&self, name: &str) -> Result<File, FileError> {
+        self.sandbox.open(Path::new(name), OpenFlags::new().read(true)).map_err(|e| match e {
+            FileError::IoError(e) => io_error(e, &self.sandbox.root().join(name)),
+            e => e,
+        })
+    }
+
+    /// Objects in a container with a manifest are only accessible once the
+    /// manifest has been verified with the loaded key.
+    fn check_unlocked(&self) -> Result<(), FileError> {
+        match (&self.manifest, &self.key) {
+            (Some(_), None) => Err(FileError::KeyNotLoaded),
+            (Some(_), Some(_)) if !self.verified => Err(FileError::WrongKey),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_readable(&self) -> Result<(), FileError> {
+        if matches!(self.mode, FileMode::Read | FileMode::ReadWrite) {
+            Ok(())
+        } else {
+            Err(FileError::PermissionDenied("container not opened for reading".to_string()))
+        }
+    }
+
+    fn check_deletable(&self) -> Result<(), FileError> {
+        if matches!(self.mode, FileMode::Write | FileMode::ReadWrite) {
+            Ok(())
+        } else {
+            Err(FileError::PermissionDenied("container does not allow deletion".to_string()))
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), FileError> {
+        if self.mode.can_write() {
+            Ok(())
+        } else {
+            Err(FileError::PermissionDenied("container not opened for writing".to_string()))
+        }
+    }
+}
+
+/// Container handles shared by every backend. `require_key` refuses to write
+/// plaintext objects, for backends whose storage is controlled by the host.
+/// Without a version store, containers cannot be opened.
+pub(crate) struct ContainerManager {
+    containers: Mutex<HashMap<u32, Container>>,
+    next_handle: Mutex<u32>,
+    require_key: bool,
+    versions: Option<Arc<dyn VersionStore>>,
+    // Confines the paths containers are opened at, if set.
+    root: Option<Arc<Sandbox>>,
+}
+
+impl ContainerManager {
+    pub(crate) fn new(require_key: bool, versions: Option<Arc<dyn VersionStore>>) -> Self {
+        Self {
+            containers: Mutex::new(HashMap::new()),
+            next_handle: Mutex::new(1),
+            require_key,
+            versions,
+            root: None,
+        }
+    }
+
+    pub(crate) fn set_root(&mut self, root: Arc<Sandbox>) {
+        self.root = Some(root);
+    }
+
+    fn versions(&self) -> Result<&dyn VersionStore, FileError> {
+        self.versions
+            .as_deref()
+            .ok_or_else(|| FileError::InvalidOperation("no container version store is configured".to_string()))
+    }
+
+    /// Stages a change with `stage`, which sees the changes already staged
+    /// by an open transaction, and commits it unless a transaction is open.
+    fn change(
+        &self,
+        container: &mut Container,
+        stage: impl FnOnce(&Container, &[Op]) -> Result<Op, FileError>,
+    ) -> Result<(), FileError> {
+        let pending = container.transaction.as_deref().unwrap_or_default();
+        let op = stage(container, pending)?;
+        let Some(ops) = &mut container.transaction else {
+            return self.commit(container, vec![op]);
+        };
+        // Rewriting an object in the same transaction replaces its contents.
+        if let Op::Put { name, .. } = &op {
+            if let Some(i) = ops.iter().position(|staged| matches!(staged, Op::Put { name: n, .. } if n == name)) {
+                journal::discard(container.sandbox.root(), &[ops.remove(i)]);
+            }
+        }
+        ops.push(op);
+        Ok(())
+    }
+
+    /// Applies `ops` atomically, together with an updated manifest when a
+    /// key is loaded. The first change made with a key creates the manifest.
+    fn commit(&self, container: &mut Container, mut ops: Vec<Op>) -> Result<(), FileError> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = container.check_unlocked() {
+            journal::discard(container.sandbox.root(), &ops);
+            return Err(e);
+        }
+        let mut manifest = None;
+        if let Some(key) = &container.key {
+            let root = container.sandbox.root();
+            let staged = container
+                .manifest
+                .clone()
+                .map_or_else(|| Manifest::create(root, key), Ok)
+                .and_then(|mut next| {
+                    next.apply(root, &ops)?;
+                    let (mut file, staged) = journal::stage(root)?;
+                    file.write_all(&next.seal(key))?;
+                    file.sync_all()?;
+                    Ok((next, staged))
+                });
+            match staged {
+                Ok((next, staged)) => {
+                    ops.push(Op::Put { name: MANIFEST_NAME.to_string(), staged });
+                    manifest = Some(next);
+                }
+                Err(e) => {
+                    journal::discard(root, &ops);
+                    return Err(e);
+                }
+            }
+        }
+        let root = container.sandbox.root();
+        if let Err(e) = journal::commit(root, &ops) {
+            // Finish or drop whatever reached the disk, and pick up the
+            // manifest that goes with it.
+            let _ = journal::recover(root);
+            if let Ok(loaded) = Manifest::load(root) {
+                container.manifest = loaded;
+            }
+            return Err(e);
+        }
+        if let Some(manifest) = manifest {
+            self.versions()?.advance(&container.id, manifest.version)?;
+            container.manifest = Some(manifest);
+            container.verified = true;
+        }
+        Ok(())
+    }
+
+    fn with_container<T>(
+        &self,
+        handle: u32,
+        f: impl FnOnce(&mut Container) -> Result<T, FileError>,
+    ) -> Result<T, FileError> {
+        let mut containers = self.containers.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let container = containers.get_mut(&handle).ok_or(FileError::InvalidHandle)?;
+        f(container)
+    }
+
+    pub(crate) fn open(&self, path: &Path, mode: FileMode) -> Result<u32, FileError> {
+        let versions = self.versions()?;
+        let path = match &self.root {
+            Some(sandbox) => &sandbox.resolve(path, true)?,
+            None => path,
+        };
+        if mode.can_write() {
+            fs::create_dir_all(path).map_err(|e| io_error(e, path))?;
+        }
+        let metadata = fs::metadata(path).map_err(|e| io_error(e, path))?;
+        if !metadata.is_dir() {
+            return Err(FileError::InvalidOperation(format!("{} is not a directory", path.display())));
+        }
+        check_access(path, mode)?;
+        let sandbox = Sandbox::new(path).map_err(|e| match e {
+            FileError::IoError(e) => io_error(e, path),
+            e => e,
+        })?;
+        let root = sandbox.root();
+        let id = root.to_string_lossy().into_owned();
+        journal::recover(root)?;
+        let manifest = Manifest::load(root)?;
+        let trusted = versions.current(&id)?;
+        match &manifest {
+            None if trusted > 0 => {
+                return Err(FileError::IntegrityError("container manifest is missing".to_string()))
+            }
+            Some(manifest) if manifest.version < trusted => {
+                return Err(FileError::IntegrityError(format!(
+                    "container was rolled back from version {} to {}",
+                    trusted, manifest.version
+                )))
+            }
+            Some(manifest) => manifest.check(root)?,
+            None => {}
+        }
+
+        let mut containers = self.containers.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let mut next_handle = self.next_handle.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let handle = *next_handle;
+        *next_handle += 1;
+
+        containers.insert(
+            handle,
+            Container {
+                sandbox,
+                id,
+                mode,
+                key: None,
+                manifest,
+                verified: false,
+                transaction: None,
+            },
+        );
+        Ok(handle)
+    }
+
+    pub(crate) fn close(&self, handle: u32) -> Result<(), FileError> {
+        let mut containers = self.containers.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let container = containers.remove(&handle).ok_or(FileError::InvalidHandle)?;
+        if let Some(ops) = &container.transaction {
+            journal::discard(container.sandbox.root(), ops);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_file(&self, handle: u32, path: &str) -> Result<Vec<u8>, FileError> {
+        self.with_container(handle, |container| {
+            container.check_readable()?;
+            container.check_unlocked()?;
+            let (_, name) = container.resolve(path, false, true)?;
+            // The object is read once and checked before it is used, so the
+            // host cannot change it between the check and the decryption.
+            let mut stored = Vec::new();
+            container.open_object(&name)?.read_to_end(&mut stored)?;
+            if let Some(manifest) = &container.manifest {
+                manifest.check_object(&name, &stored)?;
+            }
+            if !chunked::is_chunked(&stored) {
+                // A plaintext object is only trusted where one can have been
+                // written: it was recorded in the manifest, or no key is in use.
+                let vouched = container.manifest.is_some() || container.key.is_none();
+                if self.require_key || !vouched {
+                    return Err(FileError::IntegrityError(format!("object {} is not encrypted", name)));
+                }
+                return Ok(stored);
+            }
+            let key = container.key.as_ref().ok_or(FileError::KeyNotLoaded)?;
+            let mut object = ChunkedFile::open(stored.as_slice(), key, name.as_bytes(), false)?;
+            let mut data = vec![0u8; object.len() as usize];
+            let mut read = 0;
+            while read < data.len() {
+                read += object.read(&mut data[read..])?;
+            }
+            Ok(data)
+        })
+    }
+
+    pub(crate) fn write_file(&self, handle: u32, path: &str, data: &[u8]) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            container.check_writable()?;
+            container.check_unlocked()?;
+            let (full, name) = container.resolve(path, false, false)?;
+            if container.key.is_none() && self.require_key {
+                return Err(FileError::KeyNotLoaded);
+            }
+            self.change(container, |container, pending| {
+                if fs::symlink_metadata(&full).is_ok_and(|metadata| metadata.is_dir()) {
+                    return Err(FileError::InvalidOperation(format!("{} is a directory", name)));
+                }
+                let rewrite = pending.iter().any(|op| matches!(op, Op::Put { name: n, .. } if *n == name));
+                if !rewrite {
+                    check_pending(pending, &name)?;
+                }
+                if container.mode == FileMode::Append && (rewrite || fs::symlink_metadata(&full).is_ok()) {
+                    return Err(FileError::AlreadyExists(name));
+                }
+                let (file, staged) = journal::stage(container.sandbox.root())?;
+                let written = match &container.key {
+                    None => (&file).write_all(data).and_then(|_| file.sync_all()).map_err(FileError::from),
+                    Some(key) => ChunkedFile::open(file, key, name.as_bytes(), false).and_then(|mut object| {
+                        object.write(data)?;
+                        object.sync()
+                    }),
+                };
+                if let Err(e) = written {
+                    let _ = fs::remove_file(journal::staged_path(container.sandbox.root(), &staged));
+                    return Err(e);
+                }
+                Ok(Op::Put { name, staged })
+            })
+        })
+    }
+
+    pub(crate) fn delete_file(&self, handle: u32, path: &str) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            container.check_deletable()?;
+            container.check_unlocked()?;
+            let (full, name) = container.resolve(path, false, false)?;
+            self.change(container, |_, pending| {
+                check_pending(pending, &name)?;
+                let metadata = fs::symlink_metadata(&full).map_err(|e| io_error(e, &full))?;
+                if metadata.is_dir() {
+                    return Err(FileError::InvalidOperation(format!("{} is a directory", name)));
+                }
+                // Encrypted objects are shredded before they are unlinked.
+                Ok(Op::Delete { name })
+            })
+        })
+    }
+
+    pub(crate) fn rename_file(&self, handle: u32, from: &str, to: &str) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            container.check_deletable()?;
+            container.check_unlocked()?;
+            let (source, from) = container.resolve(from, false, false)?;
+            let (target, to) = container.resolve(to, false, false)?;
+            self.change(container, |container, pending| {
+                check_pending(pending, &from)?;
+                check_pending(pending, &to)?;
+                let metadata = fs::symlink_metadata(&source).map_err(|e| io_error(e, &source))?;
+                if metadata.is_dir() {
+                    return Err(FileError::InvalidOperation(format!("{} is a directory", from)));
+                }
+                if fs::symlink_metadata(&target).is_ok() {
+                    return Err(FileError::AlreadyExists(to));
+                }
+                // Only the header of an encrypted object is rewritten.
+                let file = match metadata.is_file() {
+                    true => Some(container.open_object(&from)?),
+                    false => None,
+                };
+                let header = match file {
+                    Some(file) if has_header(&file)? => {
+                        let key = container.key.as_ref().ok_or(FileError::KeyNotLoaded)?;
+                        chunked::rewrap(&file, key, from.as_bytes(), to.as_bytes())?
+                    }
+                    _ => Vec::new(),
+                };
+                Ok(Op::Rename { from, to, header })
+            })
+        })
+    }
+
+    pub(crate) fn begin_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            container.check_writable()?;
+            if container.transaction.is_some() {
+                return Err(FileError::InvalidOperation("a transaction is already open".to_string()));
+            }
+            container.transaction = Some(Vec::new());
+            Ok(())
+        })
+    }
+
+    pub(crate) fn commit_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            let ops = container.transaction.take().ok_or_else(no_transaction)?;
+            self.commit(container, ops)
+        })
+    }
+
+    pub(crate) fn abort_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            let ops = container.transaction.take().ok_or_else(no_transaction)?;
+            journal::discard(container.sandbox.root(), &ops);
+            Ok(())
+        })
+    }
+
+    pub(crate) fn list_files(&self, handle: u32, path: &str) -> Result<Vec<String>, FileError> {
+        self.with_container(handle, |container| {
+            let (full, name) = container.resolve(path, true, true)?;
+            let mut names = Vec::new();
+            for entry in fs::read_dir(&full).map_err(|e| io_error(e, &full))? {
+                let entry = entry?;
+                let entry_name = entry.file_name().to_string_lossy().into_owned();
+                if name.is_empty() && entry_name.starts_with(RESERVED_PREFIX) {
+                    continue;
+                }
+                names.push(entry_name);
+            }
+            names.sort();
+            Ok(names)
+        })
+    }
+
+    pub(crate) fn get_metadata(&self, handle: u32, path: &str) -> Result<ObjectMetadata, FileError> {
+        self.with_container(handle, |container| {
+            let (full, name) = container.resolve(path, true, false)?;
+            let metadata = fs::symlink_metadata(&full).map_err(|e| io_error(e, &full))?;
+            let size = match FileType::of(&metadata) {
+                FileType::Regular => match read_header(&container.open_object(&name)?)? {
+                    Some(header) => chunked::plaintext_len(&header, metadata.len())?,
+                    None => metadata.len(),
+                },
+                _ => metadata.len(),
+            };
+            let name = name.rsplit('/').next().unwrap_or_default().to_string();
+            Ok(ObjectMetadata::from_host(name, &metadata, size))
+        })
+    }
+
+    pub(crate) fn load_key(&self, handle: u32, source: KeySource<'_>) -> Result<(), FileError> {
+        let key = source.resolve()?;
+        self.with_container(handle, |container| {
+            container.verified = false;
+            // Under another key the manifest stays locked, like the objects.
+            if let Some(manifest) = &container.manifest {
+                if manifest.key_id == chunked::key_id(&key) {
+                    manifest.verify(&key)?;
+                    manifest.check(container.sandbox.root())?;
+                    self.versions()?.advance(&container.id, manifest.version)?;
+                    container.verified = true;
+                }
+            }
+            container.key = Some(key);
+            Ok(())
+        })
+    }
+
+    pub(crate) fn remove_key(&self, handle: u32) -> Result<(), FileError> {
+        self.with_container(handle, |container| {
+            container.verified = false;
+            container
+                .key
+                .take()
+                .map(|_| ())
+                .ok_or_else(|| FileError::InvalidOperation("no key is loaded".to_string()))
+        })
+    }
+
+    pub(crate) fn is_encrypted(&self, handle: u32, path: &str) -> Result<bool, FileError> {
+        self.with_container(handle, |container| {
+            let (full, name) = container.resolve(path, false, true)?;
+            if !full.is_file() {
+                return match full.exists() {
+                    true => Err(FileError::InvalidOperation(format!("{} is not a file", name))),
+                    false => Err(FileError::NotFound),
+                };
+            }
+            has_header(&container.open_object(&name)?)
+        })
+    }
+}
+
+fn check_pending(pending: &[Op], name: &str) -> Result<(), FileError> {
+    match pending.iter().any(|op| op.touches(name)) {
+        true => Err(FileError::InvalidOperation(format!("{} already has uncommitted changes", name))),
+        false => Ok(()),
+    }
+}
+
+fn no_transaction() -> FileError {
+    FileError::InvalidOperation("no transaction is open".to_string())
+}
+
+/// Normalizes an object path to `/`-separated components below the root.
+fn object_name(path: &str, allow_root: bool) -> Result<String, FileError> {
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(
+                part.to_str()
+                    .ok_or_else(|| FileError::InvalidOperation("object path is not UTF-8".to_string()))?,
+            ),
+            Component::CurDir => {}
+            Component::ParentDir if parts.pop().is_some() => {}
+            _ => return Err(FileError::SandboxViolation(path.to_string())),
+        }
+    }
+    if parts.is_empty() && !allow_root {
+        return Err(FileError::InvalidOperation("object path is empty".to_string()));
+    }
+    Ok(parts.join("/"))
+}
+
+fn read_header(file: &File) -> Result<Option<Vec<u8>>, FileError> {
+    let mut header = Vec::with_capacity(chunked::HEADER_LEN);
+    file.take(chunked::HEADER_LEN as u64).read_to_end(&mut header)?;
+    Ok(chunked::is_chunked(&header).then_some(header))
+}
+
+fn has_header(file: &File) -> Result<bool, FileError> {
+    read_header(file).map(|header| header.is_some())
+}
+
+fn io_error(err: io::Error, path: &Path) -> FileError {
+    match err.kind() {
+        io::ErrorKind::NotFound => FileError::NotFound,
+        io::ErrorKind::PermissionDenied => FileError::PermissionDenied(path.display().to_string()),
+        io::ErrorKind::AlreadyExists => FileError::AlreadyExists(path.display().to_string()),
+        _ => FileError::IoError(err),
+    }
+}
+
+#[cfg(unix)]
+fn check_access(path: &Path, mode: FileMode) -> Result<(), FileError> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut amode = libc::R_OK | libc::X_OK;
+    if mode.can_write() {
+        amode |= libc::W_OK;
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| FileError::InvalidOperation("path contains a NUL byte".to_string()))?;
+    if unsafe { libc::access(c_path.as_ptr(), amode) } != 0 {
+        return Err(FileError::PermissionDenied(path.display().to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_access(path: &Path, mode: FileMode) -> Result<(), FileError> {
+    let metadata = fs::metadata(path).map_err(|e| io_error(e, path))?;
+    if mode.can_write() && metadata.permissions().readonly() {
+        return Err(FileError::PermissionDenied(path.display().to_string()));
+    }
+    Ok(())
+}
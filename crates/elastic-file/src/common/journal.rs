@@ -0,0 +1,314 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use sha2::{Digest, Sha256};
+use super::chunked;
+use super::manifest::{MANIFEST_NAME, RESERVED_PREFIX};
+use super::sandbox;
+use super::FileError;
+
+// Changes to a container never write objects in place. New contents are
+// written to a file under `.elastic-staging` and synced, then renamed into
+// place and the directory synced. A change that touches more than one file
+// (several objects, an object and the manifest, or an encrypted rename that
+// rewrites a header) is first recorded in `.elastic-journal`:
+//
+//   magic(4) || version(1) || reserved(3) || op count(4, BE) || ops
+//   || SHA-256 of the preceding bytes(32)
+//   op: kind(1) || strings and headers as length(2, BE) || bytes
+//
+// The journal is written through a synced temporary file and a rename, which
+// is the commit point. Every operation can be replayed, so `recover` finishes
+// a committed change after a crash and drops an uncommitted one.
+pub(crate) const JOURNAL_NAME: &str = ".elastic-journal";
+const STAGING_NAME: &str = ".elastic-staging";
+const MAGIC: &[u8; 4] = b"EJNL";
+const VERSION: u8 = 1;
+
+/// One step of a change. A change touches each object at most once.
+pub(crate) enum Op {
+    /// Moves the staged file `staged` to `name`.
+    Put { name: String, staged: String },
+    /// Removes `name`, shredding it first if it is encrypted.
+    Delete { name: String },
+    /// Moves `from` to `to`, first writing `header` unless it is empty.
+    Rename { from: String, to: String, header: Vec<u8> },
+}
+
+impl Op {
+    pub(crate) fn touches(&self, object: &str) -> bool {
+        match self {
+            Op::Put { name, .. } | Op::Delete { name } => name == object,
+            Op::Rename { from, to, .. } => from == object || to == object,
+        }
+    }
+}
+
+/// Creates an empty staged file in the container at `root`.
+pub(crate) fn stage(root: &Path) -> Result<(File, String), FileError> {
+    let dir = root.join(STAGING_NAME);
+    fs::create_dir_all(&dir)?;
+    let mut id = [0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    let staged: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(dir.join(&staged))?;
+    Ok((file, staged))
+}
+
+pub(crate) fn staged_path(root: &Path, staged: &str) -> PathBuf {
+    root.join(STAGING_NAME).join(staged)
+}
+
+/// Removes the staged files of a change that will not be committed.
+pub(crate) fn discard(root: &Path, ops: &[Op]) {
+    for op in ops {
+        if let Op::Put { staged, .. } = op {
+            let _ = fs::remove_file(staged_path(root, staged));
+        }
+    }
+}
+
+/// Applies `ops` to the container at `root` so that either all or none of
+/// them survive a crash. Staged files must already be synced.
+pub(crate) fn commit(root: &Path, ops: &[Op]) -> Result<(), FileError> {
+    match ops {
+        [] => return Ok(()),
+        [op] if !matches!(op, Op::Rename { header, .. } if !header.is_empty()) => return apply(root, op),
+        _ => {}
+    }
+    if root.join(STAGING_NAME).exists() {
+        sync_dir(&root.join(STAGING_NAME))?;
+    }
+    if let Err(e) = replace(&root.join(JOURNAL_NAME), &encode(ops)) {
+        discard(root, ops);
+        return Err(e);
+    }
+    for op in ops {
+        apply(root, op)?;
+    }
+    fs::remove_file(root.join(JOURNAL_NAME))?;
+    sync_dir(root)
+}
+
+/// Finishes or drops a change interrupted by a crash. Runs before a
+/// container is opened.
+pub(crate) fn recover(root: &Path) -> Result<(), FileError> {
+    let journal = root.join(JOURNAL_NAME);
+    match fs::read(&journal) {
+        Ok(data) => {
+            for op in decode(&data)? {
+                apply(root, &op)?;
+            }
+            fs::remove_file(&journal)?;
+            sync_dir(root)?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    match fs::remove_file(temporary(&journal)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    match fs::remove_dir_all(root.join(STAGING_NAME)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Applies one operation. Replaying an operation that already completed is
+/// a no-op. Names are resolved beneath the root, so a symbolic link in the
+/// container cannot redirect a change outside it.
+fn apply(root: &Path, op: &Op) -> Result<(), FileError> {
+    match op {
+        Op::Put { name, staged } => {
+            let staged = staged_path(root, staged);
+            if !staged.exists() {
+                return Ok(());
+            }
+            let target = sandbox::resolve(root, Path::new(name), false)?;
+            let parent = create_parent(root, &target)?;
+            fs::rename(&staged, &target)?;
+            sync_dir(&parent)
+        }
+        Op::Delete { name } => {
+            let target = sandbox::resolve(root, Path::new(name), false)?;
+            let metadata = match fs::symlink_metadata(&target) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if metadata.is_file() {
+                let file = OpenOptions::new().read(true).write(true).open(&target)?;
+                if chunked::is_chunked(&read_prefix(&file)?) {
+                    chunked::shred(&file)?;
+                }
+            }
+            fs::remove_file(&target)?;
+            sync_dir(target.parent().unwrap_or(root))
+        }
+        Op::Rename { from, to, header } => {
+            let source = sandbox::resolve(root, Path::new(from), false)?;
+            if !source.exists() {
+                return Ok(());
+            }
+            if !header.is_empty() {
+                let file = OpenOptions::new().read(true).write(true).open(&source)?;
+                chunked::write_header(&file, header)?;
+            }
+            let target = sandbox::resolve(root, Path::new(to), false)?;
+            let parent = create_parent(root, &target)?;
+            fs::rename(&source, &target)?;
+            sync_dir(&parent)?;
+            sync_dir(source.parent().unwrap_or(root))
+        }
+    }
+}
+
+fn create_parent(root: &Path, target: &Path) -> Result<PathBuf, FileError> {
+    let parent = target.parent().unwrap_or(root).to_path_buf();
+    if !parent.exists() {
+        fs::create_dir_all(&parent)?;
+        sync_dir(parent.parent().unwrap_or(root))?;
+    }
+    Ok(parent)
+}
+
+pub(crate) fn read_prefix(file: &File) -> Result<Vec<u8>, FileError> {
+    let mut header = Vec::with_capacity(chunked::HEADER_LEN);
+    file.take(chunked::HEADER_LEN as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+fn encode(ops: &[Op]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&[VERSION, 0, 0, 0]);
+    data.extend_from_slice(&(ops.len() as u32).to_be_bytes());
+    let field = |data: &mut Vec<u8>, bytes: &[u8]| {
+        data.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        data.extend_from_slice(bytes);
+    };
+    for op in ops {
+        match op {
+            Op::Put { name, staged } => {
+                data.push(1);
+                field(&mut data, name.as_bytes());
+                field(&mut data, staged.as_bytes());
+            }
+            Op::Delete { name } => {
+                data.push(2);
+                field(&mut data, name.as_bytes());
+            }
+            Op::Rename { from, to, header } => {
+                data.push(3);
+                field(&mut data, from.as_bytes());
+                field(&mut data, to.as_bytes());
+                field(&mut data, header);
+            }
+        }
+    }
+    let checksum = Sha256::digest(&data);
+    data.extend_from_slice(&checksum);
+    data
+}
+
+fn decode(data: &[u8]) -> Result<Vec<Op>, FileError> {
+    let corrupt = || FileError::IntegrityError("container journal is corrupt".to_string());
+    let body_len = data.len().checked_sub(32).ok_or_else(corrupt)?;
+    let (body, checksum) = data.split_at(body_len);
+    if Sha256::digest(body).as_slice() != checksum {
+        return Err(corrupt());
+    }
+    let mut reader = Reader(body);
+    let header = reader.take(12).ok_or_else(corrupt)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(corrupt());
+    }
+    let count = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+    let mut ops = Vec::new();
+    for _ in 0..count {
+        let op = match reader.take(1).ok_or_else(corrupt)?[0] {
+            1 => Op::Put {
+                name: reader.name(true).ok_or_else(corrupt)?,
+                staged: reader.name(false).ok_or_else(corrupt)?,
+            },
+            2 => Op::Delete { name: reader.name(false).ok_or_else(corrupt)? },
+            3 => Op::Rename {
+                from: reader.name(false).ok_or_else(corrupt)?,
+                to: reader.name(false).ok_or_else(corrupt)?,
+                header: reader.field().ok_or_else(corrupt)?.to_vec(),
+            },
+            _ => return Err(corrupt()),
+        };
+        ops.push(op);
+    }
+    if !reader.0.is_empty() {
+        return Err(corrupt());
+    }
+    Ok(ops)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn field(&mut self) -> Option<&'a [u8]> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as usize;
+        self.take(len)
+    }
+
+    /// Reads an object path. The journal lives on storage the host controls,
+    /// so paths are checked as strictly as the container checks them; only
+    /// the manifest may be named among the reserved entries.
+    fn name(&mut self, allow_manifest: bool) -> Option<String> {
+        let name = std::str::from_utf8(self.field()?).ok()?;
+        let relative = !name.is_empty() && Path::new(name).components().all(|c| matches!(c, Component::Normal(_)));
+        let reserved = name.starts_with(RESERVED_PREFIX) && !(allow_manifest && name == MANIFEST_NAME);
+        (relative && !reserved).then(|| name.to_string())
+    }
+}
+
+/// Replaces `path` with `data` through a synced temporary file and a rename,
+/// so a crash leaves either the old or the new contents.
+pub(crate) fn replace(path: &Path, data: &[u8]) -> Result<(), FileError> {
+    let temp = temporary(path);
+    let mut file = File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+fn temporary(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+/// Makes renames and removals in `dir` durable. Directories cannot be opened
+/// for syncing outside Unix, where this is a no-op.
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir: &Path) -> Result<(), FileError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir: &Path) -> Result<(), FileError> {
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::sync::Mutex;
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use elastic_crypto::{CryptoBackend, KeyAlgorithm};
+use super::chunked;
+use super::FileError;
+
+pub const KEY_LEN: usize = 32;
+const MASTER_KEY_LABEL: &[u8] = b"elastic-file master key";
+
+/// Where the master key of a file context or container comes from.
+pub enum KeySource<'a> {
+    /// A raw 32-byte key, as passed to `load-key` in `file.wit`.
+    Raw(&'a [u8]),
+    /// An HMAC-SHA256 key handle. The master key is a MAC computed inside the
+    /// key store, so secure-storage and remote keys never leave it.
+    Handle {
+        backend: &'a dyn CryptoBackend,
+        handle: u32,
+    },
+    /// A master key sealed under an AES-SIV key handle, as returned by
+    /// [`seal_key`].
+    Sealed {
+        backend: &'a dyn CryptoBackend,
+        handle: u32,
+        sealed: &'a [u8],
+    },
+}
+
+impl KeySource<'_> {
+    pub(crate) fn resolve(&self) -> Result<Vec<u8>, FileError> {
+        let key = match self {
+            KeySource::Raw(key) => key.to_vec(),
+            KeySource::Handle { backend, handle } => {
+                check_algorithm(*backend, *handle, KeyAlgorithm::HmacSha256)?;
+                backend.calculate_mac(*handle, MASTER_KEY_LABEL.to_vec())?
+            }
+            KeySource::Sealed { backend, handle, sealed } => {
+                check_algorithm(*backend, *handle, KeyAlgorithm::AesSiv)?;
+                backend
+                    .decrypt_siv(*handle, sealed.to_vec(), vec![MASTER_KEY_LABEL.to_vec()], None)
+                    .map_err(|e| match e {
+                        elastic_crypto::Error::DecryptionError(_) => FileError::WrongKey,
+                        e => FileError::Crypto(e),
+                    })?
+            }
+        };
+        if key.len() != KEY_LEN {
+            return Err(FileError::InvalidOperation(format!("key must be {} bytes", KEY_LEN)));
+        }
+        Ok(key)
+    }
+}
+
+/// Generates a random master key sealed under the AES-SIV key `handle`. The
+/// result can be stored anywhere and loaded with [`KeySource::Sealed`].
+pub fn seal_key(backend: &dyn CryptoBackend, handle: u32) -> Result<Vec<u8>, FileError> {
+    check_algorithm(backend, handle, KeyAlgorithm::AesSiv)?;
+    let mut key = vec![0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    Ok(backend.encrypt_siv(handle, key, vec![MASTER_KEY_LABEL.to_vec()], None)?)
+}
+
+fn check_algorithm(backend: &dyn CryptoBackend, handle: u32, expected: KeyAlgorithm) -> Result<(), FileError> {
+    if backend.key_algorithm(handle)? != expected {
+        return Err(FileError::InvalidOperation(format!("key handle must be a {:?} key", expected)));
+    }
+    Ok(())
+}
+
+/// The master key currently loaded into a file context. Encrypted handles
+/// check it on every access, so removing the key locks open files as well.
+#[derive(Default)]
+pub(crate) struct KeySlot {
+    key: Mutex<Option<(Vec<u8>, [u8; chunked::KEY_ID_LEN])>>,
+}
+
+impl KeySlot {
+    pub(crate) fn load(&self, source: KeySource<'_>) -> Result<(), FileError> {
+        let key = source.resolve()?;
+        let id = chunked::key_id(&key);
+        *self.key.lock().map_err(|e| FileError::OperationFailed(e.to_string()))? = Some((key, id));
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self) -> Result<(), FileError> {
+        self.key
+            .lock()
+            .map_err(|e| FileError::OperationFailed(e.to_string()))?
+            .take()
+            .map(|_| ())
+            .ok_or_else(|| FileError::InvalidOperation("no key is loaded".to_string()))
+    }
+
+    pub(crate) fn get(&self) -> Result<Vec<u8>, FileError> {
+        self.key
+            .lock()
+            .map_err(|e| FileError::OperationFailed(e.to_string()))?
+            .as_ref()
+            .map(|(key, _)| key.clone())
+            .ok_or(FileError::KeyNotLoaded)
+    }
+
+    /// Fails unless the loaded key is the one with id `key_id`.
+    pub(crate) fn check(&self, key_id: &[u8]) -> Result<(), FileError> {
+        match &*self.key.lock().map_err(|e| FileError::OperationFailed(e.to_string()))? {
+            Some((_, id)) if id == key_id => Ok(()),
+            Some(_) => Err(FileError::WrongKey),
+            None => Err(FileError::KeyNotLoaded),
+        }
+    }
+}
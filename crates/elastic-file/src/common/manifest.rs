@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use super::chunked::{self, KEY_ID_LEN};
+use super::journal::{self, Op};
+use super::{FileError, KeySource};
+
+// A protected container keeps a manifest at its root:
+//
+//   magic(4) || version(1) || reserved(3) || key id(16) || counter(8, BE)
+//   || entry count(4, BE) || entries || tag(32)
+//   entry: path length(2, BE) || path || SHA-256 of the object on disk(32)
+//
+// The entries are the leaves of a Merkle tree, and the tag is an HMAC over
+// the fixed fields and the tree root under a key derived from the container
+// key. The counter is bumped on every change and recorded in a
+// `VersionStore`, so an older manifest with a valid tag is still refused.
+pub(crate) const MANIFEST_NAME: &str = ".elastic-manifest";
+/// Root entries with this prefix belong to the container itself.
+pub(crate) const RESERVED_PREFIX: &str = ".elastic-";
+const MAGIC: &[u8; 4] = b"EMAN";
+const VERSION: u8 = 1;
+const TAG_LEN: usize = 32;
+
+type Digest32 = [u8; 32];
+
+/// Trusted storage for container version counters. Containers are
+/// identified by their canonical root path.
+///
+/// Rollback is only detected if the store itself cannot be rolled back by
+/// whoever controls the container storage.
+pub trait VersionStore: Send + Sync {
+    /// The last version recorded for `container`, or 0.
+    fn current(&self, container: &str) -> Result<u64, FileError>;
+    /// Records `version` for `container`. Versions never go backwards.
+    fn advance(&self, container: &str, version: u64) -> Result<(), FileError>;
+}
+
+fn advance_in(versions: &mut HashMap<String, u64>, container: &str, version: u64) -> Result<bool, FileError> {
+    let current = versions.get(container).copied().unwrap_or(0);
+    if version < current {
+        return Err(FileError::IntegrityError(format!(
+            "version {} of {} is older than {}",
+            version, container, current
+        )));
+    }
+    versions.insert(container.to_string(), version);
+    Ok(version > current)
+}
+
+/// Keeps versions in memory, which protects containers for the lifetime of
+/// the process. This is the default store of a file context.
+#[derive(Default)]
+pub struct MemoryVersionStore {
+    versions: Mutex<HashMap<String, u64>>,
+}
+
+impl VersionStore for MemoryVersionStore {
+    fn current(&self, container: &str) -> Result<u64, FileError> {
+        let versions = self.versions.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        Ok(versions.get(container).copied().unwrap_or(0))
+    }
+
+    fn advance(&self, container: &str, version: u64) -> Result<(), FileError> {
+        let mut versions = self.versions.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        advance_in(&mut versions, container, version).map(|_| ())
+    }
+}
+
+/// Persists versions in a file authenticated with a key from `elastic-crypto`.
+/// The file cannot be forged without the key, but it must live on storage
+/// the host cannot roll back, e.g. an encrypted guest disk.
+pub struct SealedVersionStore {
+    path: PathBuf,
+    key: Vec<u8>,
+    versions: Mutex<HashMap<String, u64>>,
+}
+
+impl SealedVersionStore {
+    /// Opens the store at `path`, creating it on the first update.
+    pub fn open(path: impl Into<PathBuf>, source: KeySource<'_>) -> Result<Self, FileError> {
+        let path = path.into();
+        let key = derive(&source.resolve()?, b"elastic-file version store");
+        let versions = match fs::read(&path) {
+            Ok(data) => decode_versions(&data, &key)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, key, versions: Mutex::new(versions) })
+    }
+
+    fn save(&self, versions: &HashMap<String, u64>) -> Result<(), FileError> {
+        let mut names: Vec<_> = versions.iter().collect();
+        names.sort();
+        let mut data = Vec::new();
+        for (name, version) in names {
+            data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&version.to_be_bytes());
+        }
+        let tag = mac(&self.key, &[&data]);
+        data.extend_from_slice(&tag);
+        journal::replace(&self.path, &data)
+    }
+}
+
+impl VersionStore for SealedVersionStore {
+    fn current(&self, container: &str) -> Result<u64, FileError> {
+        let versions = self.versions.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        Ok(versions.get(container).copied().unwrap_or(0))
+    }
+
+    fn advance(&self, container: &str, version: u64) -> Result<(), FileError> {
+        let mut versions = self.versions.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        if advance_in(&mut versions, container, version)? {
+            self.save(&versions)?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_versions(data: &[u8], key: &[u8]) -> Result<HashMap<String, u64>, FileError> {
+    let corrupt = || FileError::IntegrityError("version store is corrupt".to_string());
+    let body_len = data.len().checked_sub(TAG_LEN).ok_or_else(corrupt)?;
+    let (body, tag) = data.split_at(body_len);
+    verify_mac(key, &[body], tag).map_err(|_| corrupt())?;
+
+    let mut versions = HashMap::new();
+    let mut reader = Reader(body);
+    while !reader.0.is_empty() {
+        let len = u16::from_be_bytes(reader.take(2).ok_or_else(corrupt)?.try_into().unwrap()) as usize;
+        let name = String::from_utf8(reader.take(len).ok_or_else(corrupt)?.to_vec()).map_err(|_| corrupt())?;
+        let version = u64::from_be_bytes(reader.take(8).ok_or_else(corrupt)?.try_into().unwrap());
+        versions.insert(name, version);
+    }
+    Ok(versions)
+}
+
+/// The manifest of a container: a digest of every object, under a version.
+#[derive(Clone)]
+pub(crate) struct Manifest {
+    pub(crate) key_id: [u8; KEY_ID_LEN],
+    pub(crate) version: u64,
+    entries: BTreeMap<String, Digest32>,
+    tag: Digest32,
+}
+
+impl Manifest {
+    /// Starts a manifest over the objects currently in `root`.
+    pub(crate) fn create(root: &Path, key: &[u8]) -> Result<Self, FileError> {
+        Ok(Self {
+            key_id: chunked::key_id(key),
+            version: 0,
+            entries: scan(root)?,
+            tag: [0; TAG_LEN],
+        })
+    }
+
+    /// Reads the manifest of `root`, if it has one.
+    pub(crate) fn load(root: &Path) -> Result<Option<Self>, FileError> {
+        let data = match fs::read(root.join(MANIFEST_NAME)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let corrupt = || FileError::IntegrityError("container manifest is corrupt".to_string());
+        let mut reader = Reader(&data);
+        let header = reader.take(8).ok_or_else(corrupt)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(corrupt());
+        }
+        let key_id = reader.take(KEY_ID_LEN).ok_or_else(corrupt)?.try_into().unwrap();
+        let version = u64::from_be_bytes(reader.take(8).ok_or_else(corrupt)?.try_into().unwrap());
+        let count = u32::from_be_bytes(reader.take(4).ok_or_else(corrupt)?.try_into().unwrap());
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let len = u16::from_be_bytes(reader.take(2).ok_or_else(corrupt)?.try_into().unwrap()) as usize;
+            let name = String::from_utf8(reader.take(len).ok_or_else(corrupt)?.to_vec()).map_err(|_| corrupt())?;
+            let digest = reader.take(32).ok_or_else(corrupt)?.try_into().unwrap();
+            entries.insert(name, digest);
+        }
+        let tag = reader.take(TAG_LEN).ok_or_else(corrupt)?.try_into().unwrap();
+        if !reader.0.is_empty() {
+            return Err(corrupt());
+        }
+        Ok(Some(Self { key_id, version, entries, tag }))
+    }
+
+    /// Verifies the tag with the container key.
+    pub(crate) fn verify(&self, key: &[u8]) -> Result<(), FileError> {
+        verify_mac(&derive(key, b"elastic-file manifest key"), &self.signed(), &self.tag)
+            .map_err(|_| FileError::IntegrityError("container manifest failed authentication".to_string()))
+    }
+
+    /// Compares the manifest with the objects in `root`.
+    pub(crate) fn check(&self, root: &Path) -> Result<(), FileError> {
+        let objects = scan(root)?;
+        for (name, digest) in &self.entries {
+            match objects.get(name) {
+                None => return Err(FileError::IntegrityError(format!("object {} is missing", name))),
+                Some(found) if found != digest => {
+                    return Err(FileError::IntegrityError(format!("object {} was modified", name)))
+                }
+                Some(_) => {}
+            }
+        }
+        match objects.keys().find(|name| !self.entries.contains_key(*name)) {
+            Some(name) => Err(FileError::IntegrityError(format!("object {} is not in the manifest", name))),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the stored bytes of one object against its entry.
+    pub(crate) fn check_object(&self, name: &str, data: &[u8]) -> Result<(), FileError> {
+        match self.entries.get(name) {
+            None => Err(FileError::IntegrityError(format!("object {} is not in the manifest", name))),
+            Some(digest) if digest[..] != Sha256::digest(data)[..] => {
+                Err(FileError::IntegrityError(format!("object {} was modified", name)))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Records the objects as they will be once `ops` are applied.
+    pub(crate) fn apply(&mut self, root: &Path, ops: &[Op]) -> Result<(), FileError> {
+        for op in ops {
+            match op {
+                Op::Put { name, staged } => {
+                    let data = fs::read(journal::staged_path(root, staged))?;
+                    self.entries.insert(name.clone(), Sha256::digest(&data).into());
+                }
+                Op::Delete { name } => {
+                    self.entries.remove(name);
+                }
+                Op::Rename { from, to, header } => {
+                    let mut data = fs::read(root.join(from))?;
+                    data[..header.len()].copy_from_slice(header);
+                    self.entries.remove(from);
+                    self.entries.insert(to.clone(), Sha256::digest(&data).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bumps the version and returns the signed manifest.
+    pub(crate) fn seal(&mut self, key: &[u8]) -> Vec<u8> {
+        self.version += 1;
+        self.tag = mac(&derive(key, b"elastic-file manifest key"), &self.signed());
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&[VERSION, 0, 0, 0]);
+        data.extend_from_slice(&self.key_id);
+        data.extend_from_slice(&self.version.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for (name, digest) in &self.entries {
+            data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(digest);
+        }
+        data.extend_from_slice(&self.tag);
+        data
+    }
+
+    fn signed(&self) -> Vec<Vec<u8>> {
+        vec![
+            MAGIC.to_vec(),
+            vec![VERSION],
+            self.key_id.to_vec(),
+            self.version.to_be_bytes().to_vec(),
+            self.root().to_vec(),
+        ]
+    }
+
+    /// The Merkle root over the entries, in path order.
+    fn root(&self) -> Digest32 {
+        let mut level: Vec<Digest32> = self
+            .entries
+            .iter()
+            .map(|(name, digest)| {
+                let mut leaf = Sha256::new();
+                leaf.update([0u8]);
+                leaf.update((name.len() as u16).to_be_bytes());
+                leaf.update(name.as_bytes());
+                leaf.update(digest);
+                leaf.finalize().into()
+            })
+            .collect();
+        if level.is_empty() {
+            return Sha256::digest([]).into();
+        }
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => {
+                        let mut node = Sha256::new();
+                        node.update([1u8]);
+                        node.update(left);
+                        node.update(right);
+                        node.finalize().into()
+                    }
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level[0]
+    }
+}
+
+/// Digests of every regular file below `root`, by object path.
+fn scan(root: &Path) -> Result<BTreeMap<String, Digest32>, FileError> {
+    let mut objects = BTreeMap::new();
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if prefix.is_empty() && name.starts_with(RESERVED_PREFIX) {
+                continue;
+            }
+            let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push((entry.path(), path));
+            } else if file_type.is_file() {
+                objects.insert(path, Sha256::digest(fs::read(entry.path())?).into());
+            }
+        }
+    }
+    Ok(objects)
+}
+
+fn derive(key: &[u8], label: &[u8]) -> Vec<u8> {
+    let mut derived = vec![0u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(label, &mut derived)
+        .expect("32 bytes is a valid HKDF output length");
+    derived
+}
+
+fn mac(key: &[u8], parts: &[impl AsRef<[u8]>]) -> Digest32 {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part.as_ref());
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn verify_mac(key: &[u8], parts: &[impl AsRef<[u8]>], tag: &[u8]) -> Result<(), hmac::digest::MacError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part.as_ref());
+    }
+    mac.verify_slice(tag)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+}
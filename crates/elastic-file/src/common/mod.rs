@@ -1,6 +1,25 @@
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+mod chunked;
+mod container;
+mod journal;
+mod keys;
+mod manifest;
+mod names;
+mod namespace;
+mod sandbox;
+pub(crate) use chunked::{is_chunked, plaintext_len, seek_from, shred, Backing, ChunkedFile, Storage, HEADER_LEN};
+pub(crate) use keys::KeySlot;
+pub use keys::{seal_key, KeySource};
+pub use manifest::{MemoryVersionStore, SealedVersionStore, VersionStore};
+pub(crate) use container::ContainerManager;
+pub(crate) use namespace::{check_plain_names, Namespace};
+pub(crate) use sandbox::{OpenFlags, Sandbox};
+
 #[derive(Error, Debug)]
 pub enum FileError {
     #[error("File not found")]
@@ -9,6 +28,26 @@ pub enum FileError {
     InvalidHandle,
     #[error("Invalid file mode")]
     InvalidMode,
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    #[error("Invalid operation: {0}")]
+    InvalidOperation(String),
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+    #[error("Decryption error: {0}")]
+    DecryptionError(String),
+    #[error("No key is loaded: encrypted data cannot be accessed")]
+    KeyNotLoaded,
+    #[error("The loaded key is not the key the data was encrypted with")]
+    WrongKey,
+    #[error("Integrity check failed: {0}")]
+    IntegrityError(String),
+    #[error("Path leaves the sandbox root: {0}")]
+    SandboxViolation(String),
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] elastic_crypto::Error),
     #[error("Operation failed: {0}")]
     OperationFailed(String),
     #[error("IO error: {0}")]
@@ -60,6 +99,36 @@ pub trait FileOperations {
     fn seek(&self, handle: u32, offset: i64, whence: i32) -> Result<u64, FileError>;
     fn flush(&self, handle: u32) -> Result<(), FileError>;
     fn metadata(&self, handle: u32) -> Result<FileMetadata, FileError>;
+    /// Loads the master key used by files opened with `secure` set. Without
+    /// one, opening a secure file fails with [`FileError::KeyNotLoaded`].
+    fn load_file_key(&self, source: KeySource<'_>) -> Result<(), FileError>;
+    /// Removes the master key; open secure files become unusable until it is
+    /// loaded again.
+    fn remove_file_key(&self) -> Result<(), FileError>;
+
+    // Directory operations. `secure` has the meaning it has for `open`: in a
+    // context with a sandbox root, secure paths are stored with every name
+    // encrypted under the file key, so the host cannot learn the directory
+    // structure, and need the key loaded. A name then holds at most 96
+    // bytes. Without a root names cannot be encrypted, so secure directory
+    // operations fail with `InvalidOperation`; `remove_file` still shreds.
+    fn create_dir(&self, path: &Path, secure: bool) -> Result<(), FileError>;
+    /// Removes an empty directory.
+    fn remove_dir(&self, path: &Path, secure: bool) -> Result<(), FileError>;
+    /// Lists a directory, sorted by name. Sizes of encrypted files are
+    /// plaintext sizes.
+    fn read_dir(&self, path: &Path, secure: bool) -> Result<Vec<ObjectMetadata>, FileError>;
+    /// Metadata of the entry at `path` alone, as `read_dir` would list it.
+    /// A symbolic link in the last component is not followed.
+    fn symlink_metadata(&self, path: &Path, secure: bool) -> Result<ObjectMetadata, FileError>;
+    fn rename(&self, from: &Path, to: &Path, secure: bool) -> Result<(), FileError>;
+    /// Removes a file, shredding it first if it is secure and encrypted.
+    fn remove_file(&self, path: &Path, secure: bool) -> Result<(), FileError>;
+    fn hard_link(&self, original: &Path, link: &Path, secure: bool) -> Result<(), FileError>;
+    /// Creates `link` pointing to `target`. A secure link stores its target
+    /// encrypted: it can be read with `read_link` but is not followed.
+    fn symlink(&self, target: &Path, link: &Path, secure: bool) -> Result<(), FileError>;
+    fn read_link(&self, path: &Path, secure: bool) -> Result<PathBuf, FileError>;
 }
 
 #[derive(Debug, Clone)]
@@ -68,4 +137,121 @@ pub struct FileMetadata {
     pub is_file: bool,
     pub is_dir: bool,
     pub permissions: u32,
-} 
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    SymbolicLink,
+}
+
+impl FileType {
+    pub(crate) fn of(metadata: &fs::Metadata) -> Self {
+        if metadata.file_type().is_symlink() {
+            FileType::SymbolicLink
+        } else if metadata.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        }
+    }
+}
+
+/// Metadata of an object inside a container. Timestamps are seconds since
+/// the Unix epoch (0 when the platform does not record them) and `size` is
+/// the plaintext size for encrypted objects.
+#[derive(Debug, Clone)]
+pub struct ObjectMetadata {
+    pub name: String,
+    pub size: u64,
+    pub file_type: FileType,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+    pub permissions: u32,
+}
+
+impl ObjectMetadata {
+    /// Metadata as the host reports it, with `size` the plaintext size.
+    pub(crate) fn from_host(name: String, metadata: &fs::Metadata, size: u64) -> Self {
+        Self {
+            name,
+            size,
+            file_type: FileType::of(metadata),
+            created: seconds(metadata.created()),
+            modified: seconds(metadata.modified()),
+            accessed: seconds(metadata.accessed()),
+            permissions: permissions(metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+fn seconds(time: io::Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The object-container API of `file.wit`.
+///
+/// A container is a directory opened with a [`FileMode`]: `Read` allows
+/// reading objects, `Write` allows writing and deleting them, `ReadWrite`
+/// allows both, and `Append` only allows creating new objects. Object paths
+/// are `/`-separated and relative to the container root. While a key is
+/// loaded, written objects are encrypted and integrity-protected with
+/// AES-256-GCM; plaintext objects already in the container stay readable.
+/// Reading an encrypted object fails with [`FileError::KeyNotLoaded`] when no
+/// key is loaded and [`FileError::WrongKey`] when another key is.
+///
+/// Once a key is loaded, a container also keeps a signed manifest of all its
+/// objects, versioned through a [`VersionStore`]. `open_container` fails
+/// with [`FileError::IntegrityError`] if objects were modified, added,
+/// removed or rolled back behind its back; the manifest signature itself is
+/// checked when the key is loaded, and until then the container can only be
+/// listed.
+///
+/// Every change is atomic and durable once it returns: objects are written
+/// to a staging file, synced and renamed into place, and changes spanning
+/// several files go through a write-ahead journal that `open_container`
+/// replays after a crash. Between `begin_transaction` and
+/// `commit_transaction`, writes, deletes and renames are staged and applied
+/// together; reads still see the last committed state, and each object can
+/// only be changed once per transaction, except that rewriting it replaces
+/// the staged contents.
+///
+/// Each encrypted object has its own key, bound to its path. Renaming an
+/// object re-wraps that key for the new path without re-encrypting the
+/// contents, and deleting an object destroys its key material first, so
+/// copies of the ciphertext left behind cannot be decrypted.
+pub trait ContainerOperations {
+    fn open_container(&self, path: &Path, mode: FileMode) -> Result<u32, FileError>;
+    fn close_container(&self, handle: u32) -> Result<(), FileError>;
+    fn read_file(&self, handle: u32, path: &str) -> Result<Vec<u8>, FileError>;
+    fn write_file(&self, handle: u32, path: &str, data: &[u8]) -> Result<(), FileError>;
+    fn delete_file(&self, handle: u32, path: &str) -> Result<(), FileError>;
+    fn rename_file(&self, handle: u32, from: &str, to: &str) -> Result<(), FileError>;
+    fn begin_transaction(&self, handle: u32) -> Result<(), FileError>;
+    fn commit_transaction(&self, handle: u32) -> Result<(), FileError>;
+    fn abort_transaction(&self, handle: u32) -> Result<(), FileError>;
+    fn list_files(&self, handle: u32, path: &str) -> Result<Vec<String>, FileError>;
+    fn get_metadata(&self, handle: u32, path: &str) -> Result<ObjectMetadata, FileError>;
+    fn load_container_key(&self, handle: u32, source: KeySource<'_>) -> Result<(), FileError>;
+    fn load_key(&self, handle: u32, key: &[u8]) -> Result<(), FileError> {
+        self.load_container_key(handle, KeySource::Raw(key))
+    }
+    fn remove_key(&self, handle: u32) -> Result<(), FileError>;
+    fn is_encrypted(&self, handle: u32, path: &str) -> Result<bool, FileError>;
+}
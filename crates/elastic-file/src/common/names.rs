@@ -0,0 +1,133 @@
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use super::FileError;
+
+// Names in a secure directory tree are stored encrypted, one component at a
+// time:
+//
+//   hex(nonce(12) || AES-256-GCM(name padded with NULs to 16 bytes) || tag(16))
+//
+// The nonce is an HMAC of the padded name, so a name always encrypts to the
+// same stored name and paths can be looked up without listing directories.
+// Both keys are derived from the file master key. Names are not bound to
+// their directory, so directories can be renamed without re-encrypting what
+// is below them; equal names in different directories look the same.
+// Symbolic link targets are encrypted the same way under a random nonce.
+const NAME_KEY_LABEL: &[u8] = b"elastic-file name key";
+const NAME_NONCE_LABEL: &[u8] = b"elastic-file name nonce";
+const TARGET_AAD: &[u8] = b"elastic-file link target";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const BLOCK: usize = 16;
+const MAX_STORED_NAME: usize = 255;
+
+/// The longest name that still fits a stored name of 255 bytes.
+const MAX_NAME: usize = ((MAX_STORED_NAME / 2 - NONCE_LEN - TAG_LEN) / BLOCK) * BLOCK;
+
+pub(crate) struct NameCipher {
+    cipher: Aes256Gcm,
+    nonce_key: [u8; 32],
+}
+
+impl NameCipher {
+    pub(crate) fn new(master: &[u8]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(&derive(master, NAME_KEY_LABEL).into()),
+            nonce_key: derive(master, NAME_NONCE_LABEL),
+        }
+    }
+
+    pub(crate) fn encrypt(&self, name: &str) -> Result<String, FileError> {
+        if name.len() > MAX_NAME {
+            return Err(FileError::InvalidOperation(format!(
+                "{} is longer than the {} bytes an encrypted name can hold",
+                name, MAX_NAME
+            )));
+        }
+        let padded = pad(name);
+        let nonce = self.nonce(&padded);
+        self.seal(&nonce, &padded, &[])
+    }
+
+    /// Returns `None` for stored names that were not encrypted under this key.
+    pub(crate) fn decrypt(&self, stored: &str) -> Option<String> {
+        let (nonce, padded) = self.open(stored, &[])?;
+        if nonce != self.nonce(&padded) {
+            return None;
+        }
+        unpad(padded)
+    }
+
+    pub(crate) fn encrypt_target(&self, target: &str) -> Result<String, FileError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        self.seal(&nonce, &pad(target), TARGET_AAD)
+    }
+
+    pub(crate) fn decrypt_target(&self, stored: &str) -> Result<String, FileError> {
+        self.open(stored, TARGET_AAD)
+            .and_then(|(_, padded)| unpad(padded))
+            .ok_or_else(|| FileError::DecryptionError("link target failed authentication".to_string()))
+    }
+
+    fn nonce(&self, padded: &[u8]) -> [u8; NONCE_LEN] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.nonce_key).expect("HMAC accepts any key length");
+        mac.update(padded);
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&mac.finalize().into_bytes()[..NONCE_LEN]);
+        nonce
+    }
+
+    fn seal(&self, nonce: &[u8; NONCE_LEN], padded: &[u8], aad: &[u8]) -> Result<String, FileError> {
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(nonce), Payload { msg: padded, aad })
+            .map_err(|e| FileError::EncryptionError(e.to_string()))?;
+        Ok(nonce.iter().chain(&sealed).map(|b| format!("{:02x}", b)).collect())
+    }
+
+    fn open(&self, stored: &str, aad: &[u8]) -> Option<([u8; NONCE_LEN], Vec<u8>)> {
+        let bytes = decode_hex(stored)?;
+        if bytes.len() < NONCE_LEN + TAG_LEN + BLOCK {
+            return None;
+        }
+        let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+        let padded = self.cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad }).ok()?;
+        Some((nonce.try_into().ok()?, padded))
+    }
+}
+
+fn derive(key: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut derived = [0u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(label, &mut derived)
+        .expect("32 bytes is a valid HKDF output length");
+    derived
+}
+
+fn pad(name: &str) -> Vec<u8> {
+    let mut padded = name.as_bytes().to_vec();
+    padded.resize(name.len().div_ceil(BLOCK).max(1) * BLOCK, 0);
+    padded
+}
+
+fn unpad(mut padded: Vec<u8>) -> Option<String> {
+    while padded.last() == Some(&0) {
+        padded.pop();
+    }
+    String::from_utf8(padded).ok().filter(|name| !name.is_empty())
+}
+
+fn decode_hex(stored: &str) -> Option<Vec<u8>> {
+    if !stored.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..stored.len())
+        .step_by(2)
+        .map(|i| stored.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
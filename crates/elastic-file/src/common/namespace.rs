@@ -0,0 +1,219 @@
+use std::fs::{self, File, OpenOptions};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use super::chunked;
+use super::journal::read_prefix;
+use super::keys::KeySlot;
+use super::names::NameCipher;
+use super::sandbox::{OpenFlags, Sandbox};
+use super::{FileError, FileType, ObjectMetadata};
+
+/// Maps the paths a backend is given to paths on the host, and implements
+/// the directory operations of [`FileOperations`](super::FileOperations) on
+/// top. With a sandbox root, paths are resolved beneath it, and secure paths
+/// are stored with every name encrypted under the file key; without one,
+/// paths are used as they are and secure directory operations are refused.
+pub(crate) struct Namespace {
+    keys: Arc<KeySlot>,
+    root: Option<Arc<Sandbox>>,
+}
+
+impl Namespace {
+    pub(crate) fn new(keys: Arc<KeySlot>) -> Self {
+        Self { keys, root: None }
+    }
+
+    pub(crate) fn set_root(&mut self, root: Arc<Sandbox>) {
+        self.root = Some(root);
+    }
+
+    fn names(&self, secure: bool) -> Result<Option<NameCipher>, FileError> {
+        match (&self.root, secure) {
+            (Some(_), true) => Ok(Some(NameCipher::new(&self.keys.get()?))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Name cipher for a directory operation, which unlike `open` would
+    /// otherwise store or reveal names in plaintext without a root.
+    fn dir_names(&self, secure: bool) -> Result<Option<NameCipher>, FileError> {
+        if self.root.is_none() {
+            check_plain_names(secure)?;
+        }
+        self.names(secure)
+    }
+
+    /// The path stored for `path`, relative to the root if there is one.
+    fn stored(&self, path: &Path, names: Option<&NameCipher>) -> Result<PathBuf, FileError> {
+        let Some(names) = names else {
+            return Ok(path.to_path_buf());
+        };
+        normalize(path)?.iter().map(|name| names.encrypt(name)).collect()
+    }
+
+    /// The host path of `path`. Symbolic links are followed in every
+    /// component but the last, and in the last too if `follow` is set.
+    fn host(&self, path: &Path, names: Option<&NameCipher>, follow: bool) -> Result<PathBuf, FileError> {
+        let stored = self.stored(path, names)?;
+        match &self.root {
+            Some(sandbox) => sandbox.resolve(&stored, follow),
+            None => Ok(stored),
+        }
+    }
+
+    /// Opens the file at `path`, returning it with its host path.
+    pub(crate) fn open(&self, path: &Path, secure: bool, flags: &OpenFlags) -> Result<(File, PathBuf), FileError> {
+        let stored = self.stored(path, self.names(secure)?.as_ref())?;
+        let file = flags.open(self.root.as_deref(), &stored)?;
+        let host = match &self.root {
+            Some(sandbox) => sandbox.resolve(&stored, true)?,
+            None => stored,
+        };
+        Ok((file, host))
+    }
+
+    pub(crate) fn create_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        Ok(fs::create_dir(self.host(path, self.dir_names(secure)?.as_ref(), false)?)?)
+    }
+
+    pub(crate) fn remove_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        Ok(fs::remove_dir(self.host(path, self.dir_names(secure)?.as_ref(), false)?)?)
+    }
+
+    /// Lists the entries of a directory, sorted by name. With encrypted
+    /// names, entries whose names were not encrypted under the loaded key
+    /// are left out.
+    pub(crate) fn read_dir(&self, path: &Path, secure: bool) -> Result<Vec<ObjectMetadata>, FileError> {
+        let names = self.dir_names(secure)?;
+        let dir = self.host(path, names.as_ref(), true)?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let stored = entry.file_name().to_string_lossy().into_owned();
+            let name = match &names {
+                Some(names) => match names.decrypt(&stored) {
+                    Some(name) => name,
+                    None => continue,
+                },
+                None => stored,
+            };
+            entries.push(object(name, &entry.path())?);
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Metadata of the single entry at `path`, found by its stored name
+    /// rather than by listing its directory.
+    pub(crate) fn symlink_metadata(&self, path: &Path, secure: bool) -> Result<ObjectMetadata, FileError> {
+        let names = self.dir_names(secure)?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| FileError::InvalidOperation(format!("{} does not name an entry", path.display())))?;
+        object(utf8(Path::new(name))?, &self.host(path, names.as_ref(), false)?)
+    }
+
+    pub(crate) fn rename(&self, from: &Path, to: &Path, secure: bool) -> Result<(), FileError> {
+        let names = self.dir_names(secure)?;
+        let from = self.host(from, names.as_ref(), false)?;
+        Ok(fs::rename(from, self.host(to, names.as_ref(), false)?)?)
+    }
+
+    /// Removes a file. Encrypted files are shredded first, so copies of the
+    /// ciphertext left behind cannot be decrypted.
+    pub(crate) fn remove_file(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        let host = self.host(path, self.names(secure)?.as_ref(), false)?;
+        if secure && fs::symlink_metadata(&host)?.is_file() {
+            let file = OpenOptions::new().read(true).write(true).open(&host)?;
+            if chunked::is_chunked(&read_prefix(&file)?) {
+                chunked::shred(&file)?;
+            }
+        }
+        Ok(fs::remove_file(host)?)
+    }
+
+    pub(crate) fn hard_link(&self, original: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        let names = self.dir_names(secure)?;
+        let original = self.host(original, names.as_ref(), false)?;
+        Ok(fs::hard_link(original, self.host(link, names.as_ref(), false)?)?)
+    }
+
+    /// Creates a symbolic link at `link` pointing to `target`. With
+    /// encrypted names the target is encrypted too, so the link can only be
+    /// read back with `read_link` and is never followed on the host.
+    pub(crate) fn symlink(&self, target: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        let names = self.dir_names(secure)?;
+        let link = self.host(link, names.as_ref(), false)?;
+        let target = match &names {
+            Some(names) => PathBuf::from(names.encrypt_target(&utf8(target)?)?),
+            None => target.to_path_buf(),
+        };
+        create_symlink(&target, &link)
+    }
+
+    pub(crate) fn read_link(&self, path: &Path, secure: bool) -> Result<PathBuf, FileError> {
+        let names = self.dir_names(secure)?;
+        let target = fs::read_link(self.host(path, names.as_ref(), false)?)?;
+        match &names {
+            Some(names) => Ok(PathBuf::from(names.decrypt_target(&utf8(&target)?)?)),
+            None => Ok(target),
+        }
+    }
+}
+
+/// Fails for `secure` operations in a context that cannot encrypt names.
+pub(crate) fn check_plain_names(secure: bool) -> Result<(), FileError> {
+    match secure {
+        true => Err(FileError::InvalidOperation(
+            "secure directory operations need a sandbox root".to_string(),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Splits a path beneath the root into names, applying `.` and `..`.
+fn normalize(path: &Path) -> Result<Vec<String>, FileError> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(utf8(Path::new(name))?),
+            Component::CurDir => {}
+            Component::ParentDir if names.pop().is_some() => {}
+            _ => return Err(FileError::SandboxViolation(path.display().to_string())),
+        }
+    }
+    Ok(names)
+}
+
+fn utf8(path: &Path) -> Result<String, FileError> {
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| FileError::InvalidOperation(format!("{} is not UTF-8", path.display())))
+}
+
+fn object(name: String, host: &Path) -> Result<ObjectMetadata, FileError> {
+    let metadata = fs::symlink_metadata(host)?;
+    let size = match FileType::of(&metadata) {
+        FileType::Regular => plaintext_len(host, metadata.len())?,
+        _ => metadata.len(),
+    };
+    Ok(ObjectMetadata::from_host(name, &metadata, size))
+}
+
+fn plaintext_len(path: &Path, physical_len: u64) -> Result<u64, FileError> {
+    let header = read_prefix(&File::open(path)?)?;
+    match chunked::is_chunked(&header) {
+        true => chunked::plaintext_len(&header, physical_len),
+        false => Ok(physical_len),
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> Result<(), FileError> {
+    Ok(std::os::unix::fs::symlink(target, link)?)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, _link: &Path) -> Result<(), FileError> {
+    Err(FileError::InvalidOperation("symbolic links are not supported on this platform".to_string()))
+}
@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use super::FileError;
+
+// Paths handed to a sandboxed context or container are resolved beneath its
+// root. On Linux files are opened with openat2(RESOLVE_BENEATH), which makes
+// the kernel refuse any `..`, absolute path or symbolic link that would leave
+// the root. Elsewhere, and for operations that take a path rather than
+// opening a file, paths are resolved one component at a time, following
+// symbolic links only while they stay beneath the root. Absolute symbolic
+// links are refused in both cases.
+const MAX_SYMLINKS: usize = 40;
+
+/// How a file is opened, mirroring `OpenOptions`.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct OpenFlags {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    truncate: bool,
+}
+
+impl OpenFlags {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub(crate) fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    pub(crate) fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    pub(crate) fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    // The WASM backend never truncates.
+    #[allow(dead_code)]
+    pub(crate) fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    fn options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options
+            .read(self.read)
+            .write(self.write)
+            .append(self.append)
+            .create(self.create)
+            .truncate(self.truncate);
+        options
+    }
+
+    /// Opens `path` directly, or beneath `sandbox` if there is one.
+    pub(crate) fn open(&self, sandbox: Option<&Sandbox>, path: &Path) -> Result<File, FileError> {
+        match sandbox {
+            Some(sandbox) => sandbox.open(path, self),
+            None => Ok(self.options().open(path)?),
+        }
+    }
+}
+
+/// A directory that paths are confined to.
+pub(crate) struct Sandbox {
+    root: PathBuf,
+    #[cfg(target_os = "linux")]
+    dir: File,
+}
+
+impl Sandbox {
+    pub(crate) fn new(root: &Path) -> Result<Self, FileError> {
+        let root = root.canonicalize()?;
+        if !root.is_dir() {
+            return Err(FileError::InvalidOperation(format!("{} is not a directory", root.display())));
+        }
+        Ok(Self {
+            #[cfg(target_os = "linux")]
+            dir: File::open(&root)?,
+            root,
+        })
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Opens `path` beneath the root.
+    pub(crate) fn open(&self, path: &Path, flags: &OpenFlags) -> Result<File, FileError> {
+        check_relative(path)?;
+        #[cfg(target_os = "linux")]
+        match self.open_beneath(path, flags) {
+            Err(FileError::IoError(e)) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) => {}
+            result => return result,
+        }
+        let resolved = self.resolve(path, true)?;
+        Ok(flags.options().open(resolved)?)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_beneath(&self, path: &Path, flags: &OpenFlags) -> Result<File, FileError> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let mut bits = match (flags.read, flags.write || flags.append) {
+            (true, true) => libc::O_RDWR,
+            (false, true) => libc::O_WRONLY,
+            _ => libc::O_RDONLY,
+        };
+        if flags.append {
+            bits |= libc::O_APPEND;
+        }
+        if flags.create {
+            bits |= libc::O_CREAT;
+        }
+        if flags.truncate {
+            bits |= libc::O_TRUNC;
+        }
+        // SAFETY: open_how is plain data; every field is set below.
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = (bits | libc::O_CLOEXEC) as u64;
+        how.mode = if flags.create { 0o666 } else { 0 };
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| FileError::InvalidOperation("path contains a NUL byte".to_string()))?;
+        // SAFETY: the pointers are valid for the duration of the call and
+        // the size matches the struct passed.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                self.dir.as_raw_fd(),
+                c_path.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EXDEV) => violation(path),
+                _ => FileError::IoError(err),
+            });
+        }
+        // SAFETY: openat2 returned a new descriptor that nothing else owns.
+        Ok(unsafe { File::from_raw_fd(fd as i32) })
+    }
+
+    /// Resolves `path` to a host path beneath the root, see [`resolve`].
+    pub(crate) fn resolve(&self, path: &Path, follow: bool) -> Result<PathBuf, FileError> {
+        resolve(&self.root, path, follow)
+    }
+}
+
+/// Resolves `path` to a host path beneath `root`, following symbolic links
+/// in every component but the last, and in the last too if `follow` is set.
+/// Components that do not exist yet are kept as they are.
+pub(crate) fn resolve(root: &Path, path: &Path, follow: bool) -> Result<PathBuf, FileError> {
+    check_relative(path)?;
+    let mut resolved: Vec<OsString> = Vec::new();
+    let mut pending: VecDeque<OsString> = parts(path);
+    let mut links = 0;
+    while let Some(part) = pending.pop_front() {
+        if part == ".." {
+            resolved.pop().ok_or_else(|| violation(path))?;
+            continue;
+        }
+        let candidate = root.join(resolved.iter().collect::<PathBuf>()).join(&part);
+        let is_link = match fs::symlink_metadata(&candidate) {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+        if !is_link || (pending.is_empty() && !follow) {
+            resolved.push(part);
+            continue;
+        }
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(FileError::InvalidOperation(format!("too many symbolic links in {}", path.display())));
+        }
+        let target = fs::read_link(&candidate)?;
+        if target.has_root() {
+            return Err(violation(path));
+        }
+        for part in parts(&target).into_iter().rev() {
+            pending.push_front(part);
+        }
+    }
+    Ok(root.join(resolved.iter().collect::<PathBuf>()))
+}
+
+fn parts(path: &Path) -> VecDeque<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+fn check_relative(path: &Path) -> Result<(), FileError> {
+    if path.has_root() {
+        return Err(violation(path));
+    }
+    Ok(())
+}
+
+fn violation(path: &Path) -> FileError {
+    FileError::SandboxViolation(path.display().to_string())
+}
@@ -6,6 +6,7 @@ mod linux;
 mod sev;
 #[cfg(feature = "wasi")]
 mod wasm;
+mod memory;
 
 #[cfg(all(feature = "linux", not(any(feature = "sev", feature = "wasi"))))]
 pub use linux::FileContext;
@@ -13,8 +14,12 @@ pub use linux::FileContext;
 pub use sev::SevFileContext as FileContext;
 #[cfg(feature = "wasi")]
 pub use wasm::WasmFileContext as FileContext;
+pub use memory::{Fault, MemoryFileContext, MemorySnapshot};
 
-pub use common::{FileConfig, FileError, FileMetadata, FileMode, FileOperations};
+pub use common::{
+    seal_key, ContainerOperations, FileConfig, FileError, FileMetadata, FileMode, FileOperations, FileType,
+    KeySource, MemoryVersionStore, ObjectMetadata, SealedVersionStore, VersionStore,
+};
 
 pub trait FileOps {
     fn open(&self, path: &str) -> Result<u32, FileError>;
@@ -1,29 +1,40 @@
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write, Seek, SeekFrom};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use crate::common::{FileError, FileMode, FileConfig, FileMetadata};
+use crate::common::{FileError, FileMode, FileConfig, FileMetadata, KeySlot, KeySource, Namespace, OpenFlags, Sandbox, Storage};
 
 pub struct FileManager {
     files: Mutex<HashMap<u32, FileHandle>>,
     next_handle: Mutex<u32>,
+    keys: Arc<KeySlot>,
+    names: Namespace,
 }
 
 struct FileHandle {
-    file: File,
+    storage: Storage,
     config: FileConfig,
 }
 
 impl FileManager {
     pub fn new() -> Self {
+        let keys = Arc::new(KeySlot::default());
         Self {
             files: Mutex::new(HashMap::new()),
             next_handle: Mutex::new(1),
+            names: Namespace::new(keys.clone()),
+            keys,
         }
     }
 
+    pub fn set_root(&mut self, root: Arc<Sandbox>) {
+        self.names.set_root(root);
+    }
+
+    pub fn names(&self) -> &Namespace {
+        &self.names
+    }
+
     pub fn open(&self, config: &FileConfig) -> Result<u32, FileError> {
         let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
         let mut next_handle = self.next_handle.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
@@ -31,17 +42,23 @@ impl FileManager {
         let handle = *next_handle;
         *next_handle += 1;
 
-        let file = OpenOptions::new()
-            .read(matches!(config.mode, FileMode::Read | FileMode::ReadWrite))
-            .write(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
-            .append(matches!(config.mode, FileMode::Append))
-            .create(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
-            .open(&config.path)
-            .map_err(|e| FileError::IoError(e))?;
-
+        // Encrypted files are updated chunk by chunk at explicit offsets, so
+        // they are always readable and never opened in append mode.
+        let (file, path) = self.names.open(
+            &config.path,
+            config.secure,
+            OpenFlags::new()
+                .read(config.secure || matches!(config.mode, FileMode::Read | FileMode::ReadWrite))
+                .write(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
+                .append(!config.secure && matches!(config.mode, FileMode::Append))
+                .create(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
+                .truncate(matches!(config.mode, FileMode::Write)),
+        )?;
+
+        let config = FileConfig { path, ..config.clone() };
         let file_handle = FileHandle {
-            file,
-            config: config.clone(),
+            storage: Storage::open(file, config.mode, config.secure.then_some(&self.keys))?,
+            config,
         };
 
         files.insert(handle, file_handle);
@@ -55,50 +72,39 @@ impl FileManager {
     }
 
     pub fn read(&self, handle: u32, buf: &mut [u8]) -> Result<usize, FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
         if !matches!(file_handle.config.mode, FileMode::Read | FileMode::ReadWrite) {
             return Err(FileError::InvalidMode);
         }
 
-        let mut file = &file_handle.file;
-        file.read(buf).map_err(|e| FileError::IoError(e))
+        file_handle.storage.read(buf)
     }
 
     pub fn write(&self, handle: u32, buf: &[u8]) -> Result<usize, FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
         if !matches!(file_handle.config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append) {
             return Err(FileError::InvalidMode);
         }
 
-        let mut file = &file_handle.file;
-        file.write(buf).map_err(|e| FileError::IoError(e))
+        file_handle.storage.write(buf)
     }
 
     pub fn seek(&self, handle: u32, offset: i64, whence: i32) -> Result<u64, FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
-
-        let mut file = &file_handle.file;
-        let seek_from = match whence {
-            0 => SeekFrom::Start(offset as u64),
-            1 => SeekFrom::Current(offset),
-            2 => SeekFrom::End(offset),
-            _ => return Err(FileError::OperationFailed("Invalid seek whence".to_string())),
-        };
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
-        file.seek(seek_from).map_err(|e| FileError::IoError(e))
+        file_handle.storage.seek(offset, whence)
     }
 
     pub fn flush(&self, handle: u32) -> Result<(), FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
-        let mut file = &file_handle.file;
-        file.flush().map_err(|e| FileError::IoError(e))
+        file_handle.storage.sync()
     }
 
     pub fn metadata(&self, handle: u32) -> Result<FileMetadata, FileError> {
@@ -109,7 +115,7 @@ impl FileManager {
             .map_err(|e| FileError::IoError(e))?;
         
         Ok(FileMetadata {
-            size: metadata.len(),
+            size: file_handle.storage.len()?,
             is_file: metadata.is_file(),
             is_dir: metadata.is_dir(),
             #[cfg(unix)]
@@ -118,4 +124,12 @@ impl FileManager {
             permissions: 0o644, // Default permissions for non-Unix systems
         })
     }
-} 
\ No newline at end of file
+
+    pub fn load_key(&self, source: KeySource<'_>) -> Result<(), FileError> {
+        self.keys.load(source)
+    }
+
+    pub fn remove_key(&self) -> Result<(), FileError> {
+        self.keys.remove()
+    }
+}
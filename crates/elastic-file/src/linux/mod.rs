@@ -1,18 +1,43 @@
-use crate::common::{FileError, FileConfig, FileOperations, FileMetadata};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::common::{
+    ContainerManager, ContainerOperations, FileConfig, FileError, FileMetadata, FileMode, FileOperations,
+    KeySource, MemoryVersionStore, ObjectMetadata, Sandbox, VersionStore,
+};
 
 mod file;
 use file::FileManager;
 
 pub struct FileContext {
     manager: FileManager,
+    containers: ContainerManager,
 }
 
 impl FileContext {
     pub fn new() -> Self {
+        Self::with_version_store(Arc::new(MemoryVersionStore::default()))
+    }
+
+    /// Like `new`, but records container versions in `versions`, e.g. a
+    /// [`SealedVersionStore`](crate::SealedVersionStore) so rollback is also
+    /// detected across restarts.
+    pub fn with_version_store(versions: Arc<dyn VersionStore>) -> Self {
         Self {
             manager: FileManager::new(),
+            containers: ContainerManager::new(false, Some(versions)),
         }
     }
+
+    /// Confines the context to the directory `root`: files and containers
+    /// are opened at paths relative to it, and a path that would leave it,
+    /// through `..`, an absolute path or a symbolic link, fails with
+    /// [`FileError::SandboxViolation`].
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Result<Self, FileError> {
+        let sandbox = Arc::new(Sandbox::new(root.as_ref())?);
+        self.manager.set_root(sandbox.clone());
+        self.containers.set_root(sandbox);
+        Ok(self)
+    }
 }
 
 impl Default for FileContext {
@@ -49,4 +74,106 @@ impl FileOperations for FileContext {
     fn metadata(&self, handle: u32) -> Result<FileMetadata, FileError> {
         self.manager.metadata(handle)
     }
-} 
\ No newline at end of file
+
+    fn load_file_key(&self, source: KeySource<'_>) -> Result<(), FileError> {
+        self.manager.load_key(source)
+    }
+
+    fn remove_file_key(&self) -> Result<(), FileError> {
+        self.manager.remove_key()
+    }
+
+    fn create_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        self.manager.names().create_dir(path, secure)
+    }
+
+    fn remove_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        self.manager.names().remove_dir(path, secure)
+    }
+
+    fn read_dir(&self, path: &Path, secure: bool) -> Result<Vec<ObjectMetadata>, FileError> {
+        self.manager.names().read_dir(path, secure)
+    }
+
+    fn symlink_metadata(&self, path: &Path, secure: bool) -> Result<ObjectMetadata, FileError> {
+        self.manager.names().symlink_metadata(path, secure)
+    }
+
+    fn rename(&self, from: &Path, to: &Path, secure: bool) -> Result<(), FileError> {
+        self.manager.names().rename(from, to, secure)
+    }
+
+    fn remove_file(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        self.manager.names().remove_file(path, secure)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        self.manager.names().hard_link(original, link, secure)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        self.manager.names().symlink(target, link, secure)
+    }
+
+    fn read_link(&self, path: &Path, secure: bool) -> Result<PathBuf, FileError> {
+        self.manager.names().read_link(path, secure)
+    }
+}
+
+impl ContainerOperations for FileContext {
+    fn open_container(&self, path: &Path, mode: FileMode) -> Result<u32, FileError> {
+        self.containers.open(path, mode)
+    }
+
+    fn close_container(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.close(handle)
+    }
+
+    fn read_file(&self, handle: u32, path: &str) -> Result<Vec<u8>, FileError> {
+        self.containers.read_file(handle, path)
+    }
+
+    fn write_file(&self, handle: u32, path: &str, data: &[u8]) -> Result<(), FileError> {
+        self.containers.write_file(handle, path, data)
+    }
+
+    fn delete_file(&self, handle: u32, path: &str) -> Result<(), FileError> {
+        self.containers.delete_file(handle, path)
+    }
+
+    fn rename_file(&self, handle: u32, from: &str, to: &str) -> Result<(), FileError> {
+        self.containers.rename_file(handle, from, to)
+    }
+
+    fn begin_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.begin_transaction(handle)
+    }
+
+    fn commit_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.commit_transaction(handle)
+    }
+
+    fn abort_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.abort_transaction(handle)
+    }
+
+    fn list_files(&self, handle: u32, path: &str) -> Result<Vec<String>, FileError> {
+        self.containers.list_files(handle, path)
+    }
+
+    fn get_metadata(&self, handle: u32, path: &str) -> Result<ObjectMetadata, FileError> {
+        self.containers.get_metadata(handle, path)
+    }
+
+    fn load_container_key(&self, handle: u32, source: KeySource<'_>) -> Result<(), FileError> {
+        self.containers.load_key(handle, source)
+    }
+
+    fn remove_key(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.remove_key(handle)
+    }
+
+    fn is_encrypted(&self, handle: u32, path: &str) -> Result<bool, FileError> {
+        self.containers.is_encrypted(handle, path)
+    }
+}
@@ -0,0 +1,777 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ffi::OsString;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::common::{
+    check_plain_names, is_chunked, plaintext_len, seek_from, shred, Backing, ChunkedFile, FileConfig, FileError, FileMetadata, FileMode,
+    FileOperations, FileType, KeySlot, KeySource, ObjectMetadata, HEADER_LEN,
+};
+
+// Everything lives in a tree of inodes behind one lock. Open handles refer
+// to their inode, so hard links share contents and a file removed while it
+// is open fails with ENOENT on its next access. Secure files are stored in
+// the same chunked format as on disk; names are stored as they are, as in a
+// context without a sandbox root.
+const MAX_SYMLINKS: usize = 40;
+const ROOT: u64 = 0;
+
+/// A failure injected into the reads and writes of a [`MemoryFileContext`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Writes fail with `ENOSPC`.
+    NoSpace,
+    /// Reads and writes fail with `EIO`.
+    Io,
+    /// Writes store at most this many bytes and report the shorter length.
+    ShortWrite(usize),
+}
+
+impl Fault {
+    fn applies_to(&self, write: bool) -> bool {
+        write || *self == Fault::Io
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, u64>),
+    Symlink(PathBuf),
+}
+
+#[derive(Clone)]
+struct Inode {
+    node: Node,
+    links: u32,
+    created: u64,
+    modified: u64,
+    accessed: u64,
+    permissions: u32,
+}
+
+impl Inode {
+    fn new(node: Node) -> Self {
+        let permissions = match node {
+            Node::File(_) => 0o644,
+            Node::Directory(_) => 0o755,
+            Node::Symlink(_) => 0o777,
+        };
+        let now = now();
+        Self {
+            node,
+            links: 1,
+            created: now,
+            modified: now,
+            accessed: now,
+            permissions,
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.node {
+            Node::File(_) => FileType::Regular,
+            Node::Directory(_) => FileType::Directory,
+            Node::Symlink(_) => FileType::SymbolicLink,
+        }
+    }
+
+    fn object(&self, name: String) -> Result<ObjectMetadata, FileError> {
+        let size = match &self.node {
+            Node::File(data) if is_chunked(data) => plaintext_len(&data[..HEADER_LEN], data.len() as u64)?,
+            Node::File(data) => data.len() as u64,
+            Node::Directory(_) => 0,
+            Node::Symlink(target) => target.as_os_str().len() as u64,
+        };
+        Ok(ObjectMetadata {
+            name,
+            size,
+            file_type: self.file_type(),
+            created: self.created,
+            modified: self.modified,
+            accessed: self.accessed,
+            permissions: self.permissions,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Tree {
+    inodes: HashMap<u64, Inode>,
+    next_inode: u64,
+}
+
+impl Tree {
+    fn new() -> Self {
+        Self {
+            inodes: HashMap::from([(ROOT, Inode::new(Node::Directory(BTreeMap::new())))]),
+            next_inode: ROOT + 1,
+        }
+    }
+
+    fn inode(&self, id: u64) -> Result<&Inode, FileError> {
+        self.inodes.get(&id).ok_or_else(|| errno(libc::ENOENT))
+    }
+
+    fn inode_mut(&mut self, id: u64) -> Result<&mut Inode, FileError> {
+        self.inodes.get_mut(&id).ok_or_else(|| errno(libc::ENOENT))
+    }
+
+    fn entries(&self, dir: u64) -> Result<&BTreeMap<String, u64>, FileError> {
+        match &self.inode(dir)?.node {
+            Node::Directory(entries) => Ok(entries),
+            _ => Err(errno(libc::ENOTDIR)),
+        }
+    }
+
+    fn entries_mut(&mut self, dir: u64) -> Result<&mut BTreeMap<String, u64>, FileError> {
+        match &mut self.inode_mut(dir)?.node {
+            Node::Directory(entries) => Ok(entries),
+            _ => Err(errno(libc::ENOTDIR)),
+        }
+    }
+
+    /// Resolves `path` to an inode, following symbolic links in every
+    /// component but the last, and in the last too if `follow` is set.
+    /// Absolute paths and link targets start at the root, which `..` cannot
+    /// leave.
+    fn lookup(&self, path: &Path, follow: bool) -> Result<u64, FileError> {
+        let mut dirs = vec![ROOT];
+        let mut pending = parts(path);
+        let mut links = 0;
+        while let Some(part) = pending.pop_front() {
+            if part == ".." {
+                if dirs.len() == 1 {
+                    return Err(FileError::SandboxViolation(path.display().to_string()));
+                }
+                dirs.pop();
+                continue;
+            }
+            let dir = *dirs.last().expect("the root is never popped");
+            let name = part.to_str().ok_or_else(|| errno(libc::ENOENT))?;
+            let child = *self.entries(dir)?.get(name).ok_or_else(|| errno(libc::ENOENT))?;
+            let Node::Symlink(target) = &self.inode(child)?.node else {
+                dirs.push(child);
+                continue;
+            };
+            if pending.is_empty() && !follow {
+                dirs.push(child);
+                continue;
+            }
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(FileError::InvalidOperation(format!("too many symbolic links in {}", path.display())));
+            }
+            if target.has_root() {
+                dirs.truncate(1);
+            }
+            for part in parts(target).into_iter().rev() {
+                pending.push_front(part);
+            }
+        }
+        Ok(*dirs.last().expect("the root is never popped"))
+    }
+
+    /// The directory `path` is in, and its last component.
+    fn parent(&self, path: &Path) -> Result<(u64, String), FileError> {
+        let name = match path.components().next_back() {
+            Some(Component::Normal(name)) => name
+                .to_str()
+                .ok_or_else(|| FileError::InvalidOperation(format!("{} is not UTF-8", path.display())))?,
+            _ => return Err(FileError::InvalidOperation(format!("{} does not name an entry", path.display()))),
+        };
+        let dir = self.lookup(path.parent().unwrap_or(Path::new("")), true)?;
+        self.entries(dir)?;
+        Ok((dir, name.to_string()))
+    }
+
+    fn add(&mut self, dir: u64, name: String, node: Node) -> Result<u64, FileError> {
+        if self.entries(dir)?.contains_key(&name) {
+            return Err(errno(libc::EEXIST));
+        }
+        let id = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(id, Inode::new(node));
+        self.entries_mut(dir)?.insert(name, id);
+        self.touch(dir);
+        Ok(id)
+    }
+
+    /// Drops one link to `id`, removing the inode with its last link.
+    fn unlink(&mut self, id: u64) {
+        if let Some(inode) = self.inodes.get_mut(&id) {
+            inode.links -= 1;
+            if inode.links == 0 {
+                self.inodes.remove(&id);
+            }
+        }
+    }
+
+    /// True if `id` is `dir` or lies below it.
+    fn within(&self, id: u64, dir: u64) -> bool {
+        id == dir
+            || match self.inodes.get(&dir).map(|inode| &inode.node) {
+                Some(Node::Directory(entries)) => entries.values().any(|child| self.within(id, *child)),
+                _ => false,
+            }
+    }
+
+    /// The contents of file `id`, for reads and writes through a handle.
+    fn contents(&mut self, id: u64) -> io::Result<&mut Vec<u8>> {
+        match self.inodes.get_mut(&id).map(|inode| &mut inode.node) {
+            Some(Node::File(data)) => Ok(data),
+            Some(_) => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+            None => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        }
+    }
+
+    fn touch(&mut self, id: u64) {
+        if let Some(inode) = self.inodes.get_mut(&id) {
+            inode.modified = now();
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.inodes
+            .values()
+            .map(|inode| match &inode.node {
+                Node::File(data) => data.len() as u64,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+struct PendingFault {
+    fault: Fault,
+    after: usize,
+}
+
+struct State {
+    tree: Tree,
+    capacity: Option<u64>,
+    faults: Vec<PendingFault>,
+}
+
+impl State {
+    /// Counts a read or write against the injected faults, returning the
+    /// one that hits it.
+    fn fault(&mut self, write: bool) -> Option<Fault> {
+        let mut hit = None;
+        for pending in self.faults.iter_mut().filter(|pending| pending.fault.applies_to(write)) {
+            match pending.after {
+                0 => {
+                    hit.get_or_insert(pending.fault);
+                }
+                _ => pending.after -= 1,
+            }
+        }
+        hit
+    }
+}
+
+fn lock(state: &Mutex<State>) -> Result<MutexGuard<'_, State>, FileError> {
+    state.lock().map_err(|e| FileError::OperationFailed(e.to_string()))
+}
+
+/// The contents of one file, as a [`Backing`] for the chunked format.
+struct MemoryFile {
+    state: Arc<Mutex<State>>,
+    inode: u64,
+}
+
+impl MemoryFile {
+    fn with_state<T>(&self, f: impl FnOnce(&mut State) -> io::Result<T>) -> io::Result<T> {
+        let mut state = self.state.lock().map_err(|e| io::Error::other(e.to_string()))?;
+        f(&mut state)
+    }
+}
+
+impl Backing for MemoryFile {
+    fn physical_len(&self) -> io::Result<u64> {
+        self.with_state(|state| Ok(state.tree.contents(self.inode)?.len() as u64))
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.with_state(|state| {
+            let data = state.tree.contents(self.inode)?;
+            let stored = usize::try_from(offset)
+                .ok()
+                .and_then(|start| data.get(start..))
+                .and_then(|rest| rest.get(..buf.len()))
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            buf.copy_from_slice(stored);
+            if let Some(inode) = state.tree.inodes.get_mut(&self.inode) {
+                inode.accessed = now();
+            }
+            Ok(())
+        })
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.with_state(|state| {
+            let start = usize::try_from(offset).map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+            let end = start + buf.len();
+            if let Some(capacity) = state.capacity {
+                let growth = end.saturating_sub(state.tree.contents(self.inode)?.len()) as u64;
+                if state.tree.used() + growth > capacity {
+                    return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+                }
+            }
+            let data = state.tree.contents(self.inode)?;
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[start..end].copy_from_slice(buf);
+            state.tree.touch(self.inode);
+            Ok(())
+        })
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Contents {
+    Plain { file: MemoryFile, pos: u64, append: bool },
+    Chunked { file: Box<ChunkedFile<MemoryFile>>, keys: Arc<KeySlot> },
+}
+
+struct FileHandle {
+    mode: FileMode,
+    inode: u64,
+    contents: Contents,
+}
+
+impl FileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        match &mut self.contents {
+            Contents::Plain { file, pos, .. } => {
+                let len = file.physical_len()?;
+                let read = (len.saturating_sub(*pos)).min(buf.len() as u64) as usize;
+                file.read_exact_at(&mut buf[..read], *pos)?;
+                *pos += read as u64;
+                Ok(read)
+            }
+            Contents::Chunked { file, keys } => {
+                keys.check(file.key_id())?;
+                file.read(buf)
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FileError> {
+        match &mut self.contents {
+            Contents::Plain { file, pos, append } => {
+                if *append {
+                    *pos = file.physical_len()?;
+                }
+                file.write_all_at(buf, *pos)?;
+                *pos += buf.len() as u64;
+                Ok(buf.len())
+            }
+            Contents::Chunked { file, keys } => {
+                keys.check(file.key_id())?;
+                file.write(buf)
+            }
+        }
+    }
+
+    fn seek(&mut self, offset: i64, whence: i32) -> Result<u64, FileError> {
+        let seek_from = seek_from(offset, whence)?;
+        match &mut self.contents {
+            Contents::Plain { file, pos, .. } => {
+                let target = match seek_from {
+                    SeekFrom::Start(offset) => Some(offset),
+                    SeekFrom::Current(offset) => pos.checked_add_signed(offset),
+                    SeekFrom::End(offset) => file.physical_len()?.checked_add_signed(offset),
+                };
+                *pos = target.ok_or_else(|| FileError::OperationFailed("Invalid seek position".to_string()))?;
+                Ok(*pos)
+            }
+            Contents::Chunked { file, .. } => file.seek(seek_from),
+        }
+    }
+
+    fn len(&self) -> Result<u64, FileError> {
+        match &self.contents {
+            Contents::Plain { file, .. } => Ok(file.physical_len()?),
+            Contents::Chunked { file, .. } => Ok(file.len()),
+        }
+    }
+}
+
+/// The contents of a [`MemoryFileContext`] at one point in time.
+#[derive(Clone)]
+pub struct MemorySnapshot {
+    tree: Tree,
+}
+
+impl MemorySnapshot {
+    /// The bytes stored for the file at `path`: ciphertext if it is secure.
+    pub fn stored_bytes(&self, path: &Path) -> Option<&[u8]> {
+        let id = self.tree.lookup(path, true).ok()?;
+        match &self.tree.inode(id).ok()?.node {
+            Node::File(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+/// A file context that keeps everything in memory, for tests and
+/// ephemeral storage. It behaves like the host backends, including
+/// encryption of secure files, and can additionally be snapshotted and
+/// restored, limited in capacity and made to fail on purpose.
+///
+/// Paths are resolved from the root of the in-memory tree whether they are
+/// absolute or not. Errors the host would report carry the same errno. Names
+/// are never encrypted, so secure directory operations are refused as in a
+/// host context without a sandbox root.
+pub struct MemoryFileContext {
+    state: Arc<Mutex<State>>,
+    files: Mutex<HashMap<u32, FileHandle>>,
+    next_handle: Mutex<u32>,
+    keys: Arc<KeySlot>,
+}
+
+impl MemoryFileContext {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                tree: Tree::new(),
+                capacity: None,
+                faults: Vec::new(),
+            })),
+            files: Mutex::new(HashMap::new()),
+            next_handle: Mutex::new(1),
+            keys: Arc::new(KeySlot::default()),
+        }
+    }
+
+    pub fn snapshot(&self) -> Result<MemorySnapshot, FileError> {
+        Ok(MemorySnapshot {
+            tree: lock(&self.state)?.tree.clone(),
+        })
+    }
+
+    /// Replaces everything with the contents of `snapshot`, as after a crash
+    /// that lost what was written since. Open handles are closed.
+    pub fn restore(&self, snapshot: &MemorySnapshot) -> Result<(), FileError> {
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        files.clear();
+        lock(&self.state)?.tree = snapshot.tree.clone();
+        Ok(())
+    }
+
+    /// Limits the bytes all files may take up together; writes that would
+    /// exceed it fail with `ENOSPC`. Secure files count with their headers
+    /// and tags.
+    pub fn set_capacity(&self, capacity: Option<u64>) -> Result<(), FileError> {
+        lock(&self.state)?.capacity = capacity;
+        Ok(())
+    }
+
+    /// Makes `fault` hit every read or write it applies to, once the next
+    /// `after` of them have succeeded, until [`clear_faults`](Self::clear_faults).
+    pub fn inject_fault(&self, fault: Fault, after: usize) -> Result<(), FileError> {
+        lock(&self.state)?.faults.push(PendingFault { fault, after });
+        Ok(())
+    }
+
+    pub fn clear_faults(&self) -> Result<(), FileError> {
+        lock(&self.state)?.faults.clear();
+        Ok(())
+    }
+
+    fn fault(&self, write: bool) -> Result<Option<Fault>, FileError> {
+        Ok(lock(&self.state)?.fault(write))
+    }
+
+    /// Finds or creates the file `config` opens, truncating it for
+    /// [`FileMode::Write`].
+    fn open_inode(&self, config: &FileConfig) -> Result<u64, FileError> {
+        let mut state = lock(&self.state)?;
+        let tree = &mut state.tree;
+        let id = match tree.lookup(&config.path, true) {
+            Ok(id) => id,
+            Err(FileError::IoError(e)) if e.kind() == io::ErrorKind::NotFound && config.mode.can_write() => {
+                let (dir, name) = tree.parent(&config.path)?;
+                return tree.add(dir, name, Node::File(Vec::new()));
+            }
+            Err(e) => return Err(e),
+        };
+        match &mut tree.inode_mut(id)?.node {
+            Node::File(data) if config.mode == FileMode::Write => data.clear(),
+            Node::File(_) => {}
+            Node::Directory(_) => return Err(errno(libc::EISDIR)),
+            Node::Symlink(_) => return Err(errno(libc::ELOOP)),
+        }
+        Ok(id)
+    }
+}
+
+impl Default for MemoryFileContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileOperations for MemoryFileContext {
+    fn open(&self, config: &FileConfig) -> Result<u32, FileError> {
+        let inode = self.open_inode(config)?;
+        let file = MemoryFile {
+            state: self.state.clone(),
+            inode,
+        };
+        let contents = match config.secure {
+            true => Contents::Chunked {
+                file: Box::new(ChunkedFile::open_for(file, config.mode, &self.keys)?),
+                keys: self.keys.clone(),
+            },
+            false => Contents::Plain {
+                file,
+                pos: 0,
+                append: config.mode == FileMode::Append,
+            },
+        };
+
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let mut next_handle = self.next_handle.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let handle = *next_handle;
+        *next_handle += 1;
+        files.insert(
+            handle,
+            FileHandle {
+                mode: config.mode,
+                inode,
+                contents,
+            },
+        );
+        Ok(handle)
+    }
+
+    fn close(&self, handle: u32) -> Result<(), FileError> {
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        files.remove(&handle).ok_or(FileError::NotFound)?;
+        Ok(())
+    }
+
+    fn read(&self, handle: u32, buf: &mut [u8]) -> Result<usize, FileError> {
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
+
+        if !matches!(file_handle.mode, FileMode::Read | FileMode::ReadWrite) {
+            return Err(FileError::InvalidMode);
+        }
+        if self.fault(false)?.is_some() {
+            return Err(errno(libc::EIO));
+        }
+
+        file_handle.read(buf)
+    }
+
+    fn write(&self, handle: u32, buf: &[u8]) -> Result<usize, FileError> {
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
+
+        if !file_handle.mode.can_write() {
+            return Err(FileError::InvalidMode);
+        }
+        match self.fault(true)? {
+            Some(Fault::NoSpace) => Err(errno(libc::ENOSPC)),
+            Some(Fault::Io) => Err(errno(libc::EIO)),
+            Some(Fault::ShortWrite(limit)) => file_handle.write(&buf[..limit.min(buf.len())]),
+            None => file_handle.write(buf),
+        }
+    }
+
+    fn seek(&self, handle: u32, offset: i64, whence: i32) -> Result<u64, FileError> {
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
+
+        file_handle.seek(offset, whence)
+    }
+
+    fn flush(&self, handle: u32) -> Result<(), FileError> {
+        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        files.get(&handle).ok_or(FileError::NotFound)?;
+        Ok(())
+    }
+
+    fn metadata(&self, handle: u32) -> Result<FileMetadata, FileError> {
+        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get(&handle).ok_or(FileError::InvalidHandle)?;
+        let size = file_handle.len()?;
+        let state = lock(&self.state)?;
+        let inode = state.tree.inode(file_handle.inode)?;
+
+        Ok(FileMetadata {
+            size,
+            is_file: inode.file_type() == FileType::Regular,
+            is_dir: inode.file_type() == FileType::Directory,
+            permissions: inode.permissions,
+        })
+    }
+
+    fn load_file_key(&self, source: KeySource<'_>) -> Result<(), FileError> {
+        self.keys.load(source)
+    }
+
+    fn remove_file_key(&self) -> Result<(), FileError> {
+        self.keys.remove()
+    }
+
+    fn create_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        check_plain_names(secure)?;
+        let tree = &mut lock(&self.state)?.tree;
+        let (dir, name) = tree.parent(path)?;
+        tree.add(dir, name, Node::Directory(BTreeMap::new()))?;
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        check_plain_names(secure)?;
+        let tree = &mut lock(&self.state)?.tree;
+        let (dir, name) = tree.parent(path)?;
+        let id = *tree.entries(dir)?.get(&name).ok_or_else(|| errno(libc::ENOENT))?;
+        if !tree.entries(id)?.is_empty() {
+            return Err(errno(libc::ENOTEMPTY));
+        }
+        tree.entries_mut(dir)?.remove(&name);
+        tree.unlink(id);
+        tree.touch(dir);
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path, secure: bool) -> Result<Vec<ObjectMetadata>, FileError> {
+        check_plain_names(secure)?;
+        let tree = &lock(&self.state)?.tree;
+        let dir = tree.lookup(path, true)?;
+        let mut entries = Vec::new();
+        for (name, id) in tree.entries(dir)? {
+            entries.push(tree.inode(*id)?.object(name.clone())?);
+        }
+        Ok(entries)
+    }
+
+    fn symlink_metadata(&self, path: &Path, secure: bool) -> Result<ObjectMetadata, FileError> {
+        check_plain_names(secure)?;
+        let tree = &lock(&self.state)?.tree;
+        let (_, name) = tree.parent(path)?;
+        tree.inode(tree.lookup(path, false)?)?.object(name)
+    }
+
+    fn rename(&self, from: &Path, to: &Path, secure: bool) -> Result<(), FileError> {
+        check_plain_names(secure)?;
+        let tree = &mut lock(&self.state)?.tree;
+        let (from_dir, from_name) = tree.parent(from)?;
+        let (to_dir, to_name) = tree.parent(to)?;
+        let id = *tree.entries(from_dir)?.get(&from_name).ok_or_else(|| errno(libc::ENOENT))?;
+        let moving_dir = tree.inode(id)?.file_type() == FileType::Directory;
+        if moving_dir && tree.within(to_dir, id) {
+            return Err(errno(libc::EINVAL));
+        }
+        if let Some(&replaced) = tree.entries(to_dir)?.get(&to_name) {
+            if replaced == id {
+                return Ok(());
+            }
+            match (moving_dir, &tree.inode(replaced)?.node) {
+                (true, Node::Directory(entries)) if !entries.is_empty() => return Err(errno(libc::ENOTEMPTY)),
+                (true, Node::Directory(_)) => {}
+                (true, _) => return Err(errno(libc::ENOTDIR)),
+                (false, Node::Directory(_)) => return Err(errno(libc::EISDIR)),
+                (false, _) => {}
+            }
+            tree.unlink(replaced);
+        }
+        tree.entries_mut(from_dir)?.remove(&from_name);
+        tree.entries_mut(to_dir)?.insert(to_name, id);
+        tree.touch(from_dir);
+        tree.touch(to_dir);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        let (id, encrypted) = {
+            let tree = &lock(&self.state)?.tree;
+            let (dir, name) = tree.parent(path)?;
+            let id = *tree.entries(dir)?.get(&name).ok_or_else(|| errno(libc::ENOENT))?;
+            match &tree.inode(id)?.node {
+                Node::Directory(_) => return Err(errno(libc::EISDIR)),
+                Node::File(data) => (id, is_chunked(data)),
+                Node::Symlink(_) => (id, false),
+            }
+        };
+        if secure && encrypted {
+            shred(&MemoryFile {
+                state: self.state.clone(),
+                inode: id,
+            })?;
+        }
+
+        let tree = &mut lock(&self.state)?.tree;
+        let (dir, name) = tree.parent(path)?;
+        if tree.entries_mut(dir)?.remove(&name).is_some() {
+            tree.unlink(id);
+            tree.touch(dir);
+        }
+        Ok(())
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        check_plain_names(secure)?;
+        let tree = &mut lock(&self.state)?.tree;
+        let id = tree.lookup(original, false)?;
+        if tree.inode(id)?.file_type() == FileType::Directory {
+            return Err(errno(libc::EPERM));
+        }
+        let (dir, name) = tree.parent(link)?;
+        if tree.entries(dir)?.contains_key(&name) {
+            return Err(errno(libc::EEXIST));
+        }
+        tree.inode_mut(id)?.links += 1;
+        tree.entries_mut(dir)?.insert(name, id);
+        tree.touch(dir);
+        Ok(())
+    }
+
+    fn symlink(&self, target: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        check_plain_names(secure)?;
+        let tree = &mut lock(&self.state)?.tree;
+        let (dir, name) = tree.parent(link)?;
+        tree.add(dir, name, Node::Symlink(target.to_path_buf()))?;
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path, secure: bool) -> Result<PathBuf, FileError> {
+        check_plain_names(secure)?;
+        let tree = &lock(&self.state)?.tree;
+        match &tree.inode(tree.lookup(path, false)?)?.node {
+            Node::Symlink(target) => Ok(target.clone()),
+            _ => Err(errno(libc::EINVAL)),
+        }
+    }
+}
+
+fn parts(path: &Path) -> VecDeque<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+fn errno(code: i32) -> FileError {
+    FileError::IoError(io::Error::from_raw_os_error(code))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -1,46 +1,54 @@
-use std::env;
 use std::sync::Arc;
 use std::collections::HashMap;
 use std::sync::Mutex;
-use std::path::PathBuf;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write, Seek, SeekFrom};
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
+use std::path::{Path, PathBuf};
+use crate::common::{
+    ContainerManager, ContainerOperations, FileConfig, FileError, FileMetadata, FileMode, FileOperations,
+    KeySlot, KeySource, MemoryVersionStore, Namespace, ObjectMetadata, OpenFlags, Sandbox, VersionStore, Storage,
 };
-use aes_gcm::aead::{Payload};
-use crate::common::{FileConfig, FileError, FileMetadata, FileOperations, FileMode};
 
 struct FileHandle {
     path: PathBuf,
     mode: FileMode,
-    secure: bool,
-    #[cfg(feature = "sevsnp")]
-    cipher: Option<Aes256Gcm>,
-    file: Option<File>,
+    storage: Storage,
 }
 
 pub struct WasmFileContext {
     files: Arc<Mutex<HashMap<u32, FileHandle>>>,
     next_handle: Arc<Mutex<u32>>,
+    keys: Arc<KeySlot>,
+    containers: ContainerManager,
+    names: Namespace,
 }
 
 impl WasmFileContext {
     pub fn new() -> Self {
+        Self::with_version_store(Arc::new(MemoryVersionStore::default()))
+    }
+
+    /// Like `new`, but records container versions in `versions`, e.g. a
+    /// [`SealedVersionStore`](crate::SealedVersionStore) so rollback is also
+    /// detected across restarts.
+    pub fn with_version_store(versions: Arc<dyn VersionStore>) -> Self {
+        let keys = Arc::new(KeySlot::default());
         Self {
             files: Arc::new(Mutex::new(HashMap::new())),
             next_handle: Arc::new(Mutex::new(1)),
+            keys: keys.clone(),
+            containers: ContainerManager::new(false, Some(versions)),
+            names: Namespace::new(keys),
         }
     }
 
-    fn is_sevsnp(&self) -> bool {
-        env::var("ELASTIC_SEV_SNP").map(|v| v == "1").unwrap_or(false)
-    }
-
-    fn create_cipher(&self, key: &[u8]) -> Result<Aes256Gcm, FileError> {
-        Aes256Gcm::new_from_slice(key)
-            .map_err(|_| FileError::OperationFailed("Failed to create cipher".to_string()))
+    /// Confines the context to the directory `root`: files and containers
+    /// are opened at paths relative to it, and a path that would leave it,
+    /// through `..`, an absolute path or a symbolic link, fails with
+    /// [`FileError::SandboxViolation`].
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Result<Self, FileError> {
+        let sandbox = Arc::new(Sandbox::new(root.as_ref())?);
+        self.names.set_root(sandbox.clone());
+        self.containers.set_root(sandbox);
+        Ok(self)
     }
 }
 
@@ -52,31 +60,29 @@ impl Default for WasmFileContext {
 
 impl FileOperations for WasmFileContext {
     fn open(&self, config: &FileConfig) -> Result<u32, FileError> {
+        // Encrypted files are updated chunk by chunk at explicit offsets, so
+        // they are always readable and never opened in append mode.
+        let (file, path) = self.names.open(
+            &config.path,
+            config.secure,
+            OpenFlags::new()
+                .read(config.secure || matches!(config.mode, FileMode::Read | FileMode::ReadWrite))
+                .write(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
+                .append(!config.secure && matches!(config.mode, FileMode::Append))
+                .create(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append)),
+        )?;
+        let storage = Storage::open(file, config.mode, config.secure.then_some(&self.keys))?;
+
         let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
         let mut next_handle = self.next_handle.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
         
         let handle = *next_handle;
         *next_handle += 1;
 
-        let file = OpenOptions::new()
-            .read(matches!(config.mode, FileMode::Read | FileMode::ReadWrite))
-            .write(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
-            .append(matches!(config.mode, FileMode::Append))
-            .create(matches!(config.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append))
-            .open(&config.path)
-            .map_err(|e| FileError::IoError(e))?;
-
         let file_handle = FileHandle {
-            path: config.path.clone(),
+            path,
             mode: config.mode,
-            secure: config.secure,
-            #[cfg(feature = "sevsnp")]
-            cipher: if config.secure && self.is_sevsnp() {
-                Some(self.create_cipher(&[0u8; 32])?) // In production, use a proper key
-            } else {
-                None
-            },
-            file: Some(file),
+            storage,
         };
 
         files.insert(handle, file_handle);
@@ -90,78 +96,39 @@ impl FileOperations for WasmFileContext {
     }
 
     fn read(&self, handle: u32, buf: &mut [u8]) -> Result<usize, FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
         if !matches!(file_handle.mode, FileMode::Read | FileMode::ReadWrite) {
             return Err(FileError::OperationFailed("File not opened for reading".to_string()));
         }
 
-        let mut file = file_handle.file.as_ref().ok_or(FileError::OperationFailed("File not open".to_string()))?;
-        let mut data = vec![0u8; buf.len()];
-        let len = file.read(&mut data).map_err(|e| FileError::IoError(e))?;
-
-        if file_handle.secure {
-            #[cfg(feature = "sevsnp")]
-            if let Some(cipher) = &file_handle.cipher {
-                let nonce = Nonce::from_slice(&[0u8; 12]); // In production, use a proper nonce
-                let decrypted = cipher.decrypt(nonce, &data[..len])
-                    .map_err(|_| FileError::OperationFailed("Decryption failed".to_string()))?;
-                buf[..decrypted.len()].copy_from_slice(&decrypted);
-                return Ok(decrypted.len());
-            }
-        }
-
-        buf[..len].copy_from_slice(&data[..len]);
-        Ok(len)
+        file_handle.storage.read(buf)
     }
 
     fn write(&self, handle: u32, buf: &[u8]) -> Result<usize, FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
         if !matches!(file_handle.mode, FileMode::Write | FileMode::ReadWrite | FileMode::Append) {
             return Err(FileError::OperationFailed("File not opened for writing".to_string()));
         }
 
-        let mut file = file_handle.file.as_ref().ok_or(FileError::OperationFailed("File not open".to_string()))?;
-        let data = if file_handle.secure {
-            #[cfg(feature = "sevsnp")]
-            if let Some(cipher) = &file_handle.cipher {
-                let nonce = Nonce::from_slice(&[0u8; 12]); // In production, use a proper nonce
-                cipher.encrypt(nonce, buf)
-                    .map_err(|_| FileError::OperationFailed("Encryption failed".to_string()))?
-            } else {
-                buf.to_vec()
-            }
-        } else {
-            buf.to_vec()
-        };
-
-        file.write(&data).map_err(|e| FileError::IoError(e))
+        file_handle.storage.write(buf)
     }
 
     fn seek(&self, handle: u32, offset: i64, whence: i32) -> Result<u64, FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
-
-        let mut file = file_handle.file.as_ref().ok_or(FileError::OperationFailed("File not open".to_string()))?;
-        let seek_from = match whence {
-            0 => SeekFrom::Start(offset as u64),
-            1 => SeekFrom::Current(offset),
-            2 => SeekFrom::End(offset),
-            _ => return Err(FileError::OperationFailed("Invalid whence value".to_string())),
-        };
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
-        file.seek(seek_from).map_err(|e| FileError::IoError(e))
+        file_handle.storage.seek(offset, whence)
     }
 
     fn flush(&self, handle: u32) -> Result<(), FileError> {
-        let files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
-        let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
+        let mut files = self.files.lock().map_err(|e| FileError::OperationFailed(e.to_string()))?;
+        let file_handle = files.get_mut(&handle).ok_or(FileError::NotFound)?;
 
-        let mut file = file_handle.file.as_ref().ok_or(FileError::OperationFailed("File not open".to_string()))?;
-        file.sync_all().map_err(|e| FileError::IoError(e))
+        file_handle.storage.sync()
     }
 
     fn metadata(&self, handle: u32) -> Result<FileMetadata, FileError> {
@@ -169,13 +136,115 @@ impl FileOperations for WasmFileContext {
         let file_handle = files.get(&handle).ok_or(FileError::NotFound)?;
         
         let metadata = std::fs::metadata(&file_handle.path)
-            .map_err(|e| FileError::IoError(e))?;
+            .map_err(FileError::IoError)?;
         
         Ok(FileMetadata {
-            size: metadata.len(),
+            size: file_handle.storage.len()?,
             is_file: metadata.is_file(),
             is_dir: metadata.is_dir(),
             permissions: 0o644, // Default permissions for WASM
         })
     }
-} 
\ No newline at end of file
+
+    fn load_file_key(&self, source: KeySource<'_>) -> Result<(), FileError> {
+        self.keys.load(source)
+    }
+
+    fn remove_file_key(&self) -> Result<(), FileError> {
+        self.keys.remove()
+    }
+
+    fn create_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        self.names.create_dir(path, secure)
+    }
+
+    fn remove_dir(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        self.names.remove_dir(path, secure)
+    }
+
+    fn read_dir(&self, path: &Path, secure: bool) -> Result<Vec<ObjectMetadata>, FileError> {
+        self.names.read_dir(path, secure)
+    }
+
+    fn symlink_metadata(&self, path: &Path, secure: bool) -> Result<ObjectMetadata, FileError> {
+        self.names.symlink_metadata(path, secure)
+    }
+
+    fn rename(&self, from: &Path, to: &Path, secure: bool) -> Result<(), FileError> {
+        self.names.rename(from, to, secure)
+    }
+
+    fn remove_file(&self, path: &Path, secure: bool) -> Result<(), FileError> {
+        self.names.remove_file(path, secure)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        self.names.hard_link(original, link, secure)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path, secure: bool) -> Result<(), FileError> {
+        self.names.symlink(target, link, secure)
+    }
+
+    fn read_link(&self, path: &Path, secure: bool) -> Result<PathBuf, FileError> {
+        self.names.read_link(path, secure)
+    }
+}
+
+impl ContainerOperations for WasmFileContext {
+    fn open_container(&self, path: &Path, mode: FileMode) -> Result<u32, FileError> {
+        self.containers.open(path, mode)
+    }
+
+    fn close_container(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.close(handle)
+    }
+
+    fn read_file(&self, handle: u32, path: &str) -> Result<Vec<u8>, FileError> {
+        self.containers.read_file(handle, path)
+    }
+
+    fn write_file(&self, handle: u32, path: &str, data: &[u8]) -> Result<(), FileError> {
+        self.containers.write_file(handle, path, data)
+    }
+
+    fn delete_file(&self, handle: u32, path: &str) -> Result<(), FileError> {
+        self.containers.delete_file(handle, path)
+    }
+
+    fn rename_file(&self, handle: u32, from: &str, to: &str) -> Result<(), FileError> {
+        self.containers.rename_file(handle, from, to)
+    }
+
+    fn begin_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.begin_transaction(handle)
+    }
+
+    fn commit_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.commit_transaction(handle)
+    }
+
+    fn abort_transaction(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.abort_transaction(handle)
+    }
+
+    fn list_files(&self, handle: u32, path: &str) -> Result<Vec<String>, FileError> {
+        self.containers.list_files(handle, path)
+    }
+
+    fn get_metadata(&self, handle: u32, path: &str) -> Result<ObjectMetadata, FileError> {
+        self.containers.get_metadata(handle, path)
+    }
+
+    fn load_container_key(&self, handle: u32, source: KeySource<'_>) -> Result<(), FileError> {
+        self.containers.load_key(handle, source)
+    }
+
+    fn remove_key(&self, handle: u32) -> Result<(), FileError> {
+        self.containers.remove_key(handle)
+    }
+
+    fn is_encrypted(&self, handle: u32, path: &str) -> Result<bool, FileError> {
+        self.containers.is_encrypted(handle, path)
+    }
+}
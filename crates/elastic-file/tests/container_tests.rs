@@ -0,0 +1,235 @@
+use elastic_file::{ContainerOperations, FileContext, FileError, FileMode, FileType, MemoryVersionStore};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+const KEY: [u8; 32] = [0x42; 32];
+
+/// Containers need a version store; an in-memory one is enough within a test.
+fn context() -> FileContext {
+    FileContext::with_version_store(Arc::new(MemoryVersionStore::default()))
+}
+
+/// Opens a container, with the key loaded on backends that refuse to store
+/// plaintext objects.
+fn open_container(ctx: &FileContext, root: &Path, mode: FileMode) -> u32 {
+    let handle = ctx.open_container(root, mode).unwrap();
+    if cfg!(feature = "sev") {
+        ctx.load_key(handle, &KEY).unwrap();
+    }
+    handle
+}
+
+#[test]
+fn test_object_round_trip() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+
+    // Writable containers are created on open.
+    let handle = open_container(&ctx, &root, FileMode::ReadWrite);
+    ctx.write_file(handle, "notes/today.txt", b"Hello, container!").unwrap();
+    ctx.write_file(handle, "readme", b"top level").unwrap();
+    assert_eq!(ctx.read_file(handle, "notes/today.txt").unwrap(), b"Hello, container!");
+    assert_eq!(ctx.read_file(handle, "./notes/today.txt").unwrap(), b"Hello, container!");
+    assert_eq!(ctx.list_files(handle, "").unwrap(), vec!["notes", "readme"]);
+    assert_eq!(ctx.list_files(handle, "notes").unwrap(), vec!["today.txt"]);
+
+    let metadata = ctx.get_metadata(handle, "notes/today.txt").unwrap();
+    assert_eq!(metadata.name, "today.txt");
+    assert_eq!(metadata.size, 17);
+    assert_eq!(metadata.file_type, FileType::Regular);
+    assert!(metadata.modified > 0);
+    assert_eq!(ctx.get_metadata(handle, "notes").unwrap().file_type, FileType::Directory);
+    assert_eq!(ctx.is_encrypted(handle, "readme").unwrap(), cfg!(feature = "sev"));
+
+    ctx.delete_file(handle, "readme").unwrap();
+    assert!(matches!(ctx.read_file(handle, "readme"), Err(FileError::NotFound)));
+    assert!(matches!(ctx.delete_file(handle, "readme"), Err(FileError::NotFound)));
+
+    ctx.close_container(handle).unwrap();
+    assert!(matches!(ctx.read_file(handle, "notes/today.txt"), Err(FileError::InvalidHandle)));
+    assert!(matches!(ctx.close_container(handle), Err(FileError::InvalidHandle)));
+}
+
+// Plaintext objects next to encrypted ones only exist where the backend
+// stores plaintext.
+#[test]
+#[cfg(not(feature = "sev"))]
+fn test_encrypted_objects() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+
+    ctx.write_file(handle, "plain", b"public").unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "secret", b"confidential data").unwrap();
+
+    // Only the object written with a key loaded is sealed on disk.
+    let stored = fs::read(dir.path().join("secret")).unwrap();
+    assert!(!stored.windows(12).any(|w| w == b"confidential"));
+    assert!(ctx.is_encrypted(handle, "secret").unwrap());
+    assert!(!ctx.is_encrypted(handle, "plain").unwrap());
+    assert_eq!(ctx.get_metadata(handle, "secret").unwrap().size, 17);
+    assert_eq!(ctx.read_file(handle, "secret").unwrap(), b"confidential data");
+    assert_eq!(ctx.read_file(handle, "plain").unwrap(), b"public");
+
+    // Without the key the object cannot be read.
+    ctx.remove_key(handle).unwrap();
+    assert!(matches!(ctx.read_file(handle, "secret"), Err(FileError::KeyNotLoaded)));
+    assert!(matches!(ctx.remove_key(handle), Err(FileError::InvalidOperation(_))));
+    assert!(matches!(ctx.load_key(handle, &KEY[..16]), Err(FileError::InvalidOperation(_))));
+
+    // Tampering, a wrong key and swapping objects are all detected.
+    ctx.load_key(handle, &[0x24; 32]).unwrap();
+    assert!(matches!(ctx.read_file(handle, "secret"), Err(FileError::WrongKey)));
+    ctx.load_key(handle, &KEY).unwrap();
+    fs::copy(dir.path().join("secret"), dir.path().join("moved")).unwrap();
+    assert!(matches!(ctx.read_file(handle, "moved"), Err(FileError::IntegrityError(_))));
+    let mut tampered = stored.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    fs::write(dir.path().join("secret"), tampered).unwrap();
+    assert!(matches!(ctx.read_file(handle, "secret"), Err(FileError::IntegrityError(_))));
+}
+
+#[test]
+fn test_container_modes() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let missing = dir.path().join("missing");
+    assert!(matches!(ctx.open_container(&missing, FileMode::Read), Err(FileError::NotFound)));
+
+    let writer = open_container(&ctx, dir.path(), FileMode::Write);
+    ctx.write_file(writer, "object", b"v1").unwrap();
+    assert!(matches!(ctx.read_file(writer, "object"), Err(FileError::PermissionDenied(_))));
+
+    let reader = open_container(&ctx, dir.path(), FileMode::Read);
+    assert_ne!(reader, writer);
+    assert_eq!(ctx.read_file(reader, "object").unwrap(), b"v1");
+    assert!(matches!(ctx.write_file(reader, "object", b"v2"), Err(FileError::PermissionDenied(_))));
+    assert!(matches!(ctx.delete_file(reader, "object"), Err(FileError::PermissionDenied(_))));
+
+    // Append-only containers may add objects but not replace or delete them.
+    let appender = open_container(&ctx, dir.path(), FileMode::Append);
+    ctx.write_file(appender, "log/1", b"entry").unwrap();
+    assert!(matches!(ctx.write_file(appender, "object", b"v2"), Err(FileError::AlreadyExists(_))));
+    assert!(matches!(ctx.delete_file(appender, "log/1"), Err(FileError::PermissionDenied(_))));
+
+    let file = dir.path().join("object");
+    assert!(matches!(ctx.open_container(&file, FileMode::Read), Err(FileError::InvalidOperation(_))));
+}
+
+#[test]
+fn test_object_paths_stay_in_container() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+    let handle = open_container(&ctx, &root, FileMode::ReadWrite);
+
+    for path in ["../outside", "/etc/passwd", "a/../../outside"] {
+        assert!(
+            matches!(ctx.write_file(handle, path, b"x"), Err(FileError::SandboxViolation(_))),
+            "{path:?} was accepted"
+        );
+    }
+    assert!(matches!(ctx.write_file(handle, "", b"x"), Err(FileError::InvalidOperation(_))));
+    assert!(!dir.path().join("outside").exists());
+    ctx.write_file(handle, "dir/object", b"x").unwrap();
+    assert!(matches!(ctx.write_file(handle, "dir", b"x"), Err(FileError::InvalidOperation(_))));
+    assert!(matches!(ctx.delete_file(handle, "dir"), Err(FileError::InvalidOperation(_))));
+}
+
+#[test]
+#[cfg(not(feature = "sev"))]
+fn test_rename_rewraps_header() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    ctx.write_file(handle, "plain", b"public").unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "a/secret", b"confidential data").unwrap();
+    let before = fs::read(dir.path().join("a/secret")).unwrap();
+
+    // Only the wrapped object key in the header changes.
+    ctx.rename_file(handle, "a/secret", "b/renamed").unwrap();
+    let after = fs::read(dir.path().join("b/renamed")).unwrap();
+    assert_eq!(before.len(), after.len());
+    assert_eq!(before[..64], after[..64]);
+    assert_ne!(before[64..112], after[64..112]);
+    assert_eq!(before[112..], after[112..]);
+    assert_eq!(ctx.read_file(handle, "b/renamed").unwrap(), b"confidential data");
+    assert!(matches!(ctx.read_file(handle, "a/secret"), Err(FileError::NotFound)));
+
+    // Renaming on disk instead still fails authentication.
+    fs::rename(dir.path().join("b/renamed"), dir.path().join("b/moved")).unwrap();
+    assert!(matches!(ctx.read_file(handle, "b/moved"), Err(FileError::IntegrityError(_))));
+    fs::rename(dir.path().join("b/moved"), dir.path().join("b/renamed")).unwrap();
+
+    ctx.rename_file(handle, "plain", "b/plain").unwrap();
+    assert_eq!(ctx.read_file(handle, "b/plain").unwrap(), b"public");
+    assert!(matches!(ctx.rename_file(handle, "b/plain", "b/renamed"), Err(FileError::AlreadyExists(_))));
+    assert!(matches!(ctx.rename_file(handle, "missing", "other"), Err(FileError::NotFound)));
+    assert!(matches!(ctx.rename_file(handle, "b", "c"), Err(FileError::InvalidOperation(_))));
+
+    ctx.remove_key(handle).unwrap();
+    assert!(matches!(ctx.rename_file(handle, "b/renamed", "c"), Err(FileError::KeyNotLoaded)));
+    assert_eq!(fs::read(dir.path().join("b/renamed")).unwrap(), after);
+
+    let reader = ctx.open_container(dir.path(), FileMode::Read).unwrap();
+    assert!(matches!(ctx.rename_file(reader, "b/plain", "c"), Err(FileError::PermissionDenied(_))));
+}
+
+#[test]
+fn test_delete_shreds_object() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "secret", b"confidential data").unwrap();
+    let stored = fs::read(dir.path().join("secret")).unwrap();
+
+    // A second link keeps the deleted blocks around, as a backup would.
+    let leftover = dir.path().join("leftover");
+    fs::hard_link(dir.path().join("secret"), &leftover).unwrap();
+    ctx.delete_file(handle, "secret").unwrap();
+    let shredded = fs::read(&leftover).unwrap();
+    assert_eq!(stored[112..], shredded[112..]);
+
+    // Putting it back is refused, since the manifest no longer lists it.
+    fs::copy(&leftover, dir.path().join("secret")).unwrap();
+    assert!(ctx.is_encrypted(handle, "secret").unwrap());
+    assert!(matches!(ctx.read_file(handle, "secret"), Err(FileError::IntegrityError(_))));
+
+    // And with the salt gone the master key no longer recovers the contents,
+    // even in a container with no manifest to consult.
+    let other = tempdir().unwrap();
+    let fresh = ctx.open_container(other.path(), FileMode::Read).unwrap();
+    ctx.load_key(fresh, &KEY).unwrap();
+    fs::rename(&leftover, other.path().join("secret")).unwrap();
+    assert!(matches!(ctx.read_file(fresh, "secret"), Err(FileError::DecryptionError(_))));
+    fs::write(other.path().join("secret"), &stored).unwrap();
+    assert_eq!(ctx.read_file(fresh, "secret").unwrap(), b"confidential data");
+}
+
+#[test]
+#[cfg(feature = "sev")]
+fn test_sev_objects_are_always_encrypted() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    assert!(matches!(ctx.write_file(handle, "object", b"data"), Err(FileError::KeyNotLoaded)));
+    assert!(!dir.path().join("object").exists());
+
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "object", b"confidential data").unwrap();
+    let stored = fs::read(dir.path().join("object")).unwrap();
+    assert!(!stored.windows(12).any(|w| w == b"confidential"));
+    assert!(ctx.is_encrypted(handle, "object").unwrap());
+    ctx.rename_file(handle, "object", "moved").unwrap();
+    assert_eq!(ctx.read_file(handle, "moved").unwrap(), b"confidential data");
+
+    // A plaintext object put in place by the host is not trusted.
+    fs::write(dir.path().join("moved"), b"confidential data").unwrap();
+    assert!(matches!(ctx.read_file(handle, "moved"), Err(FileError::IntegrityError(_))));
+}
@@ -0,0 +1,158 @@
+use elastic_file::{FileConfig, FileContext, FileError, FileMode, FileOperations, FileType, KeySource};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+const KEY: [u8; 32] = [0x42; 32];
+
+fn write(ctx: &FileContext, path: &Path, data: &[u8], secure: bool) {
+    let handle = ctx
+        .open(&FileConfig {
+            mode: FileMode::Write,
+            path: path.to_path_buf(),
+            secure,
+        })
+        .unwrap();
+    ctx.write(handle, data).unwrap();
+    ctx.close(handle).unwrap();
+}
+
+fn read(ctx: &FileContext, path: &Path, secure: bool) -> Vec<u8> {
+    let handle = ctx
+        .open(&FileConfig {
+            mode: FileMode::Read,
+            path: path.to_path_buf(),
+            secure,
+        })
+        .unwrap();
+    let mut data = vec![0u8; 64];
+    let len = ctx.read(handle, &mut data).unwrap();
+    ctx.close(handle).unwrap();
+    data.truncate(len);
+    data
+}
+
+fn names(ctx: &FileContext, path: &Path, secure: bool) -> Vec<String> {
+    ctx.read_dir(path, secure).unwrap().into_iter().map(|entry| entry.name).collect()
+}
+
+/// Every path below `dir` on the host, recursively.
+fn host_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        found.push(entry.path());
+        if entry.file_type().unwrap().is_dir() {
+            found.extend(host_paths(&entry.path()));
+        }
+    }
+    found
+}
+
+#[test]
+fn test_directory_operations() {
+    let ctx = FileContext::new();
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    ctx.create_dir(&root.join("docs"), false).unwrap();
+    assert!(matches!(ctx.create_dir(&root.join("docs"), false), Err(FileError::IoError(_))));
+    write(&ctx, &root.join("docs/report"), b"quarterly", false);
+    ctx.hard_link(&root.join("docs/report"), &root.join("docs/copy"), false).unwrap();
+    ctx.symlink(Path::new("report"), &root.join("docs/latest"), false).unwrap();
+    assert_eq!(ctx.read_link(&root.join("docs/latest"), false).unwrap(), Path::new("report"));
+
+    let entries = ctx.read_dir(&root.join("docs"), false).unwrap();
+    let summary: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.file_type, e.size)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("copy", FileType::Regular, 9),
+            ("latest", FileType::SymbolicLink, 6),
+            ("report", FileType::Regular, 9),
+        ]
+    );
+
+    ctx.rename(&root.join("docs"), &root.join("archive"), false).unwrap();
+    assert_eq!(read(&ctx, &root.join("archive/latest"), false), b"quarterly");
+    assert!(matches!(ctx.remove_dir(&root.join("archive"), false), Err(FileError::IoError(_))));
+    for name in ["copy", "latest", "report"] {
+        ctx.remove_file(&root.join("archive").join(name), false).unwrap();
+    }
+    ctx.remove_dir(&root.join("archive"), false).unwrap();
+    assert!(names(&ctx, root, false).is_empty());
+
+    // Without a root names cannot be encrypted, so secure directory
+    // operations are refused rather than storing plaintext names.
+    ctx.load_file_key(KeySource::Raw(&[0x42; 32])).unwrap();
+    assert!(matches!(ctx.create_dir(&root.join("docs"), true), Err(FileError::InvalidOperation(_))));
+    assert!(matches!(ctx.read_dir(root, true), Err(FileError::InvalidOperation(_))));
+    assert!(names(&ctx, root, false).is_empty());
+}
+
+#[test]
+fn test_secure_names_are_encrypted() {
+    let dir = tempdir().unwrap();
+    let ctx = FileContext::new().with_root(dir.path()).unwrap();
+    assert!(matches!(ctx.create_dir(Path::new("docs"), true), Err(FileError::KeyNotLoaded)));
+    ctx.load_file_key(KeySource::Raw(&KEY)).unwrap();
+
+    ctx.create_dir(Path::new("docs"), true).unwrap();
+    ctx.create_dir(Path::new("docs/2024"), true).unwrap();
+    write(&ctx, Path::new("docs/2024/salaries.csv"), b"alice,100", true);
+    ctx.hard_link(Path::new("docs/2024/salaries.csv"), Path::new("docs/backup.csv"), true).unwrap();
+    ctx.symlink(Path::new("2024/salaries.csv"), Path::new("docs/current"), true).unwrap();
+    write(&ctx, Path::new("public.txt"), b"hello", false);
+
+    // The host sees neither names, link targets nor contents.
+    let stored = host_paths(dir.path());
+    assert_eq!(stored.len(), 6);
+    for path in &stored {
+        let stored = path.strip_prefix(dir.path()).unwrap().to_string_lossy();
+        let target = fs::read_link(path).map(|t| t.to_string_lossy().into_owned()).unwrap_or_default();
+        for secret in ["docs", "2024", "salaries", "backup", "current"] {
+            assert!(!stored.contains(secret) && !target.contains(secret), "{secret} leaked: {stored}");
+        }
+    }
+    assert_eq!(fs::read(dir.path().join("public.txt")).unwrap(), b"hello");
+
+    // Through the context, everything reads back as written.
+    assert_eq!(names(&ctx, Path::new(""), true), vec!["docs"]);
+    assert_eq!(names(&ctx, Path::new(""), false).len(), 2);
+    let entries = ctx.read_dir(Path::new("docs"), true).unwrap();
+    let summary: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.file_type, e.size)).collect();
+    assert_eq!((summary[0].0, summary[0].1), ("2024", FileType::Directory));
+    assert_eq!(summary[1], ("backup.csv", FileType::Regular, 9));
+    assert_eq!(summary[2].0, "current");
+    let current = ctx.symlink_metadata(Path::new("docs/current"), true).unwrap();
+    assert_eq!((current.name.as_str(), current.file_type), ("current", FileType::SymbolicLink));
+    assert_eq!(ctx.symlink_metadata(Path::new("docs/2024/salaries.csv"), true).unwrap().size, 9);
+    assert_eq!(read(&ctx, Path::new("docs/backup.csv"), true), b"alice,100");
+    assert_eq!(
+        ctx.read_link(Path::new("docs/current"), true).unwrap(),
+        Path::new("2024/salaries.csv")
+    );
+
+    // Renaming a directory keeps everything below it readable.
+    ctx.rename(Path::new("docs/2024"), Path::new("docs/2025"), true).unwrap();
+    assert_eq!(read(&ctx, Path::new("docs/2025/salaries.csv"), true), b"alice,100");
+    ctx.remove_file(Path::new("docs/2025/salaries.csv"), true).unwrap();
+    ctx.remove_dir(Path::new("docs/2025"), true).unwrap();
+    assert_eq!(names(&ctx, Path::new("docs"), true), vec!["backup.csv", "current"]);
+
+    // Names must fit once encrypted, and stay beneath the root.
+    assert!(matches!(
+        ctx.create_dir(Path::new(&"x".repeat(97)), true),
+        Err(FileError::InvalidOperation(_))
+    ));
+    ctx.create_dir(Path::new(&"x".repeat(96)), true).unwrap();
+    for path in ["../escape", "/tmp/escape", "docs/../../escape"] {
+        assert!(matches!(ctx.create_dir(Path::new(path), true), Err(FileError::SandboxViolation(_))));
+        assert!(matches!(ctx.create_dir(Path::new(path), false), Err(FileError::SandboxViolation(_))));
+    }
+
+    // Under another key, the encrypted tree is invisible.
+    ctx.load_file_key(KeySource::Raw(&[0x24; 32])).unwrap();
+    assert!(names(&ctx, Path::new(""), true).is_empty());
+    assert!(matches!(ctx.read_dir(Path::new("docs"), true), Err(FileError::IoError(_))));
+}
@@ -0,0 +1,258 @@
+use elastic_file::{
+    ContainerOperations, FileContext, FileError, FileMode, KeySource, MemoryVersionStore, SealedVersionStore,
+    VersionStore,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+const KEY: [u8; 32] = [0x42; 32];
+const MANIFEST: &str = ".elastic-manifest";
+
+/// Containers need a version store; an in-memory one is enough within a test.
+fn context() -> FileContext {
+    FileContext::with_version_store(Arc::new(MemoryVersionStore::default()))
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+fn write(ctx: &FileContext, root: &Path, objects: &[(&str, &[u8])]) {
+    let handle = ctx.open_container(root, FileMode::ReadWrite).unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    for (path, data) in objects {
+        ctx.write_file(handle, path, data).unwrap();
+    }
+    ctx.close_container(handle).unwrap();
+}
+
+fn reopen(ctx: &FileContext, root: &Path) -> Result<u32, FileError> {
+    let handle = ctx.open_container(root, FileMode::Read)?;
+    ctx.load_key(handle, &KEY)?;
+    Ok(handle)
+}
+
+#[test]
+fn test_rollback_is_refused() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+    let snapshot = dir.path().join("snapshot");
+
+    write(&ctx, &root, &[("balance", b"100"), ("log/1", b"deposit")]);
+    copy_dir(&root, &snapshot);
+    write(&ctx, &root, &[("balance", b"0"), ("log/2", b"withdrawal")]);
+    let handle = reopen(&ctx, &root).unwrap();
+    assert_eq!(ctx.read_file(handle, "balance").unwrap(), b"0");
+    ctx.close_container(handle).unwrap();
+
+    // A consistent, correctly signed older state is still refused.
+    fs::remove_dir_all(&root).unwrap();
+    copy_dir(&snapshot, &root);
+    assert!(matches!(ctx.open_container(&root, FileMode::Read), Err(FileError::IntegrityError(_))));
+
+    // So is rolling back a single object.
+    let other = dir.path().join("other");
+    write(&ctx, &other, &[("balance", b"50")]);
+    let old = fs::read(other.join("balance")).unwrap();
+    write(&ctx, &other, &[("balance", b"10")]);
+    fs::write(other.join("balance"), old).unwrap();
+    assert!(matches!(ctx.open_container(&other, FileMode::Read), Err(FileError::IntegrityError(_))));
+}
+
+#[test]
+fn test_tampering_is_refused() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+    write(&ctx, &root, &[("a", b"first"), ("dir/b", b"second")]);
+    let original = dir.path().join("original");
+    copy_dir(&root, &original);
+
+    let restore = || {
+        fs::remove_dir_all(&root).unwrap();
+        copy_dir(&original, &root);
+        let handle = reopen(&ctx, &root).unwrap();
+        ctx.close_container(handle).unwrap();
+    };
+    let refused = |message: &str| match ctx.open_container(&root, FileMode::Read) {
+        Err(FileError::IntegrityError(reason)) => assert!(reason.contains(message), "{reason}"),
+        other => panic!("expected an integrity error, got {:?}", other),
+    };
+
+    let mut data = fs::read(root.join("a")).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+    fs::write(root.join("a"), data).unwrap();
+    refused("object a was modified");
+    restore();
+
+    fs::remove_file(root.join("dir/b")).unwrap();
+    refused("object dir/b is missing");
+    restore();
+
+    fs::write(root.join("dir/c"), b"planted").unwrap();
+    refused("object dir/c is not in the manifest");
+    restore();
+
+    fs::remove_file(root.join(MANIFEST)).unwrap();
+    refused("manifest is missing");
+    restore();
+
+    fs::write(root.join(MANIFEST), b"garbage").unwrap();
+    refused("manifest is corrupt");
+    restore();
+
+    // A forged, newer version number only shows once the key is loaded.
+    let mut manifest = fs::read(root.join(MANIFEST)).unwrap();
+    manifest[24] = 1;
+    fs::write(root.join(MANIFEST), manifest).unwrap();
+    let handle = ctx.open_container(&root, FileMode::Read).unwrap();
+    assert!(matches!(ctx.load_key(handle, &KEY), Err(FileError::IntegrityError(_))));
+    assert!(matches!(ctx.read_file(handle, "a"), Err(FileError::KeyNotLoaded)));
+}
+
+#[test]
+fn test_tampering_after_open_is_refused() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+    write(&ctx, &root, &[("balance", b"100"), ("owner", b"alice")]);
+    let old = fs::read(root.join("balance")).unwrap();
+    write(&ctx, &root, &[("balance", b"0")]);
+    let handle = reopen(&ctx, &root).unwrap();
+
+    // An older copy decrypts under the same key and name, but is not the
+    // object the manifest records.
+    fs::write(root.join("balance"), &old).unwrap();
+    assert!(matches!(ctx.read_file(handle, "balance"), Err(FileError::IntegrityError(_))));
+
+    // Neither is a plaintext stand-in.
+    fs::write(root.join("owner"), b"mallory").unwrap();
+    assert!(matches!(ctx.read_file(handle, "owner"), Err(FileError::IntegrityError(_))));
+
+    // Without a manifest yet, a keyed container still refuses plaintext.
+    let fresh = dir.path().join("fresh");
+    fs::create_dir(&fresh).unwrap();
+    let handle = reopen(&ctx, &fresh).unwrap();
+    fs::write(fresh.join("planted"), b"plaintext").unwrap();
+    assert!(matches!(ctx.read_file(handle, "planted"), Err(FileError::IntegrityError(_))));
+}
+
+#[test]
+#[cfg(not(feature = "sev"))]
+fn test_protected_container_needs_key() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    ctx.write_file(handle, "plain", b"public").unwrap();
+    assert!(!dir.path().join(MANIFEST).exists());
+
+    // The first change made with a key loaded covers existing objects too.
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "secret", b"confidential").unwrap();
+    ctx.remove_key(handle).unwrap();
+    assert!(matches!(ctx.read_file(handle, "plain"), Err(FileError::KeyNotLoaded)));
+    assert!(matches!(ctx.write_file(handle, "other", b"x"), Err(FileError::KeyNotLoaded)));
+    assert!(matches!(ctx.delete_file(handle, "plain"), Err(FileError::KeyNotLoaded)));
+    assert_eq!(ctx.list_files(handle, "").unwrap(), vec!["plain", "secret"]);
+
+    ctx.load_key(handle, &[0x24; 32]).unwrap();
+    assert!(matches!(ctx.read_file(handle, "plain"), Err(FileError::WrongKey)));
+    assert!(matches!(ctx.write_file(handle, "other", b"x"), Err(FileError::WrongKey)));
+    ctx.load_key(handle, &KEY).unwrap();
+    assert_eq!(ctx.read_file(handle, "plain").unwrap(), b"public");
+
+    for path in [MANIFEST, ".elastic-anything"] {
+        assert!(matches!(ctx.read_file(handle, path), Err(FileError::InvalidOperation(_))));
+        assert!(matches!(ctx.write_file(handle, path, b"x"), Err(FileError::InvalidOperation(_))));
+    }
+    ctx.rename_file(handle, "plain", "renamed").unwrap();
+    ctx.delete_file(handle, "secret").unwrap();
+    ctx.close_container(handle).unwrap();
+    let handle = reopen(&ctx, dir.path()).unwrap();
+    assert_eq!(ctx.list_files(handle, "").unwrap(), vec!["renamed"]);
+}
+
+// On SEV no plaintext object is ever written, so the manifest covers the
+// container from its first object.
+#[test]
+#[cfg(feature = "sev")]
+fn test_sev_container_is_protected_from_the_start() {
+    let dir = tempdir().unwrap();
+    // Without a version store a rollback would go unnoticed.
+    assert!(matches!(
+        FileContext::new().open_container(dir.path(), FileMode::ReadWrite),
+        Err(FileError::InvalidOperation(_))
+    ));
+
+    let ctx = context();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    assert!(matches!(ctx.write_file(handle, "secret", b"x"), Err(FileError::KeyNotLoaded)));
+    assert!(!dir.path().join(MANIFEST).exists());
+
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "secret", b"confidential").unwrap();
+    assert!(dir.path().join(MANIFEST).exists());
+    ctx.remove_key(handle).unwrap();
+    assert!(matches!(ctx.read_file(handle, "secret"), Err(FileError::KeyNotLoaded)));
+    ctx.load_key(handle, &[0x24; 32]).unwrap();
+    assert!(matches!(ctx.read_file(handle, "secret"), Err(FileError::WrongKey)));
+    ctx.close_container(handle).unwrap();
+
+    let handle = reopen(&ctx, dir.path()).unwrap();
+    assert_eq!(ctx.read_file(handle, "secret").unwrap(), b"confidential");
+}
+
+#[test]
+fn test_sealed_version_store() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+    let snapshot = dir.path().join("snapshot");
+    let trusted = dir.path().join("versions");
+    let store = |key: &[u8]| -> Arc<dyn VersionStore> {
+        Arc::new(SealedVersionStore::open(&trusted, KeySource::Raw(key)).unwrap())
+    };
+
+    write(&FileContext::with_version_store(store(&KEY)), &root, &[("object", b"v1")]);
+    copy_dir(&root, &snapshot);
+    write(&FileContext::with_version_store(store(&KEY)), &root, &[("object", b"v2")]);
+
+    // A restarted process still remembers the latest version.
+    fs::remove_dir_all(&root).unwrap();
+    copy_dir(&snapshot, &root);
+    let ctx = FileContext::with_version_store(store(&KEY));
+    assert!(matches!(ctx.open_container(&root, FileMode::Read), Err(FileError::IntegrityError(_))));
+    // A fresh in-memory store cannot tell.
+    assert!(reopen(&context(), &root).is_ok());
+
+    // The store itself is authenticated.
+    assert!(matches!(
+        SealedVersionStore::open(&trusted, KeySource::Raw(&[0x24; 32])),
+        Err(FileError::IntegrityError(_))
+    ));
+    let mut data = fs::read(&trusted).unwrap();
+    data[0] ^= 1;
+    fs::write(&trusted, data).unwrap();
+    assert!(matches!(
+        SealedVersionStore::open(&trusted, KeySource::Raw(&KEY)),
+        Err(FileError::IntegrityError(_))
+    ));
+
+    let memory = MemoryVersionStore::default();
+    memory.advance("container", 3).unwrap();
+    memory.advance("container", 3).unwrap();
+    assert!(matches!(memory.advance("container", 2), Err(FileError::IntegrityError(_))));
+    assert_eq!(memory.current("container").unwrap(), 3);
+    assert_eq!(memory.current("other").unwrap(), 0);
+}
@@ -0,0 +1,154 @@
+use elastic_crypto::{ElasticCrypto, KeyAlgorithm, KeyConfig, KeyType};
+use elastic_file::{
+    seal_key, ContainerOperations, FileConfig, FileContext, FileError, FileMode, FileOperations, KeySource,
+    MemoryVersionStore,
+};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+fn hmac_key(crypto: &ElasticCrypto) -> u32 {
+    crypto
+        .generate_key(KeyConfig {
+            key_type: KeyType::Hmac,
+            key_size: 256,
+            secure_storage: true,
+            algorithm: Some(KeyAlgorithm::HmacSha256),
+        })
+        .unwrap()
+}
+
+fn siv_key(crypto: &ElasticCrypto) -> u32 {
+    crypto
+        .generate_key(KeyConfig {
+            key_type: KeyType::Symmetric,
+            key_size: 512,
+            secure_storage: true,
+            algorithm: Some(KeyAlgorithm::AesSiv),
+        })
+        .unwrap()
+}
+
+fn secure(path: &Path, mode: FileMode) -> FileConfig {
+    FileConfig {
+        mode,
+        path: path.to_path_buf(),
+        secure: true,
+    }
+}
+
+#[test]
+fn test_secure_files_need_a_key() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let ctx = FileContext::new();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("data.enc");
+    assert!(matches!(ctx.open(&secure(&path, FileMode::Write)), Err(FileError::KeyNotLoaded)));
+
+    // The master key is derived inside the key store from a secure handle.
+    let key = hmac_key(&crypto);
+    ctx.load_file_key(KeySource::Handle { backend: &crypto, handle: key }).unwrap();
+    let handle = ctx.open(&secure(&path, FileMode::ReadWrite)).unwrap();
+    ctx.write(handle, b"bound to a handle").unwrap();
+
+    // Removing the key locks files that are already open.
+    ctx.remove_file_key().unwrap();
+    ctx.seek(handle, 0, 0).unwrap();
+    let mut buf = [0u8; 64];
+    assert!(matches!(ctx.read(handle, &mut buf), Err(FileError::KeyNotLoaded)));
+    assert!(matches!(ctx.write(handle, b"more"), Err(FileError::KeyNotLoaded)));
+    assert!(matches!(ctx.open(&secure(&path, FileMode::Read)), Err(FileError::KeyNotLoaded)));
+    assert!(matches!(ctx.remove_file_key(), Err(FileError::InvalidOperation(_))));
+
+    // Loading another key does not unlock them either.
+    let other = hmac_key(&crypto);
+    ctx.load_file_key(KeySource::Handle { backend: &crypto, handle: other }).unwrap();
+    assert!(matches!(ctx.read(handle, &mut buf), Err(FileError::WrongKey)));
+    assert!(matches!(ctx.open(&secure(&path, FileMode::Read)), Err(FileError::WrongKey)));
+
+    ctx.load_file_key(KeySource::Handle { backend: &crypto, handle: key }).unwrap();
+    assert_eq!(ctx.read(handle, &mut buf).unwrap(), 17);
+    assert_eq!(&buf[..17], b"bound to a handle");
+    ctx.close(handle).unwrap();
+
+    // Only HMAC-SHA256 handles can derive a master key.
+    let aes = siv_key(&crypto);
+    assert!(matches!(
+        ctx.load_file_key(KeySource::Handle { backend: &crypto, handle: aes }),
+        Err(FileError::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        ctx.load_file_key(KeySource::Handle { backend: &crypto, handle: 9999 }),
+        Err(FileError::Crypto(_))
+    ));
+}
+
+#[test]
+fn test_sealed_keys() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let wrapping = siv_key(&crypto);
+    let sealed = seal_key(&crypto, wrapping).unwrap();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("data.enc");
+
+    let writer = FileContext::new();
+    writer
+        .load_file_key(KeySource::Sealed { backend: &crypto, handle: wrapping, sealed: &sealed })
+        .unwrap();
+    let handle = writer.open(&secure(&path, FileMode::Write)).unwrap();
+    writer.write(handle, b"sealed").unwrap();
+    writer.close(handle).unwrap();
+
+    // Another context unseals the same master key.
+    let reader = FileContext::new();
+    reader
+        .load_file_key(KeySource::Sealed { backend: &crypto, handle: wrapping, sealed: &sealed })
+        .unwrap();
+    let handle = reader.open(&secure(&path, FileMode::Read)).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(reader.read(handle, &mut buf).unwrap(), 6);
+    assert_eq!(&buf[..6], b"sealed");
+
+    // A sealed key only opens under the handle it was sealed with.
+    let other = siv_key(&crypto);
+    assert!(matches!(
+        reader.load_file_key(KeySource::Sealed { backend: &crypto, handle: other, sealed: &sealed }),
+        Err(FileError::WrongKey)
+    ));
+    let resealed = seal_key(&crypto, wrapping).unwrap();
+    reader
+        .load_file_key(KeySource::Sealed { backend: &crypto, handle: wrapping, sealed: &resealed })
+        .unwrap();
+    assert!(matches!(reader.open(&secure(&path, FileMode::Read)), Err(FileError::WrongKey)));
+
+    // GCM handles are refused: they cannot seal several keys safely.
+    let gcm = crypto.generate_key(KeyConfig::default()).unwrap();
+    assert!(matches!(seal_key(&crypto, gcm), Err(FileError::InvalidOperation(_))));
+}
+
+#[test]
+fn test_container_bound_to_handle() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let key = hmac_key(&crypto);
+    let ctx = FileContext::with_version_store(Arc::new(MemoryVersionStore::default()));
+    let dir = tempdir().unwrap();
+
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    ctx.load_container_key(handle, KeySource::Handle { backend: &crypto, handle: key }).unwrap();
+    ctx.write_file(handle, "records/1", b"customer record").unwrap();
+    assert!(ctx.is_encrypted(handle, "records/1").unwrap());
+    ctx.close_container(handle).unwrap();
+
+    let handle = ctx.open_container(dir.path(), FileMode::Read).unwrap();
+    assert!(matches!(ctx.read_file(handle, "records/1"), Err(FileError::KeyNotLoaded)));
+    ctx.load_container_key(handle, KeySource::Handle { backend: &crypto, handle: hmac_key(&crypto) }).unwrap();
+    assert!(matches!(ctx.read_file(handle, "records/1"), Err(FileError::WrongKey)));
+    ctx.load_container_key(handle, KeySource::Handle { backend: &crypto, handle: key }).unwrap();
+    assert_eq!(ctx.read_file(handle, "records/1").unwrap(), b"customer record");
+
+    // Deleting the key handle leaves the data unreadable.
+    crypto.delete_key(key).unwrap();
+    ctx.remove_key(handle).unwrap();
+    assert!(ctx.load_container_key(handle, KeySource::Handle { backend: &crypto, handle: key }).is_err());
+    assert!(matches!(ctx.read_file(handle, "records/1"), Err(FileError::KeyNotLoaded)));
+}
@@ -0,0 +1,181 @@
+use elastic_file::{Fault, FileConfig, FileError, FileMode, FileOperations, FileType, KeySource, MemoryFileContext};
+use std::path::Path;
+
+const KEY: [u8; 32] = [0x42; 32];
+
+fn open(ctx: &MemoryFileContext, path: &str, mode: FileMode, secure: bool) -> Result<u32, FileError> {
+    ctx.open(&FileConfig {
+        mode,
+        path: path.into(),
+        secure,
+    })
+}
+
+fn write(ctx: &MemoryFileContext, path: &str, data: &[u8], secure: bool) {
+    let handle = open(ctx, path, FileMode::Write, secure).unwrap();
+    assert_eq!(ctx.write(handle, data).unwrap(), data.len());
+    ctx.close(handle).unwrap();
+}
+
+fn read(ctx: &MemoryFileContext, path: &str, secure: bool) -> Vec<u8> {
+    let handle = open(ctx, path, FileMode::Read, secure).unwrap();
+    let mut data = vec![0u8; 16384];
+    let len = ctx.read(handle, &mut data).unwrap();
+    ctx.close(handle).unwrap();
+    data.truncate(len);
+    data
+}
+
+fn errno(result: Result<impl std::fmt::Debug, FileError>) -> i32 {
+    match result {
+        Err(FileError::IoError(e)) => e.raw_os_error().unwrap(),
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_memory_files_and_directories() {
+    let ctx = MemoryFileContext::new();
+    ctx.create_dir(Path::new("/docs"), false).unwrap();
+    write(&ctx, "/docs/report", b"quarterly", false);
+
+    let handle = open(&ctx, "docs/report", FileMode::ReadWrite, false).unwrap();
+    assert_eq!(ctx.seek(handle, 2, 2).unwrap(), 11);
+    ctx.write(handle, b"!").unwrap();
+    ctx.seek(handle, 0, 0).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(ctx.read(handle, &mut buf).unwrap(), 12);
+    assert_eq!(&buf[..12], b"quarterly\0\0!");
+    let metadata = ctx.metadata(handle).unwrap();
+    assert_eq!((metadata.size, metadata.is_file, metadata.is_dir), (12, true, false));
+    ctx.close(handle).unwrap();
+
+    let handle = open(&ctx, "docs/report", FileMode::Append, false).unwrap();
+    ctx.write(handle, b"?").unwrap();
+    assert!(matches!(ctx.read(handle, &mut buf), Err(FileError::InvalidMode)));
+    ctx.close(handle).unwrap();
+
+    ctx.hard_link(Path::new("docs/report"), Path::new("docs/copy"), false).unwrap();
+    ctx.symlink(Path::new("report"), Path::new("docs/latest"), false).unwrap();
+    assert_eq!(ctx.read_link(Path::new("docs/latest"), false).unwrap(), Path::new("report"));
+    assert_eq!(read(&ctx, "docs/latest", false), b"quarterly\0\0!?");
+    let entries = ctx.read_dir(Path::new("docs"), false).unwrap();
+    let summary: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.file_type, e.size)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("copy", FileType::Regular, 13),
+            ("latest", FileType::SymbolicLink, 6),
+            ("report", FileType::Regular, 13),
+        ]
+    );
+    let latest = ctx.symlink_metadata(Path::new("docs/latest"), false).unwrap();
+    assert_eq!((latest.name.as_str(), latest.file_type, latest.size), ("latest", FileType::SymbolicLink, 6));
+
+    // Errors carry the errno the host would report.
+    assert_eq!(errno(open(&ctx, "missing", FileMode::Read, false)), libc::ENOENT);
+    assert_eq!(errno(open(&ctx, "docs", FileMode::Read, false)), libc::EISDIR);
+    assert_eq!(errno(ctx.create_dir(Path::new("docs"), false)), libc::EEXIST);
+    assert_eq!(errno(ctx.remove_dir(Path::new("docs"), false)), libc::ENOTEMPTY);
+    assert_eq!(errno(ctx.create_dir(Path::new("docs/report/sub"), false)), libc::ENOTDIR);
+    assert_eq!(errno(ctx.rename(Path::new("docs"), Path::new("docs/sub"), false)), libc::EINVAL);
+    assert!(matches!(open(&ctx, "../escape", FileMode::Write, false), Err(FileError::SandboxViolation(_))));
+
+    ctx.rename(Path::new("docs"), Path::new("archive"), false).unwrap();
+    ctx.remove_file(Path::new("archive/report"), false).unwrap();
+    assert_eq!(read(&ctx, "archive/copy", false), b"quarterly\0\0!?");
+    for name in ["copy", "latest"] {
+        ctx.remove_file(&Path::new("archive").join(name), false).unwrap();
+    }
+    ctx.remove_dir(Path::new("archive"), false).unwrap();
+    assert!(ctx.read_dir(Path::new(""), false).unwrap().is_empty());
+}
+
+#[test]
+fn test_memory_secure_files() {
+    let ctx = MemoryFileContext::new();
+    assert!(matches!(open(&ctx, "secret", FileMode::Write, true), Err(FileError::KeyNotLoaded)));
+    ctx.load_file_key(KeySource::Raw(&KEY)).unwrap();
+
+    let contents = b"alice,100\n".repeat(1000);
+    write(&ctx, "secret", &contents, true);
+    assert_eq!(read(&ctx, "secret", true), contents);
+    assert_eq!(ctx.read_dir(Path::new(""), false).unwrap()[0].size, contents.len() as u64);
+
+    // Names are not encrypted in memory, so secure directory operations fail.
+    assert!(matches!(ctx.read_dir(Path::new(""), true), Err(FileError::InvalidOperation(_))));
+    assert!(matches!(ctx.create_dir(Path::new("dir"), true), Err(FileError::InvalidOperation(_))));
+
+    // What is stored is ciphertext.
+    let snapshot = ctx.snapshot().unwrap();
+    let stored = snapshot.stored_bytes(Path::new("secret")).unwrap();
+    assert!(stored.len() > contents.len());
+    assert!(!stored.windows(9).any(|w| w == b"alice,100"));
+
+    ctx.load_file_key(KeySource::Raw(&[0x24; 32])).unwrap();
+    assert!(matches!(open(&ctx, "secret", FileMode::Read, true), Err(FileError::WrongKey)));
+    ctx.load_file_key(KeySource::Raw(&KEY)).unwrap();
+
+    // Removing a secure file shreds it, so a copy of its bytes is useless.
+    ctx.hard_link(Path::new("secret"), Path::new("copy"), false).unwrap();
+    ctx.remove_file(Path::new("secret"), true).unwrap();
+    assert!(open(&ctx, "copy", FileMode::Read, true).is_err());
+}
+
+#[test]
+fn test_memory_snapshot_and_restore() {
+    let ctx = MemoryFileContext::new();
+    write(&ctx, "state", b"committed", false);
+    let snapshot = ctx.snapshot().unwrap();
+
+    let handle = open(&ctx, "state", FileMode::Write, false).unwrap();
+    ctx.write(handle, b"half-writ").unwrap();
+    ctx.create_dir(Path::new("tmp"), false).unwrap();
+
+    ctx.restore(&snapshot).unwrap();
+    assert_eq!(read(&ctx, "state", false), b"committed");
+    assert!(matches!(ctx.write(handle, b"late"), Err(FileError::NotFound)));
+    let names: Vec<_> = ctx.read_dir(Path::new(""), false).unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, vec!["state"]);
+
+    // A snapshot is a copy: later changes do not reach it.
+    write(&ctx, "state", b"changed", false);
+    assert_eq!(snapshot.stored_bytes(Path::new("state")).unwrap(), b"committed");
+}
+
+#[test]
+fn test_memory_fault_injection() {
+    let ctx = MemoryFileContext::new();
+    let handle = open(&ctx, "log", FileMode::ReadWrite, false).unwrap();
+
+    ctx.inject_fault(Fault::ShortWrite(3), 1).unwrap();
+    assert_eq!(ctx.write(handle, b"first").unwrap(), 5);
+    assert_eq!(ctx.write(handle, b"second").unwrap(), 3);
+    assert_eq!(ctx.write(handle, b"third").unwrap(), 3);
+    ctx.clear_faults().unwrap();
+
+    ctx.inject_fault(Fault::NoSpace, 0).unwrap();
+    assert_eq!(errno(ctx.write(handle, b"x")), libc::ENOSPC);
+    ctx.seek(handle, 0, 0).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(ctx.read(handle, &mut buf).unwrap(), 11);
+    assert_eq!(&buf[..11], b"firstsecthi");
+    ctx.clear_faults().unwrap();
+
+    ctx.inject_fault(Fault::Io, 0).unwrap();
+    assert_eq!(errno(ctx.read(handle, &mut buf)), libc::EIO);
+    assert_eq!(errno(ctx.write(handle, b"x")), libc::EIO);
+    ctx.clear_faults().unwrap();
+    ctx.close(handle).unwrap();
+
+    // A capacity fails writes that would exceed it, secure ones included.
+    ctx.set_capacity(Some(64)).unwrap();
+    write(&ctx, "small", &[0u8; 53], false);
+    let handle = open(&ctx, "small", FileMode::Append, false).unwrap();
+    assert_eq!(errno(ctx.write(handle, b"overflow")), libc::ENOSPC);
+    ctx.close(handle).unwrap();
+    ctx.load_file_key(KeySource::Raw(&KEY)).unwrap();
+    assert_eq!(errno(open(&ctx, "secret", FileMode::Write, true)), libc::ENOSPC);
+    ctx.set_capacity(None).unwrap();
+    write(&ctx, "secret", b"fits now", true);
+}
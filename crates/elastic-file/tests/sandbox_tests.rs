@@ -0,0 +1,137 @@
+use elastic_file::{
+    ContainerOperations, FileConfig, FileContext, FileError, FileMode, FileOperations, MemoryVersionStore,
+};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+const KEY: [u8; 32] = [0x42; 32];
+
+fn config(path: &str, mode: FileMode) -> FileConfig {
+    FileConfig {
+        mode,
+        path: path.into(),
+        secure: false,
+    }
+}
+
+fn refused(ctx: &FileContext, path: &str, mode: FileMode) {
+    match ctx.open(&config(path, mode)) {
+        Err(FileError::SandboxViolation(_)) => {}
+        other => panic!("{path:?}: expected a sandbox violation, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_files_stay_beneath_root() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(dir.path().join("secret"), b"secret").unwrap();
+    let ctx = FileContext::new().with_root(&root).unwrap();
+
+    let handle = ctx.open(&config("sub/data", FileMode::Write)).unwrap();
+    ctx.write(handle, b"inside").unwrap();
+    ctx.close(handle).unwrap();
+    assert_eq!(fs::read(root.join("sub/data")).unwrap(), b"inside");
+
+    let handle = ctx.open(&config("sub/../sub/./data", FileMode::Read)).unwrap();
+    let mut buf = [0u8; 6];
+    assert_eq!(ctx.read(handle, &mut buf).unwrap(), 6);
+    assert_eq!(ctx.metadata(handle).unwrap().size, 6);
+    ctx.close(handle).unwrap();
+
+    for path in ["../secret", "sub/../../secret", "/etc/passwd"] {
+        refused(&ctx, path, FileMode::Read);
+    }
+    refused(&ctx, "../planted", FileMode::Write);
+    assert!(!dir.path().join("planted").exists());
+}
+
+#[test]
+fn test_symlinks_stay_beneath_root() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("sub/data"), b"inside").unwrap();
+    fs::write(dir.path().join("secret"), b"secret").unwrap();
+    symlink("sub", root.join("relative")).unwrap();
+    symlink("sub/data", root.join("file")).unwrap();
+    symlink("..", root.join("up")).unwrap();
+    symlink("../secret", root.join("escape")).unwrap();
+    symlink(root.join("sub"), root.join("absolute")).unwrap();
+    symlink("loop", root.join("loop")).unwrap();
+    let ctx = FileContext::new().with_root(&root).unwrap();
+
+    // Links that stay beneath the root are followed.
+    for path in ["relative/data", "file", "relative/../file"] {
+        let handle = ctx.open(&config(path, FileMode::Read)).unwrap();
+        ctx.close(handle).unwrap();
+    }
+    // Links that leave it, or are absolute, are refused even when they
+    // would lead back inside.
+    for path in ["escape", "up/secret", "up/root/file", "absolute/data"] {
+        refused(&ctx, path, FileMode::Read);
+    }
+    refused(&ctx, "up/planted", FileMode::Write);
+    assert!(!dir.path().join("planted").exists());
+    assert!(ctx.open(&config("loop", FileMode::Read)).is_err());
+}
+
+#[test]
+fn test_containers_stay_beneath_root() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir_all(&root).unwrap();
+    let ctx = FileContext::with_version_store(Arc::new(MemoryVersionStore::default()))
+        .with_root(&root)
+        .unwrap();
+
+    let handle = ctx.open_container(Path::new("store"), FileMode::ReadWrite).unwrap();
+    if cfg!(feature = "sev") {
+        ctx.load_key(handle, &KEY).unwrap();
+    }
+    ctx.write_file(handle, "object", b"inside").unwrap();
+    assert!(root.join("store/object").is_file());
+    assert_eq!(ctx.read_file(handle, "object").unwrap(), b"inside");
+    for path in ["../store", "/tmp"] {
+        assert!(matches!(
+            ctx.open_container(Path::new(path), FileMode::ReadWrite),
+            Err(FileError::SandboxViolation(_))
+        ));
+    }
+    assert!(!dir.path().join("store").exists());
+
+    // Inside a container, links are confined to the container itself.
+    let outside = dir.path().join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret"), b"secret").unwrap();
+    fs::create_dir_all(root.join("store/dir")).unwrap();
+    symlink("../object", root.join("store/dir/link")).unwrap();
+    symlink("../..", root.join("store/up")).unwrap();
+    symlink(&outside, root.join("store/absolute")).unwrap();
+    // Encrypted objects are bound to their path, so only a plaintext one can
+    // be read through a link.
+    if !cfg!(feature = "sev") {
+        assert_eq!(ctx.read_file(handle, "dir/link").unwrap(), b"inside");
+    }
+    assert_eq!(ctx.read_file(handle, "dir/../object").unwrap(), b"inside");
+    for path in ["up/outside/secret", "absolute/secret"] {
+        assert!(matches!(ctx.read_file(handle, path), Err(FileError::SandboxViolation(_))));
+    }
+    for path in ["up/outside/planted", "absolute/planted"] {
+        assert!(matches!(ctx.write_file(handle, path, b"x"), Err(FileError::SandboxViolation(_))));
+    }
+    assert!(matches!(ctx.list_files(handle, "absolute"), Err(FileError::SandboxViolation(_))));
+    assert!(!outside.join("planted").exists());
+
+    // Without a root, only the container confines object paths.
+    let ctx = FileContext::with_version_store(Arc::new(MemoryVersionStore::default()));
+    let handle = ctx.open_container(&root.join("store"), FileMode::Read).unwrap();
+    if cfg!(feature = "sev") {
+        ctx.load_key(handle, &KEY).unwrap();
+    }
+    assert!(matches!(ctx.read_file(handle, "absolute/secret"), Err(FileError::SandboxViolation(_))));
+}
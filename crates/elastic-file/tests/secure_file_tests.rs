@@ -0,0 +1,207 @@
+use elastic_file::{FileConfig, FileContext, FileError, FileMode, FileOperations, KeySource};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// Must match the on-disk format: 112-byte header, 4 KiB chunks stored with a
+// 12-byte nonce and a 16-byte tag.
+const HEADER_LEN: usize = 112;
+const CHUNK_SIZE: usize = 4096;
+const NONCE_LEN: usize = 12;
+const STRIDE: usize = CHUNK_SIZE + 28;
+
+fn secure(path: &Path, mode: FileMode) -> FileConfig {
+    FileConfig {
+        mode,
+        path: path.to_path_buf(),
+        secure: true,
+    }
+}
+
+fn context() -> FileContext {
+    let ctx = FileContext::new();
+    ctx.load_file_key(KeySource::Raw(&[0x42; 32])).unwrap();
+    ctx
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn read_to_end(ctx: &FileContext, handle: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1000];
+    loop {
+        match ctx.read(handle, &mut buf).unwrap() {
+            0 => return data,
+            n => data.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+#[test]
+fn test_random_access() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("data.enc");
+    let data = pattern(10_000);
+
+    let handle = ctx.open(&secure(&path, FileMode::ReadWrite)).unwrap();
+    for piece in data.chunks(777) {
+        assert_eq!(ctx.write(handle, piece).unwrap(), piece.len());
+    }
+    assert_eq!(ctx.metadata(handle).unwrap().size, 10_000);
+    assert_eq!(fs::metadata(&path).unwrap().len() as usize, HEADER_LEN + 2 * STRIDE + 1808 + 28);
+
+    // Partial reads across chunk boundaries at plaintext offsets.
+    assert_eq!(ctx.seek(handle, 4090, 0).unwrap(), 4090);
+    let mut buf = [0u8; 20];
+    assert_eq!(ctx.read(handle, &mut buf).unwrap(), 20);
+    assert_eq!(&buf[..], &data[4090..4110]);
+    assert_eq!(ctx.seek(handle, -5, 2).unwrap(), 9995);
+    assert_eq!(ctx.read(handle, &mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], &data[9995..]);
+    assert!(ctx.seek(handle, -1, 0).is_err());
+
+    // Overwriting in the middle leaves the rest intact.
+    ctx.seek(handle, 4000, 0).unwrap();
+    ctx.write(handle, &[0xff; 200]).unwrap();
+    ctx.seek(handle, 0, 0).unwrap();
+    let mut expected = data.clone();
+    expected[4000..4200].fill(0xff);
+    assert_eq!(read_to_end(&ctx, handle), expected);
+    ctx.close(handle).unwrap();
+
+    // Reopening reads the same plaintext.
+    let handle = ctx.open(&secure(&path, FileMode::Read)).unwrap();
+    assert_eq!(ctx.metadata(handle).unwrap().size, 10_000);
+    assert_eq!(read_to_end(&ctx, handle), expected);
+    ctx.close(handle).unwrap();
+}
+
+#[test]
+fn test_append_and_sparse_writes() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("log.enc");
+
+    let handle = ctx.open(&secure(&path, FileMode::Append)).unwrap();
+    ctx.write(handle, b"first ").unwrap();
+    ctx.close(handle).unwrap();
+    let handle = ctx.open(&secure(&path, FileMode::Append)).unwrap();
+    ctx.seek(handle, 0, 0).unwrap();
+    ctx.write(handle, b"second").unwrap();
+    assert_eq!(ctx.metadata(handle).unwrap().size, 12);
+    ctx.close(handle).unwrap();
+
+    // Writing past the end fills the gap with zeros.
+    let handle = ctx.open(&secure(&path, FileMode::ReadWrite)).unwrap();
+    ctx.seek(handle, 5000, 0).unwrap();
+    ctx.write(handle, b"end").unwrap();
+    ctx.seek(handle, 0, 0).unwrap();
+    let contents = read_to_end(&ctx, handle);
+    assert_eq!(contents.len(), 5003);
+    assert_eq!(&contents[..12], b"first second");
+    assert!(contents[12..5000].iter().all(|&b| b == 0));
+    assert_eq!(&contents[5000..], b"end");
+    ctx.close(handle).unwrap();
+}
+
+#[test]
+fn test_write_mode_replaces_contents() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("state.enc");
+
+    let handle = ctx.open(&secure(&path, FileMode::Write)).unwrap();
+    ctx.write(handle, &pattern(10_000)).unwrap();
+    ctx.close(handle).unwrap();
+
+    let handle = ctx.open(&secure(&path, FileMode::Write)).unwrap();
+    ctx.write(handle, b"short").unwrap();
+    ctx.close(handle).unwrap();
+
+    let handle = ctx.open(&secure(&path, FileMode::Read)).unwrap();
+    assert_eq!(ctx.metadata(handle).unwrap().size, 5);
+    assert_eq!(read_to_end(&ctx, handle), b"short");
+    ctx.close(handle).unwrap();
+}
+
+#[test]
+fn test_ciphertext_on_disk() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("secret.enc");
+
+    let handle = ctx.open(&secure(&path, FileMode::ReadWrite)).unwrap();
+    ctx.write(handle, b"attack at dawn").unwrap();
+    let first = fs::read(&path).unwrap();
+    assert_eq!(&first[..4], b"ESEC");
+    assert!(!first.windows(6).any(|w| w == b"attack"));
+
+    // Rewriting the same bytes at the same offset uses a fresh nonce.
+    ctx.seek(handle, 0, 0).unwrap();
+    ctx.write(handle, b"attack at dawn").unwrap();
+    let second = fs::read(&path).unwrap();
+    assert_eq!(first[..HEADER_LEN], second[..HEADER_LEN]);
+    assert_ne!(first[HEADER_LEN + NONCE_LEN..], second[HEADER_LEN + NONCE_LEN..]);
+
+    // Nonces do not depend on anything stored in the file, so rolling the
+    // file back does not make the next write reuse one.
+    fs::write(&path, &first).unwrap();
+    ctx.seek(handle, 0, 0).unwrap();
+    ctx.write(handle, b"attack at dusk").unwrap();
+    let third = fs::read(&path).unwrap();
+    assert_ne!(third[HEADER_LEN..HEADER_LEN + NONCE_LEN], first[HEADER_LEN..HEADER_LEN + NONCE_LEN]);
+    assert_ne!(third[HEADER_LEN..HEADER_LEN + NONCE_LEN], second[HEADER_LEN..HEADER_LEN + NONCE_LEN]);
+    ctx.close(handle).unwrap();
+
+    // Two files with the same contents do not share ciphertext.
+    let other = dir.path().join("other.enc");
+    let handle = ctx.open(&secure(&other, FileMode::Write)).unwrap();
+    ctx.write(handle, b"attack at dawn").unwrap();
+    ctx.close(handle).unwrap();
+    assert_ne!(fs::read(&other).unwrap()[HEADER_LEN..], second[HEADER_LEN..]);
+}
+
+#[test]
+fn test_tampering_is_detected() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("data.enc");
+    let handle = ctx.open(&secure(&path, FileMode::Write)).unwrap();
+    ctx.write(handle, &pattern(3 * CHUNK_SIZE)).unwrap();
+    ctx.close(handle).unwrap();
+    let original = fs::read(&path).unwrap();
+
+    let open = |contents: &[u8]| {
+        fs::write(&path, contents).unwrap();
+        ctx.open(&secure(&path, FileMode::Read)).map(|handle| {
+            let mut buf = vec![0u8; 3 * CHUNK_SIZE];
+            let result = ctx.read(handle, &mut buf);
+            ctx.close(handle).unwrap();
+            result
+        })
+    };
+    assert!(open(&original).unwrap().is_ok());
+
+    // Dropping the last chunk leaves a valid layout without a final chunk.
+    let truncated = &original[..HEADER_LEN + 2 * STRIDE];
+    assert!(matches!(open(truncated), Err(FileError::DecryptionError(_))));
+
+    let mut flipped = original.clone();
+    flipped[HEADER_LEN + 100] ^= 1;
+    assert!(matches!(open(&flipped).unwrap(), Err(FileError::DecryptionError(_))));
+
+    let mut swapped = original.clone();
+    swapped[HEADER_LEN..HEADER_LEN + STRIDE].copy_from_slice(&original[HEADER_LEN + STRIDE..HEADER_LEN + 2 * STRIDE]);
+    assert!(matches!(open(&swapped).unwrap(), Err(FileError::DecryptionError(_))));
+
+    let mut header = original.clone();
+    header[30] ^= 1;
+    assert!(matches!(open(&header), Err(FileError::DecryptionError(_))));
+
+    // An empty or plaintext file is not mistaken for an encrypted one.
+    assert!(matches!(open(b""), Err(FileError::DecryptionError(_))));
+    assert!(matches!(open(b"plain text"), Err(FileError::DecryptionError(_))));
+}
@@ -0,0 +1,174 @@
+use elastic_file::{ContainerOperations, FileContext, FileError, FileMode, MemoryVersionStore};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+const KEY: [u8; 32] = [0x42; 32];
+const JOURNAL: &str = ".elastic-journal";
+const STAGING: &str = ".elastic-staging";
+
+// Must match the journal format: header, ops with length-prefixed fields,
+// then a SHA-256 of everything before it.
+#[cfg_attr(feature = "sev", allow(dead_code))]
+enum Op<'a> {
+    Put(&'a str, &'a str),
+    Delete(&'a str),
+}
+
+/// Containers need a version store; an in-memory one is enough within a test.
+fn context() -> FileContext {
+    FileContext::with_version_store(Arc::new(MemoryVersionStore::default()))
+}
+
+fn journal(ops: &[Op]) -> Vec<u8> {
+    let mut data = b"EJNL\x01\0\0\0".to_vec();
+    data.extend_from_slice(&(ops.len() as u32).to_be_bytes());
+    let field = |data: &mut Vec<u8>, value: &str| {
+        data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value.as_bytes());
+    };
+    for op in ops {
+        match op {
+            Op::Put(name, staged) => {
+                data.push(1);
+                field(&mut data, name);
+                field(&mut data, staged);
+            }
+            Op::Delete(name) => {
+                data.push(2);
+                field(&mut data, name);
+            }
+        }
+    }
+    let checksum = Sha256::digest(&data);
+    data.extend_from_slice(&checksum);
+    data
+}
+
+fn stage(root: &Path, staged: &str, data: &[u8]) {
+    fs::create_dir_all(root.join(STAGING)).unwrap();
+    fs::write(root.join(STAGING).join(staged), data).unwrap();
+}
+
+fn entries(root: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_transactions() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "old", b"old").unwrap();
+    ctx.write_file(handle, "moving", b"moving").unwrap();
+
+    // Staged changes are invisible until they are committed together.
+    ctx.begin_transaction(handle).unwrap();
+    ctx.write_file(handle, "a", b"draft").unwrap();
+    ctx.write_file(handle, "a", b"first").unwrap();
+    ctx.write_file(handle, "dir/b", b"second").unwrap();
+    ctx.delete_file(handle, "old").unwrap();
+    ctx.rename_file(handle, "moving", "moved").unwrap();
+    assert!(matches!(ctx.read_file(handle, "a"), Err(FileError::NotFound)));
+    assert_eq!(ctx.read_file(handle, "old").unwrap(), b"old");
+    assert!(matches!(ctx.delete_file(handle, "a"), Err(FileError::InvalidOperation(_))));
+    assert!(matches!(ctx.write_file(handle, "moved", b"x"), Err(FileError::InvalidOperation(_))));
+    assert!(matches!(ctx.begin_transaction(handle), Err(FileError::InvalidOperation(_))));
+    ctx.commit_transaction(handle).unwrap();
+
+    assert_eq!(ctx.read_file(handle, "a").unwrap(), b"first");
+    assert_eq!(ctx.read_file(handle, "dir/b").unwrap(), b"second");
+    assert_eq!(ctx.read_file(handle, "moved").unwrap(), b"moving");
+    assert!(matches!(ctx.read_file(handle, "old"), Err(FileError::NotFound)));
+    assert!(matches!(ctx.commit_transaction(handle), Err(FileError::InvalidOperation(_))));
+
+    // Aborting, or closing with a transaction open, drops staged changes.
+    ctx.begin_transaction(handle).unwrap();
+    ctx.write_file(handle, "a", b"discarded").unwrap();
+    ctx.delete_file(handle, "dir/b").unwrap();
+    ctx.abort_transaction(handle).unwrap();
+    assert_eq!(ctx.read_file(handle, "a").unwrap(), b"first");
+    ctx.begin_transaction(handle).unwrap();
+    ctx.write_file(handle, "c", b"discarded").unwrap();
+    ctx.close_container(handle).unwrap();
+
+    let handle = ctx.open_container(dir.path(), FileMode::Read).unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    assert_eq!(ctx.list_files(handle, "").unwrap(), vec!["a", "dir", "moved"]);
+    assert!(matches!(ctx.begin_transaction(handle), Err(FileError::PermissionDenied(_))));
+    assert!(!entries(dir.path()).iter().any(|name| name == JOURNAL || name == STAGING));
+}
+
+// The journal below stages plaintext objects, which SEV never writes.
+#[test]
+#[cfg(not(feature = "sev"))]
+fn test_recovery_finishes_committed_changes() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let handle = ctx.open_container(dir.path(), FileMode::ReadWrite).unwrap();
+    ctx.write_file(handle, "old", b"old").unwrap();
+    ctx.write_file(handle, "kept", b"kept").unwrap();
+    ctx.close_container(handle).unwrap();
+
+    // A crash after the journal was written but before it was applied.
+    stage(dir.path(), "0011", b"new contents");
+    stage(dir.path(), "0022", b"replaced");
+    fs::write(
+        dir.path().join(JOURNAL),
+        journal(&[Op::Put("dir/new", "0011"), Op::Put("kept", "0022"), Op::Delete("old")]),
+    )
+    .unwrap();
+    // Applying part of it before the crash does not matter.
+    fs::create_dir(dir.path().join("dir")).unwrap();
+    fs::rename(dir.path().join(STAGING).join("0011"), dir.path().join("dir/new")).unwrap();
+
+    let handle = ctx.open_container(dir.path(), FileMode::Read).unwrap();
+    assert_eq!(ctx.read_file(handle, "dir/new").unwrap(), b"new contents");
+    assert_eq!(ctx.read_file(handle, "kept").unwrap(), b"replaced");
+    assert!(matches!(ctx.read_file(handle, "old"), Err(FileError::NotFound)));
+    assert_eq!(entries(dir.path()), vec!["dir", "kept"]);
+}
+
+#[test]
+fn test_recovery_drops_uncommitted_changes() {
+    let ctx = context();
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("store");
+    let handle = ctx.open_container(&root, FileMode::ReadWrite).unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    ctx.write_file(handle, "object", b"committed").unwrap();
+    ctx.close_container(handle).unwrap();
+
+    // A crash while staging, or while writing the journal.
+    stage(&root, "0033", b"half-written");
+    fs::write(root.join(format!("{}.tmp", JOURNAL)), b"EJNL").unwrap();
+    let handle = ctx.open_container(&root, FileMode::ReadWrite).unwrap();
+    ctx.load_key(handle, &KEY).unwrap();
+    assert_eq!(ctx.read_file(handle, "object").unwrap(), b"committed");
+    assert_eq!(entries(&root), vec![".elastic-manifest", "object"]);
+    ctx.close_container(handle).unwrap();
+
+    // A journal that does not match its checksum, or that reaches outside
+    // the container, is refused without applying anything.
+    let mut corrupt = journal(&[Op::Delete("object")]);
+    corrupt[14] ^= 1;
+    fs::write(root.join(JOURNAL), corrupt).unwrap();
+    assert!(matches!(ctx.open_container(&root, FileMode::Read), Err(FileError::IntegrityError(_))));
+
+    let outside = dir.path().join("outside");
+    fs::write(&outside, b"x").unwrap();
+    for name in ["../outside", "/etc/passwd", ".elastic-manifest"] {
+        fs::write(root.join(JOURNAL), journal(&[Op::Delete(name)])).unwrap();
+        assert!(matches!(ctx.open_container(&root, FileMode::Read), Err(FileError::IntegrityError(_))));
+    }
+    assert!(outside.exists());
+    assert!(root.join(".elastic-manifest").exists());
+}
@@ -0,0 +1,177 @@
+use crate::protocol::{self, Channel, Reply, Request, Response};
+use crate::Result;
+use elastic_crypto::{CryptoBackend, Error, HpkeAead, KeyAlgorithm, KeyConfig};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// [`CryptoBackend`] that forwards every operation to an `elastic-keyd`
+/// server. Handles refer to keys in the server's store.
+///
+/// Requests on one client are serialised over a single connection; open
+/// several clients for parallel work.
+pub struct KeydClient {
+    channel: Mutex<Channel>,
+}
+
+impl KeydClient {
+    /// Connects to the server socket at `path` and authenticates with
+    /// `auth_key`.
+    pub fn connect(path: impl AsRef<Path>, auth_key: &[u8]) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        Self::from_stream(stream, auth_key)
+    }
+
+    /// Authenticates over an already connected socket.
+    pub fn from_stream(stream: UnixStream, auth_key: &[u8]) -> Result<Self> {
+        let channel = protocol::client_handshake(stream, auth_key)?;
+        Ok(Self {
+            channel: Mutex::new(channel),
+        })
+    }
+
+    fn call(&self, request: Request) -> elastic_crypto::Result<Reply> {
+        let mut channel = self
+            .channel
+            .lock()
+            .map_err(|e| Error::KeyServer(e.to_string()))?;
+        channel.send(&request)?;
+        let response: Response = channel.receive()?;
+        response
+    }
+
+    fn call_handle(&self, request: Request) -> elastic_crypto::Result<u32> {
+        match self.call(request)? {
+            Reply::Handle(handle) => Ok(handle),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn call_data(&self, request: Request) -> elastic_crypto::Result<Vec<u8>> {
+        match self.call(request)? {
+            Reply::Data(data) => Ok(data),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn call_valid(&self, request: Request) -> elastic_crypto::Result<bool> {
+        match self.call(request)? {
+            Reply::Valid(valid) => Ok(valid),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+fn unexpected(reply: Reply) -> Error {
+    Error::KeyServer(format!("unexpected reply {:?}", reply))
+}
+
+impl CryptoBackend for KeydClient {
+    fn generate_key(&self, config: KeyConfig) -> elastic_crypto::Result<u32> {
+        self.call_handle(Request::GenerateKey { config })
+    }
+
+    fn import_key(&self, key_data: Vec<u8>, config: KeyConfig) -> elastic_crypto::Result<u32> {
+        self.call_handle(Request::ImportKey { key_data, config })
+    }
+
+    fn export_key(&self, handle: u32) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::ExportKey { handle })
+    }
+
+    fn delete_key(&self, handle: u32) -> elastic_crypto::Result<()> {
+        match self.call(Request::DeleteKey { handle })? {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn key_algorithm(&self, handle: u32) -> elastic_crypto::Result<KeyAlgorithm> {
+        match self.call(Request::KeyAlgorithm { handle })? {
+            Reply::Algorithm(algorithm) => Ok(algorithm),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn get_public_key(&self, handle: u32) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::GetPublicKey { handle })
+    }
+
+    fn get_key_id(&self, handle: u32) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::GetKeyId { handle })
+    }
+
+    fn encrypt(&self, handle: u32, data: Vec<u8>) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::Encrypt { handle, data })
+    }
+
+    fn decrypt(&self, handle: u32, data: Vec<u8>) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::Decrypt { handle, data })
+    }
+
+    fn encrypt_siv(
+        &self,
+        handle: u32,
+        plaintext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::EncryptSiv { handle, plaintext, associated_data, nonce })
+    }
+
+    fn decrypt_siv(
+        &self,
+        handle: u32,
+        ciphertext: Vec<u8>,
+        associated_data: Vec<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::DecryptSiv { handle, ciphertext, associated_data, nonce })
+    }
+
+    fn sign(&self, handle: u32, data: Vec<u8>) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::Sign { handle, data })
+    }
+
+    fn verify(&self, handle: u32, data: Vec<u8>, signature: Vec<u8>) -> elastic_crypto::Result<bool> {
+        self.call_valid(Request::Verify { handle, data, signature })
+    }
+
+    fn calculate_mac(&self, handle: u32, data: Vec<u8>) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::CalculateMac { handle, data })
+    }
+
+    fn verify_mac(&self, handle: u32, data: Vec<u8>, mac: Vec<u8>) -> elastic_crypto::Result<bool> {
+        self.call_valid(Request::VerifyMac { handle, data, mac })
+    }
+
+    fn hpke_open(
+        &self,
+        handle: u32,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::HpkeOpen { handle, sender_public_key: None, sealed, info, aad, aead })
+    }
+
+    fn hpke_open_auth(
+        &self,
+        handle: u32,
+        sender_public_key: Vec<u8>,
+        sealed: Vec<u8>,
+        info: Vec<u8>,
+        aad: Vec<u8>,
+        aead: HpkeAead,
+    ) -> elastic_crypto::Result<Vec<u8>> {
+        self.call_data(Request::HpkeOpen {
+            handle,
+            sender_public_key: Some(sender_public_key),
+            sealed,
+            info,
+            aad,
+            aead,
+        })
+    }
+}
@@ -0,0 +1,47 @@
+//! Key server that keeps key material out of workload memory.
+//!
+//! `elastic-keyd` owns an [`ElasticCrypto`](elastic_crypto::ElasticCrypto) key
+//! store and performs operations for clients connected over a Unix domain
+//! socket. [`KeydClient`] implements [`CryptoBackend`], so a workload written
+//! against the trait can switch from in-process keys to the daemon without
+//! other changes.
+//!
+//! Keys created or imported through the server are always held in secure
+//! storage and cannot be exported. A compromised or buggy workload can still
+//! use its keys for as long as it can reach the socket, but cannot read them,
+//! and keys it never held stay out of its crash dumps and swap.
+
+use thiserror::Error;
+
+mod client;
+pub mod protocol;
+mod server;
+
+pub use client::KeydClient;
+pub use elastic_crypto::CryptoBackend;
+pub use server::{KeyServer, MAX_CONNECTIONS};
+
+#[derive(Error, Debug)]
+pub enum KeydError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] elastic_crypto::Error),
+    #[error("Invalid authentication key: {0}")]
+    InvalidAuthKey(String),
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+}
+
+pub type Result<T> = std::result::Result<T, KeydError>;
+
+impl From<KeydError> for elastic_crypto::Error {
+    fn from(error: KeydError) -> Self {
+        match error {
+            KeydError::Crypto(error) => error,
+            error => elastic_crypto::Error::KeyServer(error.to_string()),
+        }
+    }
+}
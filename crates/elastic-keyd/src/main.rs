@@ -0,0 +1,34 @@
+//! `elastic-keyd <socket-path> <auth-key-file>`
+//!
+//! Runs the key server on a Unix domain socket. Clients must hold the
+//! contents of the authentication key file, which should be at least 32
+//! random bytes readable only by the daemon and the workloads it serves.
+
+use elastic_crypto::ElasticCrypto;
+use elastic_keyd::KeyServer;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::{env, fs};
+
+fn run(socket_path: &str, auth_key_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let auth_key = fs::read(auth_key_path)?;
+    let server = KeyServer::new(ElasticCrypto::new()?, auth_key)?;
+    let listener = KeyServer::bind(socket_path)?;
+    println!("elastic-keyd listening on {}", socket_path);
+    Arc::new(server).serve(listener)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: elastic-keyd <socket-path> <auth-key-file>");
+        return ExitCode::FAILURE;
+    }
+    match run(&args[1], &args[2]) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("elastic-keyd: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Wire protocol between `elastic-keyd` and its clients.
+//!
+//! Every message is a frame: a 4-byte big-endian length followed by that many
+//! bytes. A connection opens with a mutual challenge-response over a
+//! pre-shared authentication key:
+//!
+//! 1. server to client: protocol version byte and a 32-byte server nonce;
+//! 2. client to server: a 32-byte client nonce and
+//!    `HMAC(auth_key, "elastic-keyd client" || server_nonce || client_nonce)`;
+//! 3. server to client: the same HMAC with the `"elastic-keyd server"` label.
+//!
+//! Both sides then derive one session key per direction from the
+//! authentication key and both nonces. Every later frame is a 64-bit
+//! sequence number, a JSON [`Request`] or [`Response`] and an HMAC-SHA256 tag
+//! over the two. A frame with a bad tag or an unexpected sequence number ends
+//! the connection, so frames cannot be injected, altered, reordered or
+//! replayed, including from another connection.
+
+use crate::{KeydError, Result};
+use elastic_crypto::{ElasticRng, HpkeAead, KeyAlgorithm, KeyConfig};
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Shortest accepted authentication key.
+pub const MIN_AUTH_KEY_LEN: usize = 32;
+
+/// Largest frame either side will read.
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+
+const NONCE_LEN: usize = 32;
+const TAG_LEN: usize = 32;
+const SEQUENCE_LEN: usize = 8;
+
+const CLIENT_PROOF_LABEL: &[u8] = b"elastic-keyd client";
+const SERVER_PROOF_LABEL: &[u8] = b"elastic-keyd server";
+const CLIENT_KEY_LABEL: &[u8] = b"elastic-keyd client to server";
+const SERVER_KEY_LABEL: &[u8] = b"elastic-keyd server to client";
+
+/// Operation requested by a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    GenerateKey { config: KeyConfig },
+    ImportKey { key_data: Vec<u8>, config: KeyConfig },
+    ExportKey { handle: u32 },
+    DeleteKey { handle: u32 },
+    KeyAlgorithm { handle: u32 },
+    GetPublicKey { handle: u32 },
+    GetKeyId { handle: u32 },
+    Encrypt { handle: u32, data: Vec<u8> },
+    Decrypt { handle: u32, data: Vec<u8> },
+    EncryptSiv { handle: u32, plaintext: Vec<u8>, associated_data: Vec<Vec<u8>>, nonce: Option<Vec<u8>> },
+    DecryptSiv { handle: u32, ciphertext: Vec<u8>, associated_data: Vec<Vec<u8>>, nonce: Option<Vec<u8>> },
+    Sign { handle: u32, data: Vec<u8> },
+    Verify { handle: u32, data: Vec<u8>, signature: Vec<u8> },
+    CalculateMac { handle: u32, data: Vec<u8> },
+    VerifyMac { handle: u32, data: Vec<u8>, mac: Vec<u8> },
+    HpkeOpen { handle: u32, sender_public_key: Option<Vec<u8>>, sealed: Vec<u8>, info: Vec<u8>, aad: Vec<u8>, aead: HpkeAead },
+}
+
+/// Successful result of a [`Request`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Reply {
+    Handle(u32),
+    Data(Vec<u8>),
+    Valid(bool),
+    Algorithm(KeyAlgorithm),
+    Done,
+}
+
+/// Server answer to a [`Request`]; errors are returned as the key store
+/// reported them.
+pub type Response = std::result::Result<Reply, elastic_crypto::Error>;
+
+/// Rejects authentication keys too short to resist guessing.
+pub fn check_auth_key(auth_key: &[u8]) -> Result<()> {
+    if auth_key.len() < MIN_AUTH_KEY_LEN {
+        return Err(KeydError::InvalidAuthKey(format!(
+            "must be at least {} bytes",
+            MIN_AUTH_KEY_LEN
+        )));
+    }
+    Ok(())
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+fn tag(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    hmac(key, parts).finalize().into_bytes().to_vec()
+}
+
+fn check_tag(key: &[u8], parts: &[&[u8]], tag: &[u8]) -> Result<()> {
+    hmac(key, parts)
+        .verify_slice(tag)
+        .map_err(|_| KeydError::AuthenticationFailed)
+}
+
+fn nonce() -> Result<[u8; NONCE_LEN]> {
+    let mut nonce = [0u8; NONCE_LEN];
+    ElasticRng::new()?.fill(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Writes one length-prefixed frame.
+pub fn write_frame(stream: &mut impl Write, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(KeydError::Protocol(format!("frame of {} bytes is too large", payload.len())));
+    }
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads one length-prefixed frame.
+pub fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>> {
+    read_frame_within(stream, MAX_FRAME_LEN)
+}
+
+/// Reads one length-prefixed frame of at most `max_len` bytes.
+fn read_frame_within(stream: &mut impl Read, max_len: usize) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(KeydError::Protocol(format!("frame of {} bytes is too large", len)));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Whether an error means the peer closed the connection between frames.
+pub fn is_disconnect(error: &KeydError) -> bool {
+    matches!(error, KeydError::Io(e) if e.kind() == ErrorKind::UnexpectedEof)
+}
+
+/// Authenticated connection after a completed handshake.
+pub struct Channel {
+    stream: UnixStream,
+    send_key: Vec<u8>,
+    receive_key: Vec<u8>,
+    send_sequence: u64,
+    receive_sequence: u64,
+}
+
+impl Channel {
+    fn new(stream: UnixStream, auth_key: &[u8], nonces: &[u8], is_client: bool) -> Self {
+        let client_key = tag(auth_key, &[CLIENT_KEY_LABEL, nonces]);
+        let server_key = tag(auth_key, &[SERVER_KEY_LABEL, nonces]);
+        let (send_key, receive_key) = if is_client {
+            (client_key, server_key)
+        } else {
+            (server_key, client_key)
+        };
+        Self {
+            stream,
+            send_key,
+            receive_key,
+            send_sequence: 0,
+            receive_sequence: 0,
+        }
+    }
+
+    /// Sends one message as the next authenticated frame.
+    pub fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        let body = serde_json::to_vec(message).map_err(|e| KeydError::Protocol(e.to_string()))?;
+        let sequence = self.send_sequence.to_be_bytes();
+        let tag = tag(&self.send_key, &[&sequence, &body]);
+        let payload = [&sequence[..], &body, &tag].concat();
+        write_frame(&mut self.stream, &payload)?;
+        self.send_sequence += 1;
+        Ok(())
+    }
+
+    /// Receives the next frame, checking its tag and sequence number.
+    pub fn receive<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let payload = read_frame(&mut self.stream)?;
+        if payload.len() < SEQUENCE_LEN + TAG_LEN {
+            return Err(KeydError::Protocol("truncated frame".to_string()));
+        }
+        let (sequence, rest) = payload.split_at(SEQUENCE_LEN);
+        let (body, frame_tag) = rest.split_at(rest.len() - TAG_LEN);
+        check_tag(&self.receive_key, &[sequence, body], frame_tag)?;
+        let sequence = u64::from_be_bytes(sequence.try_into().expect("sequence is 8 bytes"));
+        if sequence != self.receive_sequence {
+            return Err(KeydError::AuthenticationFailed);
+        }
+        self.receive_sequence += 1;
+        serde_json::from_slice(body).map_err(|e| KeydError::Protocol(e.to_string()))
+    }
+
+    /// Underlying socket, e.g. to set timeouts.
+    pub fn get_mut(&mut self) -> &mut UnixStream {
+        &mut self.stream
+    }
+}
+
+/// Runs the client side of the handshake.
+pub fn client_handshake(mut stream: UnixStream, auth_key: &[u8]) -> Result<Channel> {
+    check_auth_key(auth_key)?;
+    let hello = read_frame(&mut stream)?;
+    if hello.len() != 1 + NONCE_LEN {
+        return Err(KeydError::Protocol("malformed server hello".to_string()));
+    }
+    if hello[0] != PROTOCOL_VERSION {
+        return Err(KeydError::Protocol(format!("unsupported protocol version {}", hello[0])));
+    }
+    let server_nonce = &hello[1..];
+    let client_nonce = nonce()?;
+    let nonces = [server_nonce, &client_nonce[..]].concat();
+
+    let proof = tag(auth_key, &[CLIENT_PROOF_LABEL, &nonces]);
+    write_frame(&mut stream, &[&client_nonce[..], &proof].concat())?;
+
+    // The server closes the connection instead of answering a bad proof.
+    let server_proof = match read_frame(&mut stream) {
+        Err(e) if is_disconnect(&e) => return Err(KeydError::AuthenticationFailed),
+        result => result?,
+    };
+    check_tag(auth_key, &[SERVER_PROOF_LABEL, &nonces], &server_proof)?;
+    Ok(Channel::new(stream, auth_key, &nonces, true))
+}
+
+/// Runs the server side of the handshake.
+pub fn server_handshake(mut stream: UnixStream, auth_key: &[u8]) -> Result<Channel> {
+    check_auth_key(auth_key)?;
+    let server_nonce = nonce()?;
+    write_frame(&mut stream, &[&[PROTOCOL_VERSION][..], &server_nonce].concat())?;
+
+    // The client is not authenticated yet, so it only gets to send a proof.
+    let answer = read_frame_within(&mut stream, NONCE_LEN + TAG_LEN)?;
+    if answer.len() != NONCE_LEN + TAG_LEN {
+        return Err(KeydError::Protocol("malformed client proof".to_string()));
+    }
+    let (client_nonce, proof) = answer.split_at(NONCE_LEN);
+    let nonces = [&server_nonce[..], client_nonce].concat();
+    check_tag(auth_key, &[CLIENT_PROOF_LABEL, &nonces], proof)?;
+
+    write_frame(&mut stream, &tag(auth_key, &[SERVER_PROOF_LABEL, &nonces]))?;
+    Ok(Channel::new(stream, auth_key, &nonces, false))
+}
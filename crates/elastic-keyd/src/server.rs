@@ -0,0 +1,171 @@
+use crate::protocol::{self, Reply, Request, Response};
+use crate::Result;
+use elastic_crypto::{ElasticCrypto, KeyConfig};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Connections served at once unless set with
+/// [`KeyServer::with_max_connections`].
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// Pause after a failed accept, so that running out of file descriptors
+/// does not turn the accept loop into a busy loop.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Serves key store operations to authenticated clients.
+pub struct KeyServer {
+    crypto: ElasticCrypto,
+    auth_key: Vec<u8>,
+    max_connections: usize,
+    connections: AtomicUsize,
+}
+
+impl KeyServer {
+    /// Creates a server that accepts clients holding `auth_key`.
+    pub fn new(crypto: ElasticCrypto, auth_key: Vec<u8>) -> Result<Self> {
+        protocol::check_auth_key(&auth_key)?;
+        Ok(Self {
+            crypto,
+            auth_key,
+            max_connections: MAX_CONNECTIONS,
+            connections: AtomicUsize::new(0),
+        })
+    }
+
+    /// Limits how many connections `serve` handles at once. Connections
+    /// beyond the limit are closed as soon as they are accepted.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Binds the socket at `path`, replacing a stale one, readable and
+    /// writable by the owner only.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<UnixListener> {
+        let path = path.as_ref();
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    /// Accepts connections forever, serving each on its own thread. Failed
+    /// accepts are logged and retried.
+    pub fn serve(self: Arc<Self>, listener: UnixListener) -> ! {
+        loop {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("elastic-keyd: accept failed: {}", e);
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            let Some(slot) = Slot::take(&self) else {
+                eprintln!("elastic-keyd: refusing connection, {} already open", self.max_connections);
+                continue;
+            };
+            thread::spawn(move || {
+                if let Err(e) = slot.server.handle_connection(stream) {
+                    eprintln!("elastic-keyd: connection closed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Authenticates one client and answers its requests until it
+    /// disconnects or sends a frame that fails authentication.
+    pub fn handle_connection(&self, stream: UnixStream) -> Result<()> {
+        let mut channel = protocol::server_handshake(stream, &self.auth_key)?;
+        loop {
+            let request: Request = match channel.receive() {
+                Err(e) if protocol::is_disconnect(&e) => return Ok(()),
+                result => result?,
+            };
+            let response: Response = self.dispatch(request);
+            channel.send(&response)?;
+        }
+    }
+
+    fn dispatch(&self, request: Request) -> Response {
+        let crypto = &self.crypto;
+        Ok(match request {
+            Request::GenerateKey { config } => Reply::Handle(crypto.generate_key(secure(config))?),
+            Request::ImportKey { key_data, config } => {
+                Reply::Handle(crypto.import_key(key_data, secure(config))?)
+            }
+            Request::ExportKey { handle } => Reply::Data(crypto.export_key(handle)?),
+            Request::DeleteKey { handle } => {
+                crypto.delete_key(handle)?;
+                Reply::Done
+            }
+            Request::KeyAlgorithm { handle } => Reply::Algorithm(crypto.key_algorithm(handle)?),
+            Request::GetPublicKey { handle } => Reply::Data(crypto.get_public_key(handle)?),
+            Request::GetKeyId { handle } => Reply::Data(crypto.get_key_id(handle)?),
+            Request::Encrypt { handle, data } => Reply::Data(crypto.encrypt(handle, data)?),
+            Request::Decrypt { handle, data } => Reply::Data(crypto.decrypt(handle, data)?),
+            Request::EncryptSiv { handle, plaintext, associated_data, nonce } => {
+                Reply::Data(crypto.encrypt_siv(handle, plaintext, associated_data, nonce)?)
+            }
+            Request::DecryptSiv { handle, ciphertext, associated_data, nonce } => {
+                Reply::Data(crypto.decrypt_siv(handle, ciphertext, associated_data, nonce)?)
+            }
+            Request::Sign { handle, data } => Reply::Data(crypto.sign(handle, data)?),
+            Request::Verify { handle, data, signature } => {
+                Reply::Valid(crypto.verify(handle, data, signature)?)
+            }
+            Request::CalculateMac { handle, data } => Reply::Data(crypto.calculate_mac(handle, data)?),
+            Request::VerifyMac { handle, data, mac } => Reply::Valid(crypto.verify_mac(handle, data, mac)?),
+            Request::HpkeOpen { handle, sender_public_key, sealed, info, aad, aead } => {
+                Reply::Data(match sender_public_key {
+                    Some(sender) => crypto.hpke_open_auth(handle, sender, sealed, info, aad, aead)?,
+                    None => crypto.hpke_open(handle, sealed, info, aad, aead)?,
+                })
+            }
+        })
+    }
+}
+
+/// One of the connections counted against `max_connections`, given back
+/// when the connection ends.
+struct Slot {
+    server: Arc<KeyServer>,
+}
+
+impl Slot {
+    fn take(server: &Arc<KeyServer>) -> Option<Self> {
+        server
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < server.max_connections).then_some(open + 1)
+            })
+            .ok()?;
+        Some(Self {
+            server: Arc::clone(server),
+        })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.server.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Keys held by the daemon never leave it, whatever the client asked for.
+fn secure(config: KeyConfig) -> KeyConfig {
+    KeyConfig {
+        secure_storage: true,
+        ..config
+    }
+}
@@ -0,0 +1,203 @@
+use elastic_crypto::{CryptoBackend, ElasticCrypto, Error, HpkeAead, KeyAlgorithm, KeyConfig, KeyType};
+use elastic_keyd::protocol::{self, Request};
+use elastic_keyd::{KeyServer, KeydClient, KeydError};
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const AUTH_KEY: [u8; 32] = [7u8; 32];
+
+fn start_server() -> (TempDir, PathBuf) {
+    serve(KeyServer::new(ElasticCrypto::new().unwrap(), AUTH_KEY.to_vec()).unwrap())
+}
+
+fn serve(server: KeyServer) -> (TempDir, PathBuf) {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("keyd.sock");
+    let listener = KeyServer::bind(&path).unwrap();
+    let server = Arc::new(server);
+    thread::spawn(move || server.serve(listener));
+    (dir, path)
+}
+
+fn config(key_type: KeyType, algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        key_type,
+        key_size: 256,
+        secure_storage: false,
+        algorithm: Some(algorithm),
+    }
+}
+
+/// Workload code written once against the trait.
+fn seal_and_sign<B: CryptoBackend>(backend: &B) -> (Vec<u8>, Vec<u8>) {
+    let aes = backend.generate_key(KeyConfig::default()).unwrap();
+    let ciphertext = backend.encrypt(aes, b"payload".to_vec()).unwrap();
+    assert_eq!(backend.decrypt(aes, ciphertext.clone()).unwrap(), b"payload");
+
+    let signing = backend
+        .generate_key(config(KeyType::Asymmetric, KeyAlgorithm::Ed25519))
+        .unwrap();
+    let signature = backend.sign(signing, b"payload".to_vec()).unwrap();
+    assert!(backend.verify(signing, b"payload".to_vec(), signature.clone()).unwrap());
+    assert!(!backend.verify(signing, b"other".to_vec(), signature).unwrap());
+    assert_eq!(backend.key_algorithm(signing).unwrap(), KeyAlgorithm::Ed25519);
+    assert_eq!(backend.get_public_key(signing).unwrap().len(), 32);
+
+    let mac_key = backend.generate_key(config(KeyType::Hmac, KeyAlgorithm::HmacSha256)).unwrap();
+    let mac = backend.calculate_mac(mac_key, b"payload".to_vec()).unwrap();
+    assert!(backend.verify_mac(mac_key, b"payload".to_vec(), mac).unwrap());
+
+    let siv = backend
+        .generate_key(config(KeyType::Symmetric, KeyAlgorithm::AesSiv))
+        .unwrap();
+    let ad = vec![b"users.email".to_vec()];
+    let token = backend.encrypt_siv(siv, b"a@example.com".to_vec(), ad.clone(), None).unwrap();
+    assert_eq!(backend.decrypt_siv(siv, token, ad, None).unwrap(), b"a@example.com");
+
+    backend.delete_key(aes).unwrap();
+    assert!(matches!(backend.encrypt(aes, b"payload".to_vec()), Err(Error::KeyNotFound)));
+    (ciphertext, backend.get_key_id(signing).unwrap())
+}
+
+#[test]
+fn test_same_api_in_process_and_remote() {
+    let (_dir, path) = start_server();
+    let client = KeydClient::connect(&path, &AUTH_KEY).unwrap();
+    let local = ElasticCrypto::new().unwrap();
+
+    let (remote_ciphertext, remote_kid) = seal_and_sign(&client);
+    let (local_ciphertext, local_kid) = seal_and_sign(&local);
+    // Keys and nonces are random, so only the ciphertext layout matches.
+    assert_ne!(remote_ciphertext, local_ciphertext);
+    assert_eq!(remote_ciphertext.len(), local_ciphertext.len());
+    assert_eq!(remote_kid.len(), local_kid.len());
+}
+
+#[test]
+fn test_keys_never_leave_the_daemon() {
+    let (_dir, path) = start_server();
+    let client = KeydClient::connect(&path, &AUTH_KEY).unwrap();
+
+    // Requests for exportable keys are upgraded to secure storage.
+    let generated = client.generate_key(KeyConfig::default()).unwrap();
+    assert!(matches!(client.export_key(generated), Err(Error::OperationNotPermitted)));
+    let imported = client
+        .import_key(vec![0x11; 32], config(KeyType::Hmac, KeyAlgorithm::HmacSha256))
+        .unwrap();
+    assert!(matches!(client.export_key(imported), Err(Error::OperationNotPermitted)));
+
+    // Handles belong to the daemon's store, not to the connection.
+    let other = KeydClient::connect(&path, &AUTH_KEY).unwrap();
+    let mac = client.calculate_mac(imported, b"data".to_vec()).unwrap();
+    assert!(other.verify_mac(imported, b"data".to_vec(), mac).unwrap());
+}
+
+#[test]
+fn test_hpke_open_in_daemon() {
+    let (_dir, path) = start_server();
+    let client = KeydClient::connect(&path, &AUTH_KEY).unwrap();
+    let sender = ElasticCrypto::new().unwrap();
+
+    let workload = client
+        .generate_key(config(KeyType::Asymmetric, KeyAlgorithm::X25519))
+        .unwrap();
+    let public_key = client.get_public_key(workload).unwrap();
+    let aead = HpkeAead::ChaCha20Poly1305;
+    let sealed = sender
+        .hpke_seal(public_key, b"info".to_vec(), Vec::new(), b"secret".to_vec(), aead)
+        .unwrap();
+    let opened = client
+        .hpke_open(workload, sealed.clone(), b"info".to_vec(), Vec::new(), aead)
+        .unwrap();
+    assert_eq!(opened, b"secret");
+
+    // Errors come back as the key store reported them.
+    assert!(matches!(
+        client.hpke_open(workload, sealed, b"other".to_vec(), Vec::new(), aead),
+        Err(Error::DecryptionFailed)
+    ));
+    assert!(matches!(client.get_public_key(9999), Err(Error::KeyNotFound)));
+}
+
+#[test]
+fn test_authentication() {
+    let (_dir, path) = start_server();
+    assert!(matches!(
+        KeydClient::connect(&path, &[8u8; 32]),
+        Err(KeydError::AuthenticationFailed)
+    ));
+    assert!(matches!(
+        KeydClient::connect(&path, &AUTH_KEY[..16]),
+        Err(KeydError::InvalidAuthKey(_))
+    ));
+    assert!(matches!(
+        KeyServer::new(ElasticCrypto::new().unwrap(), vec![0u8; 16]),
+        Err(KeydError::InvalidAuthKey(_))
+    ));
+
+    // The server still serves authenticated clients afterwards.
+    let client = KeydClient::connect(&path, &AUTH_KEY).unwrap();
+    client.generate_key(KeyConfig::default()).unwrap();
+}
+
+#[test]
+fn test_forged_frame_closes_connection() {
+    let server = KeyServer::new(ElasticCrypto::new().unwrap(), AUTH_KEY.to_vec()).unwrap();
+    let (server_end, client_end) = UnixStream::pair().unwrap();
+    let worker = thread::spawn(move || server.handle_connection(server_end));
+
+    let mut channel = protocol::client_handshake(client_end, &AUTH_KEY).unwrap();
+    channel.send(&Request::GenerateKey { config: KeyConfig::default() }).unwrap();
+    let _: protocol::Response = channel.receive().unwrap();
+
+    // A frame with the right sequence number but no valid tag.
+    let mut forged = 1u64.to_be_bytes().to_vec();
+    forged.extend_from_slice(br#"{"ExportKey":{"handle":1}}"#);
+    forged.extend_from_slice(&[0u8; 32]);
+    protocol::write_frame(channel.get_mut(), &forged).unwrap();
+
+    assert!(matches!(worker.join().unwrap(), Err(KeydError::AuthenticationFailed)));
+    let closed: Result<protocol::Response, _> = channel.receive();
+    assert!(matches!(closed, Err(ref e) if protocol::is_disconnect(e)));
+}
+
+#[test]
+fn test_unauthenticated_client_cannot_send_large_frames() {
+    let server = KeyServer::new(ElasticCrypto::new().unwrap(), AUTH_KEY.to_vec()).unwrap();
+    let (server_end, mut client_end) = UnixStream::pair().unwrap();
+    let worker = thread::spawn(move || server.handle_connection(server_end));
+
+    // Announce a frame well within MAX_FRAME_LEN but never send it: the
+    // server refuses it from the length alone instead of waiting.
+    protocol::read_frame(&mut client_end).unwrap();
+    client_end.write_all(&(protocol::MAX_FRAME_LEN as u32).to_be_bytes()).unwrap();
+    client_end.shutdown(Shutdown::Write).unwrap();
+    assert!(matches!(worker.join().unwrap(), Err(KeydError::Protocol(_))));
+}
+
+#[test]
+fn test_connections_are_capped() {
+    let server = KeyServer::new(ElasticCrypto::new().unwrap(), AUTH_KEY.to_vec()).unwrap();
+    let (_dir, path) = serve(server.with_max_connections(1));
+    let first = KeydClient::connect(&path, &AUTH_KEY).unwrap();
+    first.generate_key(KeyConfig::default()).unwrap();
+
+    // A second client is closed before the handshake.
+    assert!(matches!(KeydClient::connect(&path, &AUTH_KEY), Err(ref e) if protocol::is_disconnect(e)));
+
+    // Once the first one leaves, its slot is free again.
+    drop(first);
+    let second = (0..50)
+        .find_map(|_| {
+            thread::sleep(Duration::from_millis(20));
+            KeydClient::connect(&path, &AUTH_KEY).ok()
+        })
+        .unwrap();
+    second.generate_key(KeyConfig::default()).unwrap();
+}
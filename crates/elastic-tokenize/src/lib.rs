@@ -0,0 +1,108 @@
+//! Tokenisation and blind indexes for personal data.
+//!
+//! A [`Tokenizer`] turns a PII value into two pseudonyms, both scoped by a
+//! namespace such as a table column:
+//!
+//! - a blind index, HMAC-SHA256 over the namespace and value, for equality
+//!   lookups without being able to recover the value;
+//! - a token, the value encrypted with deterministic AES-SIV and the namespace
+//!   as associated data, which holders of the token key can reverse.
+//!
+//! Both are stable for a given key, namespace and value, so pseudonymised
+//! records can still be joined. A value tokenised in one namespace cannot be
+//! matched or detokenised in another. [`TokenVault`] keeps issued tokens in an
+//! `elastic-file` container so they can be looked up and revoked.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use elastic_crypto::{ElasticCrypto, KeyAlgorithm};
+use elastic_file::FileError;
+use thiserror::Error;
+
+mod vault;
+
+pub use vault::TokenVault;
+
+/// Prefix that marks a string as a token.
+pub const TOKEN_PREFIX: &str = "tok_";
+
+#[derive(Error, Debug)]
+pub enum TokenError {
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] elastic_crypto::Error),
+    #[error("File error: {0}")]
+    File(#[from] FileError),
+    #[error("Invalid namespace: {0}")]
+    InvalidNamespace(String),
+    #[error("Invalid token")]
+    InvalidToken,
+    #[error("Token not found in vault")]
+    TokenNotFound,
+    #[error("Corrupt token vault: {0}")]
+    CorruptVault(String),
+}
+
+pub type Result<T> = std::result::Result<T, TokenError>;
+
+fn check_namespace(namespace: &str) -> Result<()> {
+    if namespace.is_empty() {
+        return Err(TokenError::InvalidNamespace("namespace must not be empty".to_string()));
+    }
+    Ok(())
+}
+
+/// Derives blind indexes and tokens with keys held in an [`ElasticCrypto`]
+/// context.
+pub struct Tokenizer<'a> {
+    crypto: &'a ElasticCrypto,
+    index_key: u32,
+    token_key: u32,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// `index_key` must be an HMAC-SHA256 key and `token_key` an AES-SIV key.
+    /// Both may be `secure_storage` keys.
+    pub fn new(crypto: &'a ElasticCrypto, index_key: u32, token_key: u32) -> Result<Self> {
+        if crypto.key_algorithm(index_key)? != KeyAlgorithm::HmacSha256
+            || crypto.key_algorithm(token_key)? != KeyAlgorithm::AesSiv
+        {
+            return Err(elastic_crypto::Error::UnsupportedOperation.into());
+        }
+        Ok(Self { crypto, index_key, token_key })
+    }
+
+    /// Blind index of `value` in `namespace`, base64url-encoded.
+    pub fn blind_index(&self, namespace: &str, value: &[u8]) -> Result<String> {
+        check_namespace(namespace)?;
+        // Length-prefix the namespace so ("ab", "c") and ("a", "bc") differ.
+        let mut data = (namespace.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(namespace.as_bytes());
+        data.extend_from_slice(value);
+        let mac = self.crypto.calculate_mac(self.index_key, data)?;
+        Ok(URL_SAFE_NO_PAD.encode(mac))
+    }
+
+    /// Reversible token for `value` in `namespace`.
+    pub fn tokenize(&self, namespace: &str, value: &[u8]) -> Result<String> {
+        check_namespace(namespace)?;
+        let ciphertext = self.crypto.encrypt_siv(
+            self.token_key,
+            value.to_vec(),
+            vec![namespace.as_bytes().to_vec()],
+            None,
+        )?;
+        Ok(format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(ciphertext)))
+    }
+
+    /// Recovers the value behind a token issued for `namespace`.
+    pub fn detokenize(&self, namespace: &str, token: &str) -> Result<Vec<u8>> {
+        check_namespace(namespace)?;
+        let ciphertext = token
+            .strip_prefix(TOKEN_PREFIX)
+            .and_then(|encoded| URL_SAFE_NO_PAD.decode(encoded).ok())
+            .ok_or(TokenError::InvalidToken)?;
+        self.crypto
+            .decrypt_siv(self.token_key, ciphertext, vec![namespace.as_bytes().to_vec()], None)
+            .map_err(|_| TokenError::InvalidToken)
+    }
+}
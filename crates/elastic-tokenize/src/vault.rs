@@ -0,0 +1,160 @@
+use crate::{check_namespace, Result, TokenError, Tokenizer};
+use elastic_file::{ContainerOperations, FileError, FileMode, KeySource};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+const VAULT_VERSION: u8 = 1;
+
+/// Name of the vault object inside its container.
+const VAULT_OBJECT: &str = "tokens.json";
+
+/// Stored vault: tokens by namespace and blind index. It holds no plaintext
+/// values, only the pseudonyms the tokenizer derives.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct VaultFile {
+    version: u8,
+    namespaces: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Persisted record of issued tokens.
+///
+/// Only tokens present in the vault can be detokenised through it, so
+/// [`TokenVault::revoke`] withdraws a value (e.g. for an erasure request)
+/// even from holders of an old token. The vault is one object of an
+/// `elastic-file` container, encrypted under the container key and rewritten
+/// as a whole after every change. Container writes are atomic, so a crash
+/// leaves either the previous or the new vault in place.
+pub struct TokenVault<'a, C: ContainerOperations> {
+    tokenizer: Tokenizer<'a>,
+    containers: &'a C,
+    handle: u32,
+    state: Mutex<VaultFile>,
+}
+
+impl<'a, C: ContainerOperations> TokenVault<'a, C> {
+    /// Opens the vault container at `path` with `key`, starting an empty
+    /// vault if the container does not hold one yet. The container stays
+    /// open until the vault is dropped.
+    pub fn open(
+        tokenizer: Tokenizer<'a>,
+        containers: &'a C,
+        path: impl AsRef<Path>,
+        key: KeySource<'_>,
+    ) -> Result<Self> {
+        let handle = containers.open_container(path.as_ref(), FileMode::ReadWrite)?;
+        let state = containers
+            .load_container_key(handle, key)
+            .map_err(TokenError::from)
+            .and_then(|_| load(containers, handle));
+        match state {
+            Ok(state) => Ok(Self {
+                tokenizer,
+                containers,
+                handle,
+                state: Mutex::new(state),
+            }),
+            Err(e) => {
+                let _ = containers.close_container(handle);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the token for `value`, issuing and recording one if needed.
+    pub fn tokenize(&self, namespace: &str, value: &[u8]) -> Result<String> {
+        let index = self.tokenizer.blind_index(namespace, value)?;
+        let mut state = self.lock()?;
+        if let Some(token) = state.namespaces.get(namespace).and_then(|n| n.get(&index)) {
+            return Ok(token.clone());
+        }
+        let token = self.tokenizer.tokenize(namespace, value)?;
+        state
+            .namespaces
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(index, token.clone());
+        self.save(&state)?;
+        Ok(token)
+    }
+
+    /// Token previously issued for `value`, if any.
+    pub fn lookup(&self, namespace: &str, value: &[u8]) -> Result<Option<String>> {
+        let index = self.tokenizer.blind_index(namespace, value)?;
+        let state = self.lock()?;
+        Ok(state.namespaces.get(namespace).and_then(|n| n.get(&index)).cloned())
+    }
+
+    /// Recovers the value behind a token that is still in the vault.
+    pub fn detokenize(&self, namespace: &str, token: &str) -> Result<Vec<u8>> {
+        let value = self.tokenizer.detokenize(namespace, token)?;
+        let index = self.tokenizer.blind_index(namespace, &value)?;
+        let state = self.lock()?;
+        match state.namespaces.get(namespace).and_then(|n| n.get(&index)) {
+            Some(stored) if stored == token => Ok(value),
+            _ => Err(TokenError::TokenNotFound),
+        }
+    }
+
+    /// Removes the token for `value`; returns whether one was recorded.
+    pub fn revoke(&self, namespace: &str, value: &[u8]) -> Result<bool> {
+        let index = self.tokenizer.blind_index(namespace, value)?;
+        let mut state = self.lock()?;
+        let Some(tokens) = state.namespaces.get_mut(namespace) else {
+            return Ok(false);
+        };
+        if tokens.remove(&index).is_none() {
+            return Ok(false);
+        }
+        if tokens.is_empty() {
+            state.namespaces.remove(namespace);
+        }
+        self.save(&state)?;
+        Ok(true)
+    }
+
+    /// Number of tokens recorded in `namespace`.
+    pub fn token_count(&self, namespace: &str) -> Result<usize> {
+        check_namespace(namespace)?;
+        Ok(self.lock()?.namespaces.get(namespace).map_or(0, BTreeMap::len))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, VaultFile>> {
+        self.state
+            .lock()
+            .map_err(|e| TokenError::CorruptVault(e.to_string()))
+    }
+
+    fn save(&self, state: &VaultFile) -> Result<()> {
+        let data = serde_json::to_vec(state).map_err(|e| TokenError::CorruptVault(e.to_string()))?;
+        Ok(self.containers.write_file(self.handle, VAULT_OBJECT, &data)?)
+    }
+}
+
+impl<C: ContainerOperations> Drop for TokenVault<'_, C> {
+    fn drop(&mut self) {
+        let _ = self.containers.close_container(self.handle);
+    }
+}
+
+fn load<C: ContainerOperations>(containers: &C, handle: u32) -> Result<VaultFile> {
+    match containers.read_file(handle, VAULT_OBJECT) {
+        Ok(data) => {
+            let state: VaultFile =
+                serde_json::from_slice(&data).map_err(|e| TokenError::CorruptVault(e.to_string()))?;
+            if state.version != VAULT_VERSION {
+                return Err(TokenError::CorruptVault(format!("unsupported version {}", state.version)));
+            }
+            Ok(state)
+        }
+        Err(FileError::NotFound) => Ok(VaultFile {
+            version: VAULT_VERSION,
+            ..Default::default()
+        }),
+        Err(FileError::DecryptionError(reason) | FileError::IntegrityError(reason)) => {
+            Err(TokenError::CorruptVault(reason))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,155 @@
+use elastic_crypto::{ElasticCrypto, KeyAlgorithm, KeyConfig, KeyType};
+use elastic_file::{ContainerOperations, FileContext, FileError, FileMode, KeySource, MemoryVersionStore};
+use std::sync::Arc;
+use elastic_tokenize::{TokenError, TokenVault, Tokenizer, TOKEN_PREFIX};
+use tempfile::tempdir;
+
+fn keys(crypto: &ElasticCrypto) -> (u32, u32) {
+    let index_key = crypto
+        .generate_key(KeyConfig {
+            key_type: KeyType::Hmac,
+            key_size: 256,
+            secure_storage: true,
+            algorithm: Some(KeyAlgorithm::HmacSha256),
+        })
+        .unwrap();
+    let token_key = crypto
+        .generate_key(KeyConfig {
+            key_type: KeyType::Symmetric,
+            key_size: 512,
+            secure_storage: true,
+            algorithm: Some(KeyAlgorithm::AesSiv),
+        })
+        .unwrap();
+    (index_key, token_key)
+}
+
+/// A file context that can open containers.
+fn files() -> FileContext {
+    FileContext::with_version_store(Arc::new(MemoryVersionStore::default()))
+}
+
+/// A secure handle the vault container key is derived from.
+fn vault_key(crypto: &ElasticCrypto) -> u32 {
+    crypto
+        .generate_key(KeyConfig {
+            key_type: KeyType::Hmac,
+            key_size: 256,
+            secure_storage: true,
+            algorithm: Some(KeyAlgorithm::HmacSha256),
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_blind_index_and_token() {
+    let crypto = ElasticCrypto::new().unwrap();
+    let (index_key, token_key) = keys(&crypto);
+    let tokenizer = Tokenizer::new(&crypto, index_key, token_key).unwrap();
+
+    let index = tokenizer.blind_index("customers.email", b"alice@example.com").unwrap();
+    assert_eq!(index, tokenizer.blind_index("customers.email", b"alice@example.com").unwrap());
+    assert_ne!(index, tokenizer.blind_index("customers.email", b"bob@example.com").unwrap());
+    assert_ne!(index, tokenizer.blind_index("orders.email", b"alice@example.com").unwrap());
+
+    let token = tokenizer.tokenize("customers.email", b"alice@example.com").unwrap();
+    assert!(token.starts_with(TOKEN_PREFIX));
+    assert_eq!(token, tokenizer.tokenize("customers.email", b"alice@example.com").unwrap());
+    assert_eq!(tokenizer.detokenize("customers.email", &token).unwrap(), b"alice@example.com");
+
+    // Tokens are bound to their namespace.
+    assert!(matches!(tokenizer.detokenize("orders.email", &token), Err(TokenError::InvalidToken)));
+    assert!(matches!(tokenizer.detokenize("customers.email", "tok_AAAA"), Err(TokenError::InvalidToken)));
+    assert!(matches!(tokenizer.blind_index("", b"x"), Err(TokenError::InvalidNamespace(_))));
+
+    // Keys must have the right algorithms.
+    assert!(Tokenizer::new(&crypto, token_key, index_key).is_err());
+}
+
+#[test]
+fn test_vault_persists_and_revokes() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("tokens.vault");
+    let crypto = ElasticCrypto::new().unwrap();
+    let (index_key, token_key) = keys(&crypto);
+    let vault_key = vault_key(&crypto);
+    let files = files();
+    let open = || {
+        let tokenizer = Tokenizer::new(&crypto, index_key, token_key).unwrap();
+        TokenVault::open(tokenizer, &files, &path, KeySource::Handle { backend: &crypto, handle: vault_key })
+    };
+
+    let vin = b"WVWZZZ1JZXW000001";
+    let token = {
+        let vault = open().unwrap();
+        let token = vault.tokenize("vehicles.vin", vin).unwrap();
+        assert_eq!(vault.tokenize("vehicles.vin", vin).unwrap(), token);
+        vault.tokenize("vehicles.vin", b"WVWZZZ1JZXW000002").unwrap();
+        assert_eq!(vault.token_count("vehicles.vin").unwrap(), 2);
+        token
+    };
+
+    // The vault object holds pseudonyms only.
+    let stored = std::fs::read(path.join("tokens.json")).unwrap();
+    assert!(!stored.windows(vin.len()).any(|w| w == vin));
+
+    let vault = open().unwrap();
+    assert_eq!(vault.lookup("vehicles.vin", vin).unwrap(), Some(token.clone()));
+    assert_eq!(vault.detokenize("vehicles.vin", &token).unwrap(), vin);
+
+    assert!(vault.revoke("vehicles.vin", vin).unwrap());
+    assert!(!vault.revoke("vehicles.vin", vin).unwrap());
+    assert!(matches!(vault.detokenize("vehicles.vin", &token), Err(TokenError::TokenNotFound)));
+    assert_eq!(vault.lookup("vehicles.vin", vin).unwrap(), None);
+    drop(vault);
+
+    let reopened = open().unwrap();
+    assert_eq!(reopened.token_count("vehicles.vin").unwrap(), 1);
+}
+
+#[test]
+fn test_corrupt_vault() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("tokens.vault");
+    std::fs::create_dir(&path).unwrap();
+    std::fs::write(path.join("tokens.json"), b"not a vault").unwrap();
+    let crypto = ElasticCrypto::new().unwrap();
+    let (index_key, token_key) = keys(&crypto);
+    let vault_key = vault_key(&crypto);
+    let files = files();
+    let tokenizer = Tokenizer::new(&crypto, index_key, token_key).unwrap();
+    assert!(matches!(
+        TokenVault::open(tokenizer, &files, &path, KeySource::Handle { backend: &crypto, handle: vault_key }),
+        Err(TokenError::CorruptVault(_))
+    ));
+}
+
+#[test]
+fn test_vault_is_an_encrypted_container_object() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("tokens.vault");
+    let crypto = ElasticCrypto::new().unwrap();
+    let (index_key, token_key) = keys(&crypto);
+    let key = vault_key(&crypto);
+    let files = files();
+    let open = |handle: u32| {
+        let tokenizer = Tokenizer::new(&crypto, index_key, token_key).unwrap();
+        TokenVault::open(tokenizer, &files, &path, KeySource::Handle { backend: &crypto, handle })
+    };
+
+    let vault = open(key).unwrap();
+    vault.tokenize("users.email", b"a@example.com").unwrap();
+    drop(vault);
+
+    let container = files.open_container(&path, FileMode::Read).unwrap();
+    files
+        .load_container_key(container, KeySource::Handle { backend: &crypto, handle: key })
+        .unwrap();
+    assert_eq!(files.list_files(container, "").unwrap(), vec!["tokens.json"]);
+    assert!(files.is_encrypted(container, "tokens.json").unwrap());
+    files.close_container(container).unwrap();
+
+    // Another key cannot open the vault.
+    assert!(matches!(open(vault_key(&crypto)), Err(TokenError::File(FileError::WrongKey))));
+    assert_eq!(open(key).unwrap().token_count("users.email").unwrap(), 1);
+}
@@ -0,0 +1,848 @@
+use elastic_file::{FileConfig, FileContext, FileError, FileMode, FileOperations, FileType, KeySource, ObjectMetadata};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+const MAX_SYMLINKS: usize = 40;
+const SEEK_SET: i32 = 0;
+/// Largest buffer a single `read` allocates, whatever length the guest asks for.
+const MAX_READ_LEN: u64 = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum WasiFilesystemError {
+    #[error("ELASTIC file error: {0}")]
+    ElasticError(#[from] FileError),
+    #[error("Filesystem error: {0:?}")]
+    Filesystem(ErrorCode),
+}
+
+impl WasiFilesystemError {
+    /// The `error-code` a guest sees for this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            WasiFilesystemError::Filesystem(code) => *code,
+            WasiFilesystemError::ElasticError(e) => match e {
+                FileError::NotFound => ErrorCode::NoEntry,
+                FileError::InvalidHandle | FileError::InvalidMode => ErrorCode::BadDescriptor,
+                FileError::PermissionDenied(_) | FileError::KeyNotLoaded | FileError::WrongKey => ErrorCode::Access,
+                FileError::AlreadyExists(_) => ErrorCode::Exist,
+                FileError::InvalidOperation(_) => ErrorCode::Invalid,
+                FileError::SandboxViolation(_) => ErrorCode::NotPermitted,
+                FileError::IoError(e) => io_code(e),
+                _ => ErrorCode::Io,
+            },
+        }
+    }
+}
+
+impl From<ErrorCode> for WasiFilesystemError {
+    fn from(code: ErrorCode) -> Self {
+        WasiFilesystemError::Filesystem(code)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, WasiFilesystemError>;
+
+fn io_code(e: &io::Error) -> ErrorCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => ErrorCode::NoEntry,
+        io::ErrorKind::PermissionDenied => ErrorCode::Access,
+        io::ErrorKind::AlreadyExists => ErrorCode::Exist,
+        io::ErrorKind::DirectoryNotEmpty => ErrorCode::NotEmpty,
+        io::ErrorKind::NotADirectory => ErrorCode::NotDirectory,
+        io::ErrorKind::IsADirectory => ErrorCode::IsDirectory,
+        io::ErrorKind::StorageFull => ErrorCode::InsufficientSpace,
+        io::ErrorKind::ReadOnlyFilesystem => ErrorCode::ReadOnly,
+        io::ErrorKind::CrossesDevices => ErrorCode::CrossDevice,
+        io::ErrorKind::InvalidInput => ErrorCode::Invalid,
+        io::ErrorKind::Unsupported => ErrorCode::Unsupported,
+        _ => ErrorCode::Io,
+    }
+}
+
+/// WASI-compliant filesystem types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Access,
+    WouldBlock,
+    Already,
+    BadDescriptor,
+    Busy,
+    Deadlock,
+    Quota,
+    Exist,
+    FileTooLarge,
+    IllegalByteSequence,
+    InProgress,
+    Interrupted,
+    Invalid,
+    Io,
+    IsDirectory,
+    Loop,
+    TooManyLinks,
+    MessageSize,
+    NameTooLong,
+    NoDevice,
+    NoEntry,
+    NoLock,
+    InsufficientMemory,
+    InsufficientSpace,
+    NotDirectory,
+    NotEmpty,
+    NotRecoverable,
+    Unsupported,
+    NoTty,
+    NoSuchDevice,
+    Overflow,
+    NotPermitted,
+    Pipe,
+    ReadOnly,
+    InvalidSeek,
+    TextFileBusy,
+    CrossDevice,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorType {
+    Unknown,
+    BlockDevice,
+    CharacterDevice,
+    Directory,
+    Fifo,
+    SymbolicLink,
+    RegularFile,
+    Socket,
+}
+
+impl From<FileType> for DescriptorType {
+    fn from(file_type: FileType) -> Self {
+        match file_type {
+            FileType::Regular => DescriptorType::RegularFile,
+            FileType::Directory => DescriptorType::Directory,
+            FileType::SymbolicLink => DescriptorType::SymbolicLink,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DescriptorFlags {
+    pub read: bool,
+    pub write: bool,
+    pub mutate_directory: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathFlags {
+    pub symlink_follow: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenFlags {
+    pub create: bool,
+    pub directory: bool,
+    pub exclusive: bool,
+    pub truncate: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datetime {
+    pub seconds: u64,
+    pub nanoseconds: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorStat {
+    pub type_: DescriptorType,
+    pub link_count: u64,
+    pub size: u64,
+    pub data_access_timestamp: Option<Datetime>,
+    pub data_modification_timestamp: Option<Datetime>,
+    pub status_change_timestamp: Option<Datetime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub type_: DescriptorType,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataHashValue {
+    pub lower: u64,
+    pub upper: u64,
+}
+
+/// A `descriptor` resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Descriptor(u32);
+
+/// An `input-stream` reading a file from an offset.
+#[derive(Debug)]
+pub struct InputStream {
+    descriptor: Descriptor,
+    offset: u64,
+}
+
+/// An `output-stream` writing a file from an offset.
+#[derive(Debug)]
+pub struct OutputStream {
+    descriptor: Descriptor,
+    offset: u64,
+}
+
+/// A `directory-entry-stream`. Entries are listed when the stream is opened.
+#[derive(Debug)]
+pub struct DirectoryEntryStream {
+    entries: VecDeque<DirectoryEntry>,
+}
+
+impl DirectoryEntryStream {
+    pub fn read_directory_entry(&mut self) -> Result<Option<DirectoryEntry>> {
+        Ok(self.entries.pop_front())
+    }
+}
+
+struct Preopen {
+    context: FileContext,
+    secure: bool,
+}
+
+enum Node {
+    Directory,
+    File(u32),
+}
+
+struct OpenDescriptor {
+    preopen: usize,
+    path: PathBuf,
+    flags: DescriptorFlags,
+    node: Node,
+}
+
+/// WASI-compliant filesystem implementation using ELASTIC
+///
+/// Covers the `wasi:filesystem@0.2.0` interfaces `types` and `preopens`.
+/// Every preopened directory is an elastic-file context rooted at a host
+/// directory, so guests cannot reach anything outside it. In a secure
+/// preopen every file is encrypted and every name below the root is stored
+/// encrypted under the preopen's key, without the guest doing anything.
+///
+/// Metadata hashes identify a path and its last modification rather than an
+/// inode, so hard links to one file hash differently.
+pub struct WasiFilesystem {
+    preopens: Vec<Preopen>,
+    directories: Vec<(Descriptor, String)>,
+    descriptors: HashMap<Descriptor, OpenDescriptor>,
+    next_descriptor: u32,
+}
+
+impl WasiFilesystem {
+    pub fn new() -> Self {
+        Self {
+            preopens: Vec::new(),
+            directories: Vec::new(),
+            descriptors: HashMap::new(),
+            next_descriptor: 0,
+        }
+    }
+
+    /// Makes the host directory `host` available to guests as `guest`.
+    pub fn preopen(&mut self, host: impl AsRef<Path>, guest: &str) -> Result<Descriptor> {
+        let context = FileContext::new().with_root(host)?;
+        Ok(self.add_preopen(Preopen { context, secure: false }, guest))
+    }
+
+    /// Like [`preopen`](Self::preopen), but everything the guest stores
+    /// below `guest` is encrypted under `key`.
+    pub fn preopen_secure(&mut self, host: impl AsRef<Path>, guest: &str, key: KeySource<'_>) -> Result<Descriptor> {
+        let context = FileContext::new().with_root(host)?;
+        context.load_file_key(key)?;
+        Ok(self.add_preopen(Preopen { context, secure: true }, guest))
+    }
+
+    fn add_preopen(&mut self, preopen: Preopen, guest: &str) -> Descriptor {
+        self.preopens.push(preopen);
+        let descriptor = self.insert(OpenDescriptor {
+            preopen: self.preopens.len() - 1,
+            path: PathBuf::new(),
+            flags: DescriptorFlags {
+                read: true,
+                write: false,
+                mutate_directory: true,
+            },
+            node: Node::Directory,
+        });
+        self.directories.push((descriptor, guest.to_string()));
+        descriptor
+    }
+
+    fn insert(&mut self, open: OpenDescriptor) -> Descriptor {
+        let descriptor = Descriptor(self.next_descriptor);
+        self.next_descriptor += 1;
+        self.descriptors.insert(descriptor, open);
+        descriptor
+    }
+
+    /// `preopens.get-directories`
+    pub fn get_directories(&self) -> Vec<(Descriptor, String)> {
+        self.directories
+            .iter()
+            .filter(|(descriptor, _)| self.descriptors.contains_key(descriptor))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_type(&self, descriptor: Descriptor) -> Result<DescriptorType> {
+        let open = self.get(descriptor)?;
+        match open.node {
+            Node::Directory => Ok(DescriptorType::Directory),
+            Node::File(_) => Ok(DescriptorType::RegularFile),
+        }
+    }
+
+    pub fn get_flags(&self, descriptor: Descriptor) -> Result<DescriptorFlags> {
+        Ok(self.get(descriptor)?.flags)
+    }
+
+    pub fn open_at(
+        &mut self,
+        descriptor: Descriptor,
+        path_flags: PathFlags,
+        path: &str,
+        open_flags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> Result<Descriptor> {
+        let dir = self.directory(descriptor)?;
+        let mutates = flags.write || flags.mutate_directory || open_flags.create || open_flags.truncate;
+        if mutates && !dir.flags.mutate_directory {
+            return Err(ErrorCode::NotPermitted.into());
+        }
+        if open_flags.directory && (open_flags.create || open_flags.truncate || flags.write) {
+            return Err(ErrorCode::Invalid.into());
+        }
+        let preopen = dir.preopen;
+        let path = child(&dir.path, path)?;
+        let (path, found) = match self.lookup(preopen, &path, path_flags.symlink_follow) {
+            Ok((path, metadata)) => (path, Some(metadata)),
+            Err(e) if e.code() == ErrorCode::NoEntry && open_flags.create => (path, None),
+            Err(e) => return Err(e),
+        };
+        let node = match found.as_ref().map(|metadata| metadata.as_ref().map(|m| m.file_type)) {
+            Some(_) if open_flags.create && open_flags.exclusive => return Err(ErrorCode::Exist.into()),
+            Some(None | Some(FileType::Directory)) if flags.write || open_flags.truncate => {
+                return Err(ErrorCode::IsDirectory.into())
+            }
+            Some(None | Some(FileType::Directory)) => Node::Directory,
+            Some(Some(FileType::SymbolicLink)) => return Err(ErrorCode::Loop.into()),
+            _ if open_flags.directory => return Err(ErrorCode::NotDirectory.into()),
+            _ => {
+                if found.is_none() || open_flags.truncate {
+                    self.open_file(preopen, &path, FileMode::Write)
+                        .and_then(|handle| self.close_file(preopen, handle))?;
+                }
+                let mode = if flags.write { FileMode::ReadWrite } else { FileMode::Read };
+                Node::File(self.open_file(preopen, &path, mode)?)
+            }
+        };
+        Ok(self.insert(OpenDescriptor {
+            preopen,
+            path,
+            flags,
+            node,
+        }))
+    }
+
+    /// Closes a descriptor, the resource destructor of `descriptor`.
+    pub fn drop_descriptor(&mut self, descriptor: Descriptor) -> Result<()> {
+        let open = self.descriptors.remove(&descriptor).ok_or(ErrorCode::BadDescriptor)?;
+        match open.node {
+            Node::File(handle) => self.close_file(open.preopen, handle),
+            Node::Directory => Ok(()),
+        }
+    }
+
+    pub fn read_via_stream(&self, descriptor: Descriptor, offset: u64) -> Result<InputStream> {
+        let open = self.file(descriptor)?;
+        if !open.flags.read {
+            return Err(ErrorCode::BadDescriptor.into());
+        }
+        Ok(InputStream { descriptor, offset })
+    }
+
+    pub fn write_via_stream(&self, descriptor: Descriptor, offset: u64) -> Result<OutputStream> {
+        let open = self.file(descriptor)?;
+        if !open.flags.write {
+            return Err(ErrorCode::BadDescriptor.into());
+        }
+        Ok(OutputStream { descriptor, offset })
+    }
+
+    /// `input-stream.read`. An empty result means the end of the file was
+    /// reached, which `wasi:io` reports as `closed`. At most `MAX_READ_LEN`
+    /// bytes are returned per call, so callers loop for larger reads.
+    pub fn read(&self, stream: &mut InputStream, len: u64) -> Result<Vec<u8>> {
+        let (context, handle) = self.seek(stream.descriptor, stream.offset)?;
+        let remaining = context.metadata(handle)?.size.saturating_sub(stream.offset);
+        let len = len.min(MAX_READ_LEN).min(remaining);
+        let mut buf = vec![0u8; usize::try_from(len).map_err(|_| ErrorCode::Overflow)?];
+        let read = context.read(handle, &mut buf)?;
+        buf.truncate(read);
+        stream.offset += read as u64;
+        Ok(buf)
+    }
+
+    /// `output-stream.write`
+    pub fn write(&self, stream: &mut OutputStream, contents: &[u8]) -> Result<()> {
+        let (context, handle) = self.seek(stream.descriptor, stream.offset)?;
+        let mut written = 0;
+        while written < contents.len() {
+            match context.write(handle, &contents[written..])? {
+                0 => return Err(ErrorCode::Io.into()),
+                n => written += n,
+            }
+        }
+        stream.offset += written as u64;
+        Ok(())
+    }
+
+    /// `output-stream.flush`
+    pub fn flush(&self, stream: &OutputStream) -> Result<()> {
+        let open = self.file(stream.descriptor)?;
+        let Node::File(handle) = open.node else {
+            return Err(ErrorCode::BadDescriptor.into());
+        };
+        Ok(self.preopens[open.preopen].context.flush(handle)?)
+    }
+
+    pub fn stat(&self, descriptor: Descriptor) -> Result<DescriptorStat> {
+        let open = self.get(descriptor)?;
+        let (_, metadata) = self.lookup(open.preopen, &open.path, false)?;
+        Ok(stat(metadata.as_ref()))
+    }
+
+    pub fn stat_at(&self, descriptor: Descriptor, path_flags: PathFlags, path: &str) -> Result<DescriptorStat> {
+        let dir = self.directory(descriptor)?;
+        let path = child(&dir.path, path)?;
+        let (_, metadata) = self.lookup(dir.preopen, &path, path_flags.symlink_follow)?;
+        Ok(stat(metadata.as_ref()))
+    }
+
+    /// Lists a directory. `.` and `..` are not included.
+    pub fn read_directory(&self, descriptor: Descriptor) -> Result<DirectoryEntryStream> {
+        let dir = self.directory(descriptor)?;
+        if !dir.flags.read {
+            return Err(ErrorCode::BadDescriptor.into());
+        }
+        let preopen = &self.preopens[dir.preopen];
+        let entries = preopen
+            .context
+            .read_dir(&dir.path, preopen.secure)?
+            .into_iter()
+            .map(|entry| DirectoryEntry {
+                type_: entry.file_type.into(),
+                name: entry.name,
+            })
+            .collect();
+        Ok(DirectoryEntryStream { entries })
+    }
+
+    pub fn metadata_hash(&self, descriptor: Descriptor) -> Result<MetadataHashValue> {
+        let open = self.get(descriptor)?;
+        let (path, metadata) = self.lookup(open.preopen, &open.path, false)?;
+        Ok(metadata_hash(open.preopen, &path, metadata.as_ref()))
+    }
+
+    pub fn metadata_hash_at(&self, descriptor: Descriptor, path_flags: PathFlags, path: &str) -> Result<MetadataHashValue> {
+        let dir = self.directory(descriptor)?;
+        let path = child(&dir.path, path)?;
+        let (path, metadata) = self.lookup(dir.preopen, &path, path_flags.symlink_follow)?;
+        Ok(metadata_hash(dir.preopen, &path, metadata.as_ref()))
+    }
+
+    pub fn create_directory_at(&self, descriptor: Descriptor, path: &str) -> Result<()> {
+        let (preopen, path) = self.mutable_path(descriptor, path)?;
+        Ok(preopen.context.create_dir(&path, preopen.secure)?)
+    }
+
+    pub fn remove_directory_at(&self, descriptor: Descriptor, path: &str) -> Result<()> {
+        let (preopen, path) = self.mutable_path(descriptor, path)?;
+        Ok(preopen.context.remove_dir(&path, preopen.secure)?)
+    }
+
+    pub fn unlink_file_at(&self, descriptor: Descriptor, path: &str) -> Result<()> {
+        let (preopen, path) = self.mutable_path(descriptor, path)?;
+        Ok(preopen.context.remove_file(&path, preopen.secure)?)
+    }
+
+    /// Renames within one preopen; moving between preopens fails with
+    /// `cross-device`.
+    pub fn rename_at(&self, descriptor: Descriptor, old_path: &str, new_descriptor: Descriptor, new_path: &str) -> Result<()> {
+        if self.directory(descriptor)?.preopen != self.directory(new_descriptor)?.preopen {
+            return Err(ErrorCode::CrossDevice.into());
+        }
+        let (preopen, from) = self.mutable_path(descriptor, old_path)?;
+        let (_, to) = self.mutable_path(new_descriptor, new_path)?;
+        Ok(preopen.context.rename(&from, &to, preopen.secure)?)
+    }
+
+    fn get(&self, descriptor: Descriptor) -> Result<&OpenDescriptor> {
+        Ok(self.descriptors.get(&descriptor).ok_or(ErrorCode::BadDescriptor)?)
+    }
+
+    fn directory(&self, descriptor: Descriptor) -> Result<&OpenDescriptor> {
+        let open = self.get(descriptor)?;
+        match open.node {
+            Node::Directory => Ok(open),
+            Node::File(_) => Err(ErrorCode::NotDirectory.into()),
+        }
+    }
+
+    fn file(&self, descriptor: Descriptor) -> Result<&OpenDescriptor> {
+        let open = self.get(descriptor)?;
+        match open.node {
+            Node::File(_) => Ok(open),
+            Node::Directory => Err(ErrorCode::IsDirectory.into()),
+        }
+    }
+
+    fn mutable_path(&self, descriptor: Descriptor, path: &str) -> Result<(&Preopen, PathBuf)> {
+        let dir = self.directory(descriptor)?;
+        if !dir.flags.mutate_directory {
+            return Err(ErrorCode::NotPermitted.into());
+        }
+        Ok((&self.preopens[dir.preopen], child(&dir.path, path)?))
+    }
+
+    fn seek(&self, descriptor: Descriptor, offset: u64) -> Result<(&FileContext, u32)> {
+        let open = self.file(descriptor)?;
+        let Node::File(handle) = open.node else {
+            return Err(ErrorCode::BadDescriptor.into());
+        };
+        let context = &self.preopens[open.preopen].context;
+        context.seek(handle, i64::try_from(offset).map_err(|_| ErrorCode::InvalidSeek)?, SEEK_SET)?;
+        Ok((context, handle))
+    }
+
+    fn open_file(&self, preopen: usize, path: &Path, mode: FileMode) -> Result<u32> {
+        let preopen = &self.preopens[preopen];
+        Ok(preopen.context.open(&FileConfig {
+            mode,
+            path: path.to_path_buf(),
+            secure: preopen.secure,
+        })?)
+    }
+
+    fn close_file(&self, preopen: usize, handle: u32) -> Result<()> {
+        Ok(self.preopens[preopen].context.close(handle)?)
+    }
+
+    /// Looks up the entry at `path`, returning the path reached and
+    /// its metadata, or `None` for the preopened directory itself. Symbolic
+    /// links in the last component are followed if `follow` is set.
+    fn lookup(&self, preopen: usize, path: &Path, follow: bool) -> Result<(PathBuf, Option<ObjectMetadata>)> {
+        let preopen_ref = &self.preopens[preopen];
+        let mut path = path.to_path_buf();
+        for _ in 0..MAX_SYMLINKS {
+            let (Some(parent), Some(_)) = (path.parent(), path.file_name()) else {
+                return Ok((path, None));
+            };
+            let metadata = preopen_ref.context.symlink_metadata(&path, preopen_ref.secure)?;
+            if !(follow && metadata.file_type == FileType::SymbolicLink) {
+                return Ok((path, Some(metadata)));
+            }
+            let target = preopen_ref.context.read_link(&path, preopen_ref.secure)?;
+            let target = target.to_str().ok_or(ErrorCode::IllegalByteSequence)?;
+            path = child(parent, target)?;
+        }
+        Err(ErrorCode::Loop.into())
+    }
+}
+
+impl Default for WasiFilesystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Joins a guest path to the directory it is relative to, applying `.` and
+/// `..`. Guest paths may not be absolute or leave the preopen.
+fn child(dir: &Path, path: &str) -> Result<PathBuf> {
+    let mut joined = dir.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => joined.push(name),
+            Component::CurDir => {}
+            Component::ParentDir if joined.pop() => {}
+            _ => return Err(ErrorCode::NotPermitted.into()),
+        }
+    }
+    Ok(joined)
+}
+
+fn datetime(seconds: u64) -> Option<Datetime> {
+    (seconds != 0).then_some(Datetime { seconds, nanoseconds: 0 })
+}
+
+fn stat(metadata: Option<&ObjectMetadata>) -> DescriptorStat {
+    match metadata {
+        Some(metadata) => DescriptorStat {
+            type_: metadata.file_type.into(),
+            link_count: 1,
+            size: metadata.size,
+            data_access_timestamp: datetime(metadata.accessed),
+            data_modification_timestamp: datetime(metadata.modified),
+            status_change_timestamp: None,
+        },
+        None => DescriptorStat {
+            type_: DescriptorType::Directory,
+            link_count: 1,
+            size: 0,
+            data_access_timestamp: None,
+            data_modification_timestamp: None,
+            status_change_timestamp: None,
+        },
+    }
+}
+
+fn metadata_hash(preopen: usize, path: &Path, metadata: Option<&ObjectMetadata>) -> MetadataHashValue {
+    let hash = |half: u8| {
+        let mut hasher = DefaultHasher::new();
+        (half, preopen, path).hash(&mut hasher);
+        if let Some(metadata) = metadata {
+            (metadata.size, metadata.modified).hash(&mut hasher);
+        }
+        hasher.finish()
+    };
+    MetadataHashValue {
+        lower: hash(0),
+        upper: hash(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const KEY: [u8; 32] = [0x42; 32];
+
+    fn read_write() -> DescriptorFlags {
+        DescriptorFlags {
+            read: true,
+            write: true,
+            mutate_directory: false,
+        }
+    }
+
+    fn create() -> OpenFlags {
+        OpenFlags {
+            create: true,
+            ..OpenFlags::default()
+        }
+    }
+
+    fn write_file(fs: &mut WasiFilesystem, dir: Descriptor, path: &str, contents: &[u8]) {
+        let file = fs.open_at(dir, PathFlags::default(), path, create(), read_write()).unwrap();
+        let mut stream = fs.write_via_stream(file, 0).unwrap();
+        fs.write(&mut stream, contents).unwrap();
+        fs.flush(&stream).unwrap();
+        fs.drop_descriptor(file).unwrap();
+    }
+
+    fn read_file(fs: &mut WasiFilesystem, dir: Descriptor, path: &str) -> Vec<u8> {
+        let flags = DescriptorFlags {
+            read: true,
+            ..DescriptorFlags::default()
+        };
+        let file = fs.open_at(dir, PathFlags::default(), path, OpenFlags::default(), flags).unwrap();
+        let mut stream = fs.read_via_stream(file, 0).unwrap();
+        let contents = fs.read(&mut stream, 4096).unwrap();
+        fs.drop_descriptor(file).unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_preopens() {
+        let host = tempdir().unwrap();
+        let mut fs = WasiFilesystem::new();
+        let dir = fs.preopen(host.path(), "/data").unwrap();
+        assert_eq!(fs.get_directories(), vec![(dir, "/data".to_string())]);
+        assert_eq!(fs.get_type(dir).unwrap(), DescriptorType::Directory);
+        assert_eq!(fs.stat(dir).unwrap().type_, DescriptorType::Directory);
+        fs.drop_descriptor(dir).unwrap();
+        assert!(fs.get_directories().is_empty());
+        assert!(fs.preopen(host.path().join("missing"), "/missing").is_err());
+    }
+
+    #[test]
+    fn test_files_and_directories() {
+        let host = tempdir().unwrap();
+        let mut fs = WasiFilesystem::new();
+        let dir = fs.preopen(host.path(), "/").unwrap();
+
+        fs.create_directory_at(dir, "docs").unwrap();
+        write_file(&mut fs, dir, "docs/notes.txt", b"hello world");
+        assert_eq!(fs::read(host.path().join("docs/notes.txt")).unwrap(), b"hello world");
+
+        // Streams start at their offset and advance independently.
+        let file = fs.open_at(dir, PathFlags::default(), "docs/notes.txt", OpenFlags::default(), read_write()).unwrap();
+        let mut output = fs.write_via_stream(file, 6).unwrap();
+        fs.write(&mut output, b"WASI!").unwrap();
+        let mut input = fs.read_via_stream(file, 0).unwrap();
+        assert_eq!(fs.read(&mut input, 5).unwrap(), b"hello");
+        assert_eq!(fs.read(&mut input, 100).unwrap(), b" WASI!");
+        assert!(fs.read(&mut input, 100).unwrap().is_empty());
+        // The guest-supplied length does not size the buffer.
+        let mut input = fs.read_via_stream(file, 6).unwrap();
+        assert_eq!(fs.read(&mut input, u64::MAX).unwrap(), b"WASI!");
+
+        let stat = fs.stat(file).unwrap();
+        assert_eq!((stat.type_, stat.size), (DescriptorType::RegularFile, 11));
+        assert!(stat.data_modification_timestamp.is_some());
+        assert_eq!(fs.stat_at(dir, PathFlags::default(), "docs/notes.txt").unwrap(), stat);
+        assert_eq!(
+            fs.metadata_hash(file).unwrap(),
+            fs.metadata_hash_at(dir, PathFlags::default(), "docs/./notes.txt").unwrap()
+        );
+        assert_ne!(fs.metadata_hash(file).unwrap(), fs.metadata_hash(dir).unwrap());
+        fs.drop_descriptor(file).unwrap();
+
+        let docs = fs.open_at(dir, PathFlags::default(), "docs", OpenFlags::default(), DescriptorFlags {
+            read: true,
+            ..DescriptorFlags::default()
+        })
+        .unwrap();
+        let mut entries = fs.read_directory(docs).unwrap();
+        assert_eq!(
+            entries.read_directory_entry().unwrap(),
+            Some(DirectoryEntry {
+                type_: DescriptorType::RegularFile,
+                name: "notes.txt".to_string(),
+            })
+        );
+        assert_eq!(entries.read_directory_entry().unwrap(), None);
+
+        // Without mutate-directory, the tree below is read-only.
+        let err = fs.create_directory_at(docs, "more").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotPermitted);
+        fs.rename_at(dir, "docs/notes.txt", dir, "notes.txt").unwrap();
+        fs.unlink_file_at(dir, "notes.txt").unwrap();
+        fs.remove_directory_at(dir, "docs").unwrap();
+        assert!(fs::read_dir(host.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_reads_are_capped() {
+        let host = tempdir().unwrap();
+        let mut fs = WasiFilesystem::new();
+        let dir = fs.preopen(host.path(), "/").unwrap();
+        let contents: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+        write_file(&mut fs, dir, "large", &contents);
+
+        let file = fs.open_at(dir, PathFlags::default(), "large", OpenFlags::default(), read_write()).unwrap();
+        let mut input = fs.read_via_stream(file, 0).unwrap();
+        let first = fs.read(&mut input, u64::MAX).unwrap();
+        assert_eq!(first.len() as u64, MAX_READ_LEN);
+        let rest = fs.read(&mut input, u64::MAX).unwrap();
+        assert_eq!([first, rest].concat(), contents);
+        assert!(fs.read(&mut input, u64::MAX).unwrap().is_empty());
+
+        // Past the end there is nothing to allocate for.
+        let mut input = fs.read_via_stream(file, 1 << 40).unwrap();
+        assert!(fs.read(&mut input, u64::MAX).unwrap().is_empty());
+        fs.drop_descriptor(file).unwrap();
+    }
+
+    #[test]
+    fn test_secure_preopen_encrypts_transparently() {
+        let host = tempdir().unwrap();
+        let mut fs = WasiFilesystem::new();
+        let dir = fs.preopen_secure(host.path(), "/secrets", KeySource::Raw(&KEY)).unwrap();
+
+        fs.create_directory_at(dir, "payroll").unwrap();
+        write_file(&mut fs, dir, "payroll/salaries.csv", b"alice,100");
+        assert_eq!(read_file(&mut fs, dir, "payroll/salaries.csv"), b"alice,100");
+        let stat = fs.stat_at(dir, PathFlags::default(), "payroll/salaries.csv").unwrap();
+        assert_eq!(stat.size, 9);
+
+        // On the host, neither names nor contents are visible.
+        let stored = fs::read_dir(host.path()).unwrap().next().unwrap().unwrap();
+        assert!(!stored.file_name().to_string_lossy().contains("payroll"));
+        let file = fs::read_dir(stored.path()).unwrap().next().unwrap().unwrap();
+        assert!(!file.file_name().to_string_lossy().contains("salaries"));
+        let contents = fs::read(file.path()).unwrap();
+        assert!(!contents.windows(5).any(|w| w == b"alice"));
+
+        // Another preopen of the same directory without the key sees only
+        // ciphertext.
+        let mut other = WasiFilesystem::new();
+        let plain = other.preopen(host.path(), "/").unwrap();
+        let mut entries = other.read_directory(plain).unwrap();
+        let entry = entries.read_directory_entry().unwrap().unwrap();
+        assert_ne!(entry.name, "payroll");
+    }
+
+    #[test]
+    fn test_corrupt_entry_does_not_hide_its_neighbours() {
+        let host = tempdir().unwrap();
+        let mut fs = WasiFilesystem::new();
+        let dir = fs.preopen_secure(host.path(), "/", KeySource::Raw(&KEY)).unwrap();
+        write_file(&mut fs, dir, "broken", &[7; 5000]);
+        let stored = fs::read_dir(host.path()).unwrap().next().unwrap().unwrap();
+        // Cut into the first chunk, so the size no longer fits the format.
+        let contents = fs::read(stored.path()).unwrap();
+        fs::write(stored.path(), &contents[..120]).unwrap();
+        write_file(&mut fs, dir, "good", b"still here");
+
+        assert!(fs.stat_at(dir, PathFlags::default(), "broken").is_err());
+        assert_eq!(fs.stat_at(dir, PathFlags::default(), "good").unwrap().size, 10);
+        assert_eq!(read_file(&mut fs, dir, "good"), b"still here");
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let host = tempdir().unwrap();
+        fs::write(host.path().join("file"), b"data").unwrap();
+        std::os::unix::fs::symlink("file", host.path().join("link")).unwrap();
+        let mut fs = WasiFilesystem::new();
+        let dir = fs.preopen(host.path(), "/").unwrap();
+        let code = |result: Result<Descriptor>| result.unwrap_err().code();
+        let read = DescriptorFlags {
+            read: true,
+            ..DescriptorFlags::default()
+        };
+
+        for path in ["../escape", "/etc/passwd", "a/../../escape"] {
+            assert_eq!(code(fs.open_at(dir, PathFlags::default(), path, create(), read_write())), ErrorCode::NotPermitted);
+        }
+        assert_eq!(code(fs.open_at(dir, PathFlags::default(), "missing", OpenFlags::default(), read)), ErrorCode::NoEntry);
+        let exclusive = OpenFlags {
+            create: true,
+            exclusive: true,
+            ..OpenFlags::default()
+        };
+        assert_eq!(code(fs.open_at(dir, PathFlags::default(), "file", exclusive, read_write())), ErrorCode::Exist);
+        let directory = OpenFlags {
+            directory: true,
+            ..OpenFlags::default()
+        };
+        assert_eq!(code(fs.open_at(dir, PathFlags::default(), "file", directory, read)), ErrorCode::NotDirectory);
+        assert_eq!(code(fs.open_at(dir, PathFlags::default(), ".", OpenFlags::default(), read_write())), ErrorCode::IsDirectory);
+
+        // Symbolic links are only opened when followed.
+        assert_eq!(code(fs.open_at(dir, PathFlags::default(), "link", OpenFlags::default(), read)), ErrorCode::Loop);
+        let follow = PathFlags { symlink_follow: true };
+        let file = fs.open_at(dir, follow, "link", OpenFlags::default(), read).unwrap();
+        assert_eq!(fs.stat(file).unwrap().type_, DescriptorType::RegularFile);
+
+        assert_eq!(fs.write_via_stream(file, 0).unwrap_err().code(), ErrorCode::BadDescriptor);
+        assert_eq!(fs.read_directory(file).unwrap_err().code(), ErrorCode::NotDirectory);
+        assert_eq!(fs.read_via_stream(dir, 0).unwrap_err().code(), ErrorCode::IsDirectory);
+        fs.drop_descriptor(file).unwrap();
+        assert_eq!(fs.stat(file).unwrap_err().code(), ErrorCode::BadDescriptor);
+    }
+}
@@ -0,0 +1,135 @@
+use elastic_crypto::ElasticRng;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::env;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WasiRandomError {
+    #[error("ELASTIC crypto error: {0}")]
+    ElasticError(#[from] elastic_crypto::Error),
+    #[error("Random operation failed: {0}")]
+    OperationFailed(String),
+}
+
+pub type Result<T> = std::result::Result<T, WasiRandomError>;
+
+/// WASI-compliant random implementation using ELASTIC
+///
+/// Covers the `wasi:random@0.2.0` interfaces `random`, `insecure` and
+/// `insecure-seed`. Secure output comes straight from the ELASTIC DRBG, the
+/// insecure generator is a fast PRNG seeded once from it.
+pub struct WasiRandom {
+    secure: ElasticRng,
+    insecure: StdRng,
+    insecure_seed: (u64, u64),
+}
+
+impl WasiRandom {
+    pub fn new() -> Result<Self> {
+        let mut secure = ElasticRng::new()?;
+        if env::var("ELASTIC_SEV_SNP").unwrap_or_default() == "1" && !secure.is_sevsnp() {
+            println!("SEV-SNP RNG is not available, using OS entropy");
+        }
+
+        let mut seed = <StdRng as SeedableRng>::Seed::default();
+        secure.fill(&mut seed)?;
+        let insecure = StdRng::from_seed(seed);
+        let insecure_seed = (secure.get_random_u64()?, secure.get_random_u64()?);
+
+        Ok(Self {
+            secure,
+            insecure,
+            insecure_seed,
+        })
+    }
+
+    /// Returns true when the secure generator draws entropy from SEV-SNP
+    pub fn is_sevsnp(&self) -> bool {
+        self.secure.is_sevsnp()
+    }
+
+    /// WASI random interface
+    pub fn get_random_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
+        let len = request_len(len)?;
+        Ok(self.secure.get_random_bytes(len)?)
+    }
+
+    pub fn get_random_u64(&mut self) -> Result<u64> {
+        Ok(self.secure.get_random_u64()?)
+    }
+
+    /// WASI insecure interface
+    pub fn get_insecure_random_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
+        let len = request_len(len)?;
+        let mut bytes = vec![0u8; len];
+        self.insecure.fill_bytes(&mut bytes);
+        Ok(bytes)
+    }
+
+    pub fn get_insecure_random_u64(&mut self) -> Result<u64> {
+        Ok(self.insecure.next_u64())
+    }
+
+    /// WASI insecure-seed interface
+    ///
+    /// The seed is fixed for the lifetime of the instance, as callers use it to
+    /// initialise hash-map DoS protection once.
+    pub fn insecure_seed(&self) -> Result<(u64, u64)> {
+        Ok(self.insecure_seed)
+    }
+}
+
+/// Converts a WASI length, refusing requests larger than the DRBG will serve.
+fn request_len(len: u64) -> Result<usize> {
+    usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= ElasticRng::MAX_RANDOM_BYTES)
+        .ok_or_else(|| WasiRandomError::OperationFailed("Requested length too large".to_string()))
+}
+
+impl Default for WasiRandom {
+    fn default() -> Self {
+        Self::new().expect("Failed to create WasiRandom")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasi_random_creation() {
+        let random = WasiRandom::new();
+        assert!(random.is_ok());
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        let mut random = WasiRandom::new().unwrap();
+
+        let bytes1 = random.get_random_bytes(32).unwrap();
+        let bytes2 = random.get_random_bytes(32).unwrap();
+        assert_eq!(bytes1.len(), 32);
+        assert_ne!(bytes1, bytes2);
+
+        let large = random.get_random_bytes(200_000).unwrap();
+        assert_eq!(large.len(), 200_000);
+        assert!(random.get_random_bytes(u64::MAX).is_err());
+        assert!(random.get_random_bytes(ElasticRng::MAX_RANDOM_BYTES as u64 + 1).is_err());
+        assert_ne!(random.get_random_u64().unwrap(), random.get_random_u64().unwrap());
+    }
+
+    #[test]
+    fn test_insecure_random() {
+        let mut random = WasiRandom::new().unwrap();
+
+        let bytes = random.get_insecure_random_bytes(16).unwrap();
+        assert_eq!(bytes.len(), 16);
+        assert!(random.get_insecure_random_bytes(u64::MAX).is_err());
+        assert_ne!(random.get_insecure_random_u64().unwrap(), random.get_insecure_random_u64().unwrap());
+
+        let seed = random.insecure_seed().unwrap();
+        assert_eq!(seed, random.insecure_seed().unwrap());
+    }
+}
@@ -17,6 +17,7 @@ fn main() {
         key_type: KeyType::Symmetric,
         key_size: 256,
         secure_storage: false,
+        algorithm: None,
     };
     let key_handle = crypto.generate_key(key_config).expect("Failed to generate key");
     
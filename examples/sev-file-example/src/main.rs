@@ -1,9 +1,11 @@
-use elastic_file::{FileConfig, FileContext, FileMode, FileOperations};
+use elastic_file::{FileConfig, FileContext, FileMode, FileOperations, KeySource};
 
 fn main() {
     println!("Starting SEV-SNP file example...");
     
     let ctx = FileContext::new();
+    // Demo key only: real workloads load a key handle or a sealed key.
+    ctx.load_file_key(KeySource::Raw(&[0u8; 32])).expect("Failed to load key");
     let config = FileConfig {
         path: "test.txt".into(),
         mode: FileMode::ReadWrite,
@@ -20,6 +22,7 @@ fn main() {
     println!("Wrote {} bytes", written);
 
     println!("Reading data...");
+    ctx.seek(handle, 0, 0).expect("Failed to seek");
     let mut buf = vec![0u8; 1024];
     let read = ctx.read(handle, &mut buf).expect("Failed to read data");
     println!("Read {} bytes", read);